@@ -0,0 +1,51 @@
+//! Embeds build metadata served by `GET /version`.
+//!
+//! The commit comes from `GIT_COMMIT` when set (Docker builds, where `.git`
+//! is excluded from the context) and from `git rev-parse` otherwise. Anything
+//! that cannot be determined is emitted as `unknown` so the build never fails
+//! on missing tooling.
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let commit = std::env::var("GIT_COMMIT")
+        .ok()
+        .filter(|c| !c.trim().is_empty())
+        .or_else(|| command_output("git", &["rev-parse", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=OFFLOADMQ_GIT_COMMIT={commit}");
+
+    // Seconds since the epoch; formatted at runtime. Honours
+    // SOURCE_DATE_EPOCH for reproducible builds.
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs())
+        })
+        .unwrap_or(0);
+    println!("cargo:rustc-env=OFFLOADMQ_BUILD_TIMESTAMP={timestamp}");
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=OFFLOADMQ_RUSTC_VERSION={rustc_version}");
+}
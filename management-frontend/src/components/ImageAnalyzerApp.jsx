@@ -128,7 +128,7 @@ const ImageAnalyzerApp = ({ apiKey: propApiKey, addDevEntry }) => {
                     return data;
                 }
                 if (data.createdAt) setTaskCreatedAt(prev => prev ?? data.createdAt);
-                if (data.typicalRuntimeSeconds?.secs != null) setHeuristicSecs(data.typicalRuntimeSeconds.secs);
+                if (data.typicalRuntimeMs != null) setHeuristicSecs(data.typicalRuntimeMs / 1000);
                 addDevEntry?.({ key: `poll-${id}`, label: 'Poll task', method: 'POST', url: pollUrl, request: pollBody, response: data });
             } catch { /* retry */ }
         }
@@ -276,7 +276,7 @@ const Img2ImgApp = ({ apiKey, addDevEntry }) => {
           deleteBucket(outBucketUid, apiKey);
         } else {
           if (data.createdAt) setTaskCreatedAt(prev => prev ?? data.createdAt);
-          if (data.typicalRuntimeSeconds?.secs != null) setHeuristicSecs(data.typicalRuntimeSeconds.secs);
+          if (data.typicalRuntimeMs != null) setHeuristicSecs(data.typicalRuntimeMs / 1000);
           setStatusText(statusLabel(data.status, data.stage));
           attempts++;
           setTimeout(poll, 5000);
@@ -143,7 +143,7 @@ const LlmCompareApp = ({ apiKey, addDevEntry }) => {
       }
       const hPatch = {};
       if (data.createdAt) hPatch.createdAt = data.createdAt;
-      if (data.typicalRuntimeSeconds?.secs != null) hPatch.typicalRuntimeSeconds = data.typicalRuntimeSeconds.secs;
+      if (data.typicalRuntimeMs != null) hPatch.typicalRuntimeSeconds = data.typicalRuntimeMs / 1000;
       if (Object.keys(hPatch).length) updateResult(idx, hPatch);
       return 'polling';
     } catch (err) {
@@ -42,10 +42,10 @@ export function useTaskPolling({ currentTask, apiKey, addDevEntry, onResult, onE
           onError(data.error.message || String(data.error));
         } else {
           onStatus?.(data.status);
-          if (data.createdAt || data.typicalRuntimeSeconds) {
+          if (data.createdAt || data.typicalRuntimeMs != null) {
             onHeuristics?.({
               createdAt: data.createdAt ?? null,
-              typicalRuntimeSeconds: data.typicalRuntimeSeconds?.secs ?? null,
+              typicalRuntimeSeconds: data.typicalRuntimeMs != null ? data.typicalRuntimeMs / 1000 : null,
             });
           }
         }
@@ -147,7 +147,7 @@ class MockTask(BaseModel):
             "result": self.result,
             "log": self.log,
             "stage": self.stage,
-            "typicalRuntimeSeconds": None,
+            "typicalRuntimeMs": None,
             "cancelRequestedAt": None,
             "finishedAt": None,
             "lastUpdateAt": iso_z(self.assigned_at) if self.assigned_at else None,
@@ -9,7 +9,7 @@ use axum::{
         Multipart, Path, Query, State, WebSocketUpgrade,
         ws::{Message, WebSocket},
     },
-    http::{StatusCode, header},
+    http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use chrono::Utc;
@@ -20,7 +20,7 @@ use serde_json::json;
 
 use crate::{
     error::AppError,
-    middleware::AuthenticatedAgent,
+    middleware::{AuthenticatedAgent, PollingAgent, auth_metrics::AuthSource},
     models::{Agent, CommunicationMethod},
     schema::{self, TaskId},
     state::AppState,
@@ -35,7 +35,7 @@ pub async fn agent_ping(
 }
 
 pub async fn fetch_task_urgent_handler(
-    AuthenticatedAgent(agent): AuthenticatedAgent,
+    agent: PollingAgent,
     State(app_state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, AppError> {
     let task = service::poll_urgent(agent, &app_state, CommunicationMethod::Http).await?;
@@ -43,7 +43,7 @@ pub async fn fetch_task_urgent_handler(
 }
 
 pub async fn fetch_task_non_urgent_handler(
-    AuthenticatedAgent(agent): AuthenticatedAgent,
+    agent: PollingAgent,
     State(app_state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, AppError> {
     let task = service::poll_non_urgent(agent, &app_state, CommunicationMethod::Http).await?;
@@ -60,6 +60,23 @@ pub async fn update_agent_info(
     Ok(Json(resp))
 }
 
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DeregisterRequest {
+    /// Agent to remove; must be the caller when given.
+    pub agent_id: Option<String>,
+}
+
+pub async fn deregister_agent(
+    AuthenticatedAgent(agent): AuthenticatedAgent,
+    State(state): State<Arc<AppState>>,
+    req: Option<Json<DeregisterRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+    let target = req.and_then(|Json(req)| req.agent_id);
+    let resp = service::do_deregister_agent(agent, target, &state).await?;
+    Ok(Json(resp))
+}
+
 pub async fn register_agent(
     State(state): State<Arc<AppState>>,
     Json(req): Json<schema::AgentRegistrationRequest>,
@@ -76,6 +93,31 @@ pub async fn auth_agent(
     Ok(Json(resp))
 }
 
+pub async fn refresh_agent_token(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<schema::AgentRefreshTokenRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let resp = service::do_refresh_agent_token(request, &state).await?;
+    Ok(Json(resp))
+}
+
+/// POST /private/agent/auth/refresh
+///
+/// Exchanges the bearer token for a fresh one. Bypasses the JWT middleware so
+/// that tokens which expired within the grace window are still accepted.
+pub async fn refresh_agent_session(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| AppError::Authorization("Unauthorized".to_string()))?;
+    let resp = service::do_refresh_agent_session(token, &state).await?;
+    Ok(Json(resp))
+}
+
 /// GET /private/agent/bucket/{bucket_uid}/stat
 ///
 /// Returns the list of files in a bucket so the agent can discover file UIDs
@@ -275,6 +317,21 @@ pub async fn try_take_task_handler(
     Ok(Json(task))
 }
 
+/// Body answered to a resolve, shared by the HTTP and WebSocket transports.
+fn resolve_response(outcome: &service::ResolveOutcome) -> serde_json::Value {
+    match outcome {
+        service::ResolveOutcome::Confirmed => json!({"message": "task report confirmed"}),
+        service::ResolveOutcome::UnknownTask { stored } => json!({
+            "message": "task report ignored",
+            "note": if *stored {
+                "task is no longer assigned; report stored for reconciliation"
+            } else {
+                "task is no longer assigned; report discarded"
+            },
+        }),
+    }
+}
+
 pub async fn post_task_resolution(
     AuthenticatedAgent(agent): AuthenticatedAgent,
     State(app_state): State<Arc<AppState>>,
@@ -285,7 +342,7 @@ pub async fn post_task_resolution(
     if report.id != task_id {
         return Err(AppError::BadRequest(id));
     }
-    service::resolve_task(
+    let outcome = service::resolve_task(
         agent,
         task_id,
         report,
@@ -293,7 +350,7 @@ pub async fn post_task_resolution(
         CommunicationMethod::Http,
     )
     .await?;
-    Ok(Json(json!({"message": "task report confirmed"})))
+    Ok(Json(resolve_response(&outcome)))
 }
 
 pub async fn post_task_progress_update(
@@ -317,6 +374,17 @@ pub async fn post_task_progress_update(
     Ok(Json(json!({"message": "task update confirmed"})))
 }
 
+pub async fn touch_task(
+    AuthenticatedAgent(agent): AuthenticatedAgent,
+    State(app_state): State<Arc<AppState>>,
+    Path((cap, id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let task_id = TaskId::from_url(id, cap)?;
+    let response =
+        service::touch_task(agent, task_id, &app_state, CommunicationMethod::Http).await?;
+    Ok(Json(response))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct WsAuthQuery {
     pub token: String,
@@ -327,14 +395,33 @@ pub async fn websocket_handler(
     Query(query): Query<WsAuthQuery>,
     State(app_state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let claims = app_state
-        .auth
-        .decode_token(&query.token)
-        .map_err(|_| AppError::Authorization("Invalid or expired token".to_string()))?;
-    let agent = app_state
-        .storage
-        .get_agent(&claims.sub)
-        .ok_or_else(|| AppError::Authorization("Agent not found".to_string()))?;
+    let metrics = &app_state.auth_metrics;
+    let claims = app_state.auth.decode_token(&query.token).map_err(|e| {
+        metrics.failure(
+            AuthSource::AgentJwt,
+            "invalid_token",
+            &format!("WebSocket token rejected: {}", e),
+        );
+        AppError::Authorization("Invalid or expired token".to_string())
+    })?;
+    crate::middleware::ensure_session_active(&app_state.storage.agents, &claims).inspect_err(
+        |_| {
+            metrics.failure(
+                AuthSource::AgentJwt,
+                "revoked_token",
+                &format!("WebSocket token of agent '{}' revoked", claims.sub),
+            )
+        },
+    )?;
+    let agent = app_state.storage.get_agent(&claims.sub).ok_or_else(|| {
+        metrics.failure(
+            AuthSource::AgentJwt,
+            "unknown_agent",
+            &format!("WebSocket token subject '{}' not found", claims.sub),
+        );
+        AppError::Authorization("Agent not found".to_string())
+    })?;
+    metrics.success(AuthSource::AgentJwt);
     info!("Agent {} connected via WebSocket", agent.uid_short);
     if let Err(e) = app_state
         .storage
@@ -396,15 +483,22 @@ async fn ws_dispatch(
 
         // ── Poll ─────────────────────────────────────────────────
         "poll_task" => {
-            let task =
-                service::poll_non_urgent(agent.clone(), state, CommunicationMethod::WebSocket)
-                    .await?;
+            let task = service::poll_non_urgent(
+                PollingAgent::Loaded(Box::new(agent.clone())),
+                state,
+                CommunicationMethod::WebSocket,
+            )
+            .await?;
             Ok((200, serde_json::to_value(task).unwrap_or(json!(null))))
         }
 
         "poll_task_urgent" => {
-            let task =
-                service::poll_urgent(agent.clone(), state, CommunicationMethod::WebSocket).await?;
+            let task = service::poll_urgent(
+                PollingAgent::Loaded(Box::new(agent.clone())),
+                state,
+                CommunicationMethod::WebSocket,
+            )
+            .await?;
             Ok((200, serde_json::to_value(task).unwrap_or(json!(null))))
         }
 
@@ -428,7 +522,7 @@ async fn ws_dispatch(
             let report: schema::TaskResultReport = serde_json::from_value(params.clone())
                 .map_err(|e| AppError::BadRequest(format!("invalid resolve_task params: {e}")))?;
             let task_id = report.id.clone();
-            service::resolve_task(
+            let outcome = service::resolve_task(
                 agent.clone(),
                 task_id,
                 report,
@@ -436,7 +530,7 @@ async fn ws_dispatch(
                 CommunicationMethod::WebSocket,
             )
             .await?;
-            Ok((200, json!({"message": "task report confirmed"})))
+            Ok((200, resolve_response(&outcome)))
         }
 
         // ── Progress ─────────────────────────────────────────────
@@ -457,6 +551,27 @@ async fn ws_dispatch(
             Ok((200, json!({"message": "task update confirmed"})))
         }
 
+        // ── Touch (keepalive) ────────────────────────────────────
+        "touch_task" => {
+            let id = params["id"]
+                .as_str()
+                .ok_or_else(|| AppError::BadRequest("missing params.id".into()))?
+                .to_string();
+            let cap = params["cap"]
+                .as_str()
+                .ok_or_else(|| AppError::BadRequest("missing params.cap".into()))?
+                .to_string();
+            let task_id = TaskId { cap, id };
+            let response = service::touch_task(
+                agent.clone(),
+                task_id,
+                state,
+                CommunicationMethod::WebSocket,
+            )
+            .await?;
+            Ok((200, serde_json::to_value(response).unwrap_or(json!(null))))
+        }
+
         // ── Upload file ──────────────────────────────────────────
         "upload_file" => {
             use sha2::{Digest, Sha256};
@@ -580,9 +695,12 @@ async fn ws_route_get(
         }
         // /private/agent/task/poll (alias for poll_task)
         ["private", "agent", "task", "poll"] => {
-            let task =
-                service::poll_non_urgent(_agent.clone(), state, CommunicationMethod::WebSocket)
-                    .await?;
+            let task = service::poll_non_urgent(
+                PollingAgent::Loaded(Box::new(_agent.clone())),
+                state,
+                CommunicationMethod::WebSocket,
+            )
+            .await?;
             Ok((200, serde_json::to_value(task).unwrap_or(json!(null))))
         }
         _ => Err(AppError::NotFound(format!(
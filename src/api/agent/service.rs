@@ -3,22 +3,33 @@ use std::sync::Arc;
 use log::{debug, info, warn};
 
 use crate::{
-    db::heuristic_storage::HeuristicStorage,
+    config::{AppConfig, UnknownTaskReportPolicy},
+    db::{apikeys::ApiKeysStorage, heuristic_storage::HeuristicStorage},
     error::AppError,
-    models::{Agent, AssignedTask, CommunicationMethod, UnassignedTask},
+    middleware::{PollingAgent, auth_metrics::AuthSource},
+    models::{Agent, AssignedTask, CommunicationMethod, LateReport, UnassignedTask},
     mq::scheduler::{
+        dispatchable_caps, ensure_take_capacity,
         find_assignable_non_urgent_tasks_with_capabilities_for_tier,
-        find_urgent_tasks_with_capabilities, report_non_urgent_task, report_urgent_task,
+        find_urgent_tasks_with_capabilities, has_non_urgent_headroom, least_loaded_caps,
+        report_non_urgent_task, report_urgent_task, touch_non_urgent_task,
         try_pick_up_non_urgent_task, try_pick_up_urgent_task, update_non_urgent_task,
-        update_urgent_task,
+        update_urgent_task, validate_progress,
+    },
+    mq::{
+        poll_gate::PollGate,
+        quarantine::{AgentFault, report_fault},
+        types::NonUrgentReportOutcome,
     },
     schema::{
-        AgentLoginRequest, AgentLoginResponse, AgentRegistrationRequest, AgentRegistrationResponse,
+        AgentEndpoints, AgentLoginRequest, AgentLoginResponse, AgentPollingSettings,
+        AgentRefreshTokenRequest, AgentRegistrationRequest, AgentRegistrationResponse,
         AgentUpdateRequest, BucketStatResponse, DownloadedFile, FileStatEntry, TaskId,
-        TaskResultReport, TaskStatus, TaskUpdate, TypicalRuntimeParameters,
+        TaskResultReport, TaskResultStatus, TaskStatus, TaskTouchResponse, TaskUpdate,
+        TypicalRuntimeParameters,
     },
     state::{AppState, StreamEvent, TaskLifecycleEvent, TaskQueueKind},
-    utils::base_capability,
+    utils::{base_capability, validate_json_schema},
 };
 
 pub async fn do_agent_ping(
@@ -34,48 +45,121 @@ pub async fn do_agent_ping(
     Ok(())
 }
 
+/// Record the poll as contact and return the agent to match tasks for. A
+/// capability-scoped token is trusted for what the agent may poll, but a
+/// capability removed since issuance is dropped: the token never widens the
+/// stored record.
+async fn touch_polling_agent(
+    poller: PollingAgent,
+    state: &Arc<AppState>,
+    comm_method: CommunicationMethod,
+) -> Result<Agent, AppError> {
+    match poller {
+        PollingAgent::Loaded(agent) => Ok(state
+            .storage
+            .agents
+            .update_agent_last_contact(*agent, comm_method)
+            .await?),
+        PollingAgent::Scoped { uid, caps } => {
+            let mut agent = state
+                .storage
+                .agents
+                .touch_last_contact(&uid, comm_method)
+                .await?
+                .ok_or_else(|| AppError::Authorization("Agent not found".to_string()))?;
+            agent.capabilities.retain(|cap| caps.contains(cap));
+            Ok(agent)
+        }
+    }
+}
+
 pub async fn poll_urgent(
-    agent: Agent,
+    poller: PollingAgent,
     state: &Arc<AppState>,
     comm_method: CommunicationMethod,
 ) -> Result<Option<UnassignedTask>, AppError> {
-    let agent = state
-        .storage
-        .agents
-        .update_agent_last_contact(agent, comm_method)
-        .await?;
+    let agent = touch_polling_agent(poller, state, comm_method).await?;
+    if agent.is_quarantined() {
+        return Ok(None);
+    }
     let caps = &agent.capabilities;
-    Ok(find_urgent_tasks_with_capabilities(&state.urgent, caps, &agent.uid).await)
+    Ok(find_urgent_tasks_with_capabilities(
+        &state.urgent,
+        caps,
+        &agent.uid,
+        Some(&agent.system_info),
+    )
+    .await)
 }
 
 pub async fn poll_non_urgent(
-    agent: Agent,
+    poller: PollingAgent,
     state: &Arc<AppState>,
     comm_method: CommunicationMethod,
 ) -> Result<Option<UnassignedTask>, AppError> {
-    let agent = state
-        .storage
-        .agents
-        .update_agent_last_contact(agent, comm_method)
-        .await?;
+    let agent = touch_polling_agent(poller, state, comm_method).await?;
+    if agent.is_quarantined() {
+        return Ok(None);
+    }
     let caps = &agent.capabilities;
     debug!(
         "Searching for tasks for agent {:?} with tier {:?}",
         agent, agent.tier
     );
-    let urgent = find_urgent_tasks_with_capabilities(&state.urgent, caps, &agent.uid).await;
-    if let Some(task) = urgent {
-        return Ok(Some(task));
+    let gate_key = PollGate::key(caps, agent.tier);
+    let found = state
+        .poll_gate
+        .run(&gate_key, || async {
+            let urgent = find_urgent_tasks_with_capabilities(
+                &state.urgent,
+                caps,
+                &agent.uid,
+                Some(&agent.system_info),
+            )
+            .await;
+            if urgent.is_some() {
+                return urgent;
+            }
+            if !has_non_urgent_headroom(
+                &agent,
+                &state.agent_load,
+                state.config.urgent.reserved_slots,
+            ) {
+                return None;
+            }
+            let caps = least_loaded_caps(
+                &agent,
+                dispatchable_caps(state, caps),
+                &state.storage.agents,
+                &state.agent_load,
+                state.regular.preferences().selection_strategy,
+            );
+            find_assignable_non_urgent_tasks_with_capabilities_for_tier(
+                &state.regular,
+                &caps,
+                agent.tier,
+                &state.storage.agents,
+                &agent.uid,
+                &state.storage.capabilities.scheduling_weights(),
+            )
+            .await
+        })
+        .await;
+    Ok(found)
+}
+
+fn validate_requested_agent_id(agent_id: &Option<String>) -> Result<(), AppError> {
+    if let Some(id) = agent_id {
+        let valid_chars = id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if id.is_empty() || id.len() > 64 || !valid_chars {
+            return Err(AppError::BadRequest(
+                "agent_id must be 1-64 characters of [A-Za-z0-9_-]".to_string(),
+            ));
+        }
     }
-    let all = find_assignable_non_urgent_tasks_with_capabilities_for_tier(
-        &state.regular,
-        caps,
-        agent.tier,
-        &state.storage.agents,
-        &agent.uid,
-    )
-    .await;
-    Ok(all)
+    Ok(())
 }
 
 fn validate_display_name(name: &Option<String>) -> Result<(), AppError> {
@@ -96,12 +180,14 @@ pub async fn do_update_agent_info(
     comm_method: CommunicationMethod,
 ) -> Result<AgentRegistrationResponse, AppError> {
     validate_display_name(&req.display_name)?;
+    validate_agent_capabilities(&agent.allowed_capabilities, &req.capabilities)?;
     agent.capabilities = req.capabilities;
     agent.capacity = req.capacity;
     agent.system_info = req.system_info;
     agent.tier = req.tier;
     agent.app_version = req.app_version;
     agent.display_name = req.display_name;
+    agent.urgent_reserved_slots = req.urgent_reserved_slots;
     let uid = agent.uid.clone();
     let key = agent.personal_login_token.clone();
     state
@@ -109,10 +195,98 @@ pub async fn do_update_agent_info(
         .agents
         .update_agent_last_contact(agent, comm_method)
         .await?;
+    state.online_caps.invalidate();
     Ok(AgentRegistrationResponse {
         agent_id: uid,
         message: "Updated".to_string(),
         key,
+        polling: Some(polling_settings(&state.config)),
+    })
+}
+
+/// What deregistration did with the tasks the agent held.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeregisterOutcome {
+    pub agent_id: String,
+    pub requeued: usize,
+    pub failed: usize,
+}
+
+/// Agents may only deregister themselves; removing another agent is a
+/// management operation (`POST /management/agents/delete/{agent_id}`).
+fn ensure_self(agent: &Agent, target: Option<&str>) -> Result<(), AppError> {
+    match target {
+        Some(uid) if uid != agent.uid => Err(AppError::Authorization(format!(
+            "Agent {} may not deregister agent {}",
+            agent.uid, uid
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Tasks handed back by [`release_agent_work`].
+pub struct ReleasedWork {
+    pub requeued: usize,
+    pub failed: usize,
+    /// Capabilities of the re-queued and re-pended tasks, to dispatch once
+    /// the agent is gone.
+    pub caps: Vec<String>,
+}
+
+/// Hand back every task `agent_uid` holds: restartable non-urgent tasks are
+/// re-queued and the rest failed, and urgent tasks it has not started go
+/// back to pending.
+pub async fn release_agent_work(
+    state: &Arc<AppState>,
+    agent_uid: &str,
+) -> Result<ReleasedWork, AppError> {
+    let released = state
+        .storage
+        .tasks
+        .release_agent_tasks(agent_uid, state.config.task_restart.max_restarts)?;
+    let requeued = released.requeued.len();
+    let mut caps = Vec::with_capacity(requeued);
+    for task in released.requeued {
+        caps.push(task.id.cap.clone());
+        state.regular.add_task(task).await;
+    }
+    for (owner, task_id) in state.urgent.list_assigned_owners().await {
+        if owner == agent_uid && state.urgent.unassign_task(&task_id).await {
+            caps.push(task_id.cap);
+        }
+    }
+    Ok(ReleasedWork {
+        requeued,
+        failed: released.agents.len() - requeued,
+        caps,
+    })
+}
+
+/// Remove the calling agent for good. Tasks it holds are handed back first
+/// (see [`release_agent_work`]).
+pub async fn do_deregister_agent(
+    agent: Agent,
+    target: Option<String>,
+    state: &Arc<AppState>,
+) -> Result<DeregisterOutcome, AppError> {
+    ensure_self(&agent, target.as_deref())?;
+    let released = release_agent_work(state, &agent.uid).await?;
+
+    state.storage.delete_agent(&agent.uid).await?;
+    state.online_caps.invalidate();
+    info!(
+        "Agent {} deregistered ({} task(s) re-queued, {} failed)",
+        agent.uid, released.requeued, released.failed
+    );
+
+    for cap in released.caps {
+        crate::mq::dispatch::dispatch_for_capability(state, base_capability(&cap)).await;
+    }
+    Ok(DeregisterOutcome {
+        agent_id: agent.uid,
+        requeued: released.requeued,
+        failed: released.failed,
     })
 }
 
@@ -122,15 +296,57 @@ pub async fn do_register_agent(
 ) -> Result<AgentRegistrationResponse, AppError> {
     validate_api_key(&state.config.agent_api_keys, &req.api_key)?;
     validate_display_name(&req.display_name)?;
+    validate_requested_agent_id(&req.agent_id)?;
+    let allowed_capabilities = state
+        .config
+        .agent_key_capabilities
+        .get(&req.api_key)
+        .cloned();
+    validate_agent_capabilities(&allowed_capabilities, &req.capabilities)?;
     let mut agent_object: Agent = req.into();
-    state.storage.agents.create_agent(&mut agent_object).await?;
+    agent_object.allowed_capabilities = allowed_capabilities;
+    state
+        .storage
+        .agents
+        .create_agent(&mut agent_object, state.config.duplicate_agent_uid)
+        .await?;
+    state.online_caps.invalidate();
     Ok(AgentRegistrationResponse {
         agent_id: agent_object.uid,
         message: "Registered".to_string(),
         key: agent_object.personal_login_token,
+        polling: Some(polling_settings(&state.config)),
     })
 }
 
+/// Polling cadence and paths handed to agents on register and info update.
+/// The heartbeat stays within half the online window so one lost ping doesn't
+/// take the agent offline.
+fn polling_settings(config: &AppConfig) -> AgentPollingSettings {
+    let heartbeat_interval_secs = config
+        .agent_ws
+        .heartbeat_min_secs
+        .min(config.agent_online_timeout_secs / 2);
+    AgentPollingSettings {
+        urgent_poll_interval_ms: config.agent_polling.urgent_interval_ms,
+        poll_interval_ms: config.agent_polling.interval_ms,
+        heartbeat_interval_secs,
+        endpoints: AgentEndpoints {
+            auth: "/agent/auth".to_string(),
+            refresh_token: "/agent/refresh_token".to_string(),
+            websocket: "/private/agent/ws".to_string(),
+            ping: "/private/agent/ping".to_string(),
+            info_update: "/private/agent/info/update".to_string(),
+            poll_urgent: "/private/agent/task/poll_urgent".to_string(),
+            poll: "/private/agent/task/poll".to_string(),
+            take: "/private/agent/take/{cap}/{id}".to_string(),
+            progress: "/private/agent/task/progress/{cap}/{id}".to_string(),
+            resolve: "/private/agent/task/resolve/{cap}/{id}".to_string(),
+            touch: "/private/agent/task/touch/{cap}/{id}".to_string(),
+        },
+    }
+}
+
 pub async fn do_auth_agent(
     req: AgentLoginRequest,
     state: &Arc<AppState>,
@@ -141,27 +357,141 @@ pub async fn do_auth_agent(
         .agents
         .get_agent(&req.agent_id)
         .ok_or_else(|| {
-            warn!(
-                "Agent auth rejected: unknown agent_id '{}' (known_agents={})",
-                req.agent_id,
-                state.storage.agent_count()
+            state.auth_metrics.failure(
+                AuthSource::AgentLogin,
+                "unknown_agent",
+                &format!(
+                    "unknown agent_id '{}' (known_agents={})",
+                    req.agent_id,
+                    state.storage.agent_count()
+                ),
             );
             mk_auth_err()
         })?;
     if agent.personal_login_token != req.key {
-        warn!(
-            "Agent auth rejected: incorrect key for agent_id '{}' (uid_short={})",
-            req.agent_id, agent.uid_short
+        state.auth_metrics.failure(
+            AuthSource::AgentLogin,
+            "invalid_key",
+            &format!(
+                "incorrect key for agent_id '{}' (uid_short={})",
+                req.agent_id, agent.uid_short
+            ),
         );
         return Err(mk_auth_err());
     }
+    state.auth_metrics.success(AuthSource::AgentLogin);
+    let caps = agent.capabilities.clone();
     state
         .storage
         .agents
         .update_agent_last_contact(agent, CommunicationMethod::Http)
         .await?;
-    let (token, expires_in) = state.auth.create_token(&req.agent_id)?;
-    Ok(AgentLoginResponse { token, expires_in })
+    let (token, expires_in) = state.auth.create_token(&req.agent_id, Some(caps))?;
+    let refresh_token = if req.request_refresh_token {
+        let (refresh_token, jti, _) = state.auth.create_refresh_token(&req.agent_id)?;
+        state
+            .storage
+            .agents
+            .store_refresh_token(&jti, &req.agent_id)?;
+        Some(refresh_token)
+    } else {
+        None
+    };
+    Ok(AgentLoginResponse {
+        token,
+        expires_in,
+        refresh_token,
+    })
+}
+
+/// Exchange a stored, unexpired refresh token for a new session JWT. The refresh
+/// token itself is returned unchanged and stays valid until it expires or is revoked.
+pub async fn do_refresh_agent_token(
+    req: AgentRefreshTokenRequest,
+    state: &Arc<AppState>,
+) -> Result<AgentLoginResponse, AppError> {
+    let claims = state
+        .auth
+        .decode_refresh_token(&req.refresh_token)
+        .inspect_err(|e| {
+            state.auth_metrics.failure(
+                AuthSource::AgentLogin,
+                "invalid_refresh_token",
+                &format!("refresh token rejected: {}", e),
+            )
+        })?;
+    let jti = claims.jti.as_deref().unwrap_or_default();
+    if !state
+        .storage
+        .agents
+        .is_refresh_token_valid(jti, &claims.sub)
+    {
+        state.auth_metrics.failure(
+            AuthSource::AgentLogin,
+            "revoked_refresh_token",
+            &format!("refresh token {} for agent {} revoked", jti, claims.sub),
+        );
+        return Err(AppError::Authentication(
+            "Refresh token revoked".to_string(),
+        ));
+    }
+    let Some(agent) = state.storage.get_agent(&claims.sub) else {
+        return Err(AppError::Authentication("Agent not found".to_string()));
+    };
+    let (token, expires_in) = state
+        .auth
+        .create_token(&claims.sub, Some(agent.capabilities))?;
+    Ok(AgentLoginResponse {
+        token,
+        expires_in,
+        refresh_token: None,
+    })
+}
+
+/// Exchange a still-valid, or recently expired, session JWT for a new one
+/// without the login key. The agent must still be registered.
+pub async fn do_refresh_agent_session(
+    old_token: &str,
+    state: &Arc<AppState>,
+) -> Result<AgentLoginResponse, AppError> {
+    let old_claims = state
+        .auth
+        .decode_for_refresh(old_token, state.config.agent_token_refresh_grace_secs)
+        .inspect_err(|e| {
+            state.auth_metrics.failure(
+                AuthSource::AgentJwt,
+                "invalid_token",
+                &format!("session refresh rejected: {}", e),
+            )
+        })?;
+    crate::middleware::ensure_session_active(&state.storage.agents, &old_claims).inspect_err(
+        |_| {
+            state.auth_metrics.failure(
+                AuthSource::AgentJwt,
+                "revoked_token",
+                &format!("session refresh for revoked agent '{}'", old_claims.sub),
+            )
+        },
+    )?;
+    let agent_id = old_claims.sub;
+    let Some(agent) = state.storage.get_agent(&agent_id) else {
+        state.auth_metrics.failure(
+            AuthSource::AgentJwt,
+            "unknown_agent",
+            &format!("session refresh for unknown agent '{}'", agent_id),
+        );
+        return Err(AppError::Authentication("Agent not found".to_string()));
+    };
+    // Scope the new token to the stored capabilities, not the old claims,
+    // so capability changes apply without a fresh login.
+    let (token, expires_in) = state
+        .auth
+        .create_token(&agent_id, Some(agent.capabilities.clone()))?;
+    Ok(AgentLoginResponse {
+        token,
+        expires_in,
+        refresh_token: None,
+    })
 }
 
 pub fn get_bucket_stat(
@@ -229,6 +559,14 @@ pub async fn take_task(
     state: &Arc<AppState>,
 ) -> Result<AssignedTask, AppError> {
     info!("Agent {} picking up task {task_id}", agent.uid_short);
+    if let Some(until) = agent.quarantined_until.filter(|_| agent.is_quarantined()) {
+        return Err(AppError::Conflict(format!(
+            "Agent {} is quarantined until {}",
+            agent.uid_short, until
+        )));
+    }
+    let reserved = state.config.urgent.reserved_slots;
+    ensure_take_capacity(agent, &state.agent_load, true, reserved)?;
     let cap = base_capability(&task_id.cap);
     let machine_id = agent.system_info.machine_id.as_deref().unwrap_or("");
 
@@ -248,6 +586,9 @@ pub async fn take_task(
         // capacity gate's source of truth, keyed by uid). The store write above
         // already happened, so a concurrent reconcile can't drop it.
         state.agent_load.assigned(&agent.uid, task_id.clone());
+        state
+            .queue_wait
+            .record(cap, picked.assigned_at - picked.created_at);
         // Also track it on the agent's live WS connection (if any) so disconnect
         // handling can re-queue un-started tasks. No-op for unconnected agents.
         state.registry.track_assigned(&agent.uid, task_id.clone());
@@ -265,6 +606,7 @@ pub async fn take_task(
         );
         Ok(picked)
     } else {
+        ensure_take_capacity(agent, &state.agent_load, false, reserved)?;
         let mut assigned = try_pick_up_non_urgent_task(
             &state.regular,
             &state.storage.tasks,
@@ -273,6 +615,7 @@ pub async fn take_task(
         )
         .await?;
         log_runner_history(agent, &task_id, &state.storage.heuristics);
+        state.circuit.assigned(cap);
         let params = TypicalRuntimeParameters::from_payload(&task_id.cap, &assigned.data.payload);
         let estimate = state
             .storage
@@ -285,6 +628,9 @@ pub async fn take_task(
             debug!("Failed to persist runtime estimate for task {task_id}: {e}");
         }
         state.agent_load.assigned(&agent.uid, task_id.clone());
+        state
+            .queue_wait
+            .record(cap, assigned.assigned_at - assigned.created_at);
         state.registry.track_assigned(&agent.uid, task_id.clone());
         emit_task_lifecycle(
             state,
@@ -339,13 +685,51 @@ fn log_runner_history(agent: &Agent, task_id: &TaskId, heuristics: &HeuristicSto
     }
 }
 
+/// Reject a successful result whose output doesn't match the output schema the
+/// capability declares in the registry. The task stays assigned, so the agent
+/// can report again (or report a failure) instead of handing clients bad data.
+fn validate_reported_output(
+    state: &AppState,
+    task_id: &TaskId,
+    report: &TaskResultReport,
+) -> Result<(), AppError> {
+    if !matches!(report.status, TaskResultStatus::Success(_)) {
+        return Ok(());
+    }
+    let name = base_capability(&task_id.cap);
+    let Some(schema) = state
+        .storage
+        .capabilities
+        .get(name)?
+        .and_then(|definition| definition.output_schema)
+    else {
+        return Ok(());
+    };
+    let output = report.output.clone().unwrap_or_default();
+    validate_json_schema(&output, &schema).map_err(|e| {
+        AppError::Validation(format!(
+            "Output of task {} does not match the output schema of {}: {}",
+            task_id, name, e
+        ))
+    })
+}
+
+/// How a resolve call was handled.
+pub enum ResolveOutcome {
+    /// The report was applied to the task.
+    Confirmed,
+    /// The task is no longer assigned anywhere; per `UNKNOWN_TASK_REPORT_POLICY`
+    /// the report was dropped or kept as a late report (`stored`).
+    UnknownTask { stored: bool },
+}
+
 pub async fn resolve_task(
     agent: Agent,
     task_id: TaskId,
     report: TaskResultReport,
     state: &Arc<AppState>,
     comm_method: CommunicationMethod,
-) -> Result<(), AppError> {
+) -> Result<ResolveOutcome, AppError> {
     let agent = state
         .storage
         .agents
@@ -353,6 +737,10 @@ pub async fn resolve_task(
         .await?;
     info!("Agent {} reporting task {task_id}", agent.uid_short);
     debug!("Report: {:?}", &report);
+    if let Err(e) = validate_reported_output(state, &task_id, &report) {
+        report_fault(state, &agent.uid, AgentFault::MalformedReport).await;
+        return Err(e);
+    }
 
     let file_buckets: Vec<String> = state
         .urgent
@@ -374,23 +762,35 @@ pub async fn resolve_task(
     let mut cancel_err: Option<AppError> = None;
     let mut report_err: Option<AppError> = None;
     let mut queue = TaskQueueKind::Urgent;
-    match report_urgent_task(&state.urgent, report.clone(), task_id.clone()).await {
+    let mut requeued = false;
+    let mut duplicate = false;
+    let mut recorded = false;
+    match report_urgent_task(&state.urgent, report.clone(), task_id.clone(), &agent.uid).await {
         Ok(true) => {}
         Ok(false) => {
             queue = TaskQueueKind::Regular;
-            if let Err(e) = report_non_urgent_task(
+            match report_non_urgent_task(
                 &state.storage.tasks,
+                &state.regular,
                 report.clone(),
                 &agent,
                 &state.storage.heuristics,
+                state.config.task_restart.max_restarts,
             )
             .await
             {
-                if matches!(e, AppError::ClientClosedRequest(_)) {
-                    cancel_err = Some(e);
-                } else {
-                    report_err = Some(e);
+                Ok(NonUrgentReportOutcome::Recorded) => {
+                    let succeeded = matches!(report.status, TaskResultStatus::Success(_));
+                    state.circuit.record(&task_id.cap, succeeded);
+                    recorded = true;
                 }
+                Ok(NonUrgentReportOutcome::Requeued) => {
+                    state.circuit.record(&task_id.cap, false);
+                    requeued = true;
+                }
+                Ok(NonUrgentReportOutcome::AlreadyReported) => duplicate = true,
+                Err(e) if matches!(e, AppError::ClientClosedRequest(_)) => cancel_err = Some(e),
+                Err(e) => report_err = Some(e),
             }
         }
         Err(e) if matches!(e, AppError::ClientClosedRequest(_)) => {
@@ -409,7 +809,34 @@ pub async fn resolve_task(
     if let Some(e) = report_err {
         // Slot is freed above; the next dispatch trigger / 30s backstop reuses it.
         crate::mq::dispatch::dispatch_to_agent(state, &agent.uid).await;
-        return Err(e);
+        if !matches!(e, AppError::NotFound(_)) {
+            return Err(e);
+        }
+        return match state.config.unknown_task_report {
+            UnknownTaskReportPolicy::Reject => Err(e),
+            UnknownTaskReportPolicy::Ignore => {
+                info!(
+                    "Ignoring report for unknown task {task_id} from {}",
+                    agent.uid_short
+                );
+                Ok(ResolveOutcome::UnknownTask { stored: false })
+            }
+            UnknownTaskReportPolicy::Store => {
+                let late = LateReport::new(&agent.uid, report);
+                state.storage.tasks.store_late_report(&late)?;
+                info!(
+                    "Stored late report for unknown task {task_id} from {}",
+                    agent.uid_short
+                );
+                Ok(ResolveOutcome::UnknownTask { stored: true })
+            }
+        };
+    }
+
+    if duplicate {
+        // A retried report: the first one already emitted events and cleaned up.
+        crate::mq::dispatch::dispatch_to_agent(state, &agent.uid).await;
+        return Ok(ResolveOutcome::Confirmed);
     }
 
     emit_task_lifecycle(
@@ -417,7 +844,7 @@ pub async fn resolve_task(
         TaskLifecycleEvent {
             task_id: task_id.clone(),
             queue,
-            action: "resolved".to_string(),
+            action: if requeued { "requeued" } else { "resolved" }.to_string(),
             agent_id: Some(agent.uid.clone()),
             status: None,
             result_status: Some(result_status),
@@ -425,6 +852,21 @@ pub async fn resolve_task(
         },
     );
 
+    if let TaskResultStatus::Failure(error, _) = &report.status {
+        if let Err(e) = state.storage.agents.record_failure(&agent.uid, error) {
+            warn!("Failed to record failure of agent {}: {}", agent.uid, e);
+        }
+        report_fault(state, &agent.uid, AgentFault::TaskFailed).await;
+    }
+
+    if requeued {
+        // The task goes back to the queue for another agent — its input buckets
+        // are still needed, so skip the rm_after_task cleanup below.
+        crate::mq::dispatch::dispatch_for_capability(state, &task_id.cap).await;
+        crate::mq::dispatch::dispatch_to_agent(state, &agent.uid).await;
+        return Ok(ResolveOutcome::Confirmed);
+    }
+
     for bucket_uid in &file_buckets {
         let bucket = match state.storage.buckets.get_bucket(bucket_uid) {
             Ok(Some(b)) => b,
@@ -451,13 +893,52 @@ pub async fn resolve_task(
         );
     }
 
+    if recorded {
+        // Release (or fail) queued tasks that depend on this one.
+        crate::mq::dependencies::reevaluate_dependents(state, &task_id).await;
+    }
+
     // The agent just freed a slot — push it the next eligible task (if connected).
     crate::mq::dispatch::dispatch_to_agent(state, &agent.uid).await;
 
     if let Some(e) = cancel_err {
         return Err(e);
     }
-    Ok(())
+    Ok(ResolveOutcome::Confirmed)
+}
+
+pub async fn touch_task(
+    agent: Agent,
+    task_id: TaskId,
+    state: &Arc<AppState>,
+    comm_method: CommunicationMethod,
+) -> Result<TaskTouchResponse, AppError> {
+    let agent = state
+        .storage
+        .agents
+        .update_agent_last_contact(agent, comm_method)
+        .await?;
+    debug!("Agent {} touching task {task_id}", agent.uid_short);
+
+    // Urgent tasks expire once silent for their TTL; a touch renews that.
+    if let Some(lease_expires_at) = state.urgent.touch_task(&task_id, &agent.uid).await? {
+        return Ok(TaskTouchResponse {
+            id: task_id,
+            lease_expires_at: Some(lease_expires_at),
+        });
+    }
+
+    let lease_expires_at = touch_non_urgent_task(
+        &state.storage.tasks,
+        &task_id,
+        &agent.uid,
+        state.config.task_restart.orphan_silence_secs,
+    )
+    .await?;
+    Ok(TaskTouchResponse {
+        id: task_id,
+        lease_expires_at: Some(lease_expires_at),
+    })
 }
 
 pub async fn update_task_progress(
@@ -478,6 +959,7 @@ pub async fn update_task_progress(
         update.log_update.as_ref().map(|s| s.len()).unwrap_or(0)
     );
     debug!("Update: {:?}", &update);
+    validate_progress(&update)?;
 
     let event_status = update.status.clone();
     let event_stage = update.stage.clone();
@@ -512,9 +994,197 @@ pub(crate) fn validate_api_key(keys: &[String], key: &str) -> Result<(), AppErro
     Ok(())
 }
 
+/// Check that every advertised capability (by base name) matches one of the
+/// patterns allowed for the agent's API key. `None` means the key is unrestricted.
+pub(crate) fn validate_agent_capabilities(
+    allowed: &Option<Vec<String>>,
+    capabilities: &[String],
+) -> Result<(), AppError> {
+    let Some(allowed) = allowed else {
+        return Ok(());
+    };
+    let denied: Vec<&str> = capabilities
+        .iter()
+        .map(|cap| base_capability(cap))
+        .filter(|cap| !ApiKeysStorage::has_capability(allowed, cap))
+        .collect();
+    if !denied.is_empty() {
+        warn!("Agent rejected: capabilities not allowed for API key: {denied:?}");
+        return Err(AppError::Authorization(format!(
+            "Capabilities not allowed for this API key: {}",
+            denied.join(", ")
+        )));
+    }
+    Ok(())
+}
+
 fn emit_task_lifecycle(state: &Arc<AppState>, event: TaskLifecycleEvent) {
     let _ = state
         .channels
         .stream_tx
         .send(StreamEvent::TaskLifecycle(event));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agents_may_only_deregister_themselves() {
+        let agent = Agent::for_test("agent-a", &[]);
+        assert!(ensure_self(&agent, None).is_ok());
+        assert!(ensure_self(&agent, Some("agent-a")).is_ok());
+        let err = ensure_self(&agent, Some("agent-b")).unwrap_err();
+        assert_eq!(err.status_code_number(), 403);
+    }
+
+    #[tokio::test]
+    async fn capabilities_outside_the_key_allow_list_are_refused() {
+        let state = AppState::for_test(|config| {
+            config.agent_api_keys = vec!["key-a".to_string(), "key-b".to_string()];
+            config.agent_key_capabilities =
+                std::collections::HashMap::from([("key-a".to_string(), vec!["llm.*".to_string()])]);
+        });
+        let system_info = Agent::for_test("template", &[]).system_info;
+        let register = |api_key: &str, caps: &[&str]| AgentRegistrationRequest {
+            capabilities: caps.iter().map(|cap| cap.to_string()).collect(),
+            tier: 0,
+            capacity: 1,
+            system_info: system_info.clone(),
+            api_key: api_key.to_string(),
+            app_version: None,
+            display_name: None,
+            agent_id: None,
+            urgent_reserved_slots: None,
+        };
+
+        let err = do_register_agent(register("key-a", &["llm.qwen", "debug.echo"]), &state)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code_number(), 403);
+        assert!(err.to_string().contains("debug.echo"));
+        let registered = do_register_agent(register("key-a", &["llm.qwen[vision]"]), &state)
+            .await
+            .unwrap();
+        // A key without an allow-list may advertise anything.
+        do_register_agent(register("key-b", &["debug.echo"]), &state)
+            .await
+            .unwrap();
+
+        // The restriction sticks to the agent for later info updates.
+        let agent = state
+            .storage
+            .agents
+            .get_agent(&registered.agent_id)
+            .unwrap();
+        let update = |caps: &[&str]| AgentUpdateRequest {
+            capabilities: caps.iter().map(|cap| cap.to_string()).collect(),
+            tier: 0,
+            capacity: 1,
+            system_info: system_info.clone(),
+            app_version: None,
+            display_name: None,
+            urgent_reserved_slots: None,
+        };
+        let err = do_update_agent_info(
+            agent.clone(),
+            update(&["debug.echo"]),
+            &state,
+            CommunicationMethod::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code_number(), 403);
+        do_update_agent_info(
+            agent,
+            update(&["llm.mistral"]),
+            &state,
+            CommunicationMethod::default(),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn refresh_token_is_exchanged_only_while_stored_for_its_agent() {
+        let state = AppState::for_test(|_| {});
+        let mut agent = Agent::for_test("agent-a", &["debug.echo"]);
+        state
+            .storage
+            .agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        let issue = |owner: &str| {
+            let (token, jti, _) = state.auth.create_refresh_token("agent-a").unwrap();
+            state
+                .storage
+                .agents
+                .store_refresh_token(&jti, owner)
+                .unwrap();
+            token
+        };
+        let exchange = |refresh_token: String| {
+            do_refresh_agent_token(AgentRefreshTokenRequest { refresh_token }, &state)
+        };
+
+        let refresh = issue("agent-a");
+        let session = exchange(refresh.clone()).await.unwrap();
+        assert!(session.refresh_token.is_none());
+        let claims = state.auth.decode_token(&session.token).unwrap();
+        assert_eq!(claims.sub, "agent-a");
+        assert_eq!(claims.caps, Some(vec!["debug.echo".to_string()]));
+
+        // Neither kind of token stands in for the other.
+        assert!(state.auth.decode_token(&refresh).is_err());
+        let err = exchange(session.token).await.err().unwrap();
+        assert_eq!(err.status_code_number(), 401);
+
+        // The stored owner must match the token's subject.
+        let err = exchange(issue("agent-b")).await.err().unwrap();
+        assert_eq!(err.status_code_number(), 401);
+
+        state.storage.agents.revoke_sessions("agent-a").unwrap();
+        let err = exchange(refresh).await.err().unwrap();
+        assert_eq!(err.status_code_number(), 401);
+    }
+
+    #[tokio::test]
+    async fn scoped_poll_matches_only_the_token_capabilities() {
+        let state = AppState::for_test(|_| {});
+        let mut agent = Agent::for_test("agent-a", &["debug.echo", "llm.qwen"]);
+        state
+            .storage
+            .agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        let task = UnassignedTask {
+            id: TaskId::new_with_cap("llm.qwen".to_string()),
+            data: crate::schema::TaskSubmissionRequest {
+                capability: "llm.qwen".to_string(),
+                api_key: "client-a".to_string(),
+                urgent: true,
+                ..Default::default()
+            },
+            created_at: chrono::Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        state.urgent.add_task(task.clone(), 60, None).await.unwrap();
+        let poll = |poller| poll_urgent(poller, &state, CommunicationMethod::default());
+
+        // The token was issued before llm.qwen was added to the record.
+        let scoped = PollingAgent::Scoped {
+            uid: "agent-a".to_string(),
+            caps: vec!["debug.echo".to_string()],
+        };
+        assert!(poll(scoped).await.unwrap().is_none());
+        let scoped = PollingAgent::Scoped {
+            uid: "agent-a".to_string(),
+            caps: vec!["debug.echo".to_string(), "llm.qwen".to_string()],
+        };
+        assert_eq!(poll(scoped).await.unwrap().unwrap().id, task.id);
+    }
+}
@@ -5,32 +5,84 @@ use std::sync::Arc;
 
 use axum::{
     Json,
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
 };
+use serde::Deserialize;
 use serde_json::json;
 
 use crate::{
     error::AppError,
-    middleware::OptionalMgmtOverride,
-    mq::types::UrgentSubmitOutcome,
-    schema::{ApiKeyRequest, TaskId, TaskSubmissionRequest},
+    middleware::{ClientJson, OptionalMgmtOverride, header_api_key},
+    mq::types::{UrgentAssignOutcome, UrgentSubmitOutcome},
+    schema::{
+        ApiKeyRequest, BatchPollRequest, BatchSubmitRequest, ClientTaskIdRequest,
+        EscalateTaskRequest, TaskId, TaskListRequest, TaskStatus, TaskSubmissionRequest,
+    },
     state::AppState,
 };
 
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockingSubmitQuery {
+    /// Seconds to wait for the result before answering `504` with the
+    /// current status (default 60). The task is not cancelled.
+    #[serde(alias = "timeout_secs")]
+    pub timeout_secs: Option<u64>,
+}
+
 pub async fn submit_task_blocking(
     State(app_state): State<Arc<AppState>>,
     mgmt: OptionalMgmtOverride,
-    Json(req): Json<TaskSubmissionRequest>,
+    Query(query): Query<BlockingSubmitQuery>,
+    ClientJson(req): ClientJson<TaskSubmissionRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let outcome = service::do_submit_task_blocking(&app_state, req, mgmt.is_active()).await?;
+    let outcome =
+        service::do_submit_task_blocking(&app_state, req, mgmt.is_active(), query.timeout_secs)
+            .await?;
     Ok(urgent_outcome_to_response(outcome))
 }
 
+pub async fn submit_task_until_assigned(
+    State(app_state): State<Arc<AppState>>,
+    mgmt: OptionalMgmtOverride,
+    ClientJson(req): ClientJson<TaskSubmissionRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let outcome = service::do_submit_task_until_assigned(&app_state, req, mgmt.is_active()).await?;
+    let response = match outcome {
+        UrgentAssignOutcome::Assigned(task) => Json(json!({
+            "id": task.id,
+            "status": task.status,
+            "agentId": task.agent_id,
+            "assignedAt": task.assigned_at,
+            // Agents only take a task into a free slot, so it starts on pickup.
+            "estimatedStartAt": task.assigned_at,
+            "typicalRuntimeMs": task.typical_runtime_seconds.map(|d| d.as_millis() as u64),
+            "message": "Task picked up by an agent"
+        })),
+        UrgentAssignOutcome::Pending {
+            id,
+            estimated_start,
+        } => Json(json!({
+            "id": id,
+            "status": TaskStatus::Pending,
+            "estimatedStartAt": estimated_start,
+            "message": "No agent has picked up the task yet; poll for its status"
+        })),
+        UrgentAssignOutcome::Finished { id, status } => Json(json!({
+            "id": id,
+            "status": status,
+            "message": "Task finished but full info unavailable"
+        })),
+    };
+    Ok(response)
+}
+
 pub async fn submit_task(
     State(app_state): State<Arc<AppState>>,
     mgmt: OptionalMgmtOverride,
-    Json(req): Json<TaskSubmissionRequest>,
+    ClientJson(req): ClientJson<TaskSubmissionRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     match service::do_submit_task(&app_state, req, mgmt.is_active()).await? {
         service::SubmitOutcome::Urgent(outcome) => Ok(urgent_outcome_to_response(outcome)),
@@ -41,17 +93,131 @@ pub async fn submit_task(
             "message": "Added to tasks queue"
         }))
         .into_response()),
+        service::SubmitOutcome::Existing { id, capability } => Ok(Json(json!({
+            "id": id,
+            "capability": capability,
+            "status": "existing",
+            "message": "A task with this clientTaskId already exists"
+        }))
+        .into_response()),
     }
 }
 
+pub async fn submit_task_batch(
+    State(app_state): State<Arc<AppState>>,
+    mgmt: OptionalMgmtOverride,
+    ClientJson(req): ClientJson<BatchSubmitRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let ids = service::do_submit_task_batch(&app_state, &req.api_key, req.tasks, mgmt.is_active())
+        .await?;
+    Ok(Json(json!({
+        "ids": ids,
+        "status": "queued",
+        "count": ids.len(),
+    })))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PollQuery {
+    #[serde(default)]
+    pub include_timing: bool,
+    #[serde(default)]
+    pub include_position: bool,
+    #[serde(default)]
+    pub include_history: bool,
+}
+
 pub async fn poll_task_status(
     State(app_state): State<Arc<AppState>>,
     mgmt: OptionalMgmtOverride,
     Path((cap, id)): Path<(String, String)>,
-    Json(req): Json<ApiKeyRequest>,
+    Query(query): Query<PollQuery>,
+    ClientJson(req): ClientJson<ApiKeyRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let task_id = TaskId::from_url(id, cap)?;
+    let options = service::PollOptions {
+        include_timing: query.include_timing,
+        include_position: query.include_position,
+        include_history: query.include_history,
+    };
+    match service::do_poll_task_status(&app_state, task_id, &req.api_key, mgmt.is_active(), options)
+        .await?
+    {
+        service::PollOutcome::Found(report) => Ok(Json(report).into_response()),
+        service::PollOutcome::FoundUrgent(task) => Ok(Json(task).into_response()),
+    }
+}
+
+/// GET /api/task/result/{cap}/{id}
+///
+/// Authenticated with the API key header (`X-API-Key` or
+/// `Authorization: ApiKey <key>`); only the submitting key (or a
+/// management override) can read the result.
+pub async fn get_task_result(
+    State(app_state): State<Arc<AppState>>,
+    mgmt: OptionalMgmtOverride,
+    Path((cap, id)): Path<(String, String)>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
     let task_id = TaskId::from_url(id, cap)?;
-    match service::do_poll_task_status(&app_state, task_id, &req.api_key, mgmt.is_active()).await? {
+    let api_key = header_api_key(&headers);
+    let result = service::do_get_task_result(&app_state, &task_id, api_key, mgmt.is_active())?;
+    Ok(Json(result))
+}
+
+pub async fn poll_task_batch(
+    State(app_state): State<Arc<AppState>>,
+    mgmt: OptionalMgmtOverride,
+    Query(query): Query<PollQuery>,
+    ClientJson(req): ClientJson<BatchPollRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let options = service::PollOptions {
+        include_timing: query.include_timing,
+        include_position: query.include_position,
+        include_history: query.include_history,
+    };
+    let entries =
+        service::do_poll_task_batch(&app_state, req.ids, &req.api_key, mgmt.is_active(), options)
+            .await?;
+    Ok(Json(json!({ "tasks": entries })))
+}
+
+/// POST /api/tasks/list
+///
+/// The caller's own non-urgent tasks, queued, in flight or archived.
+pub async fn list_tasks(
+    State(app_state): State<Arc<AppState>>,
+    ClientJson(req): ClientJson<TaskListRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let (tasks, next_cursor) = service::do_list_tasks(&app_state, &req)?;
+    Ok(Json(json!({
+        "tasks": tasks,
+        "nextCursor": next_cursor,
+        "count": tasks.len(),
+    })))
+}
+
+pub async fn poll_task_by_client_id(
+    State(app_state): State<Arc<AppState>>,
+    mgmt: OptionalMgmtOverride,
+    Query(query): Query<PollQuery>,
+    ClientJson(req): ClientJson<ClientTaskIdRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let options = service::PollOptions {
+        include_timing: query.include_timing,
+        include_position: query.include_position,
+        include_history: query.include_history,
+    };
+    match service::do_poll_task_by_client_id(
+        &app_state,
+        &req.client_task_id,
+        &req.api_key,
+        mgmt.is_active(),
+        options,
+    )
+    .await?
+    {
         service::PollOutcome::Found(report) => Ok(Json(report).into_response()),
         service::PollOutcome::FoundUrgent(task) => Ok(Json(task).into_response()),
     }
@@ -61,17 +227,60 @@ pub async fn cancel_task(
     State(app_state): State<Arc<AppState>>,
     mgmt: OptionalMgmtOverride,
     Path((cap, id)): Path<(String, String)>,
-    Json(req): Json<ApiKeyRequest>,
+    ClientJson(req): ClientJson<ApiKeyRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     let task_id = TaskId::from_url(id, cap)?;
     let resp = service::do_cancel_task(&app_state, task_id, &req.api_key, mgmt.is_active()).await?;
     Ok(Json(resp))
 }
 
+pub async fn cancel_task_by_client_id(
+    State(app_state): State<Arc<AppState>>,
+    mgmt: OptionalMgmtOverride,
+    ClientJson(req): ClientJson<ClientTaskIdRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let resp = service::do_cancel_task_by_client_id(
+        &app_state,
+        &req.client_task_id,
+        &req.api_key,
+        mgmt.is_active(),
+    )
+    .await?;
+    Ok(Json(resp))
+}
+
+pub async fn escalate_task(
+    State(app_state): State<Arc<AppState>>,
+    mgmt: OptionalMgmtOverride,
+    Path((cap, id)): Path<(String, String)>,
+    ClientJson(req): ClientJson<EscalateTaskRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let task_id = TaskId::from_url(id, cap)?;
+    let resp = service::do_escalate_task(
+        &app_state,
+        task_id,
+        &req.api_key,
+        mgmt.is_active(),
+        req.ttl_secs,
+    )
+    .await?;
+    Ok(Json(resp))
+}
+
+pub async fn get_output_schema(
+    State(app_state): State<Arc<AppState>>,
+    mgmt: OptionalMgmtOverride,
+    Path(cap): Path<String>,
+    ClientJson(req): ClientJson<ApiKeyRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let schema = service::do_get_output_schema(&app_state, &cap, &req.api_key, mgmt.is_active())?;
+    Ok(Json(schema))
+}
+
 pub async fn capabilities_online(
     State(app_state): State<Arc<AppState>>,
     mgmt: OptionalMgmtOverride,
-    Json(req): Json<ApiKeyRequest>,
+    ClientJson(req): ClientJson<ApiKeyRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     let caps = service::do_capabilities_online(&app_state, &req.api_key, mgmt.is_active(), true)?;
     Ok(Json(caps))
@@ -80,7 +289,7 @@ pub async fn capabilities_online(
 pub async fn capabilities_online_ext(
     State(app_state): State<Arc<AppState>>,
     mgmt: OptionalMgmtOverride,
-    Json(req): Json<ApiKeyRequest>,
+    ClientJson(req): ClientJson<ApiKeyRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     let caps = service::do_capabilities_online(&app_state, &req.api_key, mgmt.is_active(), false)?;
     Ok(Json(caps))
@@ -95,5 +304,8 @@ fn urgent_outcome_to_response(outcome: UrgentSubmitOutcome) -> axum::response::R
             status,
             message,
         } => Json(json!({"id": id, "status": status, "message": message})).into_response(),
+        UrgentSubmitOutcome::TimedOut(report) => {
+            (StatusCode::GATEWAY_TIMEOUT, Json(report)).into_response()
+        }
     }
 }
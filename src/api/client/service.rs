@@ -6,13 +6,25 @@ use log::info;
 use serde::Serialize;
 
 use crate::{
-    db::apikeys::ApiKeysStorage,
-    error::AppError,
-    models::{AssignedTask, UnassignedTask},
-    mq::{scheduler::submit_urgent_task, types::UrgentSubmitOutcome},
-    schema::{TaskId, TaskStatus, TaskStatusResponse, TaskSubmissionRequest},
+    db::{agent::AgentStorage, apikeys::ApiKeysStorage, persistent_task_storage::TaskListFilter},
+    error::{AppError, FieldError},
+    middleware::auth_metrics::AuthSource,
+    models::{AssignedTask, TaskEvent, UnassignedTask},
+    mq::{
+        circuit::CircuitState,
+        dependencies::{DependencyState, dependency_state},
+        scheduler::{
+            capability_can_be_satisfied, escalate_to_urgent, submit_urgent_task,
+            submit_urgent_task_until_assigned,
+        },
+        types::{UrgentAssignOutcome, UrgentSubmitOutcome},
+    },
+    schema::{
+        BatchPollEntry, BatchPollResult, TaskId, TaskListRequest, TaskStatus, TaskStatusResponse,
+        TaskSubmissionRequest, TaskTiming,
+    },
     state::{AppState, StreamEvent, TaskLifecycleEvent, TaskQueueKind},
-    utils::base_capability,
+    utils::{base_capability, url_encode},
 };
 
 // ---------------------------------------------------------------------------
@@ -21,12 +33,83 @@ use crate::{
 
 pub enum SubmitOutcome {
     Urgent(UrgentSubmitOutcome),
-    Queued { id: TaskId, capability: String },
+    Queued {
+        id: TaskId,
+        capability: String,
+    },
+    /// A task with the same client id was already submitted under this API key.
+    Existing {
+        id: TaskId,
+        capability: String,
+    },
 }
 
 pub enum PollOutcome {
     Found(TaskStatusResponse),
-    FoundUrgent(AssignedTask),
+    FoundUrgent(UrgentPollResponse),
+}
+
+/// An urgent task as answered by a poll: the full task, plus its timing when
+/// requested.
+#[derive(Debug, Serialize)]
+pub struct UrgentPollResponse {
+    #[serde(flatten)]
+    pub task: AssignedTask,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing: Option<TaskTiming>,
+}
+
+/// Where a poll found a task.
+enum PolledTask {
+    Queued(UnassignedTask),
+    /// Assigned or archived.
+    Tracked(AssignedTask),
+    Urgent(AssignedTask),
+}
+
+impl PolledTask {
+    fn api_key(&self) -> &str {
+        match self {
+            PolledTask::Queued(task) => &task.data.api_key,
+            PolledTask::Tracked(task) | PolledTask::Urgent(task) => &task.data.api_key,
+        }
+    }
+
+    fn timing(&self) -> TaskTiming {
+        match self {
+            PolledTask::Queued(task) => task.timing(),
+            PolledTask::Tracked(task) | PolledTask::Urgent(task) => task.timing(),
+        }
+    }
+
+    fn history(&self) -> &[TaskEvent] {
+        match self {
+            PolledTask::Queued(task) => &task.history,
+            PolledTask::Tracked(task) | PolledTask::Urgent(task) => &task.history,
+        }
+    }
+
+    fn into_status_report(self) -> TaskStatusResponse {
+        match self {
+            PolledTask::Queued(task) => task.into_status_report(),
+            PolledTask::Tracked(task) | PolledTask::Urgent(task) => task.into_status_report(),
+        }
+    }
+}
+
+/// Optional extras a client can request when polling a task.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PollOptions {
+    pub include_timing: bool,
+    pub include_position: bool,
+    pub include_history: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputSchemaOutcome {
+    pub capability: String,
+    pub output_schema: serde_json::Value,
 }
 
 #[derive(Debug, Serialize)]
@@ -36,6 +119,20 @@ pub struct CancelOutcome {
     pub message: String,
 }
 
+/// Where an escalated task stands once it is in the urgent queue.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(
+    tag = "status",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum EscalateOutcome {
+    /// Waiting up to `ttl_secs` for an agent.
+    Pending { id: TaskId, ttl_secs: u64 },
+    /// Pushed to a connected agent straight away.
+    Assigned { id: TaskId, agent_id: String },
+}
+
 // ---------------------------------------------------------------------------
 // Helpers (moved from mod.rs)
 // ---------------------------------------------------------------------------
@@ -98,26 +195,245 @@ pub fn validate_file_buckets(
     Ok(())
 }
 
+/// Apply the capability registry to a submission: report inactive capabilities
+/// (and, in strict mode, unregistered ones), and fill in registry defaults for
+/// unset timeouts.
+fn apply_capability_policy(
+    state: &AppState,
+    req: &mut TaskSubmissionRequest,
+    errors: &mut Vec<FieldError>,
+) -> Result<(), AppError> {
+    let name = base_capability(&req.capability);
+    let Some(definition) = state.storage.capabilities.get(name)? else {
+        if state.config.capability_registry_strict {
+            errors.push(FieldError::new(
+                "capability",
+                "not_registered",
+                format!("Capability {} is not registered", name),
+            ));
+        }
+        return Ok(());
+    };
+    if !definition.active {
+        errors.push(FieldError::new(
+            "capability",
+            "inactive",
+            format!("Capability {} is not active", name),
+        ));
+        return Ok(());
+    }
+    req.timeout_secs = req.timeout_secs.or(definition.default_timeout_secs);
+    req.max_wait_secs = req.max_wait_secs.or(definition.default_max_wait_secs);
+    Ok(())
+}
+
+/// Reject a task no registered agent could ever run, so it can't sit in the
+/// queue forever. Offline agents count: they may come back.
+fn check_servable(
+    req: &TaskSubmissionRequest,
+    agents: &AgentStorage,
+    errors: &mut Vec<FieldError>,
+) {
+    if agents.agents_serving_task(req).is_empty() {
+        errors.push(FieldError::new(
+            "capability",
+            "unservable",
+            format!("No registered agent serves capability {}", req.capability),
+        ));
+    }
+}
+
+/// Longest client task id accepted on submission.
+const MAX_CLIENT_TASK_ID_LEN: usize = 128;
+
+/// Checks that need nothing but the request itself.
+fn check_submission_fields(req: &TaskSubmissionRequest, errors: &mut Vec<FieldError>) {
+    if req.capability.trim().is_empty() {
+        errors.push(FieldError::new(
+            "capability",
+            "required",
+            "capability must not be empty",
+        ));
+    }
+    if req
+        .capabilities
+        .iter()
+        .flatten()
+        .any(|cap| cap.trim().is_empty())
+    {
+        errors.push(FieldError::new(
+            "capabilities",
+            "required",
+            "capabilities must not contain empty names",
+        ));
+    }
+    if let Some(url) = &req.callback_url
+        && !(url.starts_with("http://") || url.starts_with("https://"))
+    {
+        errors.push(FieldError::new(
+            "callbackUrl",
+            "invalid",
+            "callbackUrl must be an http:// or https:// URL",
+        ));
+    }
+    // Client task ids are indexed per API key in persistent storage, so they
+    // are only meaningful for non-urgent tasks.
+    if let Some(client_task_id) = &req.client_task_id {
+        if req.urgent {
+            errors.push(FieldError::new(
+                "clientTaskId",
+                "unsupported",
+                "clientTaskId is only supported for non-urgent tasks",
+            ));
+        } else if client_task_id.is_empty() || client_task_id.len() > MAX_CLIENT_TASK_ID_LEN {
+            errors.push(FieldError::new(
+                "clientTaskId",
+                "length",
+                format!(
+                    "clientTaskId must be 1-{} bytes long",
+                    MAX_CLIENT_TASK_ID_LEN
+                ),
+            ));
+        }
+    }
+    if req.retry.is_some() && !req.restartable {
+        errors.push(FieldError::new(
+            "retry",
+            "unsupported",
+            "retry requires restartable to be true",
+        ));
+    }
+    if req.urgent && req.not_before.is_some() {
+        errors.push(FieldError::new(
+            "notBefore",
+            "unsupported",
+            "notBefore is only supported for non-urgent tasks",
+        ));
+    }
+    if req.urgent && !req.depends_on.is_empty() {
+        errors.push(FieldError::new(
+            "dependsOn",
+            "unsupported",
+            "dependsOn is only supported for non-urgent tasks",
+        ));
+    }
+    for (field, value) in [
+        ("timeoutSecs", req.timeout_secs),
+        ("maxWaitSecs", req.max_wait_secs),
+        ("runtimeSecs", req.runtime_secs),
+        ("deadlineSecs", req.deadline_secs),
+    ] {
+        if value == Some(0) {
+            errors.push(FieldError::new(
+                field,
+                "out_of_range",
+                format!("{} must be at least 1", field),
+            ));
+        }
+    }
+}
+
+/// Validate a submission and apply registry defaults, reporting every
+/// violation at once rather than stopping at the first.
+pub fn validate_submission(
+    state: &AppState,
+    req: &mut TaskSubmissionRequest,
+) -> Result<(), AppError> {
+    let mut errors = Vec::new();
+    check_submission_fields(req, &mut errors);
+    // Only the scheme is checked above; the host must not be internal either.
+    if let Some(url) = &req.callback_url
+        && (url.starts_with("http://") || url.starts_with("https://"))
+        && let Err(message) = crate::mq::webhook::check_callback_url(url, &state.config.webhook)
+    {
+        errors.push(FieldError::new("callbackUrl", "invalid", message));
+    }
+    if !req.capability.trim().is_empty() {
+        apply_capability_policy(state, req, &mut errors)?;
+        if state.config.reject_unservable_capabilities {
+            check_servable(req, &state.storage.agents, &mut errors);
+        }
+    }
+    if let Some(requirements) = &req.requirements
+        && !capability_can_be_satisfied(&req.capability, requirements, &state.storage.agents)
+    {
+        errors.push(FieldError::new(
+            "requirements",
+            "unsatisfiable",
+            format!(
+                "no online agent for {} meets the hardware requirements",
+                req.capability
+            ),
+        ));
+    }
+    for dependency in &req.depends_on {
+        if let DependencyState::Failed { status: None, .. } = dependency_state(
+            &state.storage.tasks,
+            &req.api_key,
+            std::slice::from_ref(dependency),
+        )? {
+            errors.push(FieldError::new(
+                "dependsOn",
+                "not_found",
+                format!("dependency {} does not exist", dependency),
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::InvalidFields(errors))
+    }
+}
+
+/// Check that the API key may use `cap`, counting the outcome in the auth
+/// metrics.
+fn verify_client_key(state: &AppState, api_key: &str, cap: &str) -> Result<(), AppError> {
+    let result = state.storage.client_keys.verify_key(api_key, cap);
+    match &result {
+        Ok(()) => state.auth_metrics.success(AuthSource::ClientCapability),
+        Err(_) => state.auth_metrics.failure(
+            AuthSource::ClientCapability,
+            "capability_denied",
+            &format!("API key not allowed to use capability {}", cap),
+        ),
+    }
+    result
+}
+
+/// Client-facing location of a capability's output schema, if it declares one.
+fn output_schema_ref(state: &AppState, cap: &str) -> Result<Option<String>, AppError> {
+    let name = base_capability(cap);
+    Ok(state
+        .storage
+        .capabilities
+        .get(name)?
+        .and_then(|definition| definition.output_schema)
+        .map(|_| format!("/api/capabilities/output_schema/{}", url_encode(name))))
+}
+
 // ---------------------------------------------------------------------------
 // Service functions
 // ---------------------------------------------------------------------------
 
-pub async fn do_submit_task_blocking(
+/// Shared front half of the urgent-only endpoints: authorize, validate and
+/// reserve buckets, producing the task ready to hand to the urgent store.
+async fn prepare_urgent_task(
     state: &Arc<AppState>,
-    req: TaskSubmissionRequest,
+    mut req: TaskSubmissionRequest,
     skip_owner: bool,
-) -> Result<UrgentSubmitOutcome, AppError> {
+) -> Result<UnassignedTask, AppError> {
     if !skip_owner {
-        state
-            .storage
-            .client_keys
-            .verify_key(&req.api_key, &req.capability)?;
+        for cap in req.match_capabilities() {
+            verify_client_key(state, &req.api_key, cap)?;
+        }
     }
     if !req.urgent {
         return Err(AppError::BadRequest(
             "Only urgent tasks can be submitted to this endpoint".to_string(),
         ));
     }
+    validate_submission(state, &mut req)?;
     let file_bucket = req.file_bucket.clone();
     let id = TaskId::new_with_cap(req.capability.clone());
     // Hold the reservation lock across validate + record so a concurrent
@@ -131,24 +447,93 @@ pub async fn do_submit_task_blocking(
         id,
         data: req,
         created_at: Utc::now(),
+        failed_agents: Vec::new(),
+        next_eligible_at: None,
+        history: Vec::new(),
     };
     info!("New urgent task: {:?}", task);
-    let outcome = submit_urgent_task(state, task).await?;
+    Ok(task)
+}
+
+/// Capabilities flagged `alwaysBlocking` in the registry accept non-urgent
+/// submissions on the blocking endpoint: the task is promoted to urgent and
+/// lives in the urgent store only for the duration of the wait, so clients can
+/// call `submit_blocking` without knowing the capability's urgency setup.
+fn promote_always_blocking(
+    state: &AppState,
+    req: &mut TaskSubmissionRequest,
+) -> Result<(), AppError> {
+    if req.urgent {
+        return Ok(());
+    }
+    let name = base_capability(&req.capability);
+    if state
+        .storage
+        .capabilities
+        .get(name)?
+        .is_some_and(|definition| definition.always_blocking)
+    {
+        info!("Promoting blocking submission for {} to urgent", name);
+        req.urgent = true;
+    }
+    Ok(())
+}
+
+/// New work is refused once a shutdown signal started the drain; tasks
+/// already submitted are left to finish.
+fn ensure_accepting_submissions(state: &AppState) -> Result<(), AppError> {
+    if state.readiness.is_draining() {
+        return Err(AppError::SchedulingImpossible(
+            "Server is shutting down and not accepting new tasks".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// How long a blocking submission waits for the result unless the client
+/// asks for another `timeoutSecs`.
+pub const DEFAULT_BLOCKING_WAIT_SECS: u64 = 60;
+
+pub async fn do_submit_task_blocking(
+    state: &Arc<AppState>,
+    mut req: TaskSubmissionRequest,
+    skip_owner: bool,
+    wait_secs: Option<u64>,
+) -> Result<UrgentSubmitOutcome, AppError> {
+    ensure_accepting_submissions(state)?;
+    promote_always_blocking(state, &mut req)?;
+    let task = prepare_urgent_task(state, req, skip_owner).await?;
+    let wait = std::time::Duration::from_secs(wait_secs.unwrap_or(DEFAULT_BLOCKING_WAIT_SECS));
+    let outcome = submit_urgent_task(state, task, wait).await?;
     emit_urgent_expired_if_needed(state, &outcome);
     Ok(outcome)
 }
 
-pub async fn do_submit_task(
+/// Submit an urgent task and return once an agent has picked it up, or with a
+/// "still pending" answer after `URGENT_ASSIGN_WAIT_SECS`.
+pub async fn do_submit_task_until_assigned(
     state: &Arc<AppState>,
     req: TaskSubmissionRequest,
     skip_owner: bool,
+) -> Result<UrgentAssignOutcome, AppError> {
+    ensure_accepting_submissions(state)?;
+    let task = prepare_urgent_task(state, req, skip_owner).await?;
+    let wait = std::time::Duration::from_secs(state.config.urgent.assign_wait_secs);
+    submit_urgent_task_until_assigned(state, task, wait).await
+}
+
+pub async fn do_submit_task(
+    state: &Arc<AppState>,
+    mut req: TaskSubmissionRequest,
+    skip_owner: bool,
 ) -> Result<SubmitOutcome, AppError> {
+    ensure_accepting_submissions(state)?;
     if !skip_owner {
-        state
-            .storage
-            .client_keys
-            .verify_key(&req.api_key, &req.capability)?;
+        for cap in req.match_capabilities() {
+            verify_client_key(state, &req.api_key, cap)?;
+        }
     }
+    validate_submission(state, &mut req)?;
     let urgent = req.urgent;
     let file_bucket = req.file_bucket.clone();
     let id = TaskId::new_with_cap(req.capability.clone());
@@ -157,81 +542,417 @@ pub async fn do_submit_task(
     {
         let _reservation = state.bucket_submit_lock.lock().await;
         validate_file_buckets(state, &req, skip_owner)?;
+        // Claim the client id before recording bucket usage so a duplicate
+        // submission leaves no trace.
+        if let Some(client_task_id) = &req.client_task_id
+            && let Some(existing) = state.storage.tasks.claim_client_task_id(
+                &req.api_key,
+                client_task_id,
+                &id,
+                state
+                    .config
+                    .client_task_id_ttl_secs
+                    .map(|ttl| Utc::now() - chrono::TimeDelta::seconds(ttl as i64)),
+            )?
+        {
+            info!(
+                "Task with client id '{}' already exists: {}",
+                client_task_id, existing
+            );
+            let capability = existing.cap.clone();
+            return Ok(SubmitOutcome::Existing {
+                id: existing,
+                capability,
+            });
+        }
         record_task_in_buckets(state, &id.to_string(), &file_bucket).await;
     }
     let task = UnassignedTask {
         id,
         data: req,
         created_at: Utc::now(),
+        failed_agents: Vec::new(),
+        next_eligible_at: None,
+        history: Vec::new(),
     };
     info!("New unassigned task: {:?}", task);
     if urgent {
-        let outcome = submit_urgent_task(state, task).await?;
+        let wait = std::time::Duration::from_secs(DEFAULT_BLOCKING_WAIT_SECS);
+        let outcome = submit_urgent_task(state, task, wait).await?;
         emit_urgent_expired_if_needed(state, &outcome);
         Ok(SubmitOutcome::Urgent(outcome))
     } else {
         let id = task.id.clone();
         let capability = task.id.cap.clone();
-        state.storage.tasks.add_unassigned(&task)?;
+        let dispatch_caps: Vec<String> =
+            task.data.match_capabilities().map(str::to_string).collect();
+        if let Err(e) = state.storage.tasks.add_unassigned(&task) {
+            if let Some(client_task_id) = &task.data.client_task_id {
+                state.storage.tasks.release_client_task_id(
+                    &task.data.api_key,
+                    client_task_id,
+                    &id,
+                )?;
+            }
+            return Err(e.into());
+        }
+        let has_dependencies = !task.data.depends_on.is_empty();
         state.regular.add_task(task).await;
+        if has_dependencies {
+            // Dependencies may already be finished (or failed).
+            crate::mq::dependencies::reevaluate_tasks(state, std::slice::from_ref(&id)).await;
+        }
         // Push the freshly-queued task to a connected agent immediately instead of
         // waiting for it to poll. No-op if no eligible agent is connected — the
         // task stays queued for HTTP pollers / a later connect.
-        crate::mq::dispatch::dispatch_for_capability(state, &capability).await;
+        for cap in &dispatch_caps {
+            crate::mq::dispatch::dispatch_for_capability(state, cap).await;
+        }
         Ok(SubmitOutcome::Queued { id, capability })
     }
 }
 
+/// Prefix field errors with the position of their task in a batch, e.g.
+/// `capability` of the fourth task becomes `tasks[3].capability`.
+fn batch_field_errors(index: usize, errors: Vec<FieldError>) -> impl Iterator<Item = FieldError> {
+    errors.into_iter().map(move |e| FieldError {
+        field: format!("tasks[{}].{}", index, e.field),
+        ..e
+    })
+}
+
+/// Parse and check one entry of a batch submission under the batch key.
+fn check_batch_entry(
+    state: &AppState,
+    api_key: &str,
+    index: usize,
+    mut entry: serde_json::Value,
+    errors: &mut Vec<FieldError>,
+) -> Result<Option<TaskSubmissionRequest>, AppError> {
+    let Some(fields) = entry.as_object_mut() else {
+        errors.push(FieldError::new(
+            &format!("tasks[{}]", index),
+            "invalid",
+            "task must be a JSON object",
+        ));
+        return Ok(None);
+    };
+    fields.insert("apiKey".to_string(), serde_json::json!(api_key));
+    let mut req: TaskSubmissionRequest = match serde_json::from_value(entry) {
+        Ok(req) => req,
+        Err(e) => {
+            errors.push(FieldError::new(
+                &format!("tasks[{}]", index),
+                "invalid",
+                e.to_string(),
+            ));
+            return Ok(None);
+        }
+    };
+    let mut entry_errors = Vec::new();
+    // Batches go straight to the persistent queue, and a client id claim
+    // could not be rolled back together with the batch write.
+    if req.urgent {
+        entry_errors.push(FieldError::new(
+            "urgent",
+            "unsupported",
+            "urgent tasks cannot be submitted in a batch",
+        ));
+    }
+    if req.client_task_id.is_some() {
+        entry_errors.push(FieldError::new(
+            "clientTaskId",
+            "unsupported",
+            "clientTaskId is not supported in a batch",
+        ));
+    }
+    match validate_submission(state, &mut req) {
+        Ok(()) => {}
+        Err(AppError::InvalidFields(fields)) => entry_errors.extend(fields),
+        Err(e) => return Err(e),
+    }
+    let valid = entry_errors.is_empty();
+    errors.extend(batch_field_errors(index, entry_errors));
+    Ok(valid.then_some(req))
+}
+
+/// Queue up to `SUBMIT_BATCH_MAX_TASKS` non-urgent tasks in one call. Every
+/// task is validated first and any violation rejects the whole batch; the
+/// tasks are then stored in a single atomic write. Returns the ids in
+/// submission order.
+pub async fn do_submit_task_batch(
+    state: &Arc<AppState>,
+    api_key: &str,
+    entries: Vec<serde_json::Value>,
+    skip_owner: bool,
+) -> Result<Vec<TaskId>, AppError> {
+    ensure_accepting_submissions(state)?;
+    let max = state.config.submit_batch_max_tasks;
+    if entries.is_empty() || entries.len() > max {
+        return Err(AppError::Validation(format!(
+            "A batch must contain 1-{} tasks, got {}",
+            max,
+            entries.len()
+        )));
+    }
+    let mut errors = Vec::new();
+    let mut requests = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.into_iter().enumerate() {
+        if let Some(req) = check_batch_entry(state, api_key, index, entry, &mut errors)? {
+            requests.push(req);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::InvalidFields(errors));
+    }
+    if !skip_owner {
+        let capabilities: HashSet<&str> = requests
+            .iter()
+            .flat_map(|r| r.match_capabilities())
+            .collect();
+        for capability in capabilities {
+            verify_client_key(state, api_key, capability)?;
+        }
+    }
+
+    let mut tasks = Vec::with_capacity(requests.len());
+    {
+        let _reservation = state.bucket_submit_lock.lock().await;
+        for req in &requests {
+            validate_file_buckets(state, req, skip_owner)?;
+        }
+        for req in requests {
+            let id = TaskId::new_with_cap(req.capability.clone());
+            record_task_in_buckets(state, &id.to_string(), &req.file_bucket).await;
+            tasks.push(UnassignedTask {
+                id,
+                data: req,
+                created_at: Utc::now(),
+                failed_agents: Vec::new(),
+                next_eligible_at: None,
+                history: Vec::new(),
+            });
+        }
+    }
+    state.storage.tasks.add_unassigned_batch(&tasks)?;
+    info!("New batch of {} unassigned tasks", tasks.len());
+
+    let ids: Vec<TaskId> = tasks.iter().map(|task| task.id.clone()).collect();
+    let capabilities: HashSet<String> = tasks
+        .iter()
+        .flat_map(|task| task.data.match_capabilities().map(str::to_string))
+        .collect();
+    let waiting: Vec<TaskId> = tasks
+        .iter()
+        .filter(|task| !task.data.depends_on.is_empty())
+        .map(|task| task.id.clone())
+        .collect();
+    for task in tasks {
+        state.regular.add_task(task).await;
+    }
+    crate::mq::dependencies::reevaluate_tasks(state, &waiting).await;
+    for capability in capabilities {
+        crate::mq::dispatch::dispatch_for_capability(state, &capability).await;
+    }
+    Ok(ids)
+}
+
 pub async fn do_poll_task_status(
     state: &Arc<AppState>,
     task_id: TaskId,
     api_key: &str,
     skip_owner: bool,
+    options: PollOptions,
 ) -> Result<PollOutcome, AppError> {
-    let task = state
-        .storage
-        .tasks
-        .get_assigned(&task_id)?
-        .map(|ass| {
-            if !skip_owner && ass.data.api_key != api_key {
-                None
-            } else {
-                Some(ass.into_status_report())
-            }
-        })
-        .flatten()
-        .or(state
-            .regular
-            .get_task(&task_id)
-            .await
-            .map(|unass| {
-                if !skip_owner && unass.data.api_key != api_key {
-                    None
-                } else {
-                    Some(unass.into_status_report())
-                }
-            })
-            .flatten())
-        .or(state
+    match find_polled_task(state, &task_id).await? {
+        Some(task) if skip_owner || task.api_key() == api_key => poll_outcome(state, task, options),
+        _ => Err(AppError::NotFound(task_id.to_string())),
+    }
+}
+
+/// Look a task up wherever it currently lives: in flight, queued, archived or
+/// in the urgent store.
+async fn find_polled_task(
+    state: &AppState,
+    task_id: &TaskId,
+) -> Result<Option<PolledTask>, AppError> {
+    if let Some(task) = state.storage.tasks.get_assigned(task_id)? {
+        return Ok(Some(PolledTask::Tracked(task)));
+    }
+    if let Some(task) = state.regular.get_task(task_id).await {
+        return Ok(Some(PolledTask::Queued(task)));
+    }
+    if let Some(task) = state.storage.tasks.get_unassigned(task_id)? {
+        return Ok(Some(PolledTask::Queued(task)));
+    }
+    if let Some(task) = state.storage.tasks.get_archived(task_id)? {
+        return Ok(Some(PolledTask::Tracked(task)));
+    }
+    Ok(state
+        .urgent
+        .get_assigned_task(task_id)
+        .await
+        .map(PolledTask::Urgent))
+}
+
+/// The poll answer for `task`, with the extras `options` asks for. Timing
+/// applies to every kind of task; urgent tasks always carry their history.
+fn poll_outcome(
+    state: &AppState,
+    task: PolledTask,
+    options: PollOptions,
+) -> Result<PollOutcome, AppError> {
+    let timing = options.include_timing.then(|| task.timing());
+    let task = match task {
+        PolledTask::Urgent(task) => {
+            return Ok(PollOutcome::FoundUrgent(UrgentPollResponse {
+                task,
+                timing,
+            }));
+        }
+        task => task,
+    };
+    let history = options.include_history.then(|| task.history().to_vec());
+    let mut response = TaskStatusResponse {
+        timing,
+        history,
+        ..task.into_status_report()
+    };
+    if response.status == TaskStatus::Completed {
+        response.output_schema_ref = output_schema_ref(state, &response.id.cap)?;
+    }
+    if response.status == TaskStatus::Queued {
+        response.circuit = Some(state.circuit.state(&response.id.cap))
+            .filter(|circuit| *circuit != CircuitState::Closed);
+        response.capability_inactive = state
             .storage
-            .tasks
-            .get_unassigned(&task_id)?
-            .map(|unass| {
-                if !skip_owner && unass.data.api_key != api_key {
-                    None
-                } else {
-                    Some(unass.into_status_report())
-                }
-            })
-            .flatten());
+            .capabilities
+            .get(base_capability(&response.id.cap))?
+            .is_some_and(|definition| !definition.active);
+        if options.include_position {
+            response.queue_position = state.storage.tasks.queue_position(&response.id)?;
+        }
+    }
+    Ok(PollOutcome::Found(response))
+}
 
-    if let Some(response) = task {
-        return Ok(PollOutcome::Found(response));
+/// Stored result of a finished non-urgent task, so pollers can check status
+/// cheaply and fetch the payload once. `404` until the task is terminal.
+pub fn do_get_task_result(
+    state: &AppState,
+    task_id: &TaskId,
+    api_key: Option<&str>,
+    skip_owner: bool,
+) -> Result<serde_json::Value, AppError> {
+    let task = state.storage.tasks.get_result(task_id)?;
+    finished_result(task, task_id, api_key, skip_owner)
+}
+
+fn finished_result(
+    task: Option<AssignedTask>,
+    task_id: &TaskId,
+    api_key: Option<&str>,
+    skip_owner: bool,
+) -> Result<serde_json::Value, AppError> {
+    let task = task
+        .filter(|task| skip_owner || api_key == Some(task.data.api_key.as_str()))
+        .ok_or_else(|| AppError::NotFound(format!("No finished task {}", task_id)))?;
+    if task.output_expired_at.is_some() {
+        return Err(AppError::NotFound(format!(
+            "Result of task {} has expired",
+            task_id
+        )));
+    }
+    Ok(task.result.unwrap_or_default())
+}
+
+/// Poll up to `POLL_BATCH_MAX_IDS` tasks in one call. Unlike the single-task
+/// poll, a task owned by another key is reported as `denied` rather than
+/// folded into `notFound`.
+pub async fn do_poll_task_batch(
+    state: &Arc<AppState>,
+    ids: Vec<TaskId>,
+    api_key: &str,
+    skip_owner: bool,
+    options: PollOptions,
+) -> Result<Vec<BatchPollEntry>, AppError> {
+    let max = state.config.poll_batch_max_ids;
+    if ids.len() > max {
+        return Err(AppError::Validation(format!(
+            "At most {} task ids can be polled per batch, got {}",
+            max,
+            ids.len()
+        )));
     }
-    if let Some(urgent) = state.urgent.get_assigned_task(&task_id).await {
-        return Ok(PollOutcome::FoundUrgent(urgent));
+    let mut entries = Vec::with_capacity(ids.len());
+    for id in ids {
+        let (result, task) = match find_polled_task(state, &id).await? {
+            None => (BatchPollResult::NotFound, None),
+            Some(task) if !skip_owner && task.api_key() != api_key => {
+                (BatchPollResult::Denied, None)
+            }
+            Some(task) => {
+                let report = match poll_outcome(state, task, options)? {
+                    PollOutcome::Found(report) => report,
+                    PollOutcome::FoundUrgent(urgent) => TaskStatusResponse {
+                        timing: urgent.timing,
+                        history: options.include_history.then(|| urgent.task.history.clone()),
+                        ..urgent.task.into_status_report()
+                    },
+                };
+                (BatchPollResult::Found, Some(report))
+            }
+        };
+        entries.push(BatchPollEntry { id, result, task });
     }
-    Err(AppError::NotFound(task_id.to_string()))
+    Ok(entries)
+}
+
+/// One page of the non-urgent tasks submitted with `req.api_key`.
+pub fn do_list_tasks(
+    state: &AppState,
+    req: &TaskListRequest,
+) -> Result<(Vec<TaskStatusResponse>, Option<String>), AppError> {
+    let filter = TaskListFilter {
+        capability: req.capability.clone(),
+        status: req.status.clone(),
+    };
+    let limit = req.limit.unwrap_or(100).clamp(1, 1000);
+    Ok(state
+        .storage
+        .tasks
+        .list_for_api_key(&req.api_key, &filter, limit, req.cursor.as_deref())?)
+}
+
+/// Poll a task by the client id it was submitted with. The lookup is scoped
+/// to `api_key`, so one client cannot resolve another client's ids.
+pub async fn do_poll_task_by_client_id(
+    state: &Arc<AppState>,
+    client_task_id: &str,
+    api_key: &str,
+    skip_owner: bool,
+    options: PollOptions,
+) -> Result<PollOutcome, AppError> {
+    let task_id = state
+        .storage
+        .tasks
+        .find_by_client_task_id(api_key, client_task_id)?
+        .ok_or_else(|| AppError::NotFound(format!("No task with client id {}", client_task_id)))?;
+    do_poll_task_status(state, task_id, api_key, skip_owner, options).await
+}
+
+pub async fn do_cancel_task_by_client_id(
+    state: &Arc<AppState>,
+    client_task_id: &str,
+    api_key: &str,
+    skip_owner: bool,
+) -> Result<CancelOutcome, AppError> {
+    let task_id = state
+        .storage
+        .tasks
+        .find_by_client_task_id(api_key, client_task_id)?
+        .ok_or_else(|| AppError::NotFound(format!("No task with client id {}", client_task_id)))?;
+    do_cancel_task(state, task_id, api_key, skip_owner).await
 }
 
 pub async fn do_cancel_task(
@@ -260,7 +981,7 @@ pub async fn do_cancel_task(
         Ok(TaskStatus::CancelRequested) => {
             info!("Urgent task {} cancel requested (was assigned)", task_id);
             if let Some(assigned) = state.urgent.get_assigned_task(&task_id).await {
-                push_cancel_to_agent(state, &assigned.agent_id, &task_id);
+                state.registry.push_cancel(&assigned.agent_id, &task_id);
             }
             emit_task_lifecycle(
                 state,
@@ -336,7 +1057,7 @@ async fn cancel_regular_task(
         }
         task.change_status(TaskStatus::CancelRequested);
         state.storage.tasks.update_assigned(&task)?;
-        push_cancel_to_agent(state, &task.agent_id, task_id);
+        state.registry.push_cancel(&task.agent_id, task_id);
         info!("Task {} cancel requested (was assigned)", task_id);
         return Ok(CancelOutcome {
             id: task_id.clone(),
@@ -386,27 +1107,117 @@ async fn cancel_regular_task(
     Err(AppError::NotFound(task_id.to_string()))
 }
 
+/// Pending TTL for an escalated task when the request doesn't set one; matches
+/// the default for urgent submissions.
+const DEFAULT_ESCALATION_TTL_SECS: u64 = 60;
+
+/// Move a still-queued non-urgent task to the urgent queue, keeping its id.
+pub async fn do_escalate_task(
+    state: &Arc<AppState>,
+    task_id: TaskId,
+    api_key: &str,
+    skip_owner: bool,
+    ttl_secs: Option<u64>,
+) -> Result<EscalateOutcome, AppError> {
+    if let Some(task) = state.storage.tasks.get_assigned(&task_id)? {
+        if !skip_owner && task.data.api_key != api_key {
+            return Err(AppError::NotFound(task_id.to_string()));
+        }
+        return Err(AppError::Conflict(format!(
+            "Task {} is no longer queued (status {:?})",
+            task_id, task.status
+        )));
+    }
+    let Some(snapshot) = state.regular.get_task(&task_id).await else {
+        return Err(AppError::NotFound(task_id.to_string()));
+    };
+    if !skip_owner && snapshot.data.api_key != api_key {
+        return Err(AppError::NotFound(task_id.to_string()));
+    }
+    if !state.storage.tasks.remove_unassigned(&task_id)? {
+        return Err(AppError::Conflict(format!(
+            "Task {} not found in persistent queue",
+            task_id
+        )));
+    }
+    let Some(task) = state.regular.remove_task(&task_id).await else {
+        // Dropped from the queue between the persistent remove and now (a
+        // reset, say). Whoever dropped it owns it: restoring the record here
+        // would leave a task queued that lives elsewhere.
+        return Err(AppError::Conflict(format!(
+            "Task {} is no longer queued",
+            task_id
+        )));
+    };
+
+    let ttl_secs = ttl_secs.unwrap_or(DEFAULT_ESCALATION_TTL_SECS);
+    if let Err(e) = escalate_to_urgent(state, task, ttl_secs).await {
+        // Nothing can run it urgently; leave it where it was.
+        state.storage.tasks.add_unassigned(&snapshot)?;
+        state.regular.add_task(snapshot).await;
+        return Err(e);
+    }
+    info!("Task {} escalated to urgent (ttl {}s)", task_id, ttl_secs);
+    emit_task_lifecycle(
+        state,
+        TaskLifecycleEvent {
+            task_id: task_id.clone(),
+            queue: TaskQueueKind::Urgent,
+            action: "escalate".to_string(),
+            agent_id: None,
+            status: Some(TaskStatus::Pending),
+            result_status: None,
+            stage: None,
+        },
+    );
+    // Dispatch may already have pushed it to a connected agent.
+    Ok(match state.urgent.get_assigned_task(&task_id).await {
+        Some(assigned) => EscalateOutcome::Assigned {
+            id: task_id,
+            agent_id: assigned.agent_id,
+        },
+        None => EscalateOutcome::Pending {
+            id: task_id,
+            ttl_secs,
+        },
+    })
+}
+
+pub fn do_get_output_schema(
+    state: &Arc<AppState>,
+    cap: &str,
+    api_key: &str,
+    skip_owner: bool,
+) -> Result<OutputSchemaOutcome, AppError> {
+    let name = base_capability(cap);
+    if !skip_owner {
+        verify_client_key(state, api_key, name)?;
+    }
+    let output_schema = state
+        .storage
+        .capabilities
+        .get(name)?
+        .and_then(|definition| definition.output_schema)
+        .ok_or_else(|| {
+            AppError::NotFound(format!("Capability {} declares no output schema", name))
+        })?;
+    Ok(OutputSchemaOutcome {
+        capability: name.to_string(),
+        output_schema,
+    })
+}
+
 pub fn do_capabilities_online(
     state: &Arc<AppState>,
     api_key: &str,
     skip_owner: bool,
     strip_extended: bool,
 ) -> Result<HashSet<String>, AppError> {
-    let mut capabilities = HashSet::new();
-    state
-        .storage
-        .agents
-        .list_all_agents()
-        .into_iter()
-        .filter(|a| a.is_online())
-        .flat_map(|agent| agent.capabilities)
-        .for_each(|cap| {
-            if strip_extended {
-                capabilities.insert(base_capability(&cap).to_string());
-            } else {
-                capabilities.insert(cap);
-            }
-        });
+    let mut capabilities = if strip_extended {
+        state.online_caps.base(&state.storage.agents)
+    } else {
+        (*state.online_caps.extended(&state.storage.agents)).clone()
+    };
     if !skip_owner {
         let key = state
             .storage
@@ -423,13 +1234,6 @@ pub fn do_capabilities_online(
 /// so it can stop work immediately instead of learning on its next progress or
 /// resolve call. No-op for HTTP-only agents (not connected over WS) — they keep
 /// receiving the cancel signal via the existing 499-on-next-call path.
-fn push_cancel_to_agent(state: &Arc<AppState>, agent_id: &str, task_id: &TaskId) {
-    if let Some(tx) = state.registry.sender(agent_id) {
-        let msg = serde_json::json!({ "type": "cancel", "taskId": task_id }).to_string();
-        let _ = tx.try_send(crate::mq::registry::WsOut::Text(msg));
-    }
-}
-
 fn emit_task_lifecycle(state: &Arc<AppState>, event: TaskLifecycleEvent) {
     let _ = state
         .channels
@@ -463,3 +1267,450 @@ fn emit_urgent_expired_if_needed(state: &Arc<AppState>, outcome: &UrgentSubmitOu
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(errors: &[FieldError]) -> Vec<(&str, &str)> {
+        errors
+            .iter()
+            .map(|e| (e.field.as_str(), e.code.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn finished_result_is_served_to_its_owner_only() {
+        let tasks = crate::db::persistent_task_storage::TaskStorage::from_db(
+            sled::Config::new().temporary(true).open().unwrap(),
+        )
+        .unwrap();
+        let task = UnassignedTask {
+            id: TaskId::new_with_cap("debug.echo".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "debug.echo".to_string(),
+                api_key: "client-a".to_string(),
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        tasks.add_unassigned(&task).unwrap();
+        let mut assigned = tasks.assign_task(&task.id, "agent-a").unwrap();
+        assigned.change_status(TaskStatus::Running);
+        tasks.update_assigned(&assigned).unwrap();
+        // Not finished yet.
+        assert!(tasks.get_result(&task.id).unwrap().is_none());
+
+        let output = serde_json::json!({ "text": "x".repeat(1 << 20) });
+        assigned.result = Some(output.clone());
+        assigned.change_status(TaskStatus::Completed);
+        tasks.update_assigned(&assigned).unwrap();
+
+        let stored = tasks.get_result(&task.id).unwrap();
+        let result = finished_result(stored.clone(), &task.id, Some("client-a"), false);
+        assert_eq!(result.unwrap(), output);
+        let denied = finished_result(stored.clone(), &task.id, Some("client-b"), false);
+        assert_eq!(denied.unwrap_err().status_code_number(), 404);
+        assert!(finished_result(stored, &task.id, None, true).is_ok());
+    }
+
+    #[test]
+    fn all_field_violations_are_reported_together() {
+        let req = TaskSubmissionRequest {
+            capability: " ".to_string(),
+            urgent: true,
+            client_task_id: Some("job-1".to_string()),
+            timeout_secs: Some(0),
+            max_wait_secs: Some(0),
+            ..Default::default()
+        };
+        let mut errors = Vec::new();
+        check_submission_fields(&req, &mut errors);
+        assert_eq!(
+            codes(&errors),
+            vec![
+                ("capability", "required"),
+                ("clientTaskId", "unsupported"),
+                ("timeoutSecs", "out_of_range"),
+                ("maxWaitSecs", "out_of_range"),
+            ]
+        );
+
+        let req = TaskSubmissionRequest {
+            capability: "debug.echo".to_string(),
+            client_task_id: Some("x".repeat(MAX_CLIENT_TASK_ID_LEN + 1)),
+            runtime_secs: Some(0),
+            timeout_secs: Some(30),
+            ..Default::default()
+        };
+        let mut errors = Vec::new();
+        check_submission_fields(&req, &mut errors);
+        assert_eq!(
+            codes(&errors),
+            vec![("clientTaskId", "length"), ("runtimeSecs", "out_of_range")]
+        );
+    }
+
+    #[tokio::test]
+    async fn unservable_capability_is_rejected_only_when_no_agent_registered_it() {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let submission = |cap: &str| TaskSubmissionRequest {
+            capability: cap.to_string(),
+            ..Default::default()
+        };
+        let mut errors = Vec::new();
+        check_servable(&submission("debug.echo"), &agents, &mut errors);
+        assert_eq!(codes(&errors), vec![("capability", "unservable")]);
+
+        // An offline agent still counts; extended attributes are ignored.
+        let mut agent = crate::models::Agent::for_test("agent-a", &["debug.echo"]);
+        agent.last_contact = Some(Utc::now() - chrono::TimeDelta::days(1));
+        agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        let mut errors = Vec::new();
+        check_servable(&submission("debug.echo[fast]"), &agents, &mut errors);
+        assert!(errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn registry_gates_submissions_and_fills_in_defaults() {
+        let state = AppState::for_test(|config| config.capability_registry_strict = true);
+        let define = |name: &str, active: bool| crate::models::CapabilityDefinition {
+            name: name.to_string(),
+            description: String::new(),
+            owner: None,
+            active,
+            default_timeout_secs: Some(120),
+            default_max_wait_secs: Some(15),
+            output_schema: None,
+            scheduling_weight: 0,
+            always_blocking: false,
+            result_ttl_secs: None,
+            created: Utc::now(),
+            updated: Utc::now(),
+        };
+        state
+            .storage
+            .capabilities
+            .upsert(&define("llm.qwen", true))
+            .unwrap();
+        state
+            .storage
+            .capabilities
+            .upsert(&define("llm.retired", false))
+            .unwrap();
+        let check = |cap: &str, timeout_secs: Option<u64>| {
+            let mut req = TaskSubmissionRequest {
+                capability: cap.to_string(),
+                timeout_secs,
+                ..Default::default()
+            };
+            let mut errors = Vec::new();
+            apply_capability_policy(&state, &mut req, &mut errors).unwrap();
+            let codes: Vec<String> = errors.iter().map(|e| e.code.clone()).collect();
+            (req, codes)
+        };
+
+        assert_eq!(check("debug.echo", None).1, vec!["not_registered"]);
+        assert_eq!(check("llm.retired", None).1, vec!["inactive"]);
+        // Looked up by base name; defaults only fill what the client left unset.
+        let (req, codes) = check("llm.qwen[vision]", Some(30));
+        assert!(codes.is_empty());
+        assert_eq!(req.timeout_secs, Some(30));
+        assert_eq!(req.max_wait_secs, Some(15));
+        assert_eq!(check("llm.qwen", None).0.timeout_secs, Some(120));
+    }
+
+    #[tokio::test]
+    async fn blocking_submission_is_promoted_for_always_blocking_capabilities() {
+        let state = AppState::for_test(|_| {});
+        let mut agent = crate::models::Agent::for_test("agent-a", &["debug.echo", "llm.qwen"]);
+        state
+            .storage
+            .agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        state
+            .storage
+            .capabilities
+            .upsert(&crate::models::CapabilityDefinition {
+                name: "llm.qwen".to_string(),
+                description: String::new(),
+                owner: None,
+                active: true,
+                default_timeout_secs: None,
+                default_max_wait_secs: None,
+                output_schema: None,
+                scheduling_weight: 0,
+                always_blocking: true,
+                result_ttl_secs: None,
+                created: Utc::now(),
+                updated: Utc::now(),
+            })
+            .unwrap();
+        let submit = |cap: &str| {
+            let req = TaskSubmissionRequest {
+                capability: cap.to_string(),
+                api_key: "client-a".to_string(),
+                urgent: false,
+                ..Default::default()
+            };
+            do_submit_task_blocking(&state, req, true, Some(0))
+        };
+
+        let refused = submit("debug.echo").await.err().unwrap();
+        assert_eq!(refused.status_code_number(), 400);
+
+        // Looked up by base name, and waited on in the urgent store.
+        let Ok(UrgentSubmitOutcome::TimedOut(report)) = submit("llm.qwen[vision]").await else {
+            panic!("always-blocking submission was not promoted");
+        };
+        assert!(state.urgent.get_pending_task(&report.id).await.is_some());
+        assert!(state.regular.get_task(&report.id).await.is_none());
+    }
+
+    #[test]
+    fn batch_errors_name_the_task_index() {
+        let errors = vec![
+            FieldError::new("capability", "required", "capability must not be empty"),
+            FieldError::new("urgent", "unsupported", "no urgent tasks in a batch"),
+        ];
+        let errors: Vec<FieldError> = batch_field_errors(3, errors).collect();
+        assert_eq!(
+            codes(&errors),
+            vec![
+                ("tasks[3].capability", "required"),
+                ("tasks[3].urgent", "unsupported"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn urgent_poll_includes_timing_when_asked() {
+        let state = AppState::for_test(|_| {});
+        let task = UnassignedTask {
+            id: TaskId::new_with_cap("debug.echo".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "debug.echo".to_string(),
+                api_key: "client-a".to_string(),
+                urgent: true,
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        state.urgent.add_task(task.clone(), 60, None).await.unwrap();
+        assert!(state.urgent.assign_task(&task.id, "agent-a").await);
+
+        let poll = |include_timing| {
+            let options = PollOptions {
+                include_timing,
+                ..Default::default()
+            };
+            do_poll_task_status(&state, task.id.clone(), "client-a", false, options)
+        };
+        let Ok(PollOutcome::FoundUrgent(urgent)) = poll(true).await else {
+            panic!("expected the urgent task");
+        };
+        let timing = urgent.timing.unwrap();
+        assert!(timing.assigned_at.is_some());
+        let Ok(PollOutcome::FoundUrgent(urgent)) = poll(false).await else {
+            panic!("expected the urgent task");
+        };
+        assert!(urgent.timing.is_none());
+
+        let foreign = do_poll_task_status(
+            &state,
+            task.id.clone(),
+            "client-b",
+            false,
+            PollOptions::default(),
+        )
+        .await;
+        assert_eq!(foreign.err().unwrap().status_code_number(), 404);
+    }
+
+    #[tokio::test]
+    async fn batch_poll_denies_tasks_of_other_keys() {
+        let state = AppState::for_test(|_| {});
+        let queued = |api_key: &str| UnassignedTask {
+            id: TaskId::new_with_cap("debug.echo".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "debug.echo".to_string(),
+                api_key: api_key.to_string(),
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        let (own, foreign) = (queued("client-a"), queued("client-b"));
+        for task in [&own, &foreign] {
+            state.storage.tasks.add_unassigned(task).unwrap();
+        }
+        let missing = TaskId::new_with_cap("debug.echo".to_string());
+
+        let ids = vec![own.id.clone(), foreign.id.clone(), missing.clone()];
+        let entries = do_poll_task_batch(&state, ids, "client-a", false, PollOptions::default())
+            .await
+            .unwrap();
+        let results: Vec<_> = entries.iter().map(|e| (e.id.clone(), e.result)).collect();
+        assert_eq!(
+            results,
+            vec![
+                (own.id.clone(), BatchPollResult::Found),
+                (foreign.id.clone(), BatchPollResult::Denied),
+                (missing, BatchPollResult::NotFound),
+            ]
+        );
+        assert_eq!(entries[0].task.as_ref().unwrap().status, TaskStatus::Queued);
+        assert!(entries[1].task.is_none());
+
+        // A management override sees every task.
+        let entries = do_poll_task_batch(
+            &state,
+            vec![foreign.id.clone()],
+            "client-a",
+            true,
+            PollOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(entries[0].result, BatchPollResult::Found);
+    }
+
+    #[tokio::test]
+    async fn draining_refuses_new_work_and_releases_blocking_waiters() {
+        let state = AppState::for_test(|_| {});
+        let mut agent = crate::models::Agent::for_test("agent-a", &["debug.echo"]);
+        state
+            .storage
+            .agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        let request = || TaskSubmissionRequest {
+            capability: "debug.echo".to_string(),
+            api_key: "client-a".to_string(),
+            urgent: true,
+            ..Default::default()
+        };
+
+        let waiter = tokio::spawn({
+            let state = state.clone();
+            let req = request();
+            async move { do_submit_task_blocking(&state, req, true, Some(60)).await }
+        });
+        let caps = vec!["debug.echo".to_string()];
+        while state
+            .urgent
+            .list_offerable(&caps, "agent-a", None)
+            .await
+            .is_empty()
+        {
+            tokio::task::yield_now().await;
+        }
+
+        state.readiness.start_draining();
+        let refused = do_submit_task(&state, request(), true).await.err().unwrap();
+        assert_eq!(refused.status_code_number(), 503);
+        let refused = do_submit_task_blocking(&state, request(), true, Some(60))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(refused.status_code_number(), 503);
+
+        // The waiter admitted before the drain is answered once the server stops.
+        state.channels.shutdown_tx.send(true).unwrap();
+        let released = waiter.await.unwrap().err().unwrap();
+        assert_eq!(released.status_code_number(), 503);
+    }
+
+    #[tokio::test]
+    async fn queued_task_is_escalated_under_its_id() {
+        let state = AppState::for_test(|_| {});
+        let mut agent = crate::models::Agent::for_test("agent-a", &["debug.echo"]);
+        state
+            .storage
+            .agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        let queued = |cap: &str| UnassignedTask {
+            id: TaskId::new_with_cap(cap.to_string()),
+            data: TaskSubmissionRequest {
+                capability: cap.to_string(),
+                api_key: "client-a".to_string(),
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        let (task, taken, unservable) = (
+            queued("debug.echo"),
+            queued("debug.echo"),
+            queued("debug.other"),
+        );
+        for task in [&task, &taken, &unservable] {
+            state.storage.tasks.add_unassigned(task).unwrap();
+            state.regular.add_task(task.clone()).await;
+        }
+        state
+            .storage
+            .tasks
+            .assign_task(&taken.id, "agent-a")
+            .unwrap();
+        state.regular.remove_task(&taken.id).await;
+
+        let escalate = |id: &TaskId, api_key: &'static str| {
+            do_escalate_task(&state, id.clone(), api_key, false, Some(30))
+        };
+        let status =
+            |result: Result<EscalateOutcome, AppError>| result.err().unwrap().status_code_number();
+        assert_eq!(status(escalate(&task.id, "client-b").await), 404);
+        assert_eq!(status(escalate(&taken.id, "client-a").await), 409);
+        // Nothing can run it urgently, so it stays queued.
+        assert_eq!(status(escalate(&unservable.id, "client-a").await), 503);
+        assert!(state.regular.get_task(&unservable.id).await.is_some());
+        assert!(
+            state
+                .storage
+                .tasks
+                .get_unassigned(&unservable.id)
+                .unwrap()
+                .is_some()
+        );
+
+        assert_eq!(
+            escalate(&task.id, "client-a").await.unwrap(),
+            EscalateOutcome::Pending {
+                id: task.id.clone(),
+                ttl_secs: 30
+            }
+        );
+        assert!(state.regular.get_task(&task.id).await.is_none());
+        assert!(
+            state
+                .storage
+                .tasks
+                .get_unassigned(&task.id)
+                .unwrap()
+                .is_none()
+        );
+        let pending = state.urgent.get_pending_task(&task.id).await.unwrap();
+        assert!(pending.data.urgent);
+        assert_eq!(pending.data.max_wait_secs, Some(30));
+    }
+}
@@ -2,7 +2,7 @@ pub mod heuristics;
 pub mod k8s;
 pub mod storage;
 
-use std::{collections::HashSet, env, sync::Arc};
+use std::{env, sync::Arc};
 
 use axum::{
     Json,
@@ -21,8 +21,9 @@ use std::convert::Infallible;
 use tracing::info;
 
 use crate::{
+    db::agent::AgentListFilter,
     error::AppError,
-    models::{Agent, ClientApiKey},
+    models::{Agent, CapabilityDefinition, ClientApiKey},
     schema::{self},
     state::{AppState, StreamEvent},
     utils::base_capability,
@@ -30,7 +31,9 @@ use crate::{
 
 /// Serialize an agent for the management UI and attach live runtime state that
 /// isn't part of the persisted record: `inFlight` (non-terminal tasks the agent
-/// currently holds, the authoritative busy count) and `connected` (live WS).
+/// currently holds, the authoritative busy count), `connected` (live WS) and
+/// `quarantined` (`quarantinedUntil` still in the future). `recentFailures` is
+/// decayed to now.
 fn agent_with_runtime(state: &Arc<AppState>, agent: &Agent) -> serde_json::Value {
     let mut value = serde_json::to_value(agent).unwrap_or_else(|_| json!({}));
     if let Some(obj) = value.as_object_mut() {
@@ -42,21 +45,67 @@ fn agent_with_runtime(state: &Arc<AppState>, agent: &Agent) -> serde_json::Value
             "connected".to_string(),
             json!(state.registry.is_connected(&agent.uid)),
         );
+        obj.insert("quarantined".to_string(), json!(agent.is_quarantined()));
+        obj.insert(
+            "recentFailures".to_string(),
+            json!(agent.recent_failures_at(chrono::Utc::now())),
+        );
     }
     value
 }
 
+#[derive(Deserialize)]
+pub struct ListAgentsQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    #[serde(default)]
+    pub online_only: bool,
+    pub capability: Option<String>,
+}
+
+impl ListAgentsQuery {
+    fn is_empty(&self) -> bool {
+        self.limit.is_none()
+            && self.offset.is_none()
+            && !self.online_only
+            && self.capability.is_none()
+    }
+}
+
+/// All agents as a plain array, or with any query parameter, one page in an
+/// `{ items, total, nextOffset }` envelope.
 pub async fn list_agents(
     State(state): State<Arc<AppState>>,
+    Query(params): Query<ListAgentsQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    let agents: Vec<serde_json::Value> = state
-        .storage
-        .agents
-        .list_all_agents()
+    if params.is_empty() {
+        let agents: Vec<serde_json::Value> = state
+            .storage
+            .agents
+            .list_all_agents()
+            .iter()
+            .map(|a| agent_with_runtime(&state, a))
+            .collect();
+        return Ok(Json(json!(agents)));
+    }
+    let limit = params.limit.unwrap_or(100).min(1000);
+    let offset = params.offset.unwrap_or(0);
+    let filter = AgentListFilter {
+        online_only: params.online_only,
+        capability: params.capability,
+    };
+    let page = state.storage.agents.list_agents_paged(limit, offset, &filter);
+    let next_offset = offset + page.items.len();
+    let items: Vec<serde_json::Value> = page
+        .items
         .iter()
         .map(|a| agent_with_runtime(&state, a))
         .collect();
-    Ok(Json(agents))
+    Ok(Json(json!({
+        "items": items,
+        "total": page.total,
+        "nextOffset": (next_offset < page.total).then_some(next_offset),
+    })))
 }
 
 pub async fn list_agents_online(
@@ -67,57 +116,279 @@ pub async fn list_agents_online(
         .agents
         .list_all_agents()
         .into_iter()
-        .filter(Agent::is_online)
+        .filter(|a| state.storage.agents.is_online(a))
         .map(|a| agent_with_runtime(&state, &a))
         .collect();
     Ok(Json(agents))
 }
 
+/// Preview the tasks a hypothetical agent would be offered right now, running
+/// the same matching and tier filters as a real poll without assigning anything.
+pub async fn simulate_agent(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<schema::SimulateAgentRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let agent_uid = req.agent_id.unwrap_or_default();
+    let mut offers: Vec<schema::SimulatedOffer> = state
+        .urgent
+        .list_offerable(&req.capabilities, &agent_uid, req.system_info.as_ref())
+        .await
+        .into_iter()
+        .map(|task| schema::SimulatedOffer {
+            id: task.id,
+            urgent: true,
+            created_at: task.created_at,
+            offer_probability: 1.0,
+        })
+        .collect();
+    offers.extend(
+        state
+            .regular
+            .list_eligible_for_tier(
+                &req.capabilities,
+                req.tier,
+                &state.storage.agents,
+                &agent_uid,
+                req.system_info.as_ref(),
+            )
+            .await
+            .into_iter()
+            .map(|(task, probability)| schema::SimulatedOffer {
+                id: task.id,
+                urgent: false,
+                created_at: task.created_at,
+                offer_probability: probability,
+            }),
+    );
+    Ok(Json(offers))
+}
+
 pub async fn remove_agent(
     State(state): State<Arc<AppState>>,
     Path(agent_id): Path<String>,
 ) -> Result<impl IntoResponse, AppError> {
     state.storage.delete_agent(&agent_id).await?;
+    state.online_caps.invalidate();
     Ok(Json(json!("Agent deleted")))
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneAgentsRequest {
+    /// Agents not heard from for longer than this are removed.
+    pub older_than_secs: u64,
+}
+
+/// Remove every agent silent for longer than `olderThanSecs`. Restartable
+/// tasks they held are re-queued for other agents, the rest failed. Each
+/// agent's work is handed back before the agent is deleted, so an error
+/// partway through never strands tasks on a deleted agent.
+pub async fn prune_agents(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<PruneAgentsRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    if req.older_than_secs < state.config.agent_online_timeout_secs {
+        return Err(AppError::Validation(format!(
+            "olderThanSecs must be at least the agent online timeout ({}s)",
+            state.config.agent_online_timeout_secs
+        )));
+    }
+    let secs = i64::try_from(req.older_than_secs).unwrap_or(i64::MAX);
+    let age = chrono::TimeDelta::try_seconds(secs).unwrap_or(chrono::TimeDelta::MAX);
+    let older_than = chrono::Utc::now()
+        .checked_sub_signed(age)
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+    let pruned = state.storage.agents.stale_agent_ids(older_than);
+    let (mut requeued, mut failed, mut caps) = (0, 0, Vec::new());
+    for agent_id in &pruned {
+        let released = crate::api::agent::service::release_agent_work(&state, agent_id).await?;
+        requeued += released.requeued;
+        failed += released.failed;
+        caps.extend(released.caps);
+        state.storage.delete_agent(agent_id).await?;
+    }
+    state.online_caps.invalidate();
+    info!(
+        "Management: pruned {} agent(s) silent for over {}s ({} task(s) re-queued, {} failed)",
+        pruned.len(),
+        req.older_than_secs,
+        requeued,
+        failed
+    );
+    for cap in caps {
+        crate::mq::dispatch::dispatch_for_capability(&state, base_capability(&cap)).await;
+    }
+    Ok(Json(json!({
+        "pruned": pruned,
+        "requeued": requeued,
+        "failed": failed,
+    })))
+}
+
+/// Invalidate every token issued to an agent so far. The agent has to log in
+/// again with its key; an open WebSocket stays up until it reconnects.
+pub async fn revoke_agent_sessions(
+    State(state): State<Arc<AppState>>,
+    Path(agent_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    if state.storage.get_agent(&agent_id).is_none() {
+        return Err(AppError::NotFound(format!("Agent {} not found", agent_id)));
+    }
+    let refresh_tokens = state.storage.agents.revoke_sessions(&agent_id)?;
+    info!("Management: revoked sessions of agent {}", agent_id);
+    Ok(Json(json!({
+        "agentId": agent_id,
+        "revokedRefreshTokens": refresh_tokens,
+    })))
+}
+
+/// Failure and availability summary of one agent, for spotting flaky workers.
+pub async fn get_agent_health(
+    State(state): State<Arc<AppState>>,
+    Path(agent_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let agent = state
+        .storage
+        .agents
+        .get_agent(&agent_id)
+        .ok_or_else(|| AppError::NotFound(format!("Agent {} not found", agent_id)))?;
+    Ok(Json(json!({
+        "agentId": agent.uid,
+        "online": agent.is_online(state.config.agent_online_timeout_secs as i64),
+        "connected": state.registry.is_connected(&agent.uid),
+        "inFlight": state.agent_load.in_flight(&agent.uid),
+        "recentFailures": agent.recent_failures_at(chrono::Utc::now()),
+        "lastError": agent.last_error,
+        "lastErrorAt": agent.last_error_at,
+        "quarantined": agent.is_quarantined(),
+        "quarantinedUntil": agent.quarantined_until,
+        "quarantineReason": agent.quarantine_reason,
+    })))
+}
+
+/// Lift an agent's quarantine early and forget its fault history.
+pub async fn clear_agent_quarantine(
+    State(state): State<Arc<AppState>>,
+    Path(agent_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let agent = state
+        .storage
+        .agents
+        .set_quarantine(&agent_id, None, None)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Agent {} not found", agent_id)))?;
+    state.agent_faults.clear(&agent_id);
+    info!("Management: cleared quarantine of agent {}", agent_id);
+    crate::mq::dispatch::dispatch_to_agent(&state, &agent_id).await;
+    Ok(Json(agent_with_runtime(&state, &agent)))
+}
+
+/// Build metadata. Unauthenticated at `/version` so health tooling can read it.
+/// `version` stays the deploy-time `APP_VERSION`; the rest is embedded by
+/// `build.rs` at compile time.
 pub async fn version() -> impl IntoResponse {
     let v = env::var("APP_VERSION").unwrap_or_else(|_| "unknown".to_string());
-    Json(json!({ "version": v }))
+    let build_timestamp = env!("OFFLOADMQ_BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .ok()
+        .filter(|secs| *secs > 0)
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0));
+    Json(json!({
+        "version": v,
+        "packageVersion": env!("CARGO_PKG_VERSION"),
+        "gitCommit": env!("OFFLOADMQ_GIT_COMMIT"),
+        "buildTimestamp": build_timestamp,
+        "rustcVersion": env!("OFFLOADMQ_RUSTC_VERSION"),
+    }))
 }
 
 pub async fn capabilities_online(
     State(state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let mut capabilities = HashSet::new();
-    state
-        .storage
-        .agents
-        .list_all_agents()
-        .into_iter()
-        .filter(Agent::is_online)
-        .flat_map(|agent| agent.capabilities)
-        .for_each(|cap| {
-            capabilities.insert(base_capability(&cap).to_string());
-        });
-    Ok(Json(capabilities))
+    Ok(Json(state.online_caps.base(&state.storage.agents)))
 }
 
 pub async fn capabilities_online_ext(
     State(state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let mut capabilities: HashSet<String> = HashSet::new();
-    state
+    let capabilities = state.online_caps.extended(&state.storage.agents);
+    Ok(Json((*capabilities).clone()))
+}
+
+pub async fn list_capability_registry(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.storage.capabilities.list_all()))
+}
+
+pub async fn get_capability_definition(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let definition = state
         .storage
-        .agents
-        .list_all_agents()
-        .into_iter()
-        .filter(Agent::is_online)
-        .flat_map(|agent| agent.capabilities)
-        .for_each(|cap| {
-            capabilities.insert(cap);
-        });
-    Ok(Json(capabilities))
+        .capabilities
+        .get(&name)?
+        .ok_or_else(|| AppError::NotFound(format!("Capability {} not registered", name)))?;
+    Ok(Json(definition))
+}
+
+pub async fn upsert_capability_definition(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(req): Json<schema::CapabilityDefinitionRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    if name != base_capability(&name) || name.is_empty() {
+        return Err(AppError::Validation(format!(
+            "Capability name must be a non-empty base capability without attributes: {}",
+            name
+        )));
+    }
+    if req
+        .output_schema
+        .as_ref()
+        .is_some_and(|schema| !schema.is_object() && !schema.is_boolean())
+    {
+        return Err(AppError::Validation(
+            "outputSchema must be a JSON Schema object".to_string(),
+        ));
+    }
+    let now = chrono::Utc::now();
+    let created = state
+        .storage
+        .capabilities
+        .get(&name)?
+        .map_or(now, |existing| existing.created);
+    let definition = CapabilityDefinition {
+        name,
+        description: req.description,
+        owner: req.owner,
+        active: req.active,
+        default_timeout_secs: req.default_timeout_secs,
+        default_max_wait_secs: req.default_max_wait_secs,
+        output_schema: req.output_schema,
+        scheduling_weight: req.scheduling_weight,
+        always_blocking: req.always_blocking,
+        result_ttl_secs: req.result_ttl_secs,
+        created,
+        updated: now,
+    };
+    state.storage.capabilities.upsert(&definition)?;
+    info!("Capability registry updated: {:?}", definition);
+    Ok(Json(definition))
+}
+
+pub async fn delete_capability_definition(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.storage.capabilities.delete(&name)? {
+        return Err(AppError::NotFound(format!(
+            "Capability {} not registered",
+            name
+        )));
+    }
+    Ok(Json(json!({ "result": "Capability removed" })))
 }
 
 pub async fn client_api_keys(
@@ -194,6 +465,7 @@ pub async fn reset_agents(
 ) -> Result<impl IntoResponse, AppError> {
     info!("Agents reset triggered");
     state.storage.agents.clear()?;
+    state.online_caps.invalidate();
     Ok(Json(json!({"result": "Reset successful"})))
 }
 
@@ -204,6 +476,163 @@ pub struct ServiceLogsQuery {
     pub cursor: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct LateReportsQuery {
+    pub limit: Option<usize>,
+}
+
+/// Reports kept under `UNKNOWN_TASK_REPORT_POLICY=store`, newest first.
+pub async fn list_late_reports(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<LateReportsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let limit = params.limit.unwrap_or(100).min(1000);
+    let items = state.storage.tasks.list_late_reports(limit)?;
+    Ok(Json(json!({
+        "items": items,
+        "count": items.len(),
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct ArchivedTasksQuery {
+    /// Exact capability string (including any extended attributes).
+    pub cap: Option<String>,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+/// Tasks moved out of the assigned tree by the archive sweeps, ordered by
+/// capability and then id.
+pub async fn list_archived_tasks(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ArchivedTasksQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let limit = params.limit.unwrap_or(100).min(1000);
+    let (items, next_cursor) = state.storage.tasks.list_archived_page(
+        params.cap.as_deref(),
+        limit,
+        params.cursor.as_deref(),
+    )?;
+    Ok(Json(json!({
+        "items": items,
+        "next_cursor": next_cursor,
+        "count": items.len(),
+    })))
+}
+
+/// Tasks that failed for good (retries exhausted or deadline exceeded),
+/// ordered by capability and then id.
+pub async fn list_dead_letter_tasks(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let items = state.storage.tasks.list_dead_letter_all()?;
+    Ok(Json(json!({
+        "items": items,
+        "count": items.len(),
+    })))
+}
+
+/// Put a dead-lettered task back in the queue for any agent to take.
+pub async fn requeue_dead_letter_task(
+    State(state): State<Arc<AppState>>,
+    Path((cap, id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let task_id = schema::TaskId::from_url(id, cap)?;
+    let Some(task) = state.storage.tasks.requeue_dead_letter(&task_id)? else {
+        return Err(AppError::NotFound(format!(
+            "Task {} is not in the dead-letter queue",
+            task_id
+        )));
+    };
+    info!("Dead-lettered task {} re-queued by management", task_id);
+    state.regular.add_task(task).await;
+    crate::mq::dispatch::dispatch_for_capability(&state, base_capability(&task_id.cap)).await;
+    Ok(Json(json!({
+        "id": task_id,
+        "status": "queued",
+        "message": "Task re-queued from the dead-letter queue"
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct UrgentAuditQuery {
+    /// Capability and id of a single task; both or neither.
+    pub cap: Option<String>,
+    pub id: Option<String>,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+/// Urgent task audit trail: one task's full history when `cap` and `id` are
+/// given, otherwise every transition in the `[from, to)` window, oldest first.
+pub async fn list_urgent_audit(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<UrgentAuditQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let audit = &state.storage.urgent_audit;
+    match (params.cap, params.id) {
+        (Some(cap), Some(id)) => {
+            let items = audit
+                .list_for_task(&schema::TaskId { cap, id })
+                .map_err(AppError::Internal)?;
+            Ok(Json(json!({
+                "items": items,
+                "count": items.len(),
+            })))
+        }
+        (None, None) => {
+            let limit = params.limit.unwrap_or(100).min(1000);
+            let (items, next_cursor) = audit
+                .list_range(params.from, params.to, limit, params.cursor.as_deref())
+                .map_err(AppError::Internal)?;
+            Ok(Json(json!({
+                "items": items,
+                "next_cursor": next_cursor,
+                "count": items.len(),
+            })))
+        }
+        _ => Err(AppError::BadRequest(
+            "cap and id must be given together".to_string(),
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TaskEventsQuery {
+    /// `record_id` of the last transition already seen (exclusive).
+    pub since: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Changefeed of task status transitions, regular and urgent, oldest first.
+/// `next_cursor` is the `since` to poll with next; it stays put while there
+/// is nothing new.
+pub async fn list_task_events(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TaskEventsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let limit = params.limit.unwrap_or(100).min(1000);
+    let (items, has_more) = state
+        .storage
+        .tasks
+        .events()
+        .list_since(params.since.as_deref(), limit)
+        .map_err(AppError::Internal)?;
+    let next_cursor = items
+        .last()
+        .map(|record| record.record_id.clone())
+        .or(params.since);
+    Ok(Json(json!({
+        "items": items,
+        "next_cursor": next_cursor,
+        "has_more": has_more,
+        "count": items.len(),
+    })))
+}
+
 pub async fn list_service_messages(
     State(state): State<Arc<AppState>>,
     Query(params): Query<ServiceLogsQuery>,
@@ -1,4 +1,4 @@
-use std::env;
+use std::{collections::HashMap, env, path::PathBuf};
 
 use dotenvy::dotenv;
 
@@ -179,21 +179,436 @@ impl AgentWsConfig {
     }
 }
 
+/// Poll cadence recommended to HTTP agents in the registration response.
+#[derive(Clone, Debug)]
+pub struct AgentPollingConfig {
+    /// Interval between urgent polls (env: AGENT_POLL_URGENT_INTERVAL_MS, default: 1000).
+    pub urgent_interval_ms: u64,
+    /// Interval between non-urgent polls (env: AGENT_POLL_INTERVAL_MS, default: 5000).
+    pub interval_ms: u64,
+}
+
+impl AgentPollingConfig {
+    pub fn from_env() -> Self {
+        let urgent_interval_ms = env::var("AGENT_POLL_URGENT_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1000);
+        let interval_ms = env::var("AGENT_POLL_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5000);
+        Self {
+            urgent_interval_ms,
+            interval_ms,
+        }
+    }
+}
+
+/// Per-capability circuit breaker for non-urgent tasks (see `mq::circuit`).
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerConfig {
+    /// Failure rate (0.0-1.0) over the window at which the circuit opens;
+    /// `None` disables the breaker (env: CIRCUIT_BREAKER_FAILURE_RATE, default: unset).
+    pub failure_rate: Option<f64>,
+    /// Outcomes needed in the window before the rate is judged
+    /// (env: CIRCUIT_BREAKER_MIN_SAMPLES, default: 10).
+    pub min_samples: usize,
+    /// Sliding window of outcomes, in seconds (env: CIRCUIT_BREAKER_WINDOW_SECS, default: 300).
+    pub window_secs: i64,
+    /// How long an open circuit holds tasks back before a probe is let through
+    /// (env: CIRCUIT_BREAKER_COOLDOWN_SECS, default: 60).
+    pub cooldown_secs: i64,
+}
+
+impl CircuitBreakerConfig {
+    pub fn from_env() -> Self {
+        let failure_rate = env::var("CIRCUIT_BREAKER_FAILURE_RATE")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| rate.min(1.0));
+        let min_samples = env::var("CIRCUIT_BREAKER_MIN_SAMPLES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+        let window_secs = env::var("CIRCUIT_BREAKER_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300);
+        let cooldown_secs = env::var("CIRCUIT_BREAKER_COOLDOWN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+        Self {
+            failure_rate,
+            min_samples,
+            window_secs,
+            cooldown_secs,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UrgentConfig {
+    /// Seconds a pending urgent task whose pickup TTL has passed stays offered in
+    /// a "last chance" state (ahead of other urgent tasks) before it is failed.
+    /// 0 disables the grace phase (env: URGENT_EXPIRY_GRACE_SECS, default: 0).
+    pub expiry_grace_secs: i64,
+    /// Seconds `submit_until_assigned` waits for an agent to pick up the task
+    /// before answering "still pending" (env: URGENT_ASSIGN_WAIT_SECS, default: 10).
+    pub assign_wait_secs: u64,
+    /// Slots per agent that non-urgent pickup leaves free for urgent tasks.
+    /// Agents can override it at registration (env: URGENT_RESERVED_SLOTS,
+    /// default: 0).
+    pub reserved_slots: u32,
+    /// Record every urgent task transition in the persistent audit trail
+    /// (env: URGENT_AUDIT_LOG, default: false).
+    pub audit_log: bool,
+    /// Write urgent tasks through to the task database and reload the pending
+    /// and in-flight ones on startup (env: URGENT_PERSIST, default: false).
+    pub persist: bool,
+    /// Seconds a completed, failed or canceled urgent task stays pollable
+    /// before the expiry sweep removes it, when its submitter did not
+    /// (env: URGENT_TERMINAL_RETENTION_SECS, default: 300).
+    pub terminal_retention_secs: i64,
+}
+
+impl UrgentConfig {
+    pub fn from_env() -> Self {
+        let expiry_grace_secs = env::var("URGENT_EXPIRY_GRACE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0i64)
+            .max(0);
+        let assign_wait_secs = env::var("URGENT_ASSIGN_WAIT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+        let reserved_slots = env::var("URGENT_RESERVED_SLOTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let audit_log = env::var("URGENT_AUDIT_LOG")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+        let persist = env::var("URGENT_PERSIST")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+        let terminal_retention_secs = env::var("URGENT_TERMINAL_RETENTION_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300i64)
+            .max(0);
+        Self {
+            expiry_grace_secs,
+            assign_wait_secs,
+            reserved_slots,
+            audit_log,
+            persist,
+            terminal_retention_secs,
+        }
+    }
+}
+
+/// What registration does when the agent asks for a uid that already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateAgentUidPolicy {
+    /// Reject the registration with `409 Conflict`.
+    #[default]
+    Reject,
+    /// Silently register the agent under a freshly generated uid.
+    Regenerate,
+}
+
+impl DuplicateAgentUidPolicy {
+    pub fn from_env() -> Self {
+        match env::var("AGENT_DUPLICATE_UID_POLICY").as_deref() {
+            Ok("regenerate") => Self::Regenerate,
+            _ => Self::Reject,
+        }
+    }
+}
+
+/// What resolve does with a report for a task that is no longer assigned
+/// anywhere (archived, expired or reset).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnknownTaskReportPolicy {
+    /// Answer `404 Not Found`.
+    Reject,
+    /// Answer `200` with a note and drop the report.
+    #[default]
+    Ignore,
+    /// Answer `200` and keep the report in the late reports tree for
+    /// reconciliation.
+    Store,
+}
+
+impl UnknownTaskReportPolicy {
+    pub fn from_env() -> Self {
+        match env::var("UNKNOWN_TASK_REPORT_POLICY").as_deref() {
+            Ok("reject") => Self::Reject,
+            Ok("store") => Self::Store,
+            _ => Self::Ignore,
+        }
+    }
+}
+
+/// What happens to queued non-urgent tasks whose capability is deactivated
+/// in the registry after they were submitted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeactivatedCapabilityPolicy {
+    /// Keep them queued, but not handed out, until the capability is active again.
+    #[default]
+    Hold,
+    /// Fail them with a note naming the deactivated capability.
+    Fail,
+}
+
+impl DeactivatedCapabilityPolicy {
+    pub fn from_env() -> Self {
+        match env::var("DEACTIVATED_CAPABILITY_POLICY").as_deref() {
+            Ok("fail") => Self::Fail,
+            _ => Self::Hold,
+        }
+    }
+}
+
+/// What happens to a queued task when one of its `dependsOn` tasks ends in
+/// any state other than `Completed`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DependencyFailurePolicy {
+    /// Mark the dependent task `Failed`.
+    #[default]
+    Fail,
+    /// Mark the dependent task `Canceled`.
+    Cancel,
+}
+
+impl DependencyFailurePolicy {
+    pub fn from_env() -> Self {
+        match env::var("DEPENDENCY_FAILURE_POLICY").as_deref() {
+            Ok("cancel") => Self::Cancel,
+            _ => Self::Fail,
+        }
+    }
+}
+
+/// Completion callbacks to a task's `callbackUrl` (see `mq::webhook`).
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    /// Delivery attempts per callback before giving up
+    /// (env: WEBHOOK_MAX_ATTEMPTS, default: 5).
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled after each failed attempt
+    /// (env: WEBHOOK_BACKOFF_MS, default: 500).
+    pub backoff_ms: u64,
+    /// Timeout of a single delivery attempt (env: WEBHOOK_TIMEOUT_SECS, default: 10).
+    pub timeout_secs: u64,
+    /// Callback hosts trusted even when they resolve to a loopback, private or
+    /// link-local address (env: WEBHOOK_ALLOWED_HOSTS, comma-separated, default:
+    /// none). Every other host must resolve to public addresses only.
+    pub allowed_hosts: Vec<String>,
+}
+
+impl WebhookConfig {
+    pub fn from_env() -> Self {
+        let max_attempts = env::var("WEBHOOK_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|attempts| *attempts > 0)
+            .unwrap_or(5);
+        let backoff_ms = env::var("WEBHOOK_BACKOFF_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(500);
+        let timeout_secs = env::var("WEBHOOK_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(10);
+        let allowed_hosts = env::var("WEBHOOK_ALLOWED_HOSTS")
+            .map(|v| {
+                v.split(',')
+                    .map(|host| host.trim().to_ascii_lowercase())
+                    .filter(|host| !host.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            max_attempts,
+            backoff_ms,
+            timeout_secs,
+            allowed_hosts,
+        }
+    }
+}
+
+/// Automatic quarantine of agents that keep faulting (see `mq::quarantine`).
+#[derive(Clone, Debug)]
+pub struct QuarantineConfig {
+    /// Faults (failed tasks, expired leases, malformed reports) within the
+    /// window that quarantine an agent; 0 disables it
+    /// (env: AGENT_QUARANTINE_FAULTS, default: 0).
+    pub fault_threshold: usize,
+    /// Sliding window of faults, in seconds (env: AGENT_QUARANTINE_WINDOW_SECS, default: 300).
+    pub window_secs: i64,
+    /// How long a quarantined agent gets no work
+    /// (env: AGENT_QUARANTINE_SECS, default: 600).
+    pub cooldown_secs: i64,
+}
+
+impl QuarantineConfig {
+    pub fn from_env() -> Self {
+        let fault_threshold = env::var("AGENT_QUARANTINE_FAULTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let window_secs = env::var("AGENT_QUARANTINE_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300);
+        let cooldown_secs = env::var("AGENT_QUARANTINE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(600);
+        Self {
+            fault_threshold,
+            window_secs,
+            cooldown_secs,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TaskRestartConfig {
+    /// How many times a failed `restartable` task is re-queued to a different
+    /// agent before it is marked `Failed` (env: TASK_MAX_RESTARTS, default: 3).
+    pub max_restarts: u32,
+    /// How long a non-urgent task may go untouched by an offline agent before
+    /// orphan recovery takes it back (env: ORPHAN_SILENCE_SECS, default: 1800).
+    /// Progress updates and touches renew this lease.
+    pub orphan_silence_secs: i64,
+}
+
+impl TaskRestartConfig {
+    pub fn from_env() -> Self {
+        let max_restarts = env::var("TASK_MAX_RESTARTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3);
+        let orphan_silence_secs = env::var("ORPHAN_SILENCE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30 * 60);
+        Self {
+            max_restarts,
+            orphan_silence_secs,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct AppConfig {
     pub jwt_secret: String,
     pub database_root_path: String,
     pub agent_api_keys: Vec<String>,
+    /// Capability patterns each agent API key may register, keyed by agent API key.
+    /// Keys without an entry are unrestricted. Patterns use the same wildcard rules
+    /// as client API keys (`*`, `prefix*`, exact)
+    /// (env: AGENT_KEY_CAPABILITIES, format: `key1=llm.*,debug.echo;key2=*`).
+    pub agent_key_capabilities: HashMap<String, Vec<String>>,
     pub client_api_keys: Vec<String>,
     pub management_token: String,
     pub host: String,
     pub port: u16,
+    /// Unix domain socket path to listen on instead of `HOST`:`PORT`
+    /// (env: LISTEN_UDS, default: unset = TCP).
+    pub listen_uds: Option<PathBuf>,
+    /// Reject submissions for capabilities missing from the capability registry
+    /// (env: CAPABILITY_REGISTRY_STRICT, default: false).
+    pub capability_registry_strict: bool,
+    /// Reject submissions for capabilities no registered agent (online or
+    /// offline) advertises (env: REJECT_UNSERVABLE_CAPABILITIES, default: false).
+    pub reject_unservable_capabilities: bool,
     /// Maximum request body size in bytes for the client API (env: MAX_REQUEST_BODY_BYTES).
     pub max_request_body_bytes: usize,
     pub storage: StorageConfig,
     pub heuristics: HeuristicsConfig,
     pub stale_agents: StaleAgentsConfig,
     pub agent_ws: AgentWsConfig,
+    pub agent_polling: AgentPollingConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub quarantine: QuarantineConfig,
+    pub webhook: WebhookConfig,
+    pub urgent: UrgentConfig,
+    pub task_restart: TaskRestartConfig,
+    /// Non-urgent scheduling preferences (env: FAIR_SHARE_SCHEDULING,
+    /// SELECTION_STRATEGY, TASK_PICK_ORDER).
+    pub preferences: crate::preferences::Config,
+    /// Handling of a registration that requests an existing agent uid
+    /// (env: AGENT_DUPLICATE_UID_POLICY, `reject` or `regenerate`, default: reject).
+    pub duplicate_agent_uid: DuplicateAgentUidPolicy,
+    /// Handling of a result report for a task that is no longer assigned
+    /// (env: UNKNOWN_TASK_REPORT_POLICY, `reject`, `ignore` or `store`,
+    /// default: ignore).
+    pub unknown_task_report: UnknownTaskReportPolicy,
+    /// Handling of queued tasks whose capability was deactivated after
+    /// submission (env: DEACTIVATED_CAPABILITY_POLICY, `hold` or `fail`,
+    /// default: hold).
+    pub deactivated_capability: DeactivatedCapabilityPolicy,
+    /// Handling of queued tasks whose dependency did not complete
+    /// (env: DEPENDENCY_FAILURE_POLICY, `fail` or `cancel`, default: fail).
+    pub dependency_failure: DependencyFailurePolicy,
+    /// Debounce window for concurrent HTTP polls from agents with the same
+    /// tier and capabilities; 0 disables it (env: POLL_GATE_MS, default: 0).
+    pub poll_gate_ms: u64,
+    /// How long the online-capabilities set is reused before the agent tree
+    /// is scanned again; 0 disables caching
+    /// (env: CAPABILITIES_ONLINE_CACHE_SECS, default: 2).
+    pub capabilities_online_cache_secs: u64,
+    /// Seconds to keep accepting connections after a shutdown signal while
+    /// `/ready` reports draining, so the load balancer can stop routing first
+    /// (env: SHUTDOWN_DRAIN_SECS, default: 0).
+    pub shutdown_drain_secs: u64,
+    /// Most task ids accepted by one `poll_batch` call
+    /// (env: POLL_BATCH_MAX_IDS, default: 100).
+    pub poll_batch_max_ids: usize,
+    /// Most tasks accepted by one `submit_batch` call
+    /// (env: SUBMIT_BATCH_MAX_TASKS, default: 1000).
+    pub submit_batch_max_tasks: usize,
+    /// Absolute task age after which an assigned task is force-failed (if not
+    /// terminal) and archived regardless of status; unset disables the sweep
+    /// (env: TASK_MAX_AGE_SECS, default: unset).
+    pub task_max_age_secs: Option<u64>,
+    /// How long a `clientTaskId` keeps returning the task first submitted
+    /// with it; afterwards the id can name a new task. Unset keeps ids for
+    /// the life of the task (env: CLIENT_TASK_ID_TTL_SECS, default: unset).
+    pub client_task_id_ttl_secs: Option<u64>,
+    /// Seconds since an agent's last contact after which it counts as offline
+    /// for scheduling and listings (env: AGENT_ONLINE_TIMEOUT_SECS, default: 120).
+    pub agent_online_timeout_secs: u64,
+    /// How long after expiry an agent access token can still be exchanged via
+    /// `POST /private/agent/auth/refresh`
+    /// (env: AGENT_TOKEN_REFRESH_GRACE_SECS, default: 3600).
+    pub agent_token_refresh_grace_secs: u64,
+    /// Auth failures (all sources together) within the alert window that
+    /// trigger a brute-force warning; 0 disables it
+    /// (env: AUTH_FAILURE_ALERT_THRESHOLD, default: 50).
+    pub auth_failure_alert_threshold: usize,
+    /// (env: AUTH_FAILURE_ALERT_WINDOW_SECS, default: 60).
+    pub auth_failure_alert_window_secs: u64,
+    /// Requests per second each client API key may make on `/api/*`; 0
+    /// disables rate limiting (env: CLIENT_RATE_LIMIT_RPS, default: 0).
+    pub client_rate_limit_rps: f64,
+    /// Requests a key may make back to back before the rate applies
+    /// (env: CLIENT_RATE_LIMIT_BURST, default: the rate rounded up).
+    pub client_rate_limit_burst: u32,
+    /// How long task status transitions stay in the `/management/events` log;
+    /// 0 keeps them forever (env: TASK_EVENTS_RETENTION_SECS, default: 604800).
+    pub task_events_retention_secs: u64,
 }
 
 impl AppConfig {
@@ -217,6 +632,9 @@ impl AppConfig {
             .map(|s| s.to_string())
             .collect();
 
+        let agent_key_capabilities =
+            parse_agent_key_capabilities(&env::var("AGENT_KEY_CAPABILITIES").unwrap_or_default());
+
         let client_api_keys = env::var("CLIENT_API_KEYS")
             .unwrap_or_else(|_| String::new())
             .split(':')
@@ -234,24 +652,154 @@ impl AppConfig {
             .unwrap_or_else(|_| "5000000".to_string())
             .parse::<usize>()?;
 
+        let capability_registry_strict = env::var("CAPABILITY_REGISTRY_STRICT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+        let reject_unservable_capabilities = env::var("REJECT_UNSERVABLE_CAPABILITIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
         let storage = StorageConfig::from_env(&database_root_path);
         let heuristics = HeuristicsConfig::from_env();
         let stale_agents = StaleAgentsConfig::from_env();
         let agent_ws = AgentWsConfig::from_env();
+        let agent_polling = AgentPollingConfig::from_env();
+        let circuit_breaker = CircuitBreakerConfig::from_env();
+        let quarantine = QuarantineConfig::from_env();
+        let webhook = WebhookConfig::from_env();
+        let urgent = UrgentConfig::from_env();
+        let task_restart = TaskRestartConfig::from_env();
+        let preferences = crate::preferences::Config::from_env();
+        let duplicate_agent_uid = DuplicateAgentUidPolicy::from_env();
+        let unknown_task_report = UnknownTaskReportPolicy::from_env();
+        let deactivated_capability = DeactivatedCapabilityPolicy::from_env();
+        let dependency_failure = DependencyFailurePolicy::from_env();
+        let poll_gate_ms = env::var("POLL_GATE_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let capabilities_online_cache_secs = env::var("CAPABILITIES_ONLINE_CACHE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2);
+        let shutdown_drain_secs = env::var("SHUTDOWN_DRAIN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let poll_batch_max_ids = env::var("POLL_BATCH_MAX_IDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(100);
+        let submit_batch_max_tasks = env::var("SUBMIT_BATCH_MAX_TASKS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1000);
+        let listen_uds = env::var("LISTEN_UDS")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let task_max_age_secs = env::var("TASK_MAX_AGE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0);
+        let client_task_id_ttl_secs = env::var("CLIENT_TASK_ID_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0);
+        let agent_online_timeout_secs = env::var("AGENT_ONLINE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(crate::models::Agent::DEFAULT_ONLINE_TIMEOUT_SECS as u64);
+        let agent_token_refresh_grace_secs = env::var("AGENT_TOKEN_REFRESH_GRACE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600);
+        let auth_failure_alert_threshold = env::var("AUTH_FAILURE_ALERT_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(50);
+        let auth_failure_alert_window_secs = env::var("AUTH_FAILURE_ALERT_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+        let client_rate_limit_rps: f64 = env::var("CLIENT_RATE_LIMIT_RPS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|rps: &f64| rps.is_finite() && *rps > 0.0)
+            .unwrap_or(0.0);
+        let client_rate_limit_burst = env::var("CLIENT_RATE_LIMIT_BURST")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|burst| *burst > 0)
+            .unwrap_or(client_rate_limit_rps.ceil().max(1.0) as u32);
+        let task_events_retention_secs = env::var("TASK_EVENTS_RETENTION_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(7 * 24 * 60 * 60);
 
         Ok(Self {
             jwt_secret,
             database_root_path,
             agent_api_keys,
+            agent_key_capabilities,
             client_api_keys,
             host,
             port,
+            listen_uds,
             management_token,
+            capability_registry_strict,
+            reject_unservable_capabilities,
             max_request_body_bytes,
             storage,
             heuristics,
             stale_agents,
             agent_ws,
+            agent_polling,
+            circuit_breaker,
+            quarantine,
+            webhook,
+            urgent,
+            task_restart,
+            preferences,
+            duplicate_agent_uid,
+            unknown_task_report,
+            deactivated_capability,
+            dependency_failure,
+            poll_gate_ms,
+            capabilities_online_cache_secs,
+            shutdown_drain_secs,
+            poll_batch_max_ids,
+            submit_batch_max_tasks,
+            task_max_age_secs,
+            client_task_id_ttl_secs,
+            agent_online_timeout_secs,
+            agent_token_refresh_grace_secs,
+            auth_failure_alert_threshold,
+            auth_failure_alert_window_secs,
+            client_rate_limit_rps,
+            client_rate_limit_burst,
+            task_events_retention_secs,
         })
     }
 }
+
+/// Parse `key1=cap1,cap2;key2=cap3` into a per-key pattern list. `;` separates
+/// entries because capability names may themselves contain `:`.
+fn parse_agent_key_capabilities(raw: &str) -> HashMap<String, Vec<String>> {
+    raw.split(';')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(key, caps)| {
+            let caps = caps
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect();
+            (key.trim().to_string(), caps)
+        })
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
@@ -1,21 +1,265 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
 use crate::{
+    config::DuplicateAgentUidPolicy,
+    db::{retry::retry_transient, versioned},
     error::AppError,
     models::{Agent, CommunicationMethod},
+    mq::capability::{agent_serves, capability_matches},
+    schema::TaskSubmissionRequest,
+    utils::base_capability,
 };
-use chrono::Utc;
-use log::info;
-use rmp_serde::{from_slice, to_vec_named};
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{info, warn};
+use ulid::Ulid;
 use uuid::Uuid;
 
+/// Filter for [`AgentStorage::list_agents_paged`].
+#[derive(Debug, Clone, Default)]
+pub struct AgentListFilter {
+    pub online_only: bool,
+    /// Base capability name the agent must advertise.
+    pub capability: Option<String>,
+}
+
+impl AgentListFilter {
+    fn matches(&self, agent: &Agent, online_timeout_secs: i64) -> bool {
+        (!self.online_only || agent.is_online(online_timeout_secs))
+            && self
+                .capability
+                .as_deref()
+                .is_none_or(|cap| agent_serves(&agent.capabilities, cap))
+    }
+}
+
+/// One window of matching agents plus the number of matches overall.
+pub struct AgentPage {
+    pub items: Vec<Agent>,
+    pub total: usize,
+}
+
+/// In-memory index of advertised capabilities, so the scheduler can find the
+/// agents serving a capability without decoding every agent record. Keys are
+/// the base names (or wildcard patterns) agents advertise.
+#[derive(Default)]
+struct CapabilityIndex {
+    agents_by_cap: HashMap<String, HashSet<String>>,
+    caps_by_agent: HashMap<String, HashSet<String>>,
+}
+
+impl CapabilityIndex {
+    fn insert(&mut self, agent: &Agent) {
+        self.remove(&agent.uid);
+        let caps: HashSet<String> = agent
+            .capabilities
+            .iter()
+            .map(|cap| base_capability(cap).to_string())
+            .collect();
+        for cap in &caps {
+            self.agents_by_cap
+                .entry(cap.clone())
+                .or_default()
+                .insert(agent.uid.clone());
+        }
+        self.caps_by_agent.insert(agent.uid.clone(), caps);
+    }
+
+    fn remove(&mut self, uid: &str) {
+        let Some(caps) = self.caps_by_agent.remove(uid) else {
+            return;
+        };
+        for cap in caps {
+            if let Some(uids) = self.agents_by_cap.get_mut(&cap) {
+                uids.remove(uid);
+                if uids.is_empty() {
+                    self.agents_by_cap.remove(&cap);
+                }
+            }
+        }
+    }
+
+    /// Uids of agents whose advertised capabilities cover `cap`: the exact
+    /// entry plus any wildcard patterns that match it.
+    fn uids_for(&self, cap: &str) -> HashSet<String> {
+        let base = base_capability(cap);
+        let mut uids = self.agents_by_cap.get(base).cloned().unwrap_or_default();
+        for (pattern, agents) in &self.agents_by_cap {
+            if pattern.ends_with('*') && capability_matches(pattern, base) {
+                uids.extend(agents.iter().cloned());
+            }
+        }
+        uids
+    }
+}
+
 pub struct AgentStorage {
     pub db: sled::Db,
+    /// Issued refresh token ids (`jti` -> agent uid). Removing an entry revokes
+    /// the refresh token.
+    refresh_tokens: sled::Tree,
+    /// Agent uid -> unix seconds of the last session revocation. Access tokens
+    /// issued at or before that time are rejected.
+    revoked_sessions: sled::Tree,
+    /// Seconds since last contact after which an agent counts as offline.
+    online_timeout_secs: i64,
+    /// Rebuilt from the tree on open and kept in step with every write that
+    /// can change an agent's capabilities.
+    capability_index: RwLock<CapabilityIndex>,
 }
 
 impl AgentStorage {
     pub fn new(path: &str) -> sled::Result<Self> {
-        Ok(Self {
-            db: sled::open(path)?,
-        })
+        Self::from_db(sled::open(path)?)
+    }
+
+    pub(crate) fn from_db(db: sled::Db) -> sled::Result<Self> {
+        let refresh_tokens = db.open_tree("refresh_tokens")?;
+        let revoked_sessions = db.open_tree("revoked_sessions")?;
+        let storage = Self {
+            db,
+            refresh_tokens,
+            revoked_sessions,
+            online_timeout_secs: Agent::DEFAULT_ONLINE_TIMEOUT_SECS,
+            capability_index: RwLock::new(CapabilityIndex::default()),
+        };
+        storage.rebuild_capability_index();
+        Ok(storage)
+    }
+
+    fn rebuild_capability_index(&self) {
+        let mut index = CapabilityIndex::default();
+        for agent in self.list_all_agents() {
+            index.insert(&agent);
+        }
+        *self.capability_index.write().unwrap() = index;
+    }
+
+    /// Registered agents (online or not) advertising `cap`, in uid order like
+    /// [`Self::list_all_agents`], looked up through the capability index.
+    pub fn agents_serving(&self, cap: &str) -> Vec<Agent> {
+        let mut uids: Vec<String> = self
+            .capability_index
+            .read()
+            .unwrap()
+            .uids_for(cap)
+            .into_iter()
+            .collect();
+        uids.sort_unstable();
+        uids.iter().filter_map(|uid| self.get_agent(uid)).collect()
+    }
+
+    /// Registered agents (online or not) that can run `task`, in uid order:
+    /// those serving its capability set under its match mode.
+    pub fn agents_serving_task(&self, task: &TaskSubmissionRequest) -> Vec<Agent> {
+        let mut uids: Vec<String> = {
+            let index = self.capability_index.read().unwrap();
+            task.match_capabilities()
+                .flat_map(|cap| index.uids_for(cap))
+                .collect()
+        };
+        uids.sort_unstable();
+        uids.dedup();
+        uids.iter()
+            .filter_map(|uid| self.get_agent(uid))
+            .filter(|agent| task.served_by(&agent.capabilities))
+            .collect()
+    }
+
+    /// Use `secs` instead of the default online timeout.
+    pub fn with_online_timeout(mut self, secs: i64) -> Self {
+        self.online_timeout_secs = secs;
+        self
+    }
+
+    /// Whether `agent` has been in contact within the configured online timeout.
+    pub fn is_online(&self, agent: &Agent) -> bool {
+        agent.is_online(self.online_timeout_secs)
+    }
+
+    /// Read the agents tree and write a throwaway key, for `/health/ready`.
+    pub fn probe(&self) -> sled::Result<()> {
+        self.db.first()?;
+        let probe = self.db.open_tree("health_probe")?;
+        probe.insert(b"probe", &[])?;
+        probe.remove(b"probe")?;
+        Ok(())
+    }
+
+    /// Wait until every write so far is durable on disk.
+    pub async fn flush(&self) -> sled::Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    pub fn store_refresh_token(&self, jti: &str, agent_id: &str) -> sled::Result<()> {
+        self.refresh_tokens
+            .insert(jti.as_bytes(), agent_id.as_bytes())?;
+        Ok(())
+    }
+
+    /// True if the refresh token id is still stored for this agent.
+    pub fn is_refresh_token_valid(&self, jti: &str, agent_id: &str) -> bool {
+        matches!(
+            self.refresh_tokens.get(jti.as_bytes()),
+            Ok(Some(owner)) if owner.as_ref() == agent_id.as_bytes()
+        )
+    }
+
+    /// Drop stored refresh token ids issued before `cutoff`, which have expired
+    /// by now. Ids are ULIDs, so they sort by issue time. Returns how many
+    /// were dropped.
+    pub fn prune_refresh_tokens_issued_before(&self, cutoff: DateTime<Utc>) -> sled::Result<usize> {
+        let upper = Ulid::from_parts(cutoff.timestamp_millis().max(0) as u64, 0).to_string();
+        let mut batch = sled::Batch::default();
+        let mut pruned = 0;
+        for item in self.refresh_tokens.range(..upper.as_bytes()) {
+            let (jti, _) = item?;
+            batch.remove(jti);
+            pruned += 1;
+        }
+        self.refresh_tokens.apply_batch(batch)?;
+        Ok(pruned)
+    }
+
+    /// Revoke every refresh token issued to the agent. Returns how many were revoked.
+    pub fn revoke_refresh_tokens(&self, agent_id: &str) -> sled::Result<usize> {
+        let mut revoked = 0;
+        for item in self.refresh_tokens.iter() {
+            let (jti, owner) = item?;
+            if owner.as_ref() == agent_id.as_bytes() {
+                self.refresh_tokens.remove(jti)?;
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+
+    /// Invalidate every token issued to the agent so far: access tokens via
+    /// the revocation time, refresh tokens by removing them. Returns how many
+    /// refresh tokens were revoked.
+    pub fn revoke_sessions(&self, agent_id: &str) -> sled::Result<usize> {
+        let now = Utc::now().timestamp_millis().max(0) as u64;
+        self.revoked_sessions
+            .insert(agent_id.as_bytes(), &now.to_be_bytes())?;
+        self.revoke_refresh_tokens(agent_id)
+    }
+
+    /// True if a token for `agent_id` issued at `issued_at_ms` (unix
+    /// milliseconds) has been revoked. Tokens without an issue time predate
+    /// revocation support and are treated as revoked once the agent's
+    /// sessions are.
+    pub fn is_session_revoked(&self, agent_id: &str, issued_at_ms: Option<u64>) -> bool {
+        let Ok(Some(raw)) = self.revoked_sessions.get(agent_id.as_bytes()) else {
+            return false;
+        };
+        let Ok(bytes) = <[u8; 8]>::try_from(raw.as_ref()) else {
+            return false;
+        };
+        let revoked_at_ms = u64::from_be_bytes(bytes);
+        issued_at_ms.is_none_or(|iat| iat <= revoked_at_ms)
     }
 
     fn generate_unique_uid(&self) -> String {
@@ -30,21 +274,42 @@ impl AgentStorage {
         }
     }
 
-    pub async fn create_agent(&self, agent: &mut Agent) -> sled::Result<()> {
-        if agent.uid.is_empty() || self.get_agent(&agent.uid).is_some() {
+    /// Persist a new agent. An empty uid is always replaced by a generated one.
+    /// A uid that is already taken either fails with `Conflict`
+    /// ([`DuplicateAgentUidPolicy::Reject`]) or is replaced as well
+    /// ([`DuplicateAgentUidPolicy::Regenerate`]).
+    pub async fn create_agent(
+        &self,
+        agent: &mut Agent,
+        on_duplicate: DuplicateAgentUidPolicy,
+    ) -> Result<(), AppError> {
+        let taken = !agent.uid.is_empty() && self.db.contains_key(agent.uid.as_bytes())?;
+        if agent.uid.is_empty() || (taken && on_duplicate == DuplicateAgentUidPolicy::Regenerate) {
             agent.uid = self.generate_unique_uid();
             agent.uid_short = agent.uid.chars().take(8).collect();
+        } else if taken {
+            return Err(AppError::Conflict(format!(
+                "Agent uid {} is already registered",
+                agent.uid
+            )));
         }
 
         let id = agent.uid.clone();
-        let data = to_vec_named(&agent).map_err(|e| {
-            sled::Error::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Serialization error: {}", e),
-            ))
-        })?;
+        let data = versioned::encode(&*agent)?;
 
-        self.db.insert(id.as_bytes(), data)?;
+        // Insert only if still absent so two concurrent registrations can't
+        // both claim the same requested uid.
+        let swapped = retry_transient("create_agent", || {
+            self.db
+                .compare_and_swap(id.as_bytes(), None as Option<&[u8]>, Some(data.clone()))
+        })?;
+        if swapped.is_err() {
+            return Err(AppError::Conflict(format!(
+                "Agent uid {} is already registered",
+                id
+            )));
+        }
+        self.capability_index.write().unwrap().insert(agent);
         self.db.flush_async().await?;
 
         info!("Created agent {:?}", agent);
@@ -53,49 +318,135 @@ impl AgentStorage {
 
     pub fn get_agent(&self, id: &str) -> Option<Agent> {
         let data = self.db.get(id.as_bytes()).ok()??;
-        from_slice::<Agent>(&data).ok()
+        versioned::decode_or_log::<Agent>(id.as_bytes(), &data)
     }
 
     pub async fn update_agent_last_contact(
         &self,
         mut agent: Agent,
         method: CommunicationMethod,
-    ) -> Result<Agent, sled::Error> {
+    ) -> Result<Agent, AppError> {
         agent.last_contact = Some(Utc::now());
         agent.last_comm_method = method;
         self.update_agent(agent.clone()).await.map(|()| agent)
     }
 
-    pub async fn update_agent(&self, agent: Agent) -> sled::Result<()> {
+    /// Stamp the agent's last contact in one atomic read-modify-write, without
+    /// a separate lookup. Returns the updated record, or `None` if the agent
+    /// does not exist.
+    pub async fn touch_last_contact(
+        &self,
+        id: &str,
+        method: CommunicationMethod,
+    ) -> sled::Result<Option<Agent>> {
+        let updated = self.db.update_and_fetch(id.as_bytes(), |old| {
+            let old = old?;
+            let Some(mut agent) = versioned::decode_or_log::<Agent>(id.as_bytes(), old) else {
+                return Some(old.to_vec());
+            };
+            agent.last_contact = Some(Utc::now());
+            agent.last_comm_method = method.clone();
+            Some(versioned::encode(&agent).unwrap_or_else(|_| old.to_vec()))
+        })?;
+        let Some(data) = updated else {
+            return Ok(None);
+        };
+        self.db.flush_async().await?;
+        Ok(versioned::decode_or_log::<Agent>(id.as_bytes(), &data))
+    }
+
+    /// Replace the agent record. Quarantine and failure counters are
+    /// server-owned and only changed through [`Self::set_quarantine`] and
+    /// [`Self::record_failure`], so the stored values are carried over in the
+    /// same atomic read-modify-write. Fails with [`AppError::NotFound`] if the
+    /// agent is not registered.
+    pub async fn update_agent(&self, agent: Agent) -> Result<(), AppError> {
         let id = agent.uid.clone();
 
-        if self.get_agent(&id).is_none() {
-            return Err(sled::Error::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("Agent {} not found", id),
-            )));
+        let mut stored_capabilities = None;
+        let updated = retry_transient("update_agent", || {
+            self.db.update_and_fetch(id.as_bytes(), |old| {
+                let old = old?;
+                let Some(stored) = versioned::decode_or_log::<Agent>(id.as_bytes(), old) else {
+                    stored_capabilities = None;
+                    return Some(old.to_vec());
+                };
+                let merged = Agent {
+                    quarantined_until: stored.quarantined_until,
+                    quarantine_reason: stored.quarantine_reason,
+                    recent_failures: stored.recent_failures,
+                    last_error: stored.last_error,
+                    last_error_at: stored.last_error_at,
+                    ..agent.clone()
+                };
+                stored_capabilities = Some(stored.capabilities);
+                Some(versioned::encode(&merged).unwrap_or_else(|_| old.to_vec()))
+            })
+        })?;
+        let (Some(_), Some(stored_capabilities)) = (updated, stored_capabilities) else {
+            return Err(AppError::NotFound(format!("Agent {} not found", id)));
+        };
+        if stored_capabilities != agent.capabilities {
+            self.capability_index.write().unwrap().insert(&agent);
         }
+        self.db.flush_async().await?;
+        Ok(())
+    }
 
-        let data = to_vec_named(&agent).map_err(|e| {
-            sled::Error::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Serialization error: {}", e),
-            ))
+    /// Set (or with `None`, clear) the agent's quarantine in one atomic
+    /// read-modify-write. Returns the updated record, or `None` if the agent
+    /// does not exist.
+    pub async fn set_quarantine(
+        &self,
+        id: &str,
+        until: Option<DateTime<Utc>>,
+        reason: Option<String>,
+    ) -> sled::Result<Option<Agent>> {
+        let updated = self.db.update_and_fetch(id.as_bytes(), |old| {
+            let old = old?;
+            let Some(mut agent) = versioned::decode_or_log::<Agent>(id.as_bytes(), old) else {
+                return Some(old.to_vec());
+            };
+            agent.quarantined_until = until;
+            agent.quarantine_reason = reason.clone();
+            Some(versioned::encode(&agent).unwrap_or_else(|_| old.to_vec()))
         })?;
-
-        self.db.insert(id.as_bytes(), data)?;
+        let Some(data) = updated else {
+            return Ok(None);
+        };
         self.db.flush_async().await?;
-        Ok(())
+        Ok(versioned::decode_or_log::<Agent>(id.as_bytes(), &data))
+    }
+
+    /// Count a failed task report against the agent in one atomic
+    /// read-modify-write. Returns the updated record, or `None` if the agent
+    /// does not exist.
+    pub fn record_failure(&self, id: &str, error: &str) -> sled::Result<Option<Agent>> {
+        let now = Utc::now();
+        let updated = self.db.update_and_fetch(id.as_bytes(), |old| {
+            let old = old?;
+            let Some(mut agent) = versioned::decode_or_log::<Agent>(id.as_bytes(), old) else {
+                return Some(old.to_vec());
+            };
+            agent.note_failure(error, now);
+            Some(versioned::encode(&agent).unwrap_or_else(|_| old.to_vec()))
+        })?;
+        Ok(updated.and_then(|data| versioned::decode_or_log::<Agent>(id.as_bytes(), &data)))
     }
 
     pub async fn delete_agent(&self, id: &str) -> sled::Result<()> {
         self.db.remove(id.as_bytes())?;
+        self.capability_index.write().unwrap().remove(id);
+        self.revoke_refresh_tokens(id)?;
         self.db.flush_async().await?;
         Ok(())
     }
 
     pub fn clear(&self) -> Result<(), AppError> {
         self.db.clear()?;
+        self.refresh_tokens.clear()?;
+        self.revoked_sessions.clear()?;
+        *self.capability_index.write().unwrap() = CapabilityIndex::default();
         Ok(())
     }
 
@@ -103,16 +454,45 @@ impl AgentStorage {
         let mut agents = Vec::new();
 
         for item in self.db.iter() {
-            if let Ok((_, v)) = item {
-                if let Ok(agent) = from_slice::<Agent>(&v) {
-                    agents.push(agent);
-                }
+            match item {
+                Ok((k, v)) => agents.extend(versioned::decode_or_log::<Agent>(&k, &v)),
+                Err(e) => warn!("Failed to read agent record: {}", e),
             }
         }
 
         agents
     }
 
+    /// Matching agents in key order, skipping `offset` and keeping at most
+    /// `limit`. Records are decoded one at a time from the tree iterator, so
+    /// only the returned window is held in memory.
+    pub fn list_agents_paged(
+        &self,
+        limit: usize,
+        offset: usize,
+        filter: &AgentListFilter,
+    ) -> AgentPage {
+        let mut items = Vec::new();
+        let mut total = 0;
+        for item in self.db.iter() {
+            let agent = match item {
+                Ok((k, v)) => versioned::decode_or_log::<Agent>(&k, &v),
+                Err(e) => {
+                    warn!("Failed to read agent record: {}", e);
+                    None
+                }
+            };
+            let Some(agent) = agent.filter(|a| filter.matches(a, self.online_timeout_secs)) else {
+                continue;
+            };
+            if total >= offset && items.len() < limit {
+                items.push(agent);
+            }
+            total += 1;
+        }
+        AgentPage { items, total }
+    }
+
     pub fn agent_count(&self) -> usize {
         self.db.len()
     }
@@ -121,7 +501,7 @@ impl AgentStorage {
         let agents: Vec<_> = self
             .list_all_agents()
             .into_iter()
-            .filter(|agent| agent.is_online())
+            .filter(|agent| self.is_online(agent))
             .collect();
         info!("Online agents: ");
         for agent in agents {
@@ -130,30 +510,286 @@ impl AgentStorage {
     }
 
     pub async fn cleanup_stale_agents(&self, ttl_days: u32) -> Result<usize, sled::Error> {
-        let mut deleted = 0usize;
-        let now = Utc::now();
         let ttl_secs = ttl_days as i64 * 24 * 60 * 60;
+        let pruned = self
+            .prune_stale(Utc::now() - TimeDelta::seconds(ttl_secs))
+            .await?;
+        Ok(pruned.len())
+    }
 
-        let agents_to_delete: Vec<String> = self
-            .list_all_agents()
+    /// Uids of every agent last heard from before `older_than`; agents that
+    /// never made contact count from their registration.
+    pub fn stale_agent_ids(&self, older_than: DateTime<Utc>) -> Vec<String> {
+        self.list_all_agents()
             .into_iter()
-            .filter_map(|agent| {
-                let age_secs = now
-                    .signed_duration_since(agent.last_activity_at())
-                    .num_seconds();
-                if age_secs > ttl_secs {
-                    Some(agent.uid)
-                } else {
-                    None
+            .filter(|agent| agent.last_activity_at() < older_than)
+            .map(|agent| agent.uid)
+            .collect()
+    }
+
+    /// Delete every agent [`stale_agent_ids`](Self::stale_agent_ids) returns,
+    /// along with their refresh tokens and capability index entries. Returns
+    /// the deleted uids. Tasks the agents hold are left to the caller.
+    pub async fn prune_stale(&self, older_than: DateTime<Utc>) -> sled::Result<Vec<String>> {
+        let stale = self.stale_agent_ids(older_than);
+        for agent_id in &stale {
+            self.delete_agent(agent_id).await?;
+        }
+        Ok(stale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    async fn add_agent(storage: &AgentStorage, uid: &str, cap: &str, online: bool) {
+        let last_contact = if online {
+            Utc::now()
+        } else {
+            Utc::now() - TimeDelta::hours(1)
+        };
+        let mut agent = Agent::for_test(uid, &[cap]);
+        agent.registered_at = last_contact;
+        agent.last_contact = Some(last_contact);
+        storage
+            .db
+            .insert(uid.as_bytes(), versioned::encode(&agent).unwrap())
+            .unwrap();
+        storage.capability_index.write().unwrap().insert(&agent);
+    }
+
+    #[tokio::test]
+    async fn prune_removes_only_agents_silent_past_the_threshold() {
+        let storage =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        add_agent(&storage, "fresh", "debug.echo", true).await;
+        add_agent(&storage, "stale", "debug.echo", false).await;
+        // Registered long ago and never heard from since.
+        let mut never = Agent::for_test("never", &["debug.echo"]);
+        never.registered_at = Utc::now() - TimeDelta::days(3);
+        never.last_contact = None;
+        storage
+            .db
+            .insert("never".as_bytes(), versioned::encode(&never).unwrap())
+            .unwrap();
+
+        let mut pruned = storage
+            .prune_stale(Utc::now() - TimeDelta::minutes(30))
+            .await
+            .unwrap();
+        pruned.sort();
+        assert_eq!(pruned, vec!["never".to_string(), "stale".to_string()]);
+        assert!(storage.get_agent("fresh").is_some());
+        assert!(storage.get_agent("stale").is_none());
+        let serving: Vec<String> = storage
+            .agents_serving("debug.echo")
+            .into_iter()
+            .map(|agent| agent.uid)
+            .collect();
+        assert_eq!(serving, vec!["fresh".to_string()]);
+    }
+
+    #[test]
+    fn only_refresh_tokens_issued_before_the_cutoff_are_pruned() {
+        let storage =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let expired = Ulid::from_parts(
+            (Utc::now() - TimeDelta::days(31)).timestamp_millis() as u64,
+            0,
+        )
+        .to_string();
+        let fresh = crate::utils::time_sortable_uid();
+        storage.store_refresh_token(&expired, "agent-a").unwrap();
+        storage.store_refresh_token(&fresh, "agent-a").unwrap();
+
+        let cutoff = Utc::now() - TimeDelta::days(30);
+        assert_eq!(
+            storage.prune_refresh_tokens_issued_before(cutoff).unwrap(),
+            1
+        );
+        assert!(!storage.is_refresh_token_valid(&expired, "agent-a"));
+        assert!(storage.is_refresh_token_valid(&fresh, "agent-a"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn failures_recorded_during_agent_updates_are_all_counted() {
+        let storage = std::sync::Arc::new(
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap(),
+        );
+        add_agent(&storage, "flaky", "debug.echo", true).await;
+        let snapshot = storage.get_agent("flaky").unwrap();
+
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let reporter = std::thread::spawn({
+            let (storage, done) = (storage.clone(), done.clone());
+            move || {
+                for _ in 0..2000 {
+                    storage.record_failure("flaky", "out of memory").unwrap();
                 }
+                done.store(true, std::sync::atomic::Ordering::Relaxed);
+            }
+        });
+        // Logins and info updates keep writing the stale snapshot meanwhile.
+        let updaters: Vec<_> = (0..4)
+            .map(|_| {
+                let (storage, snapshot, done) = (storage.clone(), snapshot.clone(), done.clone());
+                tokio::spawn(async move {
+                    while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                        storage.update_agent(snapshot.clone()).await.unwrap();
+                    }
+                })
             })
             .collect();
-
-        for agent_id in agents_to_delete {
-            self.delete_agent(&agent_id).await?;
-            deleted += 1;
+        reporter.join().unwrap();
+        for updater in updaters {
+            updater.await.unwrap();
         }
 
-        Ok(deleted)
+        assert_eq!(storage.get_agent("flaky").unwrap().recent_failures, 2000);
+    }
+
+    #[tokio::test]
+    async fn failed_reports_are_counted_and_decay() {
+        let storage =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        add_agent(&storage, "flaky", "debug.echo", true).await;
+
+        storage.record_failure("flaky", "out of memory").unwrap();
+        let agent = storage
+            .record_failure("flaky", "CUDA error")
+            .unwrap()
+            .unwrap();
+        assert_eq!(agent.recent_failures, 2);
+        assert_eq!(agent.last_error.as_deref(), Some("CUDA error"));
+
+        // Agent-sent updates don't reset the server-owned counters.
+        storage
+            .update_agent(Agent {
+                recent_failures: 0,
+                last_error: None,
+                ..agent.clone()
+            })
+            .await
+            .unwrap();
+        let stored = storage.get_agent("flaky").unwrap();
+        assert_eq!(stored.recent_failures, 2);
+
+        let at = stored.last_error_at.unwrap();
+        assert_eq!(stored.recent_failures_at(at + TimeDelta::minutes(59)), 2);
+        assert_eq!(stored.recent_failures_at(at + TimeDelta::hours(1)), 1);
+        assert_eq!(stored.recent_failures_at(at + TimeDelta::hours(2)), 0);
+        assert!(storage.record_failure("missing", "boom").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn updating_missing_agent_is_not_found() {
+        let storage =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        add_agent(&storage, "a1", "debug.echo", true).await;
+        let mut agent = storage.get_agent("a1").unwrap();
+        agent.uid = "missing".to_string();
+
+        let err = storage
+            .update_agent_last_contact(agent, CommunicationMethod::Http)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code_number(), 404);
+        assert_eq!(err.to_error_json()["error"]["type"], "not_found");
+        assert!(storage.get_agent("missing").is_none());
+    }
+
+    #[tokio::test]
+    async fn capability_index_follows_register_update_and_delete() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let storage = AgentStorage::from_db(db.clone()).unwrap();
+        let uids = |storage: &AgentStorage, cap: &str| {
+            storage
+                .agents_serving(cap)
+                .into_iter()
+                .map(|a| a.uid)
+                .collect::<Vec<_>>()
+        };
+
+        let mut agent = Agent::for_test("a1", &["llm.mistral[gpu]"]);
+        storage
+            .create_agent(&mut agent, DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        add_agent(&storage, "a2", "llm.*", false).await;
+        assert_eq!(uids(&storage, "llm.mistral"), vec!["a1", "a2"]);
+        assert!(uids(&storage, "debug.echo").is_empty());
+
+        agent.capabilities = vec!["debug.echo".to_string()];
+        storage.update_agent(agent).await.unwrap();
+        assert_eq!(uids(&storage, "llm.mistral"), vec!["a2"]);
+        assert_eq!(uids(&storage, "debug.echo"), vec!["a1"]);
+
+        // Reopening rebuilds the index from the stored records.
+        let reopened = AgentStorage::from_db(db).unwrap();
+        assert_eq!(uids(&reopened, "debug.echo"), vec!["a1"]);
+        assert_eq!(uids(&reopened, "llm.qwen"), vec!["a2"]);
+
+        storage.delete_agent("a1").await.unwrap();
+        assert!(uids(&storage, "debug.echo").is_empty());
+        assert_eq!(uids(&storage, "llm.mistral"), vec!["a2"]);
+    }
+
+    #[tokio::test]
+    async fn online_timeout_is_configurable() {
+        let storage =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        add_agent(&storage, "a1", "debug.echo", true).await;
+        let mut agent = storage.get_agent("a1").unwrap();
+        agent.last_contact = Some(Utc::now() - TimeDelta::seconds(200));
+        assert!(!agent.is_online(120));
+        assert!(agent.is_online(300));
+
+        assert!(!storage.is_online(&agent));
+        let storage = storage.with_online_timeout(300);
+        assert!(storage.is_online(&agent));
+        assert!(!storage.with_online_timeout(10).is_online(&agent));
+    }
+
+    #[tokio::test]
+    async fn paged_listing_windows_and_filters() {
+        let storage =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        add_agent(&storage, "a1", "llm.mistral", true).await;
+        add_agent(&storage, "a2", "debug.echo", false).await;
+        add_agent(&storage, "a3", "llm.mistral[gpu]", true).await;
+        add_agent(&storage, "a4", "debug.echo", true).await;
+        add_agent(&storage, "a5", "debug.echo", false).await;
+
+        let all = AgentListFilter::default();
+        let uids = |page: &AgentPage| page.items.iter().map(|a| a.uid.clone()).collect::<Vec<_>>();
+        let page = storage.list_agents_paged(2, 0, &all);
+        assert_eq!(
+            (uids(&page), page.total),
+            (vec!["a1".into(), "a2".into()], 5)
+        );
+        let page = storage.list_agents_paged(2, 4, &all);
+        assert_eq!((uids(&page), page.total), (vec!["a5".to_string()], 5));
+
+        let online = AgentListFilter {
+            online_only: true,
+            ..Default::default()
+        };
+        let page = storage.list_agents_paged(10, 1, &online);
+        assert_eq!(
+            (uids(&page), page.total),
+            (vec!["a3".into(), "a4".into()], 3)
+        );
+
+        let mistral_online = AgentListFilter {
+            online_only: true,
+            capability: Some("llm.mistral".to_string()),
+        };
+        let page = storage.list_agents_paged(10, 0, &mistral_online);
+        assert_eq!(
+            (uids(&page), page.total),
+            (vec!["a1".into(), "a3".into()], 2)
+        );
     }
 }
@@ -73,7 +73,7 @@ pub struct AgentLogRecord {
 ///   currently iterate the full tree (one severity at a time would not be a
 ///   strict subset of any per-severity prefix because the agent_id is below).
 pub struct AgentLogStorage {
-    _db: Db,
+    db: Db,
     tree: sled::Tree,
 }
 
@@ -81,7 +81,13 @@ impl AgentLogStorage {
     pub fn open(path: &str) -> Result<Self> {
         let db = sled::open(path)?;
         let tree = db.open_tree("agent_logs")?;
-        Ok(Self { _db: db, tree })
+        Ok(Self { db, tree })
+    }
+
+    /// Wait until every write so far is durable on disk.
+    pub async fn flush(&self) -> Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
     }
 
     fn build_key(severity: LogSeverity, agent_id: &str, record_id: &str) -> String {
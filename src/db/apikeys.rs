@@ -2,10 +2,14 @@ use anyhow::Result;
 use chrono::Utc;
 use sled::Db;
 
-use crate::{error::AppError, models::ClientApiKey};
+use log::warn;
+
+use crate::{
+    db::versioned, error::AppError, models::ClientApiKey, mq::capability::capability_matches,
+};
 
 pub struct ApiKeysStorage {
-    _db: Db,
+    db: Db,
     active: sled::Tree,
     archived: sled::Tree,
 }
@@ -18,17 +22,23 @@ impl ApiKeysStorage {
         let archived = db.open_tree("api_keys_archived")?;
 
         Ok(Self {
-            _db: db,
+            db,
             active,
             archived,
         })
     }
 
+    /// Wait until every write so far is durable on disk.
+    pub async fn flush(&self) -> Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
     /// Get an active key by id
     pub fn find_active(&self, id: &str) -> Result<Option<ClientApiKey>> {
         let key = id;
         if let Some(value) = self.active.get(key.as_bytes())? {
-            Ok(Some(rmp_serde::from_slice(&value)?))
+            Ok(Some(versioned::decode(&value)?))
         } else {
             Ok(None)
         }
@@ -57,10 +67,9 @@ impl ApiKeysStorage {
 
         // Get all agents from database (cache might not have all due to TTL)
         for item in self.active.iter() {
-            if let Ok((_, v)) = item {
-                if let Ok(k) = rmp_serde::from_slice::<ClientApiKey>(&v) {
-                    keys.push(k);
-                }
+            match item {
+                Ok((k, v)) => keys.extend(versioned::decode_or_log::<ClientApiKey>(&k, &v)),
+                Err(e) => warn!("Failed to read API key record: {}", e),
             }
         }
 
@@ -69,27 +78,14 @@ impl ApiKeysStorage {
 
     /// Check if the given capability is allowed by the key's capabilities (supporting wildcards)
     pub fn has_capability(key_capabilities: &[String], required_cap: &str) -> bool {
-        for cap in key_capabilities {
-            if cap == "*" {
-                // Universal wildcard allows everything
-                return true;
-            } else if cap.ends_with("*") {
-                // Prefix wildcard - check if required capability starts with the prefix
-                let prefix = &cap[..cap.len() - 1];
-                if required_cap.starts_with(prefix) {
-                    return true;
-                }
-            } else if cap == required_cap {
-                // Exact match
-                return true;
-            }
-        }
-        false
+        key_capabilities
+            .iter()
+            .any(|cap| capability_matches(cap, required_cap))
     }
 
     /// Upsert (insert or update) an API key in the active storage
     pub fn upsert_key(&self, id: &str, key: &ClientApiKey) -> Result<()> {
-        let serialized = rmp_serde::to_vec(key)?;
+        let serialized = versioned::encode(key)?;
         self.active.insert(id.as_bytes(), serialized)?;
         Ok(())
     }
@@ -99,7 +95,7 @@ impl ApiKeysStorage {
         // Check if the key is being revoked
         if key.is_revoked {
             // Serialize the key for archiving
-            let serialized = rmp_serde::to_vec(key)?;
+            let serialized = versioned::encode(key)?;
 
             // Move to archived storage
             self.archived.insert(id.as_bytes(), serialized)?;
@@ -108,7 +104,7 @@ impl ApiKeysStorage {
             self.active.remove(id.as_bytes())?;
         } else {
             // Key is still active, update in active storage
-            let serialized = rmp_serde::to_vec(key)?;
+            let serialized = versioned::encode(key)?;
             self.active.insert(id.as_bytes(), serialized)?;
         }
         Ok(())
@@ -1,17 +1,20 @@
 use std::{path::PathBuf, sync::Arc};
 
 use crate::{
-    config::StorageConfig,
+    config::{DuplicateAgentUidPolicy, StorageConfig},
     db::{
         agent::AgentStorage, agent_log_storage::AgentLogStorage, apikeys::ApiKeysStorage,
-        bucket_storage::BucketStorage, heuristic_storage::HeuristicStorage,
-        persistent_task_storage::TaskStorage, service_message_storage::ServiceMessageStorage,
+        bucket_storage::BucketStorage, capability_registry::CapabilityRegistry,
+        heuristic_storage::HeuristicStorage, persistent_task_storage::TaskStorage,
+        service_message_storage::ServiceMessageStorage, urgent_audit::UrgentAuditStorage,
     },
+    error::AppError,
     models::Agent,
     storage::FileStore,
 };
 
-// Composite storage for agents, tasks, keys, file buckets, heuristics, and service messages
+// Composite storage for agents, tasks, keys, file buckets, heuristics, service messages,
+// the capability registry and the urgent task audit trail
 #[derive(Clone)]
 pub struct AppStorage {
     pub agents: Arc<AgentStorage>,
@@ -22,10 +25,16 @@ pub struct AppStorage {
     pub heuristics: Arc<HeuristicStorage>,
     pub service_messages: Arc<ServiceMessageStorage>,
     pub agent_logs: Arc<AgentLogStorage>,
+    pub capabilities: Arc<CapabilityRegistry>,
+    pub urgent_audit: Arc<UrgentAuditStorage>,
 }
 
 impl AppStorage {
-    pub fn new(base_path: &str, storage_config: &StorageConfig) -> anyhow::Result<Self> {
+    pub fn new(
+        base_path: &str,
+        storage_config: &StorageConfig,
+        agent_online_timeout_secs: u64,
+    ) -> anyhow::Result<Self> {
         let mut agents_path = PathBuf::from(base_path);
         agents_path.push("agents");
 
@@ -47,9 +56,18 @@ impl AppStorage {
         let mut agent_logs_path = PathBuf::from(base_path);
         agent_logs_path.push("agent_logs");
 
+        let mut capabilities_path = PathBuf::from(base_path);
+        capabilities_path.push("capabilities");
+
+        let mut urgent_audit_path = PathBuf::from(base_path);
+        urgent_audit_path.push("urgent_audit");
+
         std::fs::create_dir_all(base_path)?;
 
-        let agents = Arc::new(AgentStorage::new(agents_path.to_str().unwrap())?);
+        let agents = Arc::new(
+            AgentStorage::new(agents_path.to_str().unwrap())?
+                .with_online_timeout(agent_online_timeout_secs as i64),
+        );
 
         let tasks = Arc::new(TaskStorage::open(tasks_path.to_str().unwrap())?);
         let client_keys = Arc::new(ApiKeysStorage::open(client_keys_path.to_str().unwrap())?);
@@ -60,6 +78,12 @@ impl AppStorage {
             service_messages_path.to_str().unwrap(),
         )?);
         let agent_logs = Arc::new(AgentLogStorage::open(agent_logs_path.to_str().unwrap())?);
+        let capabilities = Arc::new(CapabilityRegistry::open(
+            capabilities_path.to_str().unwrap(),
+        )?);
+        let urgent_audit = Arc::new(UrgentAuditStorage::open(
+            urgent_audit_path.to_str().unwrap(),
+        )?);
 
         Ok(Self {
             agents,
@@ -70,24 +94,58 @@ impl AppStorage {
             heuristics,
             service_messages,
             agent_logs,
+            capabilities,
+            urgent_audit,
         })
     }
 
     pub fn agent_count(&self) -> usize {
         self.agents.agent_count()
     }
+
+    /// Flush every database to disk. Called after the server stopped; a
+    /// failing store does not keep the others from being flushed.
+    pub async fn flush_all(&self) -> anyhow::Result<()> {
+        let results = [
+            (
+                "agents",
+                self.agents.flush().await.map_err(anyhow::Error::from),
+            ),
+            ("tasks", self.tasks.flush().await),
+            ("client_api_keys", self.client_keys.flush().await),
+            ("buckets", self.buckets.flush().await),
+            ("heuristics", self.heuristics.flush().await),
+            ("service_messages", self.service_messages.flush().await),
+            ("agent_logs", self.agent_logs.flush().await),
+            ("capabilities", self.capabilities.flush().await),
+            ("urgent_audit", self.urgent_audit.flush().await),
+        ];
+        let failed: Vec<String> = results
+            .into_iter()
+            .filter_map(|(name, result)| result.err().map(|e| format!("{}: {}", name, e)))
+            .collect();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Failed to flush {}", failed.join("; ")))
+        }
+    }
 }
 
 impl AppStorage {
-    pub async fn create_agent(&self, agent: &mut Agent) -> sled::Result<()> {
-        self.agents.create_agent(agent).await
+    pub async fn create_agent(
+        &self,
+        agent: &mut Agent,
+        on_duplicate: DuplicateAgentUidPolicy,
+    ) -> Result<(), AppError> {
+        self.agents.create_agent(agent, on_duplicate).await
     }
 
     pub fn get_agent(&self, id: &str) -> Option<Agent> {
         self.agents.get_agent(id)
     }
 
-    pub async fn update_agent(&self, agent: Agent) -> sled::Result<()> {
+    pub async fn update_agent(&self, agent: Agent) -> Result<(), AppError> {
         self.agents.update_agent(agent).await
     }
 
@@ -95,3 +153,42 @@ impl AppStorage {
         self.agents.delete_agent(id).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    use crate::{
+        models::UnassignedTask,
+        schema::{TaskId, TaskSubmissionRequest},
+    };
+
+    #[tokio::test]
+    async fn flushed_writes_survive_reopening() {
+        let base = std::env::temp_dir().join(format!("offloadmq-flush-{}", uuid::Uuid::new_v4()));
+        let base = base.to_str().unwrap().to_string();
+        let config = StorageConfig::from_env(&base);
+        let task = UnassignedTask {
+            id: TaskId::new_with_cap("cap".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "cap".to_string(),
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+
+        let storage = AppStorage::new(&base, &config, 120).unwrap();
+        storage.tasks.add_unassigned(&task).unwrap();
+        storage.flush_all().await.unwrap();
+        drop(storage);
+
+        let reopened = AppStorage::new(&base, &config, 120).unwrap();
+        assert!(reopened.tasks.get_unassigned(&task.id).unwrap().is_some());
+        drop(reopened);
+        let _ = std::fs::remove_dir_all(&base);
+    }
+}
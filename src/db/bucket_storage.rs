@@ -32,7 +32,7 @@ pub struct BucketMeta {
 }
 
 pub struct BucketStorage {
-    _db: sled::Db,
+    db: sled::Db,
     /// key: bucket_uid  →  value: msgpack(BucketMeta)
     buckets: sled::Tree,
     /// key: "{api_key}|{bucket_uid}"  →  value: bucket_uid bytes  (owner index)
@@ -45,12 +45,18 @@ impl BucketStorage {
         let buckets = db.open_tree("buckets")?;
         let owner_idx = db.open_tree("owner_idx")?;
         Ok(Self {
-            _db: db,
+            db,
             buckets,
             owner_idx,
         })
     }
 
+    /// Wait until every write so far is durable on disk.
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
     // ── bucket CRUD ──────────────────────────────────────────────────────────
 
     pub async fn create_bucket(
@@ -0,0 +1,77 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use log::warn;
+use sled::Db;
+
+use crate::{db::versioned, models::CapabilityDefinition};
+
+/// Operator-managed registry of the capabilities this queue supports. Keyed by
+/// base capability name (extended attributes stripped).
+pub struct CapabilityRegistry {
+    db: Db,
+    capabilities: sled::Tree,
+}
+
+impl CapabilityRegistry {
+    pub fn open(path: &str) -> Result<Self> {
+        let db = sled::open(path)?;
+        let capabilities = db.open_tree("capabilities")?;
+        Ok(Self { db, capabilities })
+    }
+
+    /// Wait until every write so far is durable on disk.
+    pub async fn flush(&self) -> Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<CapabilityDefinition>> {
+        match self.capabilities.get(name.as_bytes())? {
+            Some(value) => Ok(Some(versioned::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list_all(&self) -> Vec<CapabilityDefinition> {
+        let mut result = Vec::new();
+        for item in self.capabilities.iter() {
+            match item {
+                Ok((k, v)) => result.extend(versioned::decode_or_log(&k, &v)),
+                Err(e) => warn!("Failed to read capability record: {}", e),
+            }
+        }
+        result
+    }
+
+    /// Non-zero scheduling weights keyed by capability name. Capabilities
+    /// missing from the map have weight 0.
+    pub fn scheduling_weights(&self) -> HashMap<String, u32> {
+        self.list_all()
+            .into_iter()
+            .filter(|definition| definition.scheduling_weight > 0)
+            .map(|definition| (definition.name, definition.scheduling_weight))
+            .collect()
+    }
+
+    /// Names of registered capabilities that are deactivated.
+    pub fn inactive_names(&self) -> HashSet<String> {
+        self.list_all()
+            .into_iter()
+            .filter(|definition| !definition.active)
+            .map(|definition| definition.name)
+            .collect()
+    }
+
+    pub fn upsert(&self, definition: &CapabilityDefinition) -> Result<()> {
+        let bytes = versioned::encode(definition)?;
+        self.capabilities
+            .insert(definition.name.as_bytes(), bytes)?;
+        Ok(())
+    }
+
+    /// Remove a capability definition. Returns true if it existed.
+    pub fn delete(&self, name: &str) -> Result<bool> {
+        Ok(self.capabilities.remove(name.as_bytes())?.is_some())
+    }
+}
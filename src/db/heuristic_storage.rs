@@ -38,7 +38,7 @@ pub struct RunnerCapStats {
 /// All trees store identical data (the full HeuristicRecord); the key difference
 /// is the order of components for efficient prefix-based range queries.
 pub struct HeuristicStorage {
-    _db: Db,
+    db: Db,
     heuristics_by_cap: sled::Tree,
     heuristics_by_runner: sled::Tree,
     heuristics_by_machine: sled::Tree,
@@ -47,19 +47,28 @@ pub struct HeuristicStorage {
 impl HeuristicStorage {
     /// Open or create heuristic storage at the given path
     pub fn open(path: &str) -> Result<Self> {
-        let db = sled::open(path)?;
+        Self::from_db(sled::open(path)?)
+    }
+
+    pub(crate) fn from_db(db: Db) -> Result<Self> {
         let heuristics_by_cap = db.open_tree("heuristics_by_cap")?;
         let heuristics_by_runner = db.open_tree("heuristics_by_runner")?;
         let heuristics_by_machine = db.open_tree("heuristics_by_machine")?;
 
         Ok(Self {
-            _db: db,
+            db,
             heuristics_by_cap,
             heuristics_by_runner,
             heuristics_by_machine,
         })
     }
 
+    /// Wait until every write so far is durable on disk.
+    pub async fn flush(&self) -> Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
     /// Record a completed non-urgent task for heuristic analysis
     /// Writes to all three index trees. The machine tree is only populated when
     /// the agent reports a machine_id.
@@ -3,6 +3,11 @@ pub mod agent_log_storage;
 pub mod apikeys;
 pub mod app_storage;
 pub mod bucket_storage;
+pub mod capability_registry;
 pub mod heuristic_storage;
 pub mod persistent_task_storage;
+pub mod retry;
 pub mod service_message_storage;
+pub mod task_events;
+pub mod urgent_audit;
+pub mod versioned;
@@ -1,50 +1,280 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
+use std::sync::OnceLock;
+
 use anyhow::Result;
-use chrono::Utc;
-use log::info;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
 use sled::Db;
 use sled::Transactional;
 use sled::transaction::{TransactionError, abort};
+use tokio::sync::mpsc;
 
 use crate::{
+    db::{
+        retry::retry_transient,
+        task_events::{TaskEventLog, TaskTransition},
+        versioned,
+    },
     error::AppError,
-    models::{AssignedTask, UnassignedTask},
-    schema::{TaskId, TaskStatus},
+    models::{AssignedTask, DeadLetter, LateReport, TaskEvent, UnassignedTask, UrgentSnapshot},
+    mq::webhook::{self, WebhookDelivery},
+    schema::{TaskId, TaskStatus, TaskStatusResponse},
     utils::base_capability,
 };
 
+/// Outcome of [`TaskStorage::recover_orphaned_assigned`],
+/// [`TaskStorage::release_agent_tasks`] and [`TaskStorage::fail_past_deadline`].
+#[derive(Debug, Default)]
+pub struct OrphanRecovery {
+    /// Agent id of every recovered task, one entry per task.
+    pub agents: Vec<String>,
+    /// Restartable orphans moved back to the unassigned tree; the caller
+    /// still has to put them on the in-memory queue.
+    pub requeued: Vec<UnassignedTask>,
+    /// `(agent_id, task_id)` of tasks taken from an agent that may still be
+    /// working on them, so the caller can push it a cancel. Only the deadline
+    /// sweep fills this; the other recoveries deal with agents already gone.
+    pub interrupted: Vec<(String, TaskId)>,
+}
+
+/// Outcome of [`TaskStorage::force_archive_older_than`].
+#[derive(Debug, Default)]
+pub struct ForcedArchive {
+    /// Tasks moved to the archive tree, whatever their prior status.
+    pub archived: usize,
+    /// Queued tasks that were archived; the caller still has to drop them from
+    /// the in-memory queue.
+    pub dequeued: Vec<TaskId>,
+    /// `(agent_id, task_id)` of every task force-failed while an agent held
+    /// it, so the caller can tell the agent to stop.
+    pub interrupted: Vec<(String, TaskId)>,
+}
+
+/// Narrows [`TaskStorage::list_for_api_key`].
+#[derive(Debug, Default)]
+pub struct TaskListFilter {
+    /// Base capability; extended attributes on either side are ignored.
+    pub capability: Option<String>,
+    pub status: Option<TaskStatus>,
+}
+
+impl TaskListFilter {
+    fn matches(&self, report: &TaskStatusResponse) -> bool {
+        self.capability
+            .as_deref()
+            .is_none_or(|cap| base_capability(&report.id.cap) == base_capability(cap))
+            && self
+                .status
+                .as_ref()
+                .is_none_or(|status| *status == report.status)
+    }
+}
+
 pub struct TaskStorage {
-    _db: Db,
+    db: Db,
     unassigned: sled::Tree,
     assigned: sled::Tree,
     archived: sled::Tree,
+    /// Index of client-chosen task ids: "api_key|client_task_id" -> "capability|uuid"
+    client_ids: sled::Tree,
+    /// Reports for tasks that were no longer assigned: "record_id" -> LateReport
+    late_reports: sled::Tree,
+    /// Permanently failed tasks: "capability|uuid" -> DeadLetter
+    dead_letter: sled::Tree,
+    /// Live urgent tasks, written through by the urgent store when
+    /// `URGENT_PERSIST` is on: "capability|uuid" -> UrgentSnapshot
+    urgent: sled::Tree,
+    /// Status transitions of every task, regular and urgent.
+    events: TaskEventLog,
+    /// Webhook queue for completion callbacks; unset only in tests.
+    callbacks: OnceLock<mpsc::Sender<WebhookDelivery>>,
 }
 
 impl TaskStorage {
     /// Open or create a new task storage in the given path
     pub fn open(path: &str) -> Result<Self> {
-        let db = sled::open(path)?;
+        Self::from_db(sled::open(path)?)
+    }
+
+    pub(crate) fn from_db(db: Db) -> Result<Self> {
         let unassigned = db.open_tree("tasks_unassigned")?;
         let assigned = db.open_tree("tasks_assigned")?;
         let archived = db.open_tree("tasks_archived")?;
+        let client_ids = db.open_tree("tasks_client_ids")?;
+        let late_reports = db.open_tree("tasks_late_reports")?;
+        let dead_letter = db.open_tree("tasks_dead_letter")?;
+        let urgent = db.open_tree("tasks_urgent")?;
+        let events = TaskEventLog::from_tree(db.open_tree("task_events")?);
 
         Ok(Self {
-            _db: db,
+            db,
             unassigned,
             assigned,
             archived,
+            client_ids,
+            late_reports,
+            dead_letter,
+            urgent,
+            events,
+            callbacks: OnceLock::new(),
         })
     }
 
+    /// Queue the completion callback of every task that reaches a terminal
+    /// status on `tx` from now on.
+    pub fn send_callbacks_to(&self, tx: mpsc::Sender<WebhookDelivery>) {
+        let _ = self.callbacks.set(tx);
+    }
+
+    pub fn events(&self) -> &TaskEventLog {
+        &self.events
+    }
+
+    /// Append a regular task's status change to the event log. Best-effort:
+    /// the change itself is already stored, so a failure is only logged.
+    ///
+    /// Every transition of a regular task passes through here once stored, so
+    /// this is also where a task that just finished, by whatever path, has
+    /// its completion callback queued.
+    fn record_transition(
+        &self,
+        id: &TaskId,
+        from: Option<TaskStatus>,
+        to: TaskStatus,
+        agent_id: Option<&str>,
+    ) {
+        let finished = to.is_terminal();
+        let mut record = TaskTransition::new(id, false, from, to, agent_id);
+        if let Err(e) = self.events.append(&mut record) {
+            warn!("Failed to record transition of {}: {}", id, e);
+        }
+        if finished && let Some(tx) = self.callbacks.get() {
+            let task = match self.get_assigned(id) {
+                Ok(Some(task)) => Some(task),
+                _ => self.get_archived(id).ok().flatten(),
+            };
+            if let Some(task) = task.filter(|task| task.status.is_terminal()) {
+                webhook::queue_callback(tx, &task);
+            }
+        }
+    }
+
     /// Create composite key: "capability|uuid"
     fn make_key(id: &TaskId) -> String {
         format!("{}|{}", id.cap, id.id)
     }
 
+    /// Create client id index key: "api_key|client_task_id"
+    fn make_client_key(api_key: &str, client_task_id: &str) -> String {
+        format!("{}|{}", api_key, client_task_id)
+    }
+
+    fn parse_key(key: &[u8]) -> Option<TaskId> {
+        // The uuid part never contains '|', the capability might.
+        let (cap, id) = std::str::from_utf8(key).ok()?.rsplit_once('|')?;
+        Some(TaskId {
+            cap: cap.to_string(),
+            id: id.to_string(),
+        })
+    }
+
+    /// Reserve `client_task_id` for `id` under `api_key`.
+    ///
+    /// Returns `None` when the reservation succeeded, or the id of the task that
+    /// already holds this client id. The compare-and-swap makes concurrent
+    /// submissions with the same client id agree on a single winner.
+    ///
+    /// With `expired_before`, a reservation whose task was submitted before
+    /// that moment (or no longer exists) has expired and is taken over.
+    pub fn claim_client_task_id(
+        &self,
+        api_key: &str,
+        client_task_id: &str,
+        id: &TaskId,
+        expired_before: Option<DateTime<Utc>>,
+    ) -> Result<Option<TaskId>> {
+        let key = Self::make_client_key(api_key, client_task_id);
+        let value = Self::make_key(id);
+        let mut expected: Option<sled::IVec> = None;
+        loop {
+            let swap = retry_transient("claim_client_task_id", || {
+                self.client_ids.compare_and_swap(
+                    key.as_bytes(),
+                    expected.as_deref(),
+                    Some(value.as_bytes()),
+                )
+            })?;
+            let Err(e) = swap else {
+                return Ok(None);
+            };
+            let Some(current) = e.current else {
+                // Released concurrently; claim the free slot.
+                expected = None;
+                continue;
+            };
+            let existing = Self::parse_key(&current);
+            let expired = match (expired_before, &existing) {
+                (Some(cutoff), Some(existing)) => self
+                    .created_at(existing)?
+                    .is_none_or(|created_at| created_at < cutoff),
+                _ => false,
+            };
+            if !expired {
+                return Ok(existing);
+            }
+            expected = Some(current);
+        }
+    }
+
+    /// Submission time of a non-urgent task, wherever it is stored.
+    fn created_at(&self, id: &TaskId) -> Result<Option<DateTime<Utc>>> {
+        if let Some(task) = self.get_unassigned(id)? {
+            return Ok(Some(task.created_at));
+        }
+        if let Some(task) = self.get_assigned(id)? {
+            return Ok(Some(task.created_at));
+        }
+        Ok(self.get_archived(id)?.map(|task| task.created_at))
+    }
+
+    /// Drop a client id reservation, e.g. when the task it was made for could
+    /// not be stored. Only removes the entry if it still points at `id`.
+    pub fn release_client_task_id(
+        &self,
+        api_key: &str,
+        client_task_id: &str,
+        id: &TaskId,
+    ) -> Result<()> {
+        let key = Self::make_client_key(api_key, client_task_id);
+        let value = Self::make_key(id);
+        let _ = self.client_ids.compare_and_swap(
+            key.as_bytes(),
+            Some(value.as_bytes()),
+            None::<&[u8]>,
+        )?;
+        Ok(())
+    }
+
+    /// Look up the task submitted under `api_key` with the given client id
+    pub fn find_by_client_task_id(
+        &self,
+        api_key: &str,
+        client_task_id: &str,
+    ) -> Result<Option<TaskId>> {
+        let key = Self::make_client_key(api_key, client_task_id);
+        Ok(self
+            .client_ids
+            .get(key.as_bytes())?
+            .and_then(|v| Self::parse_key(&v)))
+    }
+
     /// Add a new unassigned task
     pub fn add_unassigned(&self, task: &UnassignedTask) -> Result<()> {
         let key = Self::make_key(&task.id);
-        let bytes = rmp_serde::to_vec_named(task)?;
+        let bytes = versioned::encode(task)?;
         self.unassigned.insert(key.as_bytes(), bytes)?;
+        self.record_transition(&task.id, None, TaskStatus::Pending, None);
         Ok(())
     }
 
@@ -56,29 +286,49 @@ impl TaskStorage {
     /// the transactional `remove` is the arbiter against concurrent claims.
     pub fn assign_task(&self, id: &TaskId, agent_id: &str) -> Result<AssignedTask, AppError> {
         let key = Self::make_key(id);
-        let value = self
-            .unassigned
-            .get(key.as_bytes())?
-            .ok_or_else(|| AppError::Conflict(format!("Unassigned task not found: {}", id)))?;
-        let unassigned: UnassignedTask = rmp_serde::from_slice(&value)?;
-        let assigned = unassigned.assign_to(agent_id);
-        let bytes = rmp_serde::to_vec_named(&assigned)?;
-
-        let res = (&self.unassigned, &self.assigned).transaction(move |(un, asg)| {
-            // If the task is gone, a racer (another agent or the timeout sweep)
-            // already claimed it — abort so we don't resurrect a stale copy.
-            if un.remove(key.as_bytes())?.is_none() {
-                return abort(());
-            }
-            asg.insert(key.as_bytes(), bytes.clone())?;
-            Ok(())
+
+        // The task is read, removed and re-inserted as assigned inside one
+        // transaction, so concurrent claimers can't both see it unassigned and
+        // no stale pre-read copy is ever written back.
+        let res = retry_transient("assign_task", || {
+            (&self.unassigned, &self.assigned).transaction(|(un, asg)| {
+                // If the task is gone, a racer (another agent or the timeout sweep)
+                // already claimed it — abort so we don't resurrect a stale copy.
+                let Some(value) = un.remove(key.as_bytes())? else {
+                    return abort(None);
+                };
+                let unassigned = match versioned::decode::<UnassignedTask>(&value) {
+                    Ok(task) => task,
+                    Err(e) => return abort(Some(e.to_string())),
+                };
+                let assigned = unassigned.assign_to(agent_id);
+                let bytes = match versioned::encode(&assigned) {
+                    Ok(bytes) => bytes,
+                    Err(e) => return abort(Some(e.to_string())),
+                };
+                asg.insert(key.as_bytes(), bytes)?;
+                Ok(assigned)
+            })
         });
 
         match res {
-            Ok(()) => Ok(assigned),
-            Err(TransactionError::Abort(())) => {
+            Ok(assigned) => {
+                self.record_transition(
+                    id,
+                    Some(TaskStatus::Pending),
+                    TaskStatus::Assigned,
+                    Some(agent_id),
+                );
+                Ok(assigned)
+            }
+            Err(TransactionError::Abort(None)) => {
                 Err(AppError::Conflict(format!("Task already taken: {}", id)))
             }
+            Err(TransactionError::Abort(Some(e))) => Err(AppError::Internal(anyhow::anyhow!(
+                "Corrupt unassigned task {}: {}",
+                id,
+                e
+            ))),
             Err(TransactionError::Storage(e)) => Err(AppError::Database(e)),
         }
     }
@@ -97,7 +347,7 @@ impl TaskStorage {
 
         for item in self.assigned.iter() {
             let (k, v) = item?;
-            let task: AssignedTask = rmp_serde::from_slice(&v)?;
+            let task: AssignedTask = versioned::decode(&v)?;
             let retain_from = task.finished_at.unwrap_or(task.assigned_at);
             if task.status.is_terminal() && retain_from < cutoff {
                 to_archive.push((k, v));
@@ -112,6 +362,136 @@ impl TaskStorage {
         Ok(())
     }
 
+    /// Hard ceiling on task age, independent of the normal archival: every
+    /// task created more than `max_age_secs` before `now` is archived whatever
+    /// its status, queued tasks included. A task not yet terminal (e.g. wedged
+    /// in `Running` on a dead agent) is force-failed first, with a history
+    /// event noting the forced closure. Each task is re-read and moved to the
+    /// archive in one transaction, so a report landing mid-sweep is neither
+    /// lost nor resurrected.
+    pub fn force_archive_older_than(
+        &self,
+        now: DateTime<Utc>,
+        max_age_secs: u64,
+    ) -> Result<ForcedArchive> {
+        let cutoff = now - chrono::Duration::seconds(max_age_secs as i64);
+        let mut outcome = ForcedArchive::default();
+
+        let mut queued = Vec::new();
+        for item in self.unassigned.iter() {
+            let (k, v) = item?;
+            let task: UnassignedTask = versioned::decode(&v)?;
+            if task.created_at < cutoff {
+                queued.push(k);
+            }
+        }
+        for k in queued {
+            let res = (&self.unassigned, &self.archived).transaction(|(un, arc)| {
+                let Some(value) = un.remove(&k)? else {
+                    return abort(());
+                };
+                let Ok(task) = versioned::decode::<UnassignedTask>(&value) else {
+                    return abort(());
+                };
+                let mut task = task.into_assigned("(max-age)");
+                Self::force_fail(&mut task, now, max_age_secs);
+                let Ok(bytes) = versioned::encode(&task) else {
+                    return abort(());
+                };
+                arc.insert(&k, bytes)?;
+                Ok(task)
+            });
+            match res {
+                Ok(task) => {
+                    self.record_transition(
+                        &task.id,
+                        Some(TaskStatus::Pending),
+                        TaskStatus::Failed,
+                        None,
+                    );
+                    outcome.archived += 1;
+                    outcome.dequeued.push(task.id);
+                }
+                Err(TransactionError::Abort(())) => {}
+                Err(TransactionError::Storage(e)) => return Err(e.into()),
+            }
+        }
+
+        let mut assigned = Vec::new();
+        for item in self.assigned.iter() {
+            let (k, v) = item?;
+            let task: AssignedTask = versioned::decode(&v)?;
+            if task.created_at < cutoff {
+                assigned.push(k);
+            }
+        }
+        for k in assigned {
+            let res = (&self.assigned, &self.archived).transaction(|(asg, arc)| {
+                let Some(value) = asg.remove(&k)? else {
+                    return abort(());
+                };
+                let Ok(mut task) = versioned::decode::<AssignedTask>(&value) else {
+                    return abort(());
+                };
+                let previous = Self::force_fail(&mut task, now, max_age_secs);
+                let Ok(bytes) = versioned::encode(&task) else {
+                    return abort(());
+                };
+                arc.insert(&k, bytes)?;
+                Ok((task, previous))
+            });
+            match res {
+                Ok((task, previous)) => {
+                    outcome.archived += 1;
+                    if let Some(previous) = previous {
+                        self.record_transition(
+                            &task.id,
+                            Some(previous),
+                            TaskStatus::Failed,
+                            Some(&task.agent_id),
+                        );
+                        outcome.interrupted.push((task.agent_id, task.id));
+                    }
+                }
+                Err(TransactionError::Abort(())) => {}
+                Err(TransactionError::Storage(e)) => return Err(e.into()),
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    /// Fail a non-terminal task for exceeding the maximum task age. Returns
+    /// the status it was in, or `None` if it was already terminal.
+    fn force_fail(
+        task: &mut AssignedTask,
+        now: DateTime<Utc>,
+        max_age_secs: u64,
+    ) -> Option<TaskStatus> {
+        if task.status.is_terminal() {
+            return None;
+        }
+        let previous = task.status.clone();
+        task.change_status(TaskStatus::Failed);
+        task.stage = None;
+        task.history.push(TaskEvent {
+            timestamp: now,
+            description: format!(
+                "Forced closure: task older than {}s (was {:?})",
+                max_age_secs, previous
+            ),
+        });
+        task.append_log(Some(format!(
+            "\n[server] Task failed: exceeded the maximum task age of {}s",
+            max_age_secs
+        )));
+        info!(
+            "Task {} exceeded max age while {:?}, force-failed",
+            task.id, previous
+        );
+        Some(previous)
+    }
+
     /// Revert an assigned task back to the unassigned queue.
     ///
     /// Used by the push dispatcher when a push send fails before the agent
@@ -127,7 +507,7 @@ impl TaskStorage {
             Some(v) => v,
             None => return Ok(None),
         };
-        let assigned: AssignedTask = rmp_serde::from_slice(&value)?;
+        let assigned: AssignedTask = versioned::decode(&value)?;
         if assigned.status != TaskStatus::Assigned {
             return Ok(None);
         }
@@ -135,8 +515,11 @@ impl TaskStorage {
             id: assigned.id.clone(),
             data: assigned.data.clone(),
             created_at: assigned.created_at,
+            failed_agents: assigned.failed_agents.clone(),
+            next_eligible_at: None,
+            history: assigned.history.clone(),
         };
-        let bytes = rmp_serde::to_vec_named(&unassigned)?;
+        let bytes = versioned::encode(&unassigned)?;
         let res = (&self.assigned, &self.unassigned).transaction(move |(asg, un)| {
             // If the assigned record is gone, a concurrent resolve / sweep handled
             // it — abort so we don't resurrect a stale copy.
@@ -147,12 +530,101 @@ impl TaskStorage {
             Ok(())
         });
         match res {
-            Ok(()) => Ok(Some(unassigned)),
+            Ok(()) => {
+                self.record_transition(
+                    id,
+                    Some(TaskStatus::Assigned),
+                    TaskStatus::Pending,
+                    Some(&assigned.agent_id),
+                );
+                Ok(Some(unassigned))
+            }
+            Err(TransactionError::Abort(())) => Ok(None),
+            Err(TransactionError::Storage(e)) => Err(AppError::Database(e)),
+        }
+    }
+
+    /// Move a failed restartable task back to the unassigned queue, recording
+    /// the agent that failed it so it is offered to a different agent next.
+    /// Unlike `unassign_task` this does not require the task to be un-started.
+    /// Returns the re-queued task, or `None` if the assigned record is gone.
+    pub fn requeue_failed(
+        &self,
+        failed: &AssignedTask,
+    ) -> Result<Option<UnassignedTask>, AppError> {
+        let key = Self::make_key(&failed.id);
+        let unassigned = Self::retry_copy(failed);
+        let bytes = versioned::encode(&unassigned)?;
+        let res = (&self.assigned, &self.unassigned).transaction(move |(asg, un)| {
+            let Some(previous) = asg.remove(key.as_bytes())? else {
+                return abort(());
+            };
+            un.insert(key.as_bytes(), bytes.clone())?;
+            Ok(previous)
+        });
+        match res {
+            Ok(previous) => {
+                // The stored status, not `failed.status`: callers may already
+                // have marked their copy failed.
+                let from = versioned::decode::<AssignedTask>(&previous)
+                    .map(|task| task.status)
+                    .unwrap_or(TaskStatus::Failed);
+                self.record_transition(
+                    &failed.id,
+                    Some(from),
+                    TaskStatus::Pending,
+                    Some(&failed.agent_id),
+                );
+                Ok(Some(unassigned))
+            }
             Err(TransactionError::Abort(())) => Ok(None),
             Err(TransactionError::Storage(e)) => Err(AppError::Database(e)),
         }
     }
 
+    /// The queued copy of a failed restartable task, with the failing agent
+    /// excluded from its next attempt.
+    fn retry_copy(failed: &AssignedTask) -> UnassignedTask {
+        let mut failed_agents = failed.failed_agents.clone();
+        failed_agents.push(failed.agent_id.clone());
+        let next_eligible_at = failed
+            .data
+            .retry
+            .as_ref()
+            .and_then(|retry| retry.retry_delay_secs)
+            .map(|secs| Utc::now() + chrono::Duration::seconds(secs as i64));
+        let mut history = failed.history.clone();
+        history.push(TaskEvent {
+            timestamp: Utc::now(),
+            description: format!("Re-queued for retry (attempt {})", failed_agents.len() + 1),
+        });
+        UnassignedTask {
+            id: failed.id.clone(),
+            data: failed.data.clone(),
+            created_at: failed.created_at,
+            failed_agents,
+            next_eligible_at,
+            history,
+        }
+    }
+
+    /// Add several unassigned tasks in one atomic batch: either all of them are
+    /// stored or none is.
+    pub fn add_unassigned_batch(&self, tasks: &[UnassignedTask]) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for task in tasks {
+            batch.insert(
+                Self::make_key(&task.id).as_bytes(),
+                versioned::encode(task)?,
+            );
+        }
+        self.unassigned.apply_batch(batch)?;
+        for task in tasks {
+            self.record_transition(&task.id, None, TaskStatus::Pending, None);
+        }
+        Ok(())
+    }
+
     /// Remove an unassigned task by id (returns true if it existed)
     pub fn remove_unassigned(&self, id: &TaskId) -> Result<bool> {
         let key = Self::make_key(id);
@@ -163,7 +635,7 @@ impl TaskStorage {
     pub fn get_unassigned(&self, id: &TaskId) -> Result<Option<UnassignedTask>> {
         let key = Self::make_key(id);
         if let Some(value) = self.unassigned.get(key.as_bytes())? {
-            Ok(Some(rmp_serde::from_slice(&value)?))
+            Ok(Some(versioned::decode(&value)?))
         } else {
             Ok(None)
         }
@@ -173,46 +645,433 @@ impl TaskStorage {
     pub fn get_assigned(&self, id: &TaskId) -> Result<Option<AssignedTask>> {
         let key = Self::make_key(id);
         if let Some(value) = self.assigned.get(key.as_bytes())? {
-            Ok(Some(rmp_serde::from_slice(&value)?))
+            Ok(Some(versioned::decode(&value)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The finished task carrying `id`'s result, from the assigned tree or the
+    /// archive. `None` while the task is unknown or not terminal yet.
+    pub fn get_result(&self, id: &TaskId) -> Result<Option<AssignedTask>> {
+        let task = match self.get_assigned(id)? {
+            Some(task) => Some(task),
+            None => self.get_archived(id)?,
+        };
+        Ok(task.filter(|task| task.status.is_terminal()))
+    }
+
+    /// Get an archived task by id
+    pub fn get_archived(&self, id: &TaskId) -> Result<Option<AssignedTask>> {
+        let key = Self::make_key(id);
+        if let Some(value) = self.archived.get(key.as_bytes())? {
+            Ok(Some(versioned::decode(&value)?))
         } else {
             Ok(None)
         }
     }
 
+    /// Archive the finished record of a task that ran outside this store (an
+    /// urgent task escalated from the queue) so its result outlives the urgent
+    /// store. Its transitions were already recorded there.
+    pub fn archive_result(&self, task: &AssignedTask) -> Result<()> {
+        let key = Self::make_key(&task.id);
+        self.archived
+            .insert(key.as_bytes(), versioned::encode(task)?)?;
+        Ok(())
+    }
+
+    pub fn list_archived_all(&self) -> Result<Vec<AssignedTask>> {
+        let mut result = Vec::new();
+        for item in self.archived.iter() {
+            let (_key, value) = item?;
+            result.push(versioned::decode(&value)?);
+        }
+        Ok(result)
+    }
+
+    /// Archived tasks whose capability string is exactly `cap`.
+    pub fn list_archived_for_capability(&self, cap: &str) -> Result<Vec<AssignedTask>> {
+        let mut result = Vec::new();
+        for item in self.archived.scan_prefix(format!("{}|", cap).as_bytes()) {
+            let (_key, value) = item?;
+            result.push(versioned::decode(&value)?);
+        }
+        Ok(result)
+    }
+
+    /// Archived tasks in key order (capability, then id), optionally limited
+    /// to one capability, with cursor-based pagination.
+    ///
+    /// - `cursor`: the `cap|id` key of the last item from the previous page
+    ///   (exclusive). Pass `None` for the first page.
+    ///
+    /// Returns `(items, next_cursor)`; `next_cursor` is `None` on the last page.
+    pub fn list_archived_page(
+        &self,
+        cap: Option<&str>,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<AssignedTask>, Option<String>)> {
+        let prefix = cap.map(|cap| format!("{}|", cap)).unwrap_or_default();
+        let lower = match cursor {
+            Some(c) if c > prefix.as_str() => c.to_string(),
+            _ => prefix.clone(),
+        };
+
+        let mut items: Vec<AssignedTask> = Vec::new();
+        for item in self.archived.range(lower.as_bytes()..) {
+            let (key, value) = item?;
+            if cursor.is_some_and(|c| key.as_ref() == c.as_bytes()) {
+                continue;
+            }
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            items.push(versioned::decode(&value)?);
+            if items.len() > limit {
+                break;
+            }
+        }
+
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|task| Self::make_key(&task.id))
+        } else {
+            None
+        };
+        Ok((items, next_cursor))
+    }
+
+    /// Queued, assigned and archived tasks submitted with `api_key`, as status
+    /// reports ordered by capability and then id, with the same cursor
+    /// pagination as [`Self::list_archived_page`]. Scans every task tree.
+    pub fn list_for_api_key(
+        &self,
+        api_key: &str,
+        filter: &TaskListFilter,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<TaskStatusResponse>, Option<String>)> {
+        let mut found: BTreeMap<String, TaskStatusResponse> = BTreeMap::new();
+        let mut keep = |key: &[u8], report: TaskStatusResponse| {
+            if filter.matches(&report) {
+                found.insert(String::from_utf8_lossy(key).into_owned(), report);
+            }
+        };
+        for item in self.unassigned.iter() {
+            let (key, value) = item?;
+            let task: UnassignedTask = versioned::decode(&value)?;
+            if task.data.api_key == api_key {
+                keep(&key, task.into_status_report());
+            }
+        }
+        for tree in [&self.assigned, &self.archived] {
+            for item in tree.iter() {
+                let (key, value) = item?;
+                let task: AssignedTask = versioned::decode(&value)?;
+                if task.data.api_key == api_key {
+                    keep(&key, task.into_status_report());
+                }
+            }
+        }
+
+        let lower = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+        let mut items: Vec<(String, TaskStatusResponse)> = found
+            .range::<str, _>((lower, Bound::Unbounded))
+            .take(limit + 1)
+            .map(|(key, report)| (key.clone(), report.clone()))
+            .collect();
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|(key, _)| key.clone())
+        } else {
+            None
+        };
+        Ok((
+            items.into_iter().map(|(_, report)| report).collect(),
+            next_cursor,
+        ))
+    }
+
     pub fn update_assigned(&self, assigned: &AssignedTask) -> Result<()> {
-        let bytes = rmp_serde::to_vec_named(assigned)?;
+        let bytes = versioned::encode(assigned)?;
         let key = Self::make_key(&assigned.id);
-        self.assigned.insert(key.as_bytes(), bytes)?;
+        let previous = retry_transient("update_assigned", || {
+            self.assigned.insert(key.as_bytes(), bytes.clone())
+        })?;
+        // No assigned record yet means the task just left the queue.
+        let from = match previous {
+            Some(value) => versioned::decode::<AssignedTask>(&value)
+                .ok()
+                .map(|task| task.status),
+            None => Some(TaskStatus::Pending),
+        };
+        if let Some(from) = from
+            && from != assigned.status
+        {
+            self.record_transition(
+                &assigned.id,
+                Some(from),
+                assigned.status.clone(),
+                Some(&assigned.agent_id),
+            );
+        }
         return Ok(());
     }
 
+    /// Append `event` to the history of a task that has left the queue,
+    /// whether it is still assigned or already archived. Returns whether the
+    /// task was found.
+    pub fn add_history(&self, id: &TaskId, event: &TaskEvent) -> Result<bool> {
+        let key = Self::make_key(id);
+        for tree in [&self.assigned, &self.archived] {
+            let mut found = false;
+            tree.fetch_and_update(key.as_bytes(), |old| {
+                let old = old?;
+                let Ok(mut task) = versioned::decode::<AssignedTask>(old) else {
+                    return Some(old.to_vec());
+                };
+                task.history.push(event.clone());
+                found = true;
+                versioned::encode(&task).ok().or_else(|| Some(old.to_vec()))
+            })?;
+            if found {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Keep a report that arrived for a task no longer assigned anywhere.
+    pub fn store_late_report(&self, late: &LateReport) -> Result<()> {
+        let bytes = versioned::encode(late)?;
+        retry_transient("store_late_report", || {
+            self.late_reports
+                .insert(late.record_id.as_bytes(), bytes.clone())
+        })?;
+        Ok(())
+    }
+
+    /// Stored late reports, newest first.
+    pub fn list_late_reports(&self, limit: usize) -> Result<Vec<LateReport>> {
+        let mut out = Vec::new();
+        for item in self.late_reports.iter().rev().take(limit) {
+            let (_k, v) = item?;
+            out.push(versioned::decode(&v)?);
+        }
+        Ok(out)
+    }
+
+    /// Record a permanently failed task. The assigned record stays as it is,
+    /// so clients can still poll the failure.
+    pub fn add_dead_letter(&self, task: &AssignedTask, failure_reason: String) -> Result<()> {
+        let letter = DeadLetter {
+            task: task.clone(),
+            failure_reason,
+            dead_at: Utc::now(),
+        };
+        let bytes = versioned::encode(&letter)?;
+        let key = Self::make_key(&task.id);
+        retry_transient("add_dead_letter", || {
+            self.dead_letter.insert(key.as_bytes(), bytes.clone())
+        })?;
+        Ok(())
+    }
+
+    pub fn get_dead_letter(&self, id: &TaskId) -> Result<Option<DeadLetter>> {
+        match self.dead_letter.get(Self::make_key(id).as_bytes())? {
+            Some(value) => Ok(Some(versioned::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every dead-lettered task, ordered by capability and then id.
+    pub fn list_dead_letter_all(&self) -> Result<Vec<DeadLetter>> {
+        let mut out = Vec::new();
+        for item in self.dead_letter.iter() {
+            let (_k, v) = item?;
+            out.push(versioned::decode(&v)?);
+        }
+        Ok(out)
+    }
+
+    /// Put a dead-lettered task back in the queue as if freshly submitted:
+    /// its failed-agent history is cleared and its wait limits count from
+    /// now. The failed assigned (or archived) record is removed. Returns the
+    /// queued task, or `None` if `id` is not dead-lettered.
+    pub fn requeue_dead_letter(&self, id: &TaskId) -> Result<Option<UnassignedTask>, AppError> {
+        let Some(letter) = self.get_dead_letter(id)? else {
+            return Ok(None);
+        };
+        let unassigned = UnassignedTask {
+            id: letter.task.id.clone(),
+            data: letter.task.data.clone(),
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        let key = Self::make_key(id);
+        let bytes = versioned::encode(&unassigned)?;
+        let res = (
+            &self.dead_letter,
+            &self.assigned,
+            &self.archived,
+            &self.unassigned,
+        )
+            .transaction(move |(dead, asg, arc, un)| {
+                if dead.remove(key.as_bytes())?.is_none() {
+                    return abort(());
+                }
+                asg.remove(key.as_bytes())?;
+                arc.remove(key.as_bytes())?;
+                un.insert(key.as_bytes(), bytes.clone())?;
+                Ok(())
+            });
+        match res {
+            Ok(()) => {
+                self.record_transition(
+                    id,
+                    Some(letter.task.status.clone()),
+                    TaskStatus::Pending,
+                    None,
+                );
+                Ok(Some(unassigned))
+            }
+            Err(TransactionError::Abort(())) => Ok(None),
+            Err(TransactionError::Storage(e)) => Err(AppError::Database(e)),
+        }
+    }
+
+    pub fn put_urgent(&self, snapshot: &UrgentSnapshot) -> Result<()> {
+        let bytes = versioned::encode(snapshot)?;
+        let key = Self::make_key(&snapshot.task.id);
+        retry_transient("put_urgent", || {
+            self.urgent.insert(key.as_bytes(), bytes.clone())
+        })?;
+        Ok(())
+    }
+
+    pub fn remove_urgent(&self, id: &TaskId) -> Result<()> {
+        self.urgent.remove(Self::make_key(id).as_bytes())?;
+        Ok(())
+    }
+
+    /// Every persisted urgent task, ordered by capability and then id.
+    pub fn list_urgent_all(&self) -> Result<Vec<UrgentSnapshot>> {
+        let mut out = Vec::new();
+        for item in self.urgent.iter() {
+            let (_k, v) = item?;
+            out.push(versioned::decode(&v)?);
+        }
+        Ok(out)
+    }
+
+    pub fn clear_urgent(&self) -> Result<()> {
+        self.urgent.clear()?;
+        Ok(())
+    }
+
+    /// Read the task trees and write a throwaway key, for `/health/ready`.
+    pub fn probe(&self) -> Result<()> {
+        self.unassigned.first()?;
+        self.assigned.first()?;
+        let probe = self.db.open_tree("health_probe")?;
+        probe.insert(b"probe", &[])?;
+        probe.remove(b"probe")?;
+        Ok(())
+    }
+
+    /// Wait until every write so far is durable on disk. Called before
+    /// acknowledging a result report, so an agent never sees success for a
+    /// result that a crash could still lose.
+    pub async fn flush(&self) -> Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
     pub fn hard_clear(&self) -> Result<()> {
         info!("Performing tasks database cleanup");
         self.assigned.clear()?;
         self.unassigned.clear()?;
         self.archived.clear()?;
+        self.client_ids.clear()?;
+        self.late_reports.clear()?;
+        self.dead_letter.clear()?;
+        self.urgent.clear()?;
+        self.events.clear()?;
         Ok(())
     }
 
     /// List unassigned tasks for a given capability
     pub fn list_unassigned_for_capability(&self, capability: &str) -> Result<Vec<UnassignedTask>> {
-        let prefix = format!("{}|", capability);
+        self.scan_unassigned(&format!("{}|", capability))
+    }
+
+    fn scan_unassigned(&self, prefix: &str) -> Result<Vec<UnassignedTask>> {
         let mut result = Vec::new();
 
         for item in self.unassigned.scan_prefix(prefix.as_bytes()) {
             let (_k, v) = item?;
-            let task: UnassignedTask = rmp_serde::from_slice(&v)?;
+            let task: UnassignedTask = versioned::decode(&v)?;
             result.push(task);
         }
 
         Ok(result)
     }
 
+    /// How many unassigned tasks for the same capability are ahead of `id`,
+    /// by a prefix scan of the capability's keys (ids sort by creation time).
+    /// `priority` only reorders a key's own tasks, so ahead are: tasks of
+    /// other keys queued earlier, and tasks of the same key with a higher
+    /// priority, or the same priority and queued earlier. `None` when `id` is
+    /// not queued.
+    pub fn queue_position(&self, id: &TaskId) -> Result<Option<usize>> {
+        let key = Self::make_key(id);
+        let Some(value) = self.unassigned.get(key.as_bytes())? else {
+            return Ok(None);
+        };
+        let task: UnassignedTask = versioned::decode(&value)?;
+        let prefix = format!("{}|", id.cap);
+        let mut ahead = 0;
+        for item in self.unassigned.scan_prefix(prefix.as_bytes()) {
+            let (k, v) = item?;
+            if k == key.as_bytes() {
+                continue;
+            }
+            let other: UnassignedTask = versioned::decode(&v)?;
+            let earlier = k.as_ref() < key.as_bytes();
+            let is_ahead = if other.data.api_key == task.data.api_key {
+                other.data.priority > task.data.priority
+                    || (other.data.priority == task.data.priority && earlier)
+            } else {
+                earlier
+            };
+            if is_ahead {
+                ahead += 1;
+            }
+        }
+        Ok(Some(ahead))
+    }
+
+    /// Unassigned tasks for any of `caps`, one prefix scan per capability; a
+    /// wildcard such as `llm.*` scans every capability it covers. Empty `caps`
+    /// match nothing — use [`Self::list_unassigned_all`] for every task.
     pub fn list_unassigned_with_caps(&self, caps: &Vec<String>) -> Result<Vec<UnassignedTask>> {
+        let mut seen = HashSet::new();
         Ok(caps
             .iter()
-            .filter_map(|x| self.list_unassigned_for_capability(base_capability(x)).ok())
+            .filter_map(|x| {
+                let base = base_capability(x);
+                match base.strip_suffix('*') {
+                    Some(prefix) => self.scan_unassigned(prefix),
+                    None => self.list_unassigned_for_capability(base),
+                }
+                .ok()
+            })
             .flatten()
+            .filter(|task| seen.insert(task.id.clone()))
             .collect())
     }
 
@@ -222,7 +1081,7 @@ impl TaskStorage {
         for item in self.unassigned.iter() {
             // Each item is a sled::Result<(IVec, IVec)>
             let (_key, value) = item?;
-            let task: UnassignedTask = rmp_serde::from_slice(&value)?;
+            let task: UnassignedTask = versioned::decode(&value)?;
             result.push(task);
         }
         Ok(result)
@@ -238,7 +1097,7 @@ impl TaskStorage {
 
         for item in self.unassigned.iter() {
             let (_k, v) = item?;
-            let task: UnassignedTask = rmp_serde::from_slice(&v)?;
+            let task: UnassignedTask = versioned::decode(&v)?;
 
             let elapsed = (now - task.created_at).num_seconds().max(0) as u64;
             let wait_expired = task.data.max_wait_secs.map_or(false, |mw| elapsed >= mw);
@@ -282,7 +1141,7 @@ impl TaskStorage {
 
         for item in self.assigned.iter() {
             let (_k, v) = item?;
-            let task: AssignedTask = rmp_serde::from_slice(&v)?;
+            let task: AssignedTask = versioned::decode(&v)?;
 
             let timeout_secs = match task.data.timeout_secs {
                 Some(ts) => ts,
@@ -333,7 +1192,7 @@ impl TaskStorage {
 
         for item in self.assigned.iter() {
             let (_k, v) = item?;
-            let task: AssignedTask = rmp_serde::from_slice(&v)?;
+            let task: AssignedTask = versioned::decode(&v)?;
             if task.status != TaskStatus::CancelRequested {
                 continue;
             }
@@ -357,17 +1216,80 @@ impl TaskStorage {
         Ok(count)
     }
 
+    /// Purge the output of completed tasks whose capability result TTL has
+    /// passed since completion, archived tasks included. The status and
+    /// history are kept; `result` is dropped and `output_expired_at` set, so
+    /// polls can tell "expired" from "no output". `result_ttl` maps a base
+    /// capability to its TTL in seconds. Returns the number of tasks purged.
+    pub fn purge_expired_results<F>(&self, now: DateTime<Utc>, result_ttl: F) -> Result<usize>
+    where
+        F: Fn(&str) -> Option<u64>,
+    {
+        let is_expired = |task: &AssignedTask| {
+            if task.status != TaskStatus::Completed || task.output_expired_at.is_some() {
+                return false;
+            }
+            let Some(ttl) = result_ttl(base_capability(&task.id.cap)) else {
+                return false;
+            };
+            let finished = task.finished_at.unwrap_or(task.assigned_at);
+            (now - finished).num_seconds() >= ttl as i64
+        };
+
+        let mut count = 0;
+        for tree in [&self.assigned, &self.archived] {
+            let mut expired = Vec::new();
+            for item in tree.iter() {
+                let (k, v) = item?;
+                let task: AssignedTask = versioned::decode(&v)?;
+                if is_expired(&task) {
+                    expired.push(k);
+                }
+            }
+
+            for k in expired {
+                // Re-checked under the update so a concurrent rewrite of the
+                // record is neither lost nor purged twice.
+                let mut purged = None;
+                tree.fetch_and_update(&k, |old| {
+                    let old = old?;
+                    let Ok(mut task) = versioned::decode::<AssignedTask>(old) else {
+                        return Some(old.to_vec());
+                    };
+                    if !is_expired(&task) {
+                        return Some(old.to_vec());
+                    }
+                    task.result = None;
+                    task.output_expired_at = Some(now);
+                    let Ok(bytes) = versioned::encode(&task) else {
+                        return Some(old.to_vec());
+                    };
+                    purged = Some(task.id);
+                    Some(bytes)
+                })?;
+                if let Some(id) = purged {
+                    count += 1;
+                    info!("Task {} output purged after result TTL", id);
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
     /// Recover tasks abandoned by a dead agent. A task is orphaned when it is in
     /// an active (non-terminal, non-cancel-requested) status, its assigned agent
-    /// is offline, and it has not been touched for `silence_secs`. Such tasks are
-    /// failed so they reach a terminal state. `is_agent_online` reports whether
-    /// the agent that holds the task is currently online.
-    /// Returns the number of tasks that were recovered.
+    /// is offline, and it has not been touched for `silence_secs`. Restartable
+    /// orphans with retries left are re-queued for another agent (the dead one
+    /// counts as a failed attempt); the rest are failed so they reach a
+    /// terminal state. `is_agent_online` reports whether the agent that holds
+    /// the task is currently online.
     pub fn recover_orphaned_assigned<F>(
         &self,
         silence_secs: i64,
+        max_restarts: u32,
         is_agent_online: F,
-    ) -> Result<usize>
+    ) -> Result<OrphanRecovery>
     where
         F: Fn(&str) -> bool,
     {
@@ -376,7 +1298,7 @@ impl TaskStorage {
 
         for item in self.assigned.iter() {
             let (_k, v) = item?;
-            let task: AssignedTask = rmp_serde::from_slice(&v)?;
+            let task: AssignedTask = versioned::decode(&v)?;
             // Only actively-held tasks can be orphaned. Terminal tasks are done;
             // CancelRequested is handled by fail_stale_cancel_requested.
             match task.status {
@@ -392,23 +1314,173 @@ impl TaskStorage {
             }
         }
 
-        let count = orphaned.len();
-        for mut task in orphaned {
+        self.release_held(orphaned, max_restarts, "went offline and stopped reporting")
+    }
+
+    /// Give up every task `agent_uid` actively holds, for an agent leaving on
+    /// its own: restartable tasks with retries left are re-queued, the rest
+    /// failed.
+    pub fn release_agent_tasks(
+        &self,
+        agent_uid: &str,
+        max_restarts: u32,
+    ) -> Result<OrphanRecovery> {
+        let mut held: Vec<AssignedTask> = Vec::new();
+        for item in self.assigned.iter() {
+            let (_k, v) = item?;
+            let task: AssignedTask = versioned::decode(&v)?;
+            if task.agent_id == agent_uid
+                && matches!(
+                    task.status,
+                    TaskStatus::Assigned | TaskStatus::Starting | TaskStatus::Running
+                )
+            {
+                held.push(task);
+            }
+        }
+        self.release_held(held, max_restarts, "deregistered")
+    }
+
+    /// Re-queue or fail tasks whose agent will not finish them; `why` says
+    /// what happened to the agent, for the logs.
+    fn release_held(
+        &self,
+        held: Vec<AssignedTask>,
+        max_restarts: u32,
+        why: &str,
+    ) -> Result<OrphanRecovery> {
+        let mut recovery = OrphanRecovery::default();
+        for mut task in held {
             let agent_id = task.agent_id.clone();
+            if task.can_restart(max_restarts) {
+                if let Some(unassigned) = self.requeue_failed(&task)? {
+                    info!(
+                        "Task {} released (agent {} {}), re-queued",
+                        task.id, agent_id, why
+                    );
+                    recovery.requeued.push(unassigned);
+                    recovery.agents.push(agent_id);
+                }
+                continue;
+            }
             task.change_status(TaskStatus::Failed);
             task.stage = None;
             task.append_log(Some(format!(
-                "\n[server] Task failed: agent {} went offline and stopped reporting",
-                agent_id
+                "\n[server] Task failed: agent {} {}",
+                agent_id, why
             )));
             self.update_assigned(&task)?;
             info!(
-                "Task {} orphaned (agent {} offline and silent), marked failed",
-                task.id, agent_id
+                "Task {} released (agent {} {}), marked failed",
+                task.id, agent_id, why
             );
+            recovery.agents.push(agent_id);
         }
 
-        Ok(count)
+        Ok(recovery)
+    }
+
+    /// Fail actively-held tasks whose `deadlineSecs` since assignment has
+    /// passed, whatever the agent is doing. Restartable tasks with retries
+    /// left are re-queued for another agent instead. Each task is re-checked
+    /// and rewritten inside one transaction, so a report that lands between
+    /// the scan and the rewrite wins rather than being overwritten.
+    pub fn fail_past_deadline(&self, max_restarts: u32) -> Result<OrphanRecovery> {
+        let now = Utc::now();
+        let overdue_now = |task: &AssignedTask| {
+            matches!(
+                task.status,
+                TaskStatus::Assigned | TaskStatus::Starting | TaskStatus::Running
+            ) && task.deadline_passed(now)
+        };
+
+        let mut overdue = Vec::new();
+        for item in self.assigned.iter() {
+            let (k, v) = item?;
+            let task: AssignedTask = versioned::decode(&v)?;
+            if overdue_now(&task) {
+                overdue.push(k);
+            }
+        }
+
+        let mut recovery = OrphanRecovery::default();
+        for k in overdue {
+            let res = (&self.assigned, &self.unassigned).transaction(|(asg, un)| {
+                let Some(value) = asg.get(&k)? else {
+                    return abort(());
+                };
+                let Ok(mut task) = versioned::decode::<AssignedTask>(&value) else {
+                    return abort(());
+                };
+                if !overdue_now(&task) {
+                    return abort(());
+                }
+                let previous = task.status.clone();
+                if task.can_restart(max_restarts) {
+                    let unassigned = Self::retry_copy(&task);
+                    let Ok(bytes) = versioned::encode(&unassigned) else {
+                        return abort(());
+                    };
+                    asg.remove(&k)?;
+                    un.insert(&k, bytes)?;
+                    return Ok((task, previous, Some(unassigned)));
+                }
+                let deadline = task.data.deadline_secs.unwrap_or(0);
+                task.change_status(TaskStatus::Failed);
+                task.stage = None;
+                task.append_log(Some(format!(
+                    "\n[server] Task failed: deadline exceeded ({}s since assignment)",
+                    deadline
+                )));
+                let Ok(bytes) = versioned::encode(&task) else {
+                    return abort(());
+                };
+                asg.insert(&k, bytes)?;
+                Ok((task, previous, None))
+            });
+            let (task, previous, requeued) = match res {
+                Ok(done) => done,
+                Err(TransactionError::Abort(())) => continue,
+                Err(TransactionError::Storage(e)) => return Err(e.into()),
+            };
+            let agent_id = task.agent_id.clone();
+            let deadline = task.data.deadline_secs.unwrap_or(0);
+            match requeued {
+                Some(unassigned) => {
+                    self.record_transition(
+                        &task.id,
+                        Some(previous),
+                        TaskStatus::Pending,
+                        Some(&agent_id),
+                    );
+                    info!(
+                        "Task {} exceeded its {}s deadline on agent {}, re-queued",
+                        task.id, deadline, agent_id
+                    );
+                    recovery.requeued.push(unassigned);
+                }
+                None => {
+                    self.record_transition(
+                        &task.id,
+                        Some(previous),
+                        TaskStatus::Failed,
+                        Some(&agent_id),
+                    );
+                    self.add_dead_letter(
+                        &task,
+                        format!("deadline exceeded ({}s since assignment)", deadline),
+                    )?;
+                    info!(
+                        "Task {} exceeded its {}s deadline on agent {}, marked failed",
+                        task.id, deadline, agent_id
+                    );
+                }
+            }
+            recovery.interrupted.push((agent_id.clone(), task.id));
+            recovery.agents.push(agent_id);
+        }
+
+        Ok(recovery)
     }
 
     pub fn list_assigned_all(&self) -> Result<Vec<AssignedTask>> {
@@ -417,9 +1489,857 @@ impl TaskStorage {
         for item in self.assigned.iter() {
             // Each item is a sled::Result<(IVec, IVec)>
             let (_key, value) = item?;
-            let task: AssignedTask = rmp_serde::from_slice(&value)?;
+            let task: AssignedTask = versioned::decode(&value)?;
             result.push(task);
         }
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc, thread};
+
+    use super::*;
+    use crate::schema::TaskSubmissionRequest;
+
+    fn temp_storage() -> TaskStorage {
+        TaskStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    fn queue_task(storage: &TaskStorage, cap: &str) -> TaskId {
+        let task = UnassignedTask {
+            id: TaskId::new_with_cap(cap.to_string()),
+            data: TaskSubmissionRequest {
+                capability: cap.to_string(),
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        storage.add_unassigned(&task).unwrap();
+        task.id
+    }
+
+    #[test]
+    fn queue_position_drops_as_earlier_tasks_are_assigned() {
+        let storage = temp_storage();
+        let mut ids: Vec<TaskId> = (0..3).map(|_| queue_task(&storage, "debug.echo")).collect();
+        // Ids from the same millisecond are not ordered by creation.
+        ids.sort_by(|a, b| a.id.cmp(&b.id));
+        queue_task(&storage, "debug.other");
+
+        assert_eq!(storage.queue_position(&ids[2]).unwrap(), Some(2));
+        storage.assign_task(&ids[0], "agent-a").unwrap();
+        assert_eq!(storage.queue_position(&ids[2]).unwrap(), Some(1));
+        storage.assign_task(&ids[1], "agent-a").unwrap();
+        assert_eq!(storage.queue_position(&ids[2]).unwrap(), Some(0));
+        assert_eq!(storage.queue_position(&ids[0]).unwrap(), None);
+    }
+
+    #[test]
+    fn queue_position_counts_higher_priorities_of_the_same_key_only() {
+        let storage = temp_storage();
+        let submit = |api_key: &str, priority: i32| {
+            let task = UnassignedTask {
+                id: TaskId::new_with_cap("debug.echo".to_string()),
+                data: TaskSubmissionRequest {
+                    capability: "debug.echo".to_string(),
+                    api_key: api_key.to_string(),
+                    priority,
+                    ..Default::default()
+                },
+                created_at: Utc::now(),
+                failed_agents: Vec::new(),
+                next_eligible_at: None,
+                history: Vec::new(),
+            };
+            storage.add_unassigned(&task).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            task.id
+        };
+        let mine = submit("key-a", 0);
+        let urgent_own = submit("key-a", 5);
+        let urgent_other = submit("key-b", 5);
+
+        // Only the later, higher-priority task of the same key is ahead.
+        assert_eq!(storage.queue_position(&mine).unwrap(), Some(1));
+        assert_eq!(storage.queue_position(&urgent_own).unwrap(), Some(0));
+        // Other keys' priorities never jump the line.
+        assert_eq!(storage.queue_position(&urgent_other).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn tasks_past_their_deadline_are_failed_or_requeued() {
+        let storage = temp_storage();
+        let queue_with_deadline = |restartable: bool| {
+            let task = UnassignedTask {
+                id: TaskId::new_with_cap("debug.echo".to_string()),
+                data: TaskSubmissionRequest {
+                    capability: "debug.echo".to_string(),
+                    restartable,
+                    deadline_secs: Some(1),
+                    ..Default::default()
+                },
+                created_at: Utc::now(),
+                failed_agents: Vec::new(),
+                next_eligible_at: None,
+                history: Vec::new(),
+            };
+            storage.add_unassigned(&task).unwrap();
+            task.id
+        };
+        let one_shot = queue_with_deadline(false);
+        let restartable = queue_with_deadline(true);
+        let no_deadline = queue_task(&storage, "debug.echo");
+        for id in [&one_shot, &restartable, &no_deadline] {
+            let mut task = storage.assign_task(id, "agent-a").unwrap();
+            task.change_status(TaskStatus::Running);
+            storage.update_assigned(&task).unwrap();
+        }
+        // Still within the deadline: nothing happens.
+        assert!(storage.fail_past_deadline(3).unwrap().agents.is_empty());
+
+        for id in [&one_shot, &restartable, &no_deadline] {
+            let mut task = storage.get_assigned(id).unwrap().unwrap();
+            task.assigned_at -= chrono::Duration::seconds(2);
+            storage.update_assigned(&task).unwrap();
+        }
+        let expired = storage.fail_past_deadline(3).unwrap();
+        assert_eq!(expired.agents, vec!["agent-a", "agent-a"]);
+        assert_eq!(expired.requeued.len(), 1);
+        assert_eq!(expired.requeued[0].id, restartable);
+        assert!(storage.get_unassigned(&restartable).unwrap().is_some());
+        // The agent is told to stop both tasks taken from it.
+        let mut interrupted: Vec<TaskId> =
+            expired.interrupted.into_iter().map(|(_, id)| id).collect();
+        interrupted.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut expected = vec![one_shot.clone(), restartable.clone()];
+        expected.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(interrupted, expected);
+
+        let failed = storage.get_assigned(&one_shot).unwrap().unwrap();
+        assert_eq!(failed.status, TaskStatus::Failed);
+        assert!(failed.log.unwrap().contains("deadline exceeded"));
+        assert_eq!(
+            storage.get_assigned(&no_deadline).unwrap().unwrap().status,
+            TaskStatus::Running
+        );
+        // A second sweep finds nothing left to do.
+        assert!(storage.fail_past_deadline(3).unwrap().agents.is_empty());
+    }
+
+    #[test]
+    fn deadline_sweep_does_not_overwrite_a_finished_report() {
+        let storage = temp_storage();
+        let id = queue_task(&storage, "debug.echo");
+        let mut task = storage.assign_task(&id, "agent-a").unwrap();
+        task.data.deadline_secs = Some(1);
+        task.assigned_at -= chrono::Duration::seconds(2);
+        task.change_status(TaskStatus::Completed);
+        task.result = Some(serde_json::json!({ "text": "done" }));
+        storage.update_assigned(&task).unwrap();
+
+        let expired = storage.fail_past_deadline(3).unwrap();
+        assert!(expired.agents.is_empty());
+        assert!(expired.interrupted.is_empty());
+        let stored = storage.get_assigned(&id).unwrap().unwrap();
+        assert_eq!(stored.status, TaskStatus::Completed);
+        assert!(storage.get_dead_letter(&id).unwrap().is_none());
+    }
+
+    #[test]
+    fn tasks_failed_by_a_sweep_queue_their_callbacks() {
+        let storage = temp_storage();
+        let (tx, mut rx) = mpsc::channel(16);
+        storage.send_callbacks_to(tx);
+        let id = queue_task(&storage, "debug.echo");
+        let mut task = storage.assign_task(&id, "agent-a").unwrap();
+        task.data.callback_url = Some("https://example.com/hook".to_string());
+        task.data.deadline_secs = Some(1);
+        task.assigned_at -= chrono::Duration::seconds(2);
+        task.change_status(TaskStatus::Running);
+        storage.update_assigned(&task).unwrap();
+        assert!(rx.try_recv().is_err());
+
+        storage.fail_past_deadline(3).unwrap();
+        let delivery = rx.try_recv().unwrap();
+        assert_eq!(delivery.task_id, id);
+        let report: serde_json::Value = serde_json::from_slice(&delivery.body).unwrap();
+        assert_eq!(report["status"], "failed");
+        assert!(rx.try_recv().is_err());
+
+        // Failed deliveries are noted on the task wherever it now lives.
+        storage
+            .force_archive_older_than(Utc::now() + chrono::Duration::seconds(1), 0)
+            .unwrap();
+        let event = TaskEvent {
+            timestamp: Utc::now(),
+            description: "Callback failed".to_string(),
+        };
+        assert!(storage.add_history(&id, &event).unwrap());
+        let archived = storage.get_archived(&id).unwrap().unwrap();
+        assert_eq!(
+            archived.history.last().unwrap().description,
+            "Callback failed"
+        );
+    }
+
+    #[test]
+    fn dead_lettered_task_can_be_requeued() {
+        let storage = temp_storage();
+        let id = queue_task(&storage, "debug.echo");
+        let mut task = storage.assign_task(&id, "agent-a").unwrap();
+        task.data.deadline_secs = Some(1);
+        task.assigned_at -= chrono::Duration::seconds(2);
+        task.failed_agents.push("agent-b".to_string());
+        storage.update_assigned(&task).unwrap();
+
+        storage.fail_past_deadline(3).unwrap();
+        let letter = storage.get_dead_letter(&id).unwrap().unwrap();
+        assert_eq!(letter.task.status, TaskStatus::Failed);
+        assert!(letter.failure_reason.starts_with("deadline exceeded"));
+        assert_eq!(storage.list_dead_letter_all().unwrap().len(), 1);
+
+        let requeued = storage.requeue_dead_letter(&id).unwrap().unwrap();
+        assert!(requeued.failed_agents.is_empty());
+        assert!(storage.get_dead_letter(&id).unwrap().is_none());
+        assert!(storage.get_assigned(&id).unwrap().is_none());
+        assert!(storage.get_unassigned(&id).unwrap().is_some());
+        assert!(storage.requeue_dead_letter(&id).unwrap().is_none());
+    }
+
+    #[test]
+    fn orphans_of_offline_agents_are_requeued_or_failed() {
+        let storage = temp_storage();
+        let restartable = TaskId::new_with_cap("debug.echo".to_string());
+        storage
+            .add_unassigned(&UnassignedTask {
+                id: restartable.clone(),
+                data: TaskSubmissionRequest {
+                    capability: "debug.echo".to_string(),
+                    restartable: true,
+                    ..Default::default()
+                },
+                created_at: Utc::now(),
+                failed_agents: Vec::new(),
+                next_eligible_at: None,
+                history: Vec::new(),
+            })
+            .unwrap();
+        let one_shot = queue_task(&storage, "debug.echo");
+        let held_by_online = queue_task(&storage, "debug.echo");
+        for (id, agent) in [
+            (&restartable, "stale"),
+            (&one_shot, "stale"),
+            (&held_by_online, "alive"),
+        ] {
+            let mut task = storage.assign_task(id, agent).unwrap();
+            task.change_status(TaskStatus::Running);
+            task.last_update_at = Some(Utc::now() - chrono::Duration::hours(1));
+            storage.update_assigned(&task).unwrap();
+        }
+
+        let recovery = storage
+            .recover_orphaned_assigned(60, 3, |agent| agent == "alive")
+            .unwrap();
+        assert_eq!(recovery.agents, vec!["stale", "stale"]);
+        assert_eq!(recovery.requeued.len(), 1);
+        assert_eq!(recovery.requeued[0].id, restartable);
+        assert_eq!(recovery.requeued[0].failed_agents, vec!["stale"]);
+
+        assert!(storage.get_assigned(&restartable).unwrap().is_none());
+        assert!(storage.get_unassigned(&restartable).unwrap().is_some());
+        assert_eq!(
+            storage.get_assigned(&one_shot).unwrap().unwrap().status,
+            TaskStatus::Failed
+        );
+        assert_eq!(
+            storage
+                .get_assigned(&held_by_online)
+                .unwrap()
+                .unwrap()
+                .status,
+            TaskStatus::Running
+        );
+    }
+
+    #[test]
+    fn deregistered_agent_releases_only_its_own_tasks() {
+        let storage = temp_storage();
+        let restartable = TaskId::new_with_cap("debug.echo".to_string());
+        storage
+            .add_unassigned(&UnassignedTask {
+                id: restartable.clone(),
+                data: TaskSubmissionRequest {
+                    capability: "debug.echo".to_string(),
+                    restartable: true,
+                    ..Default::default()
+                },
+                created_at: Utc::now(),
+                failed_agents: Vec::new(),
+                next_eligible_at: None,
+                history: Vec::new(),
+            })
+            .unwrap();
+        let one_shot = queue_task(&storage, "debug.echo");
+        let other_agents = queue_task(&storage, "debug.echo");
+        storage.assign_task(&restartable, "leaving").unwrap();
+        storage.assign_task(&one_shot, "leaving").unwrap();
+        storage.assign_task(&other_agents, "staying").unwrap();
+
+        let released = storage.release_agent_tasks("leaving", 3).unwrap();
+        assert_eq!(released.agents, vec!["leaving", "leaving"]);
+        assert_eq!(released.requeued.len(), 1);
+        assert_eq!(released.requeued[0].id, restartable);
+        assert!(storage.get_unassigned(&restartable).unwrap().is_some());
+
+        let failed = storage.get_assigned(&one_shot).unwrap().unwrap();
+        assert_eq!(failed.status, TaskStatus::Failed);
+        assert!(failed.log.unwrap().contains("deregistered"));
+        assert_eq!(
+            storage.get_assigned(&other_agents).unwrap().unwrap().status,
+            TaskStatus::Assigned
+        );
+    }
+
+    #[test]
+    fn batch_of_unassigned_tasks_is_stored_together() {
+        let storage = temp_storage();
+        let tasks: Vec<UnassignedTask> = (0..100)
+            .map(|i| UnassignedTask {
+                id: TaskId::new_with_cap("debug.echo".to_string()),
+                data: TaskSubmissionRequest {
+                    capability: "debug.echo".to_string(),
+                    payload: serde_json::json!({ "n": i }),
+                    ..Default::default()
+                },
+                created_at: Utc::now(),
+                failed_agents: Vec::new(),
+                next_eligible_at: None,
+                history: Vec::new(),
+            })
+            .collect();
+        storage.add_unassigned_batch(&tasks).unwrap();
+
+        assert_eq!(storage.list_unassigned_all().unwrap().len(), 100);
+        for task in &tasks {
+            let stored = storage.get_unassigned(&task.id).unwrap().unwrap();
+            assert_eq!(stored.data.payload, task.data.payload);
+        }
+    }
+
+    #[test]
+    fn concurrent_pickup_of_one_task_has_a_single_winner() {
+        for _ in 0..20 {
+            let storage = Arc::new(temp_storage());
+            let id = queue_task(&storage, "debug.echo");
+            let handles: Vec<_> = ["agent-a", "agent-b"]
+                .into_iter()
+                .map(|agent| {
+                    let storage = storage.clone();
+                    let id = id.clone();
+                    thread::spawn(move || storage.assign_task(&id, agent))
+                })
+                .collect();
+            let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+            let winners: Vec<&AssignedTask> = results.iter().flatten().collect();
+            assert_eq!(winners.len(), 1);
+            assert!(
+                results
+                    .iter()
+                    .any(|r| matches!(r, Err(AppError::Conflict(_))))
+            );
+            let stored = storage.get_assigned(&id).unwrap().unwrap();
+            assert_eq!(stored.agent_id, winners[0].agent_id);
+            assert!(storage.get_unassigned(&id).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn concurrent_pickups_of_overlapping_tasks_never_double_assign() {
+        let storage = Arc::new(temp_storage());
+        let ids: Vec<TaskId> = (0..50)
+            .map(|_| queue_task(&storage, "debug.echo"))
+            .collect();
+
+        // Both agents target every task, in opposite orders, to maximise overlap.
+        let handles: Vec<_> = ["agent-a", "agent-b"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, agent)| {
+                let storage = storage.clone();
+                let mut candidates = ids.clone();
+                if i == 1 {
+                    candidates.reverse();
+                }
+                thread::spawn(move || {
+                    candidates
+                        .iter()
+                        .filter_map(|id| storage.assign_task(id, agent).ok())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let results: Vec<Vec<AssignedTask>> =
+            handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        let mut seen = HashSet::new();
+        for task in results.iter().flatten() {
+            assert!(seen.insert(task.id.clone()), "{} assigned twice", task.id);
+            let stored = storage.get_assigned(&task.id).unwrap().unwrap();
+            assert_eq!(stored.agent_id, task.agent_id);
+        }
+        assert_eq!(seen.len(), ids.len());
+        assert!(storage.list_unassigned_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn client_task_id_lookup_is_scoped_to_api_key() {
+        let storage = temp_storage();
+        let id = TaskId::new_with_cap("llm.qwen3[vision]".to_string());
+        assert!(
+            storage
+                .claim_client_task_id("key-a", "order-1", &id, None)
+                .unwrap()
+                .is_none()
+        );
+
+        assert_eq!(
+            storage.find_by_client_task_id("key-a", "order-1").unwrap(),
+            Some(id)
+        );
+        assert!(
+            storage
+                .find_by_client_task_id("key-b", "order-1")
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            storage
+                .find_by_client_task_id("key-a", "order-2")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn duplicate_client_task_id_returns_existing_task() {
+        let storage = temp_storage();
+        let first = queue_task(&storage, "debug.echo");
+        let second = TaskId::new_with_cap("debug.echo".to_string());
+        storage
+            .claim_client_task_id("key-a", "order-1", &first, None)
+            .unwrap();
+
+        let existing = storage
+            .claim_client_task_id("key-a", "order-1", &second, None)
+            .unwrap();
+        assert_eq!(existing, Some(first.clone()));
+        // Releasing on behalf of the loser must not drop the winner's entry.
+        storage
+            .release_client_task_id("key-a", "order-1", &second)
+            .unwrap();
+        assert_eq!(
+            storage.find_by_client_task_id("key-a", "order-1").unwrap(),
+            Some(first.clone())
+        );
+
+        storage
+            .release_client_task_id("key-a", "order-1", &first)
+            .unwrap();
+        assert!(
+            storage
+                .claim_client_task_id("key-a", "order-1", &second, None)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn client_task_id_is_reusable_once_expired() {
+        let storage = temp_storage();
+        let first = queue_task(&storage, "debug.echo");
+        let second = TaskId::new_with_cap("debug.echo".to_string());
+        let third = TaskId::new_with_cap("debug.echo".to_string());
+        assert_eq!(
+            storage
+                .claim_client_task_id("key-a", "order-1", &first, None)
+                .unwrap(),
+            None
+        );
+
+        // Within the window the same key keeps returning the first task.
+        let window_start = Utc::now() - chrono::TimeDelta::seconds(60);
+        for _ in 0..2 {
+            assert_eq!(
+                storage
+                    .claim_client_task_id("key-a", "order-1", &second, Some(window_start))
+                    .unwrap(),
+                Some(first.clone())
+            );
+        }
+
+        // Once the first task is older than the window, the key moves on.
+        let later = Utc::now() + chrono::TimeDelta::seconds(1);
+        assert_eq!(
+            storage
+                .claim_client_task_id("key-a", "order-1", &third, Some(later))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            storage.find_by_client_task_id("key-a", "order-1").unwrap(),
+            Some(third)
+        );
+    }
+
+    #[test]
+    fn late_reports_are_kept_until_reset() {
+        use crate::schema::{TaskResultReport, TaskResultStatus};
+
+        let storage = temp_storage();
+        let id = TaskId::new_with_cap("debug.echo".to_string());
+        let report = TaskResultReport {
+            id: id.clone(),
+            capability: id.cap.clone(),
+            status: TaskResultStatus::Success(1.0),
+            output: None,
+        };
+        storage
+            .store_late_report(&LateReport::new("agent-a", report))
+            .unwrap();
+
+        let stored = storage.list_late_reports(10).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].agent_id, "agent-a");
+        assert_eq!(stored[0].report.id, id);
+
+        storage.hard_clear().unwrap();
+        assert!(storage.list_late_reports(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn stuck_running_task_is_force_failed_and_archived_past_max_age() {
+        let storage = temp_storage();
+        let stuck = queue_task(&storage, "llm.qwen");
+        let mut task = storage.assign_task(&stuck, "agent-dead").unwrap();
+        task.change_status(TaskStatus::Running);
+        storage.update_assigned(&task).unwrap();
+        let done = queue_task(&storage, "debug.echo");
+        let mut task = storage.assign_task(&done, "agent-a").unwrap();
+        task.change_status(TaskStatus::Completed);
+        storage.update_assigned(&task).unwrap();
+
+        let created = task.created_at;
+        let young = created + chrono::TimeDelta::seconds(3599);
+        assert_eq!(
+            storage
+                .force_archive_older_than(young, 3600)
+                .unwrap()
+                .archived,
+            0
+        );
+        assert!(storage.get_assigned(&stuck).unwrap().is_some());
+
+        let old = created + chrono::TimeDelta::seconds(3601);
+        let forced = storage.force_archive_older_than(old, 3600).unwrap();
+        assert_eq!(forced.archived, 2);
+        // Only the task still held by an agent needs a cancel pushed.
+        assert_eq!(
+            forced.interrupted,
+            vec![("agent-dead".to_string(), stuck.clone())]
+        );
+        assert!(storage.get_assigned(&stuck).unwrap().is_none());
+        let archived: AssignedTask = versioned::decode(
+            &storage
+                .archived
+                .get(TaskStorage::make_key(&stuck).as_bytes())
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(archived.status, TaskStatus::Failed);
+        assert!(
+            archived
+                .history
+                .iter()
+                .any(|e| e.description.starts_with("Forced closure"))
+        );
+        // Already-terminal tasks are archived as they are.
+        let archived: AssignedTask = versioned::decode(
+            &storage
+                .archived
+                .get(TaskStorage::make_key(&done).as_bytes())
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(archived.status, TaskStatus::Completed);
+    }
+
+    #[test]
+    fn queued_task_past_max_age_is_failed_and_archived() {
+        let storage = temp_storage();
+        let queued = queue_task(&storage, "debug.echo");
+        let created = storage.get_unassigned(&queued).unwrap().unwrap().created_at;
+
+        let forced = storage
+            .force_archive_older_than(created + chrono::TimeDelta::seconds(61), 60)
+            .unwrap();
+        assert_eq!(forced.archived, 1);
+        assert_eq!(forced.dequeued, vec![queued.clone()]);
+        assert!(forced.interrupted.is_empty());
+        assert!(storage.get_unassigned(&queued).unwrap().is_none());
+        let archived = storage.get_archived(&queued).unwrap().unwrap();
+        assert_eq!(archived.status, TaskStatus::Failed);
+        assert!(
+            archived
+                .history
+                .iter()
+                .any(|e| e.description.starts_with("Forced closure"))
+        );
+    }
+
+    #[test]
+    fn archived_tasks_are_readable_by_id_and_by_page() {
+        let storage = temp_storage();
+        let mut ids = Vec::new();
+        for cap in ["debug.echo", "debug.echo", "debug.echo", "llm.qwen"] {
+            let id = queue_task(&storage, cap);
+            let mut task = storage.assign_task(&id, "agent-a").unwrap();
+            task.change_status(TaskStatus::Completed);
+            task.result = Some(serde_json::json!({ "text": "done" }));
+            storage.update_assigned(&task).unwrap();
+            ids.push(id);
+        }
+        let later = Utc::now() + chrono::TimeDelta::seconds(1);
+        assert_eq!(
+            storage.force_archive_older_than(later, 0).unwrap().archived,
+            4
+        );
+
+        let archived = storage.get_archived(&ids[0]).unwrap().unwrap();
+        assert_eq!(archived.status, TaskStatus::Completed);
+        assert_eq!(archived.result, Some(serde_json::json!({ "text": "done" })));
+        assert_eq!(storage.list_archived_all().unwrap().len(), 4);
+        assert_eq!(
+            storage
+                .list_archived_for_capability("debug.echo")
+                .unwrap()
+                .len(),
+            3
+        );
+
+        let (first, cursor) = storage
+            .list_archived_page(Some("debug.echo"), 2, None)
+            .unwrap();
+        assert_eq!(first.len(), 2);
+        let (rest, cursor) = storage
+            .list_archived_page(Some("debug.echo"), 2, cursor.as_deref())
+            .unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(cursor, None);
+        assert!(
+            first
+                .iter()
+                .chain(&rest)
+                .all(|task| task.id.cap == "debug.echo")
+        );
+        assert_ne!(first[1].id, rest[0].id);
+    }
+
+    #[test]
+    fn task_listing_is_scoped_to_the_submitting_key() {
+        let storage = temp_storage();
+        let submit = |cap: &str, api_key: &str| {
+            let task = UnassignedTask {
+                id: TaskId::new_with_cap(cap.to_string()),
+                data: TaskSubmissionRequest {
+                    capability: cap.to_string(),
+                    api_key: api_key.to_string(),
+                    ..Default::default()
+                },
+                created_at: Utc::now(),
+                failed_agents: Vec::new(),
+                next_eligible_at: None,
+                history: Vec::new(),
+            };
+            storage.add_unassigned(&task).unwrap();
+            task.id
+        };
+        let archived = submit("debug.echo", "key-a");
+        let mut done = storage.assign_task(&archived, "agent-a").unwrap();
+        done.change_status(TaskStatus::Completed);
+        storage.update_assigned(&done).unwrap();
+        storage
+            .force_archive_older_than(Utc::now() + chrono::TimeDelta::seconds(1), 0)
+            .unwrap();
+        let queued = submit("debug.echo", "key-a");
+        let running = submit("llm.qwen[vision]", "key-a");
+        let other = submit("debug.echo", "key-b");
+        storage.assign_task(&running, "agent-a").unwrap();
+        storage.assign_task(&other, "agent-b").unwrap();
+
+        let all = TaskListFilter::default();
+        let (tasks, cursor) = storage.list_for_api_key("key-a", &all, 10, None).unwrap();
+        let mut ids: Vec<TaskId> = tasks.iter().map(|task| task.id.clone()).collect();
+        ids.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut expected = vec![queued.clone(), running.clone(), archived.clone()];
+        expected.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(ids, expected);
+        assert_eq!(cursor, None);
+        let (tasks, _) = storage.list_for_api_key("key-b", &all, 10, None).unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].id, other);
+        let (tasks, _) = storage.list_for_api_key("key-c", &all, 10, None).unwrap();
+        assert!(tasks.is_empty());
+
+        let by_cap = TaskListFilter {
+            capability: Some("llm.qwen".to_string()),
+            status: None,
+        };
+        let (tasks, _) = storage
+            .list_for_api_key("key-a", &by_cap, 10, None)
+            .unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].id, running);
+        let by_status = TaskListFilter {
+            capability: None,
+            status: Some(TaskStatus::Queued),
+        };
+        let (tasks, _) = storage
+            .list_for_api_key("key-a", &by_status, 10, None)
+            .unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].id, queued);
+
+        let (first, cursor) = storage.list_for_api_key("key-a", &all, 2, None).unwrap();
+        let (rest, cursor) = storage
+            .list_for_api_key("key-a", &all, 2, cursor.as_deref())
+            .unwrap();
+        assert_eq!((first.len(), rest.len(), cursor), (2, 1, None));
+    }
+
+    #[test]
+    fn completed_output_is_purged_after_result_ttl() {
+        let storage = temp_storage();
+        let secret = queue_task(&storage, "llm.secret[vision]");
+        let kept = queue_task(&storage, "debug.echo");
+        for id in [&secret, &kept] {
+            let mut task = storage.assign_task(id, "agent-a").unwrap();
+            task.result = Some(serde_json::json!({"text": "hunter2"}));
+            task.change_status(TaskStatus::Completed);
+            storage.update_assigned(&task).unwrap();
+        }
+        let ttl = |cap: &str| (cap == "llm.secret").then_some(60);
+
+        let finished = storage
+            .get_assigned(&secret)
+            .unwrap()
+            .unwrap()
+            .finished_at
+            .unwrap();
+        let before = finished + chrono::TimeDelta::seconds(59);
+        assert_eq!(storage.purge_expired_results(before, ttl).unwrap(), 0);
+        let report = storage
+            .get_assigned(&secret)
+            .unwrap()
+            .unwrap()
+            .into_status_report();
+        assert!(report.output.is_some());
+        assert!(!report.output_expired);
+
+        let after = finished + chrono::TimeDelta::seconds(60);
+        assert_eq!(storage.purge_expired_results(after, ttl).unwrap(), 1);
+        let report = storage
+            .get_assigned(&secret)
+            .unwrap()
+            .unwrap()
+            .into_status_report();
+        assert_eq!(report.status, TaskStatus::Completed);
+        assert!(report.output.is_none());
+        assert!(report.output_expired);
+        assert!(
+            storage
+                .get_assigned(&kept)
+                .unwrap()
+                .unwrap()
+                .result
+                .is_some()
+        );
+
+        // Already purged tasks are not counted again.
+        assert_eq!(storage.purge_expired_results(after, ttl).unwrap(), 0);
+    }
+
+    #[test]
+    fn expired_results_of_archived_tasks_are_purged() {
+        let storage = temp_storage();
+        let id = queue_task(&storage, "llm.secret");
+        let mut task = storage.assign_task(&id, "agent-a").unwrap();
+        task.result = Some(serde_json::json!({"text": "hunter2"}));
+        task.change_status(TaskStatus::Completed);
+        storage.update_assigned(&task).unwrap();
+        let finished = storage
+            .get_assigned(&id)
+            .unwrap()
+            .unwrap()
+            .finished_at
+            .unwrap();
+        storage
+            .force_archive_older_than(Utc::now() + chrono::TimeDelta::seconds(1), 0)
+            .unwrap();
+        assert!(storage.get_assigned(&id).unwrap().is_none());
+
+        let ttl = |_: &str| Some(60);
+        let after = finished + chrono::TimeDelta::seconds(60);
+        assert_eq!(storage.purge_expired_results(after, ttl).unwrap(), 1);
+        let archived = storage.get_archived(&id).unwrap().unwrap();
+        assert_eq!(archived.status, TaskStatus::Completed);
+        assert!(archived.result.is_none());
+        assert_eq!(archived.output_expired_at, Some(after));
+    }
+
+    #[test]
+    fn transitions_are_read_back_in_order_by_cursor() {
+        let storage = temp_storage();
+        let id = queue_task(&storage, "debug.echo");
+        let mut task = storage.assign_task(&id, "agent-a").unwrap();
+        task.change_status(TaskStatus::Running);
+        storage.update_assigned(&task).unwrap();
+        // Touching the task without a status change records nothing.
+        task.append_log(Some("working".to_string()));
+        storage.update_assigned(&task).unwrap();
+        task.change_status(TaskStatus::Completed);
+        storage.update_assigned(&task).unwrap();
+
+        let events = storage.events();
+        let (first, has_more) = events.list_since(None, 2).unwrap();
+        assert!(has_more);
+        let (rest, has_more) = events.list_since(Some(&first[1].record_id), 10).unwrap();
+        assert!(!has_more);
+        let steps: Vec<_> = first
+            .iter()
+            .chain(&rest)
+            .map(|t| (t.from.clone(), t.to.clone()))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                (None, TaskStatus::Pending),
+                (Some(TaskStatus::Pending), TaskStatus::Assigned),
+                (Some(TaskStatus::Assigned), TaskStatus::Running),
+                (Some(TaskStatus::Running), TaskStatus::Completed),
+            ]
+        );
+        assert!(rest.iter().all(|t| t.task_id == id && !t.urgent));
+        assert_eq!(rest[0].agent_id.as_deref(), Some("agent-a"));
+
+        let (none, _) = events.list_since(Some(&rest[1].record_id), 10).unwrap();
+        assert!(none.is_empty());
+    }
+}
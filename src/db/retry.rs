@@ -0,0 +1,121 @@
+//! Bounded retry for sled writes that fail with transient errors.
+//!
+//! Under contention sled can surface short-lived I/O conditions (interrupted
+//! syscalls, `WouldBlock`, timeouts) that succeed when simply retried. Anything
+//! else — corruption, unsupported operations, a genuine `NotFound` — is
+//! permanent and returned on the first attempt.
+
+use std::{io::ErrorKind, thread, time::Duration};
+
+use log::warn;
+use sled::transaction::TransactionError;
+
+/// Total attempts, including the first one.
+pub const MAX_ATTEMPTS: u32 = 4;
+/// Backoff before the first retry; doubled for each subsequent one.
+const BASE_BACKOFF: Duration = Duration::from_millis(2);
+
+/// Errors that can tell whether retrying the failed operation may succeed.
+pub trait TransientError {
+    fn is_transient(&self) -> bool;
+}
+
+impl TransientError for sled::Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            sled::Error::Io(e) => matches!(
+                e.kind(),
+                ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl<A> TransientError for TransactionError<A> {
+    fn is_transient(&self) -> bool {
+        match self {
+            // An abort is the transaction's own decision, never a storage hiccup.
+            TransactionError::Abort(_) => false,
+            TransactionError::Storage(e) => e.is_transient(),
+        }
+    }
+}
+
+/// Run `op`, retrying with exponential backoff while it fails with a transient
+/// error, up to [`MAX_ATTEMPTS`] attempts. `what` names the operation in logs.
+/// The backoff sleeps the calling thread; it totals a few milliseconds, so it
+/// is acceptable on the async paths that call this.
+pub fn retry_transient<T, E, F>(what: &str, mut op: F) -> Result<T, E>
+where
+    E: TransientError + std::fmt::Debug,
+    F: FnMut() -> Result<T, E>,
+{
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(e) if e.is_transient() && attempt < MAX_ATTEMPTS => {
+                let backoff = BASE_BACKOFF * 2u32.pow(attempt - 1);
+                warn!(
+                    "{} failed with transient error (attempt {}/{}), retrying in {:?}: {:?}",
+                    what, attempt, MAX_ATTEMPTS, backoff, e
+                );
+                thread::sleep(backoff);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, io};
+
+    use super::*;
+
+    fn io_error(kind: ErrorKind) -> sled::Error {
+        sled::Error::Io(io::Error::new(kind, "simulated"))
+    }
+
+    #[test]
+    fn transient_error_is_retried_until_success() {
+        let calls = Cell::new(0);
+        let result = retry_transient("test write", || {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 {
+                Err(io_error(ErrorKind::Interrupted))
+            } else {
+                Ok("written")
+            }
+        });
+        assert_eq!(result.unwrap(), "written");
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn transient_error_gives_up_after_max_attempts() {
+        let calls = Cell::new(0);
+        let result: sled::Result<()> = retry_transient("test write", || {
+            calls.set(calls.get() + 1);
+            Err(io_error(ErrorKind::TimedOut))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls.get(), MAX_ATTEMPTS);
+    }
+
+    #[test]
+    fn permanent_error_is_not_retried() {
+        let calls = Cell::new(0);
+        let result: sled::Result<()> = retry_transient("test write", || {
+            calls.set(calls.get() + 1);
+            Err(io_error(ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+
+        let aborted: TransactionError<()> = TransactionError::Abort(());
+        assert!(!aborted.is_transient());
+        assert!(TransactionError::<()>::Storage(io_error(ErrorKind::WouldBlock)).is_transient());
+    }
+}
@@ -22,7 +22,7 @@ pub struct ServiceMessage {
 /// This enables efficient prefix-based range scans by class.
 /// message_kind is stored only in the value — not indexed.
 pub struct ServiceMessageStorage {
-    _db: Db,
+    db: Db,
     tree: sled::Tree,
 }
 
@@ -30,7 +30,13 @@ impl ServiceMessageStorage {
     pub fn open(path: &str) -> Result<Self> {
         let db = sled::open(path)?;
         let tree = db.open_tree("service_messages")?;
-        Ok(Self { _db: db, tree })
+        Ok(Self { db, tree })
+    }
+
+    /// Wait until every write so far is durable on disk.
+    pub async fn flush(&self) -> Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
     }
 
     /// Append a new message. record_id is generated automatically.
@@ -0,0 +1,194 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    db::{urgent_audit::next_record_id, versioned},
+    schema::{TaskId, TaskStatus},
+};
+
+/// One status change of a task, regular or urgent.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskTransition {
+    /// Time-sortable UID given by [`TaskEventLog::append`] when the record is
+    /// stored; the storage key and the cursor clients page with.
+    pub record_id: String,
+    pub task_id: TaskId,
+    pub urgent: bool,
+    /// Status before the transition; `None` when the task was just submitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<TaskStatus>,
+    pub to: TaskStatus,
+    /// Agent holding the task at the time of the transition, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl TaskTransition {
+    pub fn new(
+        task_id: &TaskId,
+        urgent: bool,
+        from: Option<TaskStatus>,
+        to: TaskStatus,
+        agent_id: Option<&str>,
+    ) -> Self {
+        Self {
+            record_id: String::new(),
+            task_id: task_id.clone(),
+            urgent,
+            from,
+            to,
+            agent_id: agent_id.map(str::to_string),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// Append-only changefeed of task status transitions, behind
+/// `GET /management/events`.
+///
+/// Key format: `{record_id}` in `task_events`, in storage order. Records are
+/// never updated; [`TaskEventLog::compact_before`] drops the oldest ones once
+/// they fall out of the retention window.
+///
+/// Urgent transitions reach the log through the background DB write queue,
+/// so they may be stored after later regular ones (`timestamp` still says
+/// when they happened) and are dropped when that queue is full.
+pub struct TaskEventLog {
+    records: sled::Tree,
+    /// Held across id generation and insert.
+    append_lock: Mutex<()>,
+}
+
+impl TaskEventLog {
+    pub(crate) fn from_tree(records: sled::Tree) -> Self {
+        Self {
+            records,
+            append_lock: Mutex::new(()),
+        }
+    }
+
+    /// Store `record` under a fresh `record_id`. Ids are taken together with
+    /// the insert, so they follow storage order and a cursor handed to a
+    /// reader never passes a record stored after it.
+    pub fn append(&self, record: &mut TaskTransition) -> Result<()> {
+        let _append = self.append_lock.lock().unwrap();
+        record.record_id = next_record_id();
+        let bytes = versioned::encode(record)?;
+        self.records.insert(record.record_id.as_bytes(), bytes)?;
+        Ok(())
+    }
+
+    /// Up to `limit` transitions recorded after `cursor` (exclusive), oldest
+    /// first. Pass `None` to start from the oldest record kept.
+    ///
+    /// Returns `(items, has_more)`.
+    pub fn list_since(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<TaskTransition>, bool)> {
+        let lower = cursor.unwrap_or_default();
+        let mut items = Vec::new();
+        for item in self.records.range(lower.as_bytes()..) {
+            let (key, value) = item?;
+            if key.as_ref() == lower.as_bytes() {
+                continue;
+            }
+            if items.len() == limit {
+                return Ok((items, true));
+            }
+            items.push(versioned::decode::<TaskTransition>(&value)?);
+        }
+        Ok((items, false))
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.records.clear()?;
+        Ok(())
+    }
+
+    /// Drop every transition recorded before `cutoff`. Returns how many were
+    /// removed.
+    pub fn compact_before(&self, cutoff: DateTime<Utc>) -> Result<usize> {
+        let upper = Ulid::from_parts(cutoff.timestamp_millis().max(0) as u64, 0).to_string();
+        let mut batch = sled::Batch::default();
+        let mut removed = 0;
+        for item in self.records.range(..upper.as_bytes()) {
+            let (key, _) = item?;
+            batch.remove(key);
+            removed += 1;
+        }
+        self.records.apply_batch(batch)?;
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    #[test]
+    fn compaction_drops_only_transitions_past_the_cutoff() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let log = TaskEventLog::from_tree(db.open_tree("task_events").unwrap());
+        let task = TaskId::new_with_cap("debug.echo".to_string());
+        let mut old = TaskTransition::new(&task, false, None, TaskStatus::Pending, None);
+        old.record_id = Ulid::from_parts(
+            (Utc::now() - TimeDelta::hours(2)).timestamp_millis() as u64,
+            0,
+        )
+        .to_string();
+        log.records
+            .insert(old.record_id.as_bytes(), versioned::encode(&old).unwrap())
+            .unwrap();
+        let mut recent = TaskTransition::new(
+            &task,
+            false,
+            Some(TaskStatus::Pending),
+            TaskStatus::Assigned,
+            Some("agent-a"),
+        );
+        log.append(&mut recent).unwrap();
+
+        let cutoff = Utc::now() - TimeDelta::hours(1);
+        assert_eq!(log.compact_before(cutoff).unwrap(), 1);
+        assert_eq!(log.compact_before(cutoff).unwrap(), 0);
+
+        // A cursor that was compacted away still resumes after it.
+        let (items, has_more) = log.list_since(Some(&old.record_id), 10).unwrap();
+        assert_eq!(items, vec![recent]);
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn cursor_does_not_pass_a_transition_stored_later() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let log = TaskEventLog::from_tree(db.open_tree("task_events").unwrap());
+        let task = TaskId::new_with_cap("debug.echo".to_string());
+        // Taken first, but queued and stored only after the next one.
+        let mut delayed = TaskTransition::new(&task, true, None, TaskStatus::Pending, None);
+        let mut stored = TaskTransition::new(
+            &task,
+            false,
+            Some(TaskStatus::Pending),
+            TaskStatus::Assigned,
+            Some("agent-a"),
+        );
+        log.append(&mut stored).unwrap();
+        let (items, _) = log.list_since(None, 10).unwrap();
+        let cursor = items.last().unwrap().record_id.clone();
+
+        log.append(&mut delayed).unwrap();
+        let (items, has_more) = log.list_since(Some(&cursor), 10).unwrap();
+        assert_eq!(items, vec![delayed]);
+        assert!(!has_more);
+    }
+}
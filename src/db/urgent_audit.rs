@@ -0,0 +1,252 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use ulid::{Generator, Ulid};
+
+use crate::{db::versioned, schema::TaskId, utils::time_sortable_uid};
+
+/// Monotonic, so transitions recorded within the same millisecond (submit and
+/// an immediate assign) still sort in the order they happened.
+static RECORD_IDS: Mutex<Generator> = Mutex::new(Generator::new());
+
+pub(crate) fn next_record_id() -> String {
+    RECORD_IDS
+        .lock()
+        .unwrap()
+        .generate()
+        .map(|ulid| ulid.to_string())
+        // Only fails once the random part overflows within one millisecond.
+        .unwrap_or_else(|_| time_sortable_uid())
+}
+
+/// A lifecycle transition of an urgent task.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum UrgentAuditEvent {
+    Submitted,
+    Assigned,
+    /// Handed back to the queue before the agent started it.
+    Unassigned,
+    Starting,
+    Running,
+    Completed,
+    Failed,
+    /// The pickup TTL, agent silence window or global timeout ran out.
+    Expired,
+    CancelRequested,
+    Canceled,
+}
+
+/// One immutable entry of the urgent task audit trail.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UrgentAuditRecord {
+    /// Time-sortable UID, taken when the transition happened — also the
+    /// storage key, so the log is ordered by transition time.
+    pub record_id: String,
+    pub task_id: TaskId,
+    pub event: UrgentAuditEvent,
+    /// Agent holding the task at the time of the transition, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl UrgentAuditRecord {
+    pub fn new(task_id: &TaskId, event: UrgentAuditEvent, agent_id: Option<&str>) -> Self {
+        Self {
+            record_id: next_record_id(),
+            task_id: task_id.clone(),
+            event,
+            agent_id: agent_id.map(str::to_string),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// Append-only audit trail of urgent task transitions. Urgent tasks live only
+/// in memory; their history outlives them here.
+///
+/// Key format: `{record_id}` in `urgent_audit` (time-ordered), plus an index
+/// `{task uuid}|{record_id}` -> `()` in `urgent_audit_by_task`.
+/// Nothing is ever updated or deleted.
+pub struct UrgentAuditStorage {
+    db: Db,
+    records: sled::Tree,
+    by_task: sled::Tree,
+}
+
+impl UrgentAuditStorage {
+    pub fn open(path: &str) -> Result<Self> {
+        Self::from_db(sled::open(path)?)
+    }
+
+    pub(crate) fn from_db(db: Db) -> Result<Self> {
+        let records = db.open_tree("urgent_audit")?;
+        let by_task = db.open_tree("urgent_audit_by_task")?;
+        Ok(Self {
+            db,
+            records,
+            by_task,
+        })
+    }
+
+    /// Wait until every write so far is durable on disk.
+    pub async fn flush(&self) -> Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    pub fn append(&self, record: &UrgentAuditRecord) -> Result<()> {
+        let bytes = versioned::encode(record)?;
+        self.records.insert(record.record_id.as_bytes(), bytes)?;
+        let index_key = format!("{}|{}", record.task_id.id, record.record_id);
+        self.by_task.insert(index_key.as_bytes(), &[])?;
+        Ok(())
+    }
+
+    /// Every recorded transition of `task_id`, oldest first.
+    pub fn list_for_task(&self, task_id: &TaskId) -> Result<Vec<UrgentAuditRecord>> {
+        let prefix = format!("{}|", task_id.id);
+        let mut out = Vec::new();
+        for item in self.by_task.scan_prefix(prefix.as_bytes()) {
+            let (key, _) = item?;
+            let record_id = &key[prefix.len()..];
+            if let Some(value) = self.records.get(record_id)? {
+                let record: UrgentAuditRecord = versioned::decode(&value)?;
+                if record.task_id == *task_id {
+                    out.push(record);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Transitions in `[from, to)`, oldest first, with cursor-based pagination.
+    ///
+    /// - `cursor`: the `record_id` of the last item from the previous page
+    ///   (exclusive). Pass `None` for the first page.
+    ///
+    /// Returns `(items, next_cursor)`; `next_cursor` is `None` on the last page.
+    pub fn list_range(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<UrgentAuditRecord>, Option<String>)> {
+        let lower = from
+            .map(|t| Ulid::from_parts(t.timestamp_millis().max(0) as u64, 0).to_string())
+            .unwrap_or_default();
+        let lower = match cursor {
+            Some(c) if c > lower.as_str() => c.to_string(),
+            _ => lower,
+        };
+        let upper = to.map(|t| Ulid::from_parts(t.timestamp_millis().max(0) as u64, 0).to_string());
+
+        let mut items = Vec::new();
+        for item in self.records.range(lower.as_bytes()..) {
+            let (key, value) = item?;
+            if cursor.is_some_and(|c| key.as_ref() == c.as_bytes()) {
+                continue;
+            }
+            if upper.as_ref().is_some_and(|u| key.as_ref() >= u.as_bytes()) {
+                break;
+            }
+            items.push(versioned::decode::<UrgentAuditRecord>(&value)?);
+            if items.len() > limit {
+                break;
+            }
+        }
+
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|r| r.record_id.clone())
+        } else {
+            None
+        };
+        Ok((items, next_cursor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn storage() -> UrgentAuditStorage {
+        UrgentAuditStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn lists_a_tasks_history_in_order() {
+        let audit = storage();
+        let task = TaskId::new_with_cap("debug.echo".to_string());
+        let other = TaskId::new_with_cap("debug.echo".to_string());
+        audit
+            .append(&UrgentAuditRecord::new(
+                &task,
+                UrgentAuditEvent::Submitted,
+                None,
+            ))
+            .unwrap();
+        audit
+            .append(&UrgentAuditRecord::new(
+                &other,
+                UrgentAuditEvent::Submitted,
+                None,
+            ))
+            .unwrap();
+        audit
+            .append(&UrgentAuditRecord::new(
+                &task,
+                UrgentAuditEvent::Assigned,
+                Some("agent-a"),
+            ))
+            .unwrap();
+
+        let events: Vec<_> = audit
+            .list_for_task(&task)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.event)
+            .collect();
+        assert_eq!(
+            events,
+            vec![UrgentAuditEvent::Submitted, UrgentAuditEvent::Assigned]
+        );
+    }
+
+    #[test]
+    fn range_query_pages_through_a_time_window() {
+        let audit = storage();
+        let task = TaskId::new_with_cap("debug.echo".to_string());
+        let start = Utc::now() - TimeDelta::seconds(1);
+        for _ in 0..3 {
+            audit
+                .append(&UrgentAuditRecord::new(
+                    &task,
+                    UrgentAuditEvent::Submitted,
+                    None,
+                ))
+                .unwrap();
+        }
+        let end = Utc::now() + TimeDelta::seconds(1);
+
+        let (page, cursor) = audit.list_range(Some(start), Some(end), 2, None).unwrap();
+        assert_eq!(page.len(), 2);
+        let (rest, last) = audit
+            .list_range(Some(start), Some(end), 2, cursor.as_deref())
+            .unwrap();
+        assert_eq!(rest.len(), 1);
+        assert!(last.is_none());
+        assert!(rest[0].record_id > page[1].record_id);
+
+        let (none, _) = audit.list_range(None, Some(start), 10, None).unwrap();
+        assert!(none.is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+//! Versioned envelope for records persisted in Sled.
+//!
+//! Records are written as `[RECORD_MARKER, version, msgpack...]`. Records written
+//! before versioning existed are bare msgpack maps and are read as version 0.
+//! `0xC1` is never emitted by MessagePack, so the marker can't collide with a
+//! legacy payload's first byte.
+
+use log::warn;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    db::{task_events::TaskTransition, urgent_audit::UrgentAuditRecord},
+    models::{
+        Agent, AssignedTask, CapabilityDefinition, ClientApiKey, DeadLetter, LateReport,
+        UnassignedTask, UrgentSnapshot,
+    },
+};
+
+pub const RECORD_MARKER: u8 = 0xC1;
+
+/// A record type stored in Sled with a schema version.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Version written for new records. Bump when a change can't be absorbed by
+    /// `#[serde(default)]` alone and add the upgrade step to [`Versioned::migrate`].
+    const VERSION: u8 = 1;
+
+    /// Upgrade a record decoded from an older on-disk version.
+    fn migrate(self, _from_version: u8) -> Self {
+        self
+    }
+}
+
+impl Versioned for Agent {}
+impl Versioned for AssignedTask {}
+impl Versioned for UnassignedTask {}
+impl Versioned for ClientApiKey {}
+impl Versioned for CapabilityDefinition {}
+impl Versioned for UrgentAuditRecord {}
+impl Versioned for LateReport {}
+impl Versioned for DeadLetter {}
+impl Versioned for UrgentSnapshot {}
+impl Versioned for TaskTransition {}
+
+pub fn encode<T: Versioned>(record: &T) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    let mut bytes = vec![RECORD_MARKER, T::VERSION];
+    bytes.extend(rmp_serde::to_vec_named(record)?);
+    Ok(bytes)
+}
+
+/// Decode a record, migrating it in memory if it was written by an older version.
+pub fn decode<T: Versioned>(bytes: &[u8]) -> Result<T, rmp_serde::decode::Error> {
+    let (version, payload) = match bytes {
+        [RECORD_MARKER, version, payload @ ..] => (*version, payload),
+        _ => (0, bytes),
+    };
+    if version > T::VERSION {
+        return Err(rmp_serde::decode::Error::Uncategorized(format!(
+            "record version {} is newer than supported version {}",
+            version,
+            T::VERSION
+        )));
+    }
+    let record: T = rmp_serde::from_slice(payload)?;
+    Ok(if version < T::VERSION {
+        record.migrate(version)
+    } else {
+        record
+    })
+}
+
+/// Like [`decode`], but logs and discards records that fail to decode instead of
+/// returning the error. Used by list scans so one bad record doesn't hide the rest,
+/// while still making the loss visible.
+pub fn decode_or_log<T: Versioned>(key: &[u8], bytes: &[u8]) -> Option<T> {
+    match decode(bytes) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            warn!(
+                "Failed to decode {} record {}: {}",
+                std::any::type_name::<T>(),
+                String::from_utf8_lossy(key),
+                e
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn sample_key() -> ClientApiKey {
+        ClientApiKey {
+            key: "k1".to_string(),
+            capabilities: vec!["llm.*".to_string()],
+            is_predefined: false,
+            created: Utc::now(),
+            is_revoked: false,
+        }
+    }
+
+    #[test]
+    fn round_trips_with_envelope() {
+        let bytes = encode(&sample_key()).unwrap();
+        assert_eq!(bytes[0], RECORD_MARKER);
+        assert_eq!(bytes[1], ClientApiKey::VERSION);
+        let decoded: ClientApiKey = decode(&bytes).unwrap();
+        assert_eq!(decoded.key, "k1");
+    }
+
+    #[test]
+    fn reads_legacy_unversioned_records() {
+        // Both encodings used before versioning: named maps and positional arrays.
+        let named = rmp_serde::to_vec_named(&sample_key()).unwrap();
+        let positional = rmp_serde::to_vec(&sample_key()).unwrap();
+        assert_eq!(decode::<ClientApiKey>(&named).unwrap().key, "k1");
+        assert_eq!(decode::<ClientApiKey>(&positional).unwrap().key, "k1");
+    }
+
+    #[test]
+    fn reads_legacy_typical_runtime_as_duration() {
+        let task = AssignedTask {
+            typical_runtime_seconds: Some(std::time::Duration::from_millis(8500)),
+            ..Default::default()
+        };
+        let decoded: AssignedTask = decode(&encode(&task).unwrap()).unwrap();
+        assert_eq!(
+            decoded.typical_runtime_seconds,
+            task.typical_runtime_seconds
+        );
+
+        // Before millisecond serialization the field was a `{secs, nanos}` map.
+        let mut legacy = serde_json::to_value(&task).unwrap();
+        let map = legacy.as_object_mut().unwrap();
+        map.remove("typicalRuntimeMs");
+        map.insert(
+            "typicalRuntimeSeconds".to_string(),
+            serde_json::json!({ "secs": 8, "nanos": 500_000_000 }),
+        );
+        let bytes = rmp_serde::to_vec_named(&legacy).unwrap();
+        let decoded: AssignedTask = decode(&bytes).unwrap();
+        assert_eq!(
+            decoded.typical_runtime_seconds,
+            task.typical_runtime_seconds
+        );
+    }
+
+    #[test]
+    fn rejects_records_from_newer_versions() {
+        let mut bytes = encode(&sample_key()).unwrap();
+        bytes[1] = ClientApiKey::VERSION + 1;
+        assert!(decode::<ClientApiKey>(&bytes).is_err());
+        assert!(decode_or_log::<ClientApiKey>(b"k1", &bytes).is_none());
+    }
+}
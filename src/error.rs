@@ -3,9 +3,38 @@ use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
+use serde::Serialize;
 use serde_json::json;
 use thiserror::Error;
 
+/// One problem with one field of a request.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct FieldError {
+    /// JSON name of the offending field, e.g. `maxWaitSecs`.
+    pub field: String,
+    /// Machine-readable reason: `required`, `length`, `out_of_range`, ...
+    pub code: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            code: code.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+fn describe_fields(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| format!("{}: {}", e.field, e.message))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Database error: {0}")]
@@ -26,6 +55,10 @@ pub enum AppError {
     #[error("Validation error: {0}")]
     Validation(String),
 
+    /// Every field-level violation found in a request, reported together.
+    #[error("Validation error: {}", describe_fields(.0))]
+    InvalidFields(Vec<FieldError>),
+
     #[error("Not found: {0}")]
     NotFound(String),
 
@@ -52,6 +85,12 @@ pub enum AppError {
 
     #[error("Client closed request: {0}")]
     ClientClosedRequest(String),
+
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
 }
 
 impl AppError {
@@ -66,11 +105,16 @@ impl AppError {
             | AppError::BcryptError(_) => 500,
             AppError::Authentication(_) | AppError::Jwt(_) => 401,
             AppError::Authorization(_) => 403,
-            AppError::Validation(_) | AppError::BadRequest(_) | AppError::Parse(_) => 400,
+            AppError::Validation(_)
+            | AppError::InvalidFields(_)
+            | AppError::BadRequest(_)
+            | AppError::Parse(_) => 400,
             AppError::NotFound(_) => 404,
             AppError::Conflict(_) => 409,
             AppError::SchedulingImpossible(_) => 503,
             AppError::ClientClosedRequest(_) => 499,
+            AppError::RateLimited(_) => 429,
+            AppError::PayloadTooLarge(_) => 413,
         }
     }
 
@@ -87,7 +131,7 @@ impl AppError {
             AppError::Serialization(_) => "serialization_error",
             AppError::Authentication(_) => "authentication_error",
             AppError::Authorization(_) => "authorization_error",
-            AppError::Validation(_) => "validation_error",
+            AppError::Validation(_) | AppError::InvalidFields(_) => "validation_error",
             AppError::NotFound(_) => "not_found",
             AppError::Conflict(_) => "conflict",
             AppError::BadRequest(_) => "bad_request",
@@ -97,6 +141,8 @@ impl AppError {
             AppError::BcryptError(_) => "bcrypt_error",
             AppError::SchedulingImpossible(_) => "scheduling impossible",
             AppError::ClientClosedRequest(_) => "client_closed_request",
+            AppError::RateLimited(_) => "rate_limited",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
         }
     }
 
@@ -106,11 +152,14 @@ impl AppError {
             AppError::Authentication(_)
             | AppError::Authorization(_)
             | AppError::Validation(_)
+            | AppError::InvalidFields(_)
             | AppError::NotFound(_)
             | AppError::BadRequest(_)
             | AppError::Jwt(_)
             | AppError::Parse(_)
-            | AppError::ClientClosedRequest(_) => false,
+            | AppError::ClientClosedRequest(_)
+            | AppError::RateLimited(_)
+            | AppError::PayloadTooLarge(_) => false,
             AppError::Database(_)
             | AppError::Internal(_)
             | AppError::Serialization(_)
@@ -124,13 +173,17 @@ impl AppError {
     /// Build the standard error JSON envelope without any framework dependency.
     /// Usable by WebSocket, gRPC, or any other transport adapter.
     pub fn to_error_json(&self) -> serde_json::Value {
-        json!({
+        let mut body = json!({
             "error": {
                 "type": self.error_type(),
                 "message": self.to_string(),
                 "status": self.status_code_number()
             }
-        })
+        });
+        if let AppError::InvalidFields(fields) = self {
+            body["error"]["fields"] = json!(fields);
+        }
+        body
     }
 }
 
@@ -264,6 +317,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn invalid_fields_are_listed_in_the_error_body() {
+        let err = AppError::InvalidFields(vec![
+            FieldError::new("capability", "required", "must not be empty"),
+            FieldError::new("maxWaitSecs", "out_of_range", "must be at least 1"),
+        ]);
+        assert_eq!(err.status_code_number(), 400);
+        let json = err.to_error_json();
+        assert_eq!(json["error"]["type"], "validation_error");
+        assert_eq!(json["error"]["fields"][1]["field"], "maxWaitSecs");
+        assert_eq!(json["error"]["fields"][1]["code"], "out_of_range");
+        assert!(
+            json["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("capability: must not be empty")
+        );
+    }
+
     #[test]
     fn test_to_error_json() {
         let err = AppError::not_found("thing");
@@ -7,7 +7,9 @@ pub mod middleware;
 pub mod models;
 pub mod mq;
 pub mod preferences;
+pub mod readiness;
 pub mod schema;
 pub mod state;
 pub mod storage;
+pub mod supervisor;
 pub mod utils;
@@ -6,11 +6,13 @@ use axum::{
     middleware::from_fn_with_state,
     routing::*,
 };
-use log::{info, warn};
+use log::{error, info, warn};
 use offloadmq::{
-    api::agent::{agent_ping, auth_agent, register_agent, update_agent_info, websocket_handler},
+    api::agent::{
+        agent_ping, auth_agent, refresh_agent_token, register_agent, update_agent_info,
+        websocket_handler,
+    },
     db::app_storage::AppStorage,
-    preferences::init_config,
     state::{AppChannels, AppState, DbWriteRequest, StreamEvent},
 };
 use offloadmq::{middleware::auth::Auth, *};
@@ -24,10 +26,11 @@ use tower_http::{
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    init_config(true, false);
-
-    let config = config::AppConfig::from_env()?;
+    // Load .env before the logger so RUST_LOG from it applies, and set up the
+    // logger before the config so parse warnings are not lost.
+    dotenvy::dotenv().ok();
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    let config = config::AppConfig::from_env()?;
 
     info!("Starting application with config:");
     info!("  Host: {}", config.host);
@@ -38,8 +41,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("  Management token: {}", config.management_token);
     info!("  Storage backend: {}", config.storage.backend);
 
-    let app_storage = AppStorage::new(&config.database_root_path, &config.storage)
-        .expect("Failed to initialize storage");
+    let app_storage = AppStorage::new(
+        &config.database_root_path,
+        &config.storage,
+        config.agent_online_timeout_secs,
+    )
+    .expect("Failed to initialize storage");
 
     let auth = Auth::new(config.jwt_secret.as_bytes());
     let (channels, workers) = AppChannels::new();
@@ -58,10 +65,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         ),
     }
     // Seed the authoritative per-agent in-flight load from persisted assigned
-    // tasks so a restart with in-flight work starts with a correct capacity gate.
-    shared_state
-        .agent_load
-        .reconcile(rebuild_agent_load(&shared_state).await);
+    // tasks and restored urgent assignments so a restart with in-flight work
+    // starts with a correct capacity gate.
+    shared_state.reconcile_agent_load().await;
+    tokio::spawn(mq::webhook::run_worker(
+        shared_state.clone(),
+        workers.webhook_rx,
+        workers.shutdown_rx.clone(),
+    ));
     tokio::spawn(run_db_write_worker(
         shared_state.clone(),
         workers.db_write_rx,
@@ -77,17 +88,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         // Agent routes
         .route("/agent/register", post(register_agent))
         .route("/agent/auth", post(auth_agent))
+        .route("/agent/refresh_token", post(refresh_agent_token))
         // Agent WebSocket (auth via query params)
         .route("/private/agent/ws", get(websocket_handler))
         // Health check and stats
+        .route("/", get(root_info))
         .route("/health", get(health_check))
+        .route("/health/live", get(liveness_check))
         .route("/stats", get(get_stats))
+        .route("/metrics", get(get_metrics))
         .route("/version", get(api::mgmt::version))
         .nest(
             "/private/agent",
             Router::new()
                 .route("/ping", get(agent_ping))
                 .route("/info/update", post(update_agent_info))
+                .route("/deregister", post(api::agent::deregister_agent))
+                .route("/auth/refresh", post(api::agent::refresh_agent_session))
                 .route(
                     "/task/poll_urgent",
                     get(api::agent::fetch_task_urgent_handler),
@@ -102,6 +119,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "/task/progress/{cap}/{id}",
                     post(api::agent::post_task_progress_update),
                 )
+                .route("/task/touch/{cap}/{id}", post(api::agent::touch_task))
                 .route("/bucket/{bucket_uid}/stat", get(api::agent::bucket_stat))
                 .route(
                     "/bucket/{bucket_uid}/file/{file_uid}",
@@ -132,13 +150,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "/capabilities/list/online_ext",
                     get(api::mgmt::capabilities_online_ext),
                 )
+                .route(
+                    "/capabilities/registry",
+                    get(api::mgmt::list_capability_registry),
+                )
+                .route(
+                    "/capabilities/registry/{name}",
+                    get(api::mgmt::get_capability_definition)
+                        .post(api::mgmt::upsert_capability_definition),
+                )
+                .route(
+                    "/capabilities/registry/{name}/delete",
+                    post(api::mgmt::delete_capability_definition),
+                )
+                .route("/events", get(api::mgmt::list_task_events))
                 .route("/tasks/list", get(api::mgmt::list_tasks))
                 .route("/tasks/reset", post(api::mgmt::reset_tasks))
                 .route("/tasks/cancel/{cap}/{id}", post(api::mgmt::cancel_task))
+                .route("/tasks/urgent/audit", get(api::mgmt::list_urgent_audit))
+                .route("/tasks/late_reports", get(api::mgmt::list_late_reports))
+                .route("/tasks/archived", get(api::mgmt::list_archived_tasks))
+                .route("/tasks/dead_letter", get(api::mgmt::list_dead_letter_tasks))
+                .route(
+                    "/tasks/dead_letter/{cap}/{id}/requeue",
+                    post(api::mgmt::requeue_dead_letter_task),
+                )
                 .route("/agents/list", get(api::mgmt::list_agents))
                 .route("/agents/reset", post(api::mgmt::reset_agents))
                 .route("/agents/list/online", get(api::mgmt::list_agents_online))
                 .route("/agents/delete/{agent_id}", post(api::mgmt::remove_agent))
+                .route("/agents/prune", post(api::mgmt::prune_agents))
+                .route(
+                    "/agents/revoke_sessions/{agent_id}",
+                    post(api::mgmt::revoke_agent_sessions),
+                )
+                .route(
+                    "/agents/health/{agent_id}",
+                    get(api::mgmt::get_agent_health),
+                )
+                .route(
+                    "/agents/quarantine/clear/{agent_id}",
+                    post(api::mgmt::clear_agent_quarantine),
+                )
+                .route("/simulate/agent", post(api::mgmt::simulate_agent))
                 .route("/client_api_keys/list", get(api::mgmt::client_api_keys))
                 .route(
                     "/client_api_keys/update",
@@ -225,12 +279,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             Router::new()
                 .route("/ping", get(health_check))
                 .route("/task/submit", post(api::client::submit_task))
+                .route("/task/submit_batch", post(api::client::submit_task_batch))
                 .route("/task/poll/{cap}/{id}", post(api::client::poll_task_status))
+                .route("/task/result/{cap}/{id}", get(api::client::get_task_result))
+                .route(
+                    "/task/poll_by_client_id",
+                    post(api::client::poll_task_by_client_id),
+                )
+                .route("/tasks/poll_batch", post(api::client::poll_task_batch))
+                .route("/tasks/list", post(api::client::list_tasks))
                 .route(
                     "/task/submit_blocking",
                     post(api::client::submit_task_blocking),
                 )
+                .route(
+                    "/task/submit_until_assigned",
+                    post(api::client::submit_task_until_assigned),
+                )
                 .route("/task/cancel/{cap}/{id}", post(api::client::cancel_task))
+                .route(
+                    "/task/cancel_by_client_id",
+                    post(api::client::cancel_task_by_client_id),
+                )
+                .route(
+                    "/task/escalate/{cap}/{id}",
+                    post(api::client::escalate_task),
+                )
                 .route(
                     "/capabilities/online",
                     post(api::client::capabilities_online),
@@ -239,9 +313,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "/capabilities/list/online_ext",
                     post(api::client::capabilities_online_ext),
                 )
+                .route(
+                    "/capabilities/output_schema/{cap}",
+                    post(api::client::get_output_schema),
+                )
                 .layer(from_fn_with_state(
                     shared_state.clone(),
                     middleware::apikey_auth_middleware_user,
+                ))
+                .layer(DefaultBodyLimit::max(
+                    shared_state.config.max_request_body_bytes,
                 )),
         )
         // Storage API — uses X-API-Key header auth (supports multipart + GET + DELETE)
@@ -280,6 +361,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     shared_state.config.storage.bucket_size_bytes as usize,
                 )),
         )
+        .layer(from_fn_with_state(
+            shared_state.clone(),
+            middleware::count_in_flight,
+        ))
+        // Registered after the counting layer so probes don't count themselves.
+        .route("/ready", get(readiness_check))
+        .route("/health/ready", get(readiness_check))
+        .fallback(not_found_fallback)
         .with_state(shared_state.clone())
         .layer(TraceLayer::new_for_http())
         .layer(
@@ -290,15 +379,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         );
 
     // Start the server
-    let bind_address = format!("{}:{}", config.host, config.port);
-    let listener = TcpListener::bind(&bind_address).await?;
-    info!("Server starting on http://{}", bind_address);
+    let listener = bind_listener(&config).await?;
 
     // Background: log online agents every 120 s
     {
+        let shared = shared_state.clone();
+        supervisor::spawn_supervised("online-agents-log", move || {
+            let state = shared.clone();
+            async move {
+                let mut interval = time::interval(time::Duration::from_secs(120));
+                let mut shutdown = state.subscribe_shutdown();
+                loop {
+                    tokio::select! {
+                        _ = shutdown.changed() => {
+                            if *shutdown.borrow() {
+                                break;
+                            }
+                        }
+                        _ = interval.tick() => {
+                            state.storage.agents.log_online_agents();
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    // Background: drop idle client rate-limit buckets every 60 s
+    if shared_state.rate_limiter.is_enabled() {
         let state = shared_state.clone();
         tokio::spawn(async move {
-            let mut interval = time::interval(time::Duration::from_secs(120));
+            let mut interval = time::interval(time::Duration::from_secs(60));
             let mut shutdown = state.subscribe_shutdown();
             loop {
                 tokio::select! {
@@ -308,7 +419,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         }
                     }
                     _ = interval.tick() => {
-                        state.storage.agents.log_online_agents();
+                        state.rate_limiter.sweep();
                     }
                 }
             }
@@ -515,8 +626,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
     }
 
-    // Background: purge agent log records older than 14 days. Runs once at
-    // startup, then every 6 hours.
+    // Background: purge agent log records older than 14 days and stored
+    // refresh token ids that have expired. Runs once at startup, then every
+    // 6 hours.
     {
         const AGENT_LOG_TTL_DAYS: i64 = 14;
         let state = shared_state.clone();
@@ -554,6 +666,43 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         );
                     }
                 }
+                let cutoff = chrono::Utc::now()
+                    - chrono::Duration::seconds(middleware::auth::REFRESH_TOKEN_TTL as i64);
+                match state
+                    .storage
+                    .agents
+                    .prune_refresh_tokens_issued_before(cutoff)
+                {
+                    Ok(pruned) if pruned > 0 => {
+                        info!("Refresh tokens cleanup: dropped {} expired id(s)", pruned);
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Refresh tokens cleanup failed: {}", e),
+                }
+            }
+        });
+    }
+
+    // Background: compact the task event log down to the retention window
+    // every hour. Disabled when TASK_EVENTS_RETENTION_SECS is 0.
+    if shared_state.config.task_events_retention_secs > 0 {
+        let state = shared_state.clone();
+        tokio::spawn(async move {
+            let retention_secs = state.config.task_events_retention_secs;
+            let mut interval = time::interval(time::Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                let cutoff = chrono::Utc::now() - chrono::Duration::seconds(retention_secs as i64);
+                match state.storage.tasks.events().compact_before(cutoff) {
+                    Ok(removed) if removed > 0 => {
+                        info!(
+                            "Task events compaction: removed {} transition(s) older than {}s",
+                            removed, retention_secs
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Task events compaction failed: {}", e),
+                }
             }
         });
     }
@@ -563,89 +712,160 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // - Assigned tasks past timeoutSecs are set to CancelRequested so the
     //   executing agent receives HTTP 499 on its next progress/resolve call.
     // - CancelRequested tasks unacknowledged past the grace window are failed.
+    // - Completed tasks past their capability's result TTL lose their output.
+    // - Assigned tasks past deadlineSecs since assignment are failed or re-queued.
     // - Tasks held by an offline, silent agent are recovered (failed).
     {
         // How long to wait after requesting cancel before presuming the agent
         // is dead and force-failing the task.
         const CANCEL_ACK_GRACE_SECS: i64 = 120;
-        // How long a task may go untouched by an offline agent before it is
-        // treated as orphaned. Long enough not to disturb legitimately running
-        // tasks that report progress infrequently.
-        const ORPHAN_SILENCE_SECS: i64 = 30 * 60;
 
-        let state = shared_state.clone();
-        tokio::spawn(async move {
-            let mut interval = time::interval(time::Duration::from_secs(30));
-            let mut shutdown = state.subscribe_shutdown();
-            loop {
-                tokio::select! {
-                    _ = shutdown.changed() => {
-                        if *shutdown.borrow() {
-                            break;
-                        }
-                    }
-                    _ = interval.tick() => {
-                        match state.regular.expire_timed_out_unassigned(&state.storage.tasks).await {
-                            Ok(n) if n > 0 => {
-                                info!("Task timeout: failed {} unassigned task(s) past wait/total deadline", n);
+        let shared = shared_state.clone();
+        supervisor::spawn_supervised("task-sweeper", move || {
+            let state = shared.clone();
+            async move {
+                let mut interval = time::interval(time::Duration::from_secs(30));
+                let mut shutdown = state.subscribe_shutdown();
+                loop {
+                    tokio::select! {
+                        _ = shutdown.changed() => {
+                            if *shutdown.borrow() {
+                                break;
                             }
-                            Err(e) => log::warn!("Task timeout check (unassigned) error: {}", e),
-                            _ => {}
                         }
-                        match state.storage.tasks.cancel_timed_out_assigned() {
-                            Ok(n) if n > 0 => {
-                                info!("Task timeout: sent cancel signal to {} assigned task(s) past total deadline", n);
+                        _ = interval.tick() => {
+                            match state.regular.expire_timed_out_unassigned(&state.storage.tasks).await {
+                                Ok(n) if n > 0 => {
+                                    info!("Task timeout: failed {} unassigned task(s) past wait/total deadline", n);
+                                }
+                                Err(e) => log::warn!("Task timeout check (unassigned) error: {}", e),
+                                _ => {}
                             }
-                            Err(e) => log::warn!("Task timeout check (assigned) error: {}", e),
-                            _ => {}
-                        }
-                        match state.storage.tasks.fail_stale_cancel_requested(CANCEL_ACK_GRACE_SECS) {
-                            Ok(n) if n > 0 => {
-                                info!("Task cleanup: failed {} cancel-requested task(s) the agent never acknowledged", n);
+                            if state.config.deactivated_capability == config::DeactivatedCapabilityPolicy::Fail {
+                                let inactive = state.storage.capabilities.inactive_names();
+                                match state.regular.fail_for_inactive_capabilities(&state.storage.tasks, &inactive).await {
+                                    Ok(n) if n > 0 => {
+                                        info!("Capability deactivated: failed {} queued task(s)", n);
+                                    }
+                                    Err(e) => log::warn!("Deactivated capability sweep error: {}", e),
+                                    _ => {}
+                                }
                             }
-                            Err(e) => log::warn!("Cancel-requested escalation error: {}", e),
-                            _ => {}
-                        }
-                        let agents = &state.storage.agents;
-                        match state.storage.tasks.recover_orphaned_assigned(
-                            ORPHAN_SILENCE_SECS,
-                            |agent_id| agents.get_agent(agent_id).map(|a| a.is_online()).unwrap_or(false),
-                        ) {
-                            Ok(n) if n > 0 => {
-                                info!("Task cleanup: recovered {} orphaned task(s) from offline agents", n);
+                            mq::dependencies::reevaluate(&state).await;
+                            match state.storage.tasks.cancel_timed_out_assigned() {
+                                Ok(n) if n > 0 => {
+                                    info!("Task timeout: sent cancel signal to {} assigned task(s) past total deadline", n);
+                                }
+                                Err(e) => log::warn!("Task timeout check (assigned) error: {}", e),
+                                _ => {}
                             }
-                            Err(e) => log::warn!("Orphan recovery error: {}", e),
-                            _ => {}
-                        }
-                        // Pull any unassigned tasks that exist in persistent
-                        // storage but are missing from the in-memory queue —
-                        // notably tasks that were already unassigned when this
-                        // process started — into the dispatchable queue so the
-                        // backstop below delivers them.
-                        match state
-                            .regular
-                            .reconcile_unassigned_from_persistent(&state.storage.tasks)
-                            .await
-                        {
-                            Ok(n) if n > 0 => {
-                                info!("Reconcile: restored {n} unassigned task(s) into the in-memory queue");
+                            match state.storage.tasks.fail_stale_cancel_requested(CANCEL_ACK_GRACE_SECS) {
+                                Ok(n) if n > 0 => {
+                                    info!("Task cleanup: failed {} cancel-requested task(s) the agent never acknowledged", n);
+                                }
+                                Err(e) => log::warn!("Cancel-requested escalation error: {}", e),
+                                _ => {}
+                            }
+                            if let Some(max_age) = state.config.task_max_age_secs {
+                                match state.storage.tasks.force_archive_older_than(chrono::Utc::now(), max_age) {
+                                    Ok(forced) if forced.archived > 0 => {
+                                        info!("Task max age: archived {} task(s) older than {}s", forced.archived, max_age);
+                                        for task_id in &forced.dequeued {
+                                            state.regular.remove_task(task_id).await;
+                                        }
+                                        for (agent_id, task_id) in &forced.interrupted {
+                                            state.registry.push_cancel(agent_id, task_id);
+                                        }
+                                    }
+                                    Err(e) => log::warn!("Task max age sweep error: {}", e),
+                                    _ => {}
+                                }
+                            }
+                            let result_ttls: std::collections::HashMap<String, u64> = state
+                                .storage
+                                .capabilities
+                                .list_all()
+                                .into_iter()
+                                .filter_map(|c| c.result_ttl_secs.map(|ttl| (c.name, ttl)))
+                                .collect();
+                            if !result_ttls.is_empty() {
+                                match state.storage.tasks.purge_expired_results(chrono::Utc::now(), |cap| {
+                                    result_ttls.get(cap).copied()
+                                }) {
+                                    Ok(n) if n > 0 => {
+                                        info!("Result TTL: purged output of {} completed task(s)", n);
+                                    }
+                                    Err(e) => log::warn!("Result TTL purge error: {}", e),
+                                    _ => {}
+                                }
+                            }
+                            match state.storage.tasks.fail_past_deadline(state.config.task_restart.max_restarts) {
+                                Ok(expired) if !expired.agents.is_empty() => {
+                                    info!(
+                                        "Task deadline: {} task(s) exceeded deadlineSecs ({} re-queued)",
+                                        expired.agents.len(),
+                                        expired.requeued.len()
+                                    );
+                                    for task in expired.requeued {
+                                        state.regular.add_task(task).await;
+                                    }
+                                    for (agent_id, task_id) in &expired.interrupted {
+                                        state.registry.push_cancel(agent_id, task_id);
+                                    }
+                                }
+                                Err(e) => log::warn!("Task deadline sweep error: {}", e),
+                                _ => {}
+                            }
+                            let agents = &state.storage.agents;
+                            match state.storage.tasks.recover_orphaned_assigned(
+                                state.config.task_restart.orphan_silence_secs,
+                                state.config.task_restart.max_restarts,
+                                |agent_id| agents.get_agent(agent_id).is_some_and(|a| agents.is_online(&a)),
+                            ) {
+                                Ok(recovery) if !recovery.agents.is_empty() => {
+                                    info!(
+                                        "Task cleanup: recovered {} orphaned task(s) from offline agents ({} re-queued)",
+                                        recovery.agents.len(),
+                                        recovery.requeued.len()
+                                    );
+                                    for task in recovery.requeued {
+                                        state.regular.add_task(task).await;
+                                    }
+                                    for agent_id in recovery.agents {
+                                        mq::quarantine::report_fault(&state, &agent_id, mq::quarantine::AgentFault::LeaseExpired).await;
+                                    }
+                                }
+                                Err(e) => log::warn!("Orphan recovery error: {}", e),
+                                _ => {}
+                            }
+                            // Pull any unassigned tasks that exist in persistent
+                            // storage but are missing from the in-memory queue —
+                            // notably tasks that were already unassigned when this
+                            // process started — into the dispatchable queue so the
+                            // backstop below delivers them.
+                            match state
+                                .regular
+                                .reconcile_unassigned_from_persistent(&state.storage.tasks)
+                                .await
+                            {
+                                Ok(n) if n > 0 => {
+                                    info!("Reconcile: restored {n} unassigned task(s) into the in-memory queue");
+                                }
+                                Err(e) => log::warn!("Unassigned queue reconcile error: {}", e),
+                                _ => {}
+                            }
+                            // Self-heal the per-agent in-flight load from the source
+                            // of truth BEFORE the backstop runs. Any slot left
+                            // occupied by a task the sweeps above just drove terminal
+                            // (or a missed incremental release) is reclaimed here, so
+                            // the gate can never pin an idle agent at capacity forever.
+                            state.reconcile_agent_load().await;
+                            // Dispatch backstop: deliver any queued work that a push
+                            // missed (submit/connect/resolve races, reconnects, or
+                            // capacity self-heal) to currently-connected agents.
+                            for uid in state.registry.connected_uids() {
+                                crate::mq::dispatch::dispatch_to_agent(&state, &uid).await;
                             }
-                            Err(e) => log::warn!("Unassigned queue reconcile error: {}", e),
-                            _ => {}
-                        }
-                        // Self-heal the per-agent in-flight load from the source
-                        // of truth BEFORE the backstop runs. Any slot left
-                        // occupied by a task the sweeps above just drove terminal
-                        // (or a missed incremental release) is reclaimed here, so
-                        // the gate can never pin an idle agent at capacity forever.
-                        state
-                            .agent_load
-                            .reconcile(rebuild_agent_load(&state).await);
-                        // Dispatch backstop: deliver any queued work that a push
-                        // missed (submit/connect/resolve races, reconnects, or
-                        // capacity self-heal) to currently-connected agents.
-                        for uid in state.registry.connected_uids() {
-                            crate::mq::dispatch::dispatch_to_agent(&state, &uid).await;
                         }
                     }
                 }
@@ -653,44 +873,94 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
     }
 
-    let shutdown_state = shared_state.clone();
-    axum::serve(listener, app)
-        .with_graceful_shutdown(async move {
-            if tokio::signal::ctrl_c().await.is_ok() {
-                info!("Shutdown requested, signaling workers");
-                let _ = shutdown_state.channels.shutdown_tx.send(true);
-            }
-        })
-        .await?;
+    serve(listener, app, graceful_shutdown(shared_state.clone())).await?;
+
+    // Workers have been told to stop; make whatever they and the last
+    // requests wrote durable before the process exits.
+    match shared_state.storage.flush_all().await {
+        Ok(()) => info!("Storage flushed, shutdown complete"),
+        Err(e) => error!("{e}"),
+    }
 
     Ok(())
 }
 
-/// Rebuild the authoritative per-agent in-flight load from the source of truth:
-/// non-terminal assigned tasks in the persistent regular store plus in-flight
-/// urgent assignments, grouped by owning agent uid. Used to seed the load at
-/// startup and to reconcile it on every maintenance tick (self-healing).
-async fn rebuild_agent_load(
-    state: &Arc<AppState>,
-) -> std::collections::HashMap<String, std::collections::HashSet<offloadmq::schema::TaskId>> {
-    use std::collections::{HashMap, HashSet};
-    let mut live: HashMap<String, HashSet<offloadmq::schema::TaskId>> = HashMap::new();
-    match state.storage.tasks.list_assigned_all() {
-        Ok(assigned) => {
-            for task in assigned {
-                if !task.status.is_terminal() {
-                    live.entry(task.agent_id.clone())
-                        .or_default()
-                        .insert(task.id.clone());
-                }
+/// Where the HTTP server accepts connections.
+enum ServerListener {
+    Tcp(TcpListener),
+    /// `LISTEN_UDS`; the path is removed again after shutdown.
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, std::path::PathBuf),
+}
+
+/// Serve `app` until `shutdown` resolves and in-flight requests finish, then
+/// remove the socket file of a Unix listener.
+async fn serve(
+    listener: ServerListener,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    match listener {
+        ServerListener::Tcp(listener) => {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+        #[cfg(unix)]
+        ServerListener::Unix(listener, path) => {
+            let served = axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await;
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("Failed to remove socket {}: {}", path.display(), e);
             }
+            served
         }
-        Err(e) => warn!("agent_load reconcile: failed to list assigned tasks: {e}"),
     }
-    for (agent_id, task_id) in state.urgent.list_assigned_owners().await {
-        live.entry(agent_id).or_default().insert(task_id);
+}
+
+/// Bind `LISTEN_UDS` when set, otherwise `HOST`:`PORT`.
+async fn bind_listener(config: &config::AppConfig) -> std::io::Result<ServerListener> {
+    if let Some(path) = &config.listen_uds {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            // A socket left behind by an unclean exit would make bind fail;
+            // anything else at the path is left alone and bind reports it.
+            if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                std::fs::remove_file(path)?;
+            }
+            let listener = tokio::net::UnixListener::bind(path)?;
+            info!("Server starting on unix:{}", path.display());
+            return Ok(ServerListener::Unix(listener, path.clone()));
+        }
+        #[cfg(not(unix))]
+        warn!(
+            "LISTEN_UDS={} ignored: Unix domain sockets are not supported here",
+            path.display()
+        );
     }
-    live
+    let bind_address = format!("{}:{}", config.host, config.port);
+    let listener = TcpListener::bind(&bind_address).await?;
+    info!("Server starting on http://{}", bind_address);
+    Ok(ServerListener::Tcp(listener))
+}
+
+async fn graceful_shutdown(state: Arc<AppState>) {
+    shutdown_signal().await;
+    // Report not-ready first and keep serving for the drain window so
+    // the load balancer diverts new traffic before the listener closes;
+    // in-flight requests then finish under graceful shutdown.
+    state.readiness.start_draining();
+    let drain = state.config.shutdown_drain_secs;
+    info!(
+        "Shutdown requested, draining for {}s ({} requests in flight)",
+        drain,
+        state.readiness.in_flight()
+    );
+    time::sleep(time::Duration::from_secs(drain)).await;
+    info!("Signaling workers to stop");
+    let _ = state.channels.shutdown_tx.send(true);
 }
 
 // Utility handlers
@@ -702,9 +972,86 @@ async fn health_check(State(state): State<Arc<AppState>>) -> Json<Value> {
     }))
 }
 
+/// Resolves on Ctrl+C, or SIGTERM on Unix (what Kubernetes sends on rollout).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut term =
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(term) => term,
+                Err(e) => {
+                    warn!("Failed to install SIGTERM handler: {e}");
+                    let _ = tokio::signal::ctrl_c().await;
+                    return;
+                }
+            };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Liveness probe: the process is up and serving requests.
+async fn liveness_check() -> Json<Value> {
+    Json(json!({ "status": "alive" }))
+}
+
+/// Readiness probe: `503` once a shutdown signal put the server into draining,
+/// or when the agent/task databases can't be read and written or the urgent
+/// store lock can't be taken within a second.
+async fn readiness_check(State(state): State<Arc<AppState>>) -> impl axum::response::IntoResponse {
+    let urgent_lock = time::timeout(time::Duration::from_secs(1), state.urgent.tasks.read())
+        .await
+        .map(drop);
+    let failures: Vec<_> = [
+        readiness::probe("agents", || state.storage.agents.probe()),
+        readiness::probe("tasks", || state.storage.tasks.probe()),
+        readiness::probe("urgent", || {
+            urgent_lock.map_err(|_| "urgent store lock not acquired within 1s")
+        }),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    for failure in &failures {
+        warn!(
+            "Readiness probe failed for {}: {}",
+            failure.subsystem, failure.error
+        );
+    }
+    let (status, body) = state.readiness.report(&failures);
+    (status, Json(body))
+}
+
+async fn root_info(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let uptime = chrono::Utc::now() - state.started_at;
+    Json(json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "startedAt": state.started_at,
+        "uptimeSecs": uptime.num_seconds(),
+    }))
+}
+
+async fn not_found_fallback(uri: axum::http::Uri) -> error::AppError {
+    error::AppError::NotFound(format!("No route for {}", uri.path()))
+}
+
 async fn get_stats(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let last_sweep = state.urgent.last_expiry_sweep();
     Json(json!({
         "agents": state.storage.agent_count(),
+        "urgent_expiry": {
+            "last_sweep": last_sweep,
+            "seconds_since_last_sweep": last_sweep.map(|t| (chrono::Utc::now() - t).num_seconds()),
+        },
+        "queue_wait": state.queue_wait.snapshot(),
+        "poll_gate": state.poll_gate.stats(),
+        "circuits": state.circuit.snapshot(),
+        "auth": state.auth_metrics.snapshot(),
         "storage_paths": {
             "agents": "./data/agents",
             "tasks": "./data/tasks"
@@ -712,6 +1059,60 @@ async fn get_stats(State(state): State<Arc<AppState>>) -> Json<Value> {
     }))
 }
 
+/// Prometheus text exposition of the autoscaling signals.
+async fn get_metrics(State(state): State<Arc<AppState>>) -> impl axum::response::IntoResponse {
+    let mut out = String::new();
+    out.push_str("# HELP offloadmq_agents Registered agents.\n");
+    out.push_str("# TYPE offloadmq_agents gauge\n");
+    out.push_str(&format!(
+        "offloadmq_agents {}\n",
+        state.storage.agent_count()
+    ));
+
+    let queue_wait = state.queue_wait.snapshot();
+    out.push_str(
+        "# HELP offloadmq_queue_wait_avg_ms Time-decayed average wait between task creation and assignment.\n",
+    );
+    out.push_str("# TYPE offloadmq_queue_wait_avg_ms gauge\n");
+    for entry in &queue_wait {
+        out.push_str(&format!(
+            "offloadmq_queue_wait_avg_ms{{capability=\"{}\"}} {}\n",
+            utils::prometheus_label(&entry.capability),
+            entry.avg_wait_ms
+        ));
+    }
+    out.push_str(
+        "# HELP offloadmq_queue_wait_samples_total Assignments observed per capability.\n",
+    );
+    out.push_str("# TYPE offloadmq_queue_wait_samples_total counter\n");
+    for entry in &queue_wait {
+        out.push_str(&format!(
+            "offloadmq_queue_wait_samples_total{{capability=\"{}\"}} {}\n",
+            utils::prometheus_label(&entry.capability),
+            entry.samples
+        ));
+    }
+    out.push_str(
+        "# HELP offloadmq_auth_total Authentication checks by credential source and outcome.\n",
+    );
+    out.push_str("# TYPE offloadmq_auth_total counter\n");
+    for (source, outcomes) in state.auth_metrics.snapshot() {
+        for (outcome, count) in outcomes {
+            out.push_str(&format!(
+                "offloadmq_auth_total{{source=\"{}\",outcome=\"{}\"}} {}\n",
+                source, outcome, count
+            ));
+        }
+    }
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        out,
+    )
+}
+
 async fn enqueue_service_message(state: &Arc<AppState>, class: &str, kind: &str, content: Value) {
     if let Err(e) = state
         .channels
@@ -759,8 +1160,75 @@ async fn run_db_write_worker(
                             }
                         }
                     }
+                    DbWriteRequest::UrgentAudit(record) => {
+                        if let Err(e) = state.storage.urgent_audit.append(&record) {
+                            warn!("Failed to persist urgent audit record for {}: {}", record.task_id, e);
+                        }
+                    }
+                    DbWriteRequest::TaskTransition(mut record) => {
+                        if let Err(e) = state.storage.tasks.events().append(&mut record) {
+                            warn!("Failed to record transition of {}: {}", record.task_id, e);
+                        }
+                    }
+                    DbWriteRequest::UrgentSnapshot(snapshot) => {
+                        if let Err(e) = state.storage.tasks.put_urgent(&snapshot) {
+                            warn!("Failed to persist urgent task {}: {}", snapshot.task.id, e);
+                        }
+                    }
+                    DbWriteRequest::UrgentForget(task_id) => {
+                        if let Err(e) = state.storage.tasks.remove_urgent(&task_id) {
+                            warn!("Failed to remove persisted urgent task {}: {}", task_id, e);
+                        }
+                    }
+                    DbWriteRequest::UrgentClear => {
+                        if let Err(e) = state.storage.tasks.clear_urgent() {
+                            warn!("Failed to clear persisted urgent tasks: {}", e);
+                        }
+                    }
                 }
             }
         }
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn unix_socket_serves_and_is_removed_after_shutdown() {
+        let path =
+            std::env::temp_dir().join(format!("offloadmq-test-{}.sock", uuid::Uuid::new_v4()));
+        // A socket left behind by an unclean exit is replaced.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let mut config = config::AppConfig::from_env().unwrap();
+        config.listen_uds = Some(path.clone());
+        let listener = bind_listener(&config).await.unwrap();
+
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let server = tokio::spawn(serve(listener, app, async {
+            stop_rx.await.ok();
+        }));
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("ok"));
+
+        stop_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+
+        // Anything other than a socket at the path is left alone.
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(bind_listener(&config).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
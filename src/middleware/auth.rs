@@ -8,11 +8,49 @@ use crate::error::AppError;
 
 // Token expiration time (e.g., 7 days)
 const ONE_WEEK: usize = 60 * 60 * 24 * 7;
+// Refresh tokens live longer than access tokens; they are revocable server-side.
+pub const REFRESH_TOKEN_TTL: usize = 60 * 60 * 24 * 30;
+
+const REFRESH_TOKEN_KIND: &str = "refresh";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    /// Issue time; compared with the agent's session revocation time. Absent
+    /// in tokens issued before revocation existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    /// Issue time in milliseconds, so a token minted in the same second as a
+    /// session revocation but after it stays valid. Absent in older tokens,
+    /// which fall back to `iat`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat_ms: Option<u64>,
+    /// `Some("refresh")` for refresh tokens; absent for access tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Unique refresh token id, stored server-side so it can be revoked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// Agent capabilities at issuance. Lets the poll path skip loading the
+    /// agent record to learn what to match; absent in tokens issued before
+    /// capability scoping, which fall back to the stored record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caps: Option<Vec<String>>,
+}
+
+fn now_secs() -> usize {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap() // Safe to unwrap unless system time is before epoch
+        .as_secs() as usize
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
 }
 
 // Auth struct holds the JWT keys
@@ -54,18 +92,26 @@ impl Auth {
         verify(password, hash).map_err(|e| AppError::BcryptError(e))
     }
 
-    /// Creates a new JWT token for the given user email.
-    pub fn create_token(&self, user_email: &str) -> Result<(String, usize), AppError> {
+    /// Creates a new JWT token for the given user email, optionally scoped to
+    /// the agent's capabilities.
+    pub fn create_token(
+        &self,
+        user_email: &str,
+        caps: Option<Vec<String>>,
+    ) -> Result<(String, usize), AppError> {
         // Calculate expiration time
-        let expiration_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap() // Safe to unwrap unless system time is before epoch
-            .as_secs() as usize
-            + ONE_WEEK;
+        let issued_at_ms = now_millis();
+        let issued_at = (issued_at_ms / 1000) as usize;
+        let expiration_time = issued_at + ONE_WEEK;
 
         let claims = Claims {
             sub: user_email.to_owned(), // Subject is the user's email
             exp: expiration_time,       // Expiration time
+            iat: Some(issued_at),
+            iat_ms: Some(issued_at_ms),
+            kind: None,
+            jti: None,
+            caps,
         };
 
         // Encode the claims into a JWT
@@ -75,10 +121,112 @@ impl Auth {
     }
 
     /// Decodes and validates a JWT token, returning the claims if valid.
+    /// Refresh tokens are rejected: they can only be exchanged, never used for access.
     pub fn decode_token(&self, token: &str) -> Result<Claims, AppError> {
         // Decode the token and validate it (signature, expiration)
-        decode::<Claims>(token, &self.decoding_key, &Validation::default())
+        let claims = decode::<Claims>(token, &self.decoding_key, &Validation::default())
             .map(|data| data.claims) // Extract the claims from the token data
-            .map_err(|e| AppError::Jwt(e)) // Convert jsonwebtoken error to AppError
+            .map_err(|e| AppError::Jwt(e))?; // Convert jsonwebtoken error to AppError
+        if claims.kind.is_some() {
+            return Err(AppError::Authentication(
+                "Refresh tokens cannot be used for access".to_string(),
+            ));
+        }
+        Ok(claims)
+    }
+
+    /// Validates an access token presented for refresh and returns its
+    /// claims. The token may have expired up to `grace_secs` ago; refresh
+    /// tokens are rejected. The caller issues the new token from the stored
+    /// agent record so capability changes are picked up.
+    pub fn decode_for_refresh(&self, old_token: &str, grace_secs: u64) -> Result<Claims, AppError> {
+        let mut validation = Validation::default();
+        validation.leeway = grace_secs;
+        let claims = decode::<Claims>(old_token, &self.decoding_key, &validation)
+            .map(|data| data.claims)
+            .map_err(AppError::Jwt)?;
+        if claims.kind.is_some() {
+            return Err(AppError::Authentication(
+                "Refresh tokens cannot be refreshed this way".to_string(),
+            ));
+        }
+        Ok(claims)
+    }
+
+    /// Creates a long-lived refresh token for the agent. Returns the token, its
+    /// id (to be stored so it can be revoked) and its expiration timestamp.
+    pub fn create_refresh_token(
+        &self,
+        agent_id: &str,
+    ) -> Result<(String, String, usize), AppError> {
+        let jti = crate::utils::time_sortable_uid();
+        let issued_at = now_secs();
+        let expiration_time = issued_at + REFRESH_TOKEN_TTL;
+        let claims = Claims {
+            sub: agent_id.to_owned(),
+            exp: expiration_time,
+            iat: Some(issued_at),
+            iat_ms: None,
+            kind: Some(REFRESH_TOKEN_KIND.to_string()),
+            jti: Some(jti.clone()),
+            caps: None,
+        };
+        encode(&Header::default(), &claims, &self.encoding_key)
+            .map(|token| (token, jti, expiration_time))
+            .map_err(AppError::Jwt)
+    }
+
+    /// Decodes a refresh token, validating signature, expiration and kind.
+    /// The caller must still check the returned `jti` against revocation storage.
+    pub fn decode_refresh_token(&self, token: &str) -> Result<Claims, AppError> {
+        let claims = decode::<Claims>(token, &self.decoding_key, &Validation::default())
+            .map(|data| data.claims)
+            .map_err(AppError::Jwt)?;
+        if claims.kind.as_deref() != Some(REFRESH_TOKEN_KIND) || claims.jti.is_none() {
+            return Err(AppError::Authentication("Not a refresh token".to_string()));
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_expired_secs_ago(auth: &Auth, secs: usize) -> String {
+        let claims = Claims {
+            sub: "agent-a".to_string(),
+            exp: now_secs() - secs,
+            iat: None,
+            iat_ms: None,
+            kind: None,
+            jti: None,
+            caps: Some(vec!["debug.echo".to_string()]),
+        };
+        encode(&Header::default(), &claims, &auth.encoding_key).unwrap()
+    }
+
+    #[test]
+    fn refresh_accepts_tokens_within_grace() {
+        let auth = Auth::new(b"secret");
+        let (old, _) = auth
+            .create_token("agent-a", Some(vec!["debug.echo".to_string()]))
+            .unwrap();
+        let old_claims = auth.decode_for_refresh(&old, 0).unwrap();
+        assert_eq!(old_claims.sub, "agent-a");
+
+        // Recently expired tokens are accepted within the grace window.
+        let expired = token_expired_secs_ago(&auth, 120);
+        assert!(auth.decode_for_refresh(&expired, 600).is_ok());
+    }
+
+    #[test]
+    fn refresh_rejects_tokens_expired_beyond_grace() {
+        let auth = Auth::new(b"secret");
+        let expired = token_expired_secs_ago(&auth, 3600);
+        assert!(auth.decode_for_refresh(&expired, 600).is_err());
+
+        let (refresh, _, _) = auth.create_refresh_token("agent-a").unwrap();
+        assert!(auth.decode_for_refresh(&refresh, 600).is_err());
     }
 }
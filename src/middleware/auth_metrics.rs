@@ -0,0 +1,176 @@
+//! Authentication outcome counters and failure-spike alerting.
+//!
+//! Every auth check in the middleware (and agent login / client capability
+//! checks in the services) reports its outcome here, keyed by where the
+//! credential came from. Counts are exposed in `/stats` and `/metrics`. Each
+//! failure is logged once, in one format; when failures across all sources
+//! reach `AUTH_FAILURE_ALERT_THRESHOLD` within `AUTH_FAILURE_ALERT_WINDOW_SECS`
+//! an error is logged as a possible brute-force attempt, at most once per
+//! window. In-memory only.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Which credential was checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AuthSource {
+    /// Bearer JWT on `/private/agent/*` or the agent WebSocket.
+    AgentJwt,
+    /// Agent login and refresh-token exchange.
+    AgentLogin,
+    /// Client API key on `/api/*`.
+    ClientApiKey,
+    /// Client API key lacking the capability it is used for.
+    ClientCapability,
+    /// `X-MGMT-API-KEY` override on `/api/*`.
+    MgmtOverride,
+    /// Management bearer token on `/management/*`.
+    MgmtToken,
+    /// `X-API-Key` on the storage API.
+    StorageApiKey,
+}
+
+impl AuthSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuthSource::AgentJwt => "agentJwt",
+            AuthSource::AgentLogin => "agentLogin",
+            AuthSource::ClientApiKey => "clientApiKey",
+            AuthSource::ClientCapability => "clientCapability",
+            AuthSource::MgmtOverride => "mgmtOverride",
+            AuthSource::MgmtToken => "mgmtToken",
+            AuthSource::StorageApiKey => "storageApiKey",
+        }
+    }
+}
+
+/// Outcome recorded for successful checks.
+pub const SUCCESS: &str = "success";
+
+pub struct AuthMetrics {
+    /// `(source, outcome)` -> count; outcome is [`SUCCESS`] or a failure reason.
+    counters: Mutex<HashMap<(AuthSource, &'static str), u64>>,
+    /// Failure timestamps within the alert window, oldest first.
+    recent_failures: Mutex<VecDeque<Instant>>,
+    last_alert: Mutex<Option<Instant>>,
+    /// Zero disables the spike alert.
+    alert_threshold: usize,
+    alert_window: Duration,
+}
+
+impl AuthMetrics {
+    pub fn new(alert_threshold: usize, alert_window_secs: u64) -> Arc<Self> {
+        Arc::new(Self {
+            counters: Mutex::new(HashMap::new()),
+            recent_failures: Mutex::new(VecDeque::new()),
+            last_alert: Mutex::new(None),
+            alert_threshold,
+            alert_window: Duration::from_secs(alert_window_secs),
+        })
+    }
+
+    fn count(&self, source: AuthSource, outcome: &'static str) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry((source, outcome))
+            .or_default() += 1;
+    }
+
+    pub fn success(&self, source: AuthSource) {
+        self.count(source, SUCCESS);
+    }
+
+    /// Count and log a failed check. `reason` is a short machine-readable
+    /// label (`missing_token`, `invalid_key`, ...); `detail` only goes to the log.
+    pub fn failure(&self, source: AuthSource, reason: &'static str, detail: &str) {
+        self.count(source, reason);
+        log::warn!("Auth failure [{}/{}]: {}", source.as_str(), reason, detail);
+        if let Some(failures) = self.note_failure_at(Instant::now()) {
+            log::error!(
+                "Auth failure spike: {} failures in the last {}s (latest {}/{}) — possible brute force",
+                failures,
+                self.alert_window.as_secs(),
+                source.as_str(),
+                reason
+            );
+        }
+    }
+
+    /// Track a failure for the spike alert. Returns the failure count in the
+    /// window when an alert is due.
+    fn note_failure_at(&self, now: Instant) -> Option<usize> {
+        if self.alert_threshold == 0 {
+            return None;
+        }
+        let failures = {
+            let mut recent = self.recent_failures.lock().unwrap();
+            recent.push_back(now);
+            while recent
+                .front()
+                .is_some_and(|at| now.duration_since(*at) > self.alert_window)
+            {
+                recent.pop_front();
+            }
+            recent.len()
+        };
+        if failures < self.alert_threshold {
+            return None;
+        }
+        let mut last_alert = self.last_alert.lock().unwrap();
+        if last_alert.is_some_and(|at| now.duration_since(at) < self.alert_window) {
+            return None;
+        }
+        *last_alert = Some(now);
+        Some(failures)
+    }
+
+    /// Counts per source, then per outcome.
+    pub fn snapshot(&self) -> BTreeMap<&'static str, BTreeMap<&'static str, u64>> {
+        let mut out: BTreeMap<&'static str, BTreeMap<&'static str, u64>> = BTreeMap::new();
+        for ((source, outcome), count) in self.counters.lock().unwrap().iter() {
+            out.entry(source.as_str())
+                .or_default()
+                .insert(outcome, *count);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_by_source_and_alerts_once_per_window_on_spike() {
+        let metrics = AuthMetrics::new(3, 60);
+        metrics.success(AuthSource::AgentJwt);
+        metrics.success(AuthSource::AgentJwt);
+        metrics.count(AuthSource::ClientApiKey, "invalid_key");
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot["agentJwt"][SUCCESS], 2);
+        assert_eq!(snapshot["clientApiKey"]["invalid_key"], 1);
+
+        let t0 = Instant::now();
+        assert_eq!(metrics.note_failure_at(t0), None);
+        assert_eq!(metrics.note_failure_at(t0), None);
+        assert_eq!(metrics.note_failure_at(t0), Some(3));
+        // Still spiking, but already alerted in this window.
+        assert_eq!(metrics.note_failure_at(t0 + Duration::from_secs(1)), None);
+
+        // Old failures age out of the window; a fresh spike alerts again.
+        let t1 = t0 + Duration::from_secs(120);
+        assert_eq!(metrics.note_failure_at(t1), None);
+        assert_eq!(metrics.note_failure_at(t1), None);
+        assert_eq!(metrics.note_failure_at(t1), Some(3));
+
+        let off = AuthMetrics::new(0, 60);
+        for _ in 0..10 {
+            assert_eq!(off.note_failure_at(t0), None);
+        }
+    }
+}
@@ -1,17 +1,40 @@
 use std::sync::Arc;
 
 use axum::{
-    body::Body,
-    extract::{FromRequestParts, Request, State},
-    http::request::Parts,
+    Json,
+    body::{Body, Bytes},
+    extract::{FromRequest, FromRequestParts, Request, State},
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION, request::Parts},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
-use serde::Deserialize;
+use futures::StreamExt;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::Value;
 
 pub mod auth;
+pub mod auth_metrics;
+pub mod rate_limit;
 
-use crate::{error::AppError, models::Agent, state::AppState};
+use crate::{
+    db::agent::AgentStorage, error::AppError, models::Agent, schema::ClientRequest, state::AppState,
+};
+
+use auth::Claims;
+use auth_metrics::AuthSource;
+
+/// Reject access tokens issued before the agent's sessions were revoked via
+/// `POST /management/agents/revoke_sessions/{agent_id}`.
+pub(crate) fn ensure_session_active(
+    agents: &AgentStorage,
+    claims: &Claims,
+) -> Result<(), AppError> {
+    let issued_at_ms = claims.iat_ms.or(claims.iat.map(|secs| secs as u64 * 1000));
+    if agents.is_session_revoked(&claims.sub, issued_at_ms) {
+        return Err(AppError::Authorization("Session revoked".to_string()));
+    }
+    Ok(())
+}
 
 /// Marker inserted into request extensions when a valid management token was
 /// supplied via the `X-MGMT-API-KEY` header on a client API route.
@@ -62,6 +85,42 @@ pub struct AuthenticatedAgentId(pub String);
 
 pub struct AuthenticatedAgent(pub Agent);
 
+/// Capabilities carried by the agent's token, inserted instead of the agent
+/// record on the poll routes.
+#[derive(Clone)]
+struct TokenCapabilities(Vec<String>);
+
+/// Agent routes that trust the token's capability claims instead of loading
+/// the agent record up front.
+const SCOPED_POLL_PATHS: [&str; 2] = ["/task/poll", "/task/poll_urgent"];
+
+/// Who is polling: the stored agent record (tokens without capability
+/// claims, WebSocket sessions) or just the uid and the token's capabilities.
+pub enum PollingAgent {
+    Loaded(Box<Agent>),
+    Scoped { uid: String, caps: Vec<String> },
+}
+
+impl<S: Send + Sync + 'static> FromRequestParts<S> for PollingAgent {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(agent) = parts.extensions.get::<Agent>() {
+            return Ok(PollingAgent::Loaded(Box::new(agent.clone())));
+        }
+        match (
+            parts.extensions.get::<String>(),
+            parts.extensions.get::<TokenCapabilities>(),
+        ) {
+            (Some(uid), Some(TokenCapabilities(caps))) => Ok(PollingAgent::Scoped {
+                uid: uid.clone(),
+                caps: caps.clone(),
+            }),
+            _ => Err(AppError::Authentication("agent type".to_string())),
+        }
+    }
+}
+
 // Removed #[async_trait]
 impl<S> FromRequestParts<S> for AuthenticatedAgent
 where
@@ -109,7 +168,8 @@ pub async fn jwt_auth_middleware_agent(
     let path = parts.uri.path();
     let method = parts.method.clone();
 
-    if path == "/register" || path == "/login" {
+    // Token refresh validates the (possibly expired) bearer token itself.
+    if path == "/register" || path == "/login" || path == "/auth/refresh" {
         let req = Request::from_parts(parts, body);
         return Ok(next.run(req).await);
     }
@@ -122,33 +182,56 @@ pub async fn jwt_auth_middleware_agent(
     let token =
         auth_header.and_then(|header| header.strip_prefix("Bearer ").map(|s| s.to_string()));
 
+    let metrics = &app_state.auth_metrics;
     let token = token.ok_or_else(|| {
-        log::warn!(
-            "Agent auth rejected: missing/invalid bearer token for {} {}",
-            method,
-            path
+        metrics.failure(
+            AuthSource::AgentJwt,
+            "missing_token",
+            &format!("missing/invalid bearer token for {} {}", method, path),
         );
         AppError::Authorization("Unauthorized".to_string())
     })?;
 
     match app_state.auth.decode_token(&token) {
         Ok(claims) => {
+            ensure_session_active(&app_state.storage.agents, &claims).inspect_err(|_| {
+                metrics.failure(
+                    AuthSource::AgentJwt,
+                    "revoked_token",
+                    &format!(
+                        "revoked session of agent '{}' used for {} {}",
+                        claims.sub, method, path
+                    ),
+                )
+            })?;
             parts.extensions.insert(claims.sub.clone());
+            if let Some(caps) = claims.caps
+                && SCOPED_POLL_PATHS.contains(&path)
+            {
+                metrics.success(AuthSource::AgentJwt);
+                parts.extensions.insert(TokenCapabilities(caps));
+                let req = Request::from_parts(parts, body);
+                return Ok(next.run(req).await);
+            }
             // insert actual user
             let user = app_state.storage.get_agent(&claims.sub);
 
             match user {
                 Some(u) => {
+                    metrics.success(AuthSource::AgentJwt);
                     parts.extensions.insert(u);
-                    ()
                 }
                 _ => {
-                    log::warn!(
-                        "Agent auth rejected: token subject '{}' not found for {} {} (known_agents={})",
-                        claims.sub,
-                        method,
-                        path,
-                        app_state.storage.agent_count()
+                    metrics.failure(
+                        AuthSource::AgentJwt,
+                        "unknown_agent",
+                        &format!(
+                            "token subject '{}' not found for {} {} (known_agents={})",
+                            claims.sub,
+                            method,
+                            path,
+                            app_state.storage.agent_count()
+                        ),
                     );
                     return Err(AppError::Authorization("Agent not found".to_string()));
                 }
@@ -158,7 +241,11 @@ pub async fn jwt_auth_middleware_agent(
             Ok(next.run(req).await)
         }
         Err(e) => {
-            log::warn!("JWT validation failed for {} {}: {}", method, path, e);
+            metrics.failure(
+                AuthSource::AgentJwt,
+                "invalid_token",
+                &format!("JWT validation failed for {} {}: {}", method, path, e),
+            );
             Err(AppError::Authorization("JWT token invalid".to_string()))
         }
     }
@@ -186,12 +273,26 @@ pub async fn token_auth_middleware_mgmt(
     let token =
         auth_header.and_then(|header| header.strip_prefix("Bearer ").map(|s| s.to_string()));
 
-    let token = token.ok_or(AppError::Authorization("Unauthorized".to_string()))?;
+    let metrics = &app_state.auth_metrics;
+    let Some(token) = token else {
+        metrics.failure(
+            AuthSource::MgmtToken,
+            "missing_token",
+            &format!("no bearer token for {}", path),
+        );
+        return Err(AppError::Authorization("Unauthorized".to_string()));
+    };
 
     if token == app_state.config.management_token {
+        metrics.success(AuthSource::MgmtToken);
         let req = Request::from_parts(parts, body);
         Ok(next.run(req).await)
     } else {
+        metrics.failure(
+            AuthSource::MgmtToken,
+            "invalid_token",
+            &format!("wrong management token for {}", path),
+        );
         Err(AppError::Authorization("Unauthorized".to_string()))
     }
 }
@@ -202,6 +303,82 @@ struct ApiKeyPayload {
     api_key: String,
 }
 
+/// A request body [`apikey_auth_middleware_user`] already parsed to find the
+/// `apiKey`, handed on so the handler does not parse the bytes again.
+#[derive(Clone)]
+struct ParsedBody(Value);
+
+/// The client API key a request authenticated with through a header.
+#[derive(Clone)]
+struct HeaderApiKey(String);
+
+/// Client API key sent as `X-API-Key: <key>` or `Authorization: ApiKey <key>`.
+pub fn header_api_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get("X-API-Key").and_then(|v| v.to_str().ok()) {
+        return Some(key);
+    }
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("ApiKey "))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+/// Parse a body that carries its own `apiKey`. The key is read from the
+/// parsed document, so the bytes are parsed only once.
+fn parse_keyed_body(bytes: &[u8]) -> Result<(ApiKeyPayload, Value), serde_json::Error> {
+    let body: Value = serde_json::from_slice(bytes)?;
+    let key = ApiKeyPayload::deserialize(&body)?;
+    Ok((key, body))
+}
+
+/// JSON body extractor for the `/api/*` handlers behind
+/// [`apikey_auth_middleware_user`]. Takes the body the middleware parsed when
+/// the key came in the body; otherwise (header or management auth) it reads
+/// the body the way `Json` does, with the same rejections.
+///
+/// With header auth the body may leave `apiKey` out and gets the header key;
+/// a body naming a different key is refused, so a valid header cannot act
+/// for another client.
+pub struct ClientJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ClientJson<T>
+where
+    T: DeserializeOwned + ClientRequest,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(mut req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(ParsedBody(body)) = req.extensions_mut().remove::<ParsedBody>() {
+            return serde_json::from_value(body).map(ClientJson).map_err(|e| {
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("Failed to deserialize the JSON body into the target type: {e}"),
+                )
+                    .into_response()
+            });
+        }
+        let header_key = req.extensions_mut().remove::<HeaderApiKey>();
+        let Json(mut value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        if let Some(HeaderApiKey(key)) = header_key {
+            let body_key = value.api_key_mut();
+            if body_key.is_empty() {
+                *body_key = key;
+            } else if *body_key != key {
+                return Err(AppError::Authorization(
+                    "apiKey does not match the API key header".to_string(),
+                )
+                .into_response());
+            }
+        }
+        Ok(ClientJson(value))
+    }
+}
+
 pub async fn apikey_auth_middleware_user(
     State(app_state): State<Arc<AppState>>,
     req: Request<Body>,
@@ -216,12 +393,20 @@ pub async fn apikey_auth_middleware_user(
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string());
 
+    let metrics = &app_state.auth_metrics;
+    let path = parts.uri.path().to_string();
     if let Some(ref key) = mgmt_key {
         if *key == app_state.config.management_token {
+            metrics.success(AuthSource::MgmtOverride);
             parts.extensions.insert(MgmtOverride);
             let req = Request::from_parts(parts, body);
             return Ok(next.run(req).await);
         }
+        metrics.failure(
+            AuthSource::MgmtOverride,
+            "invalid_token",
+            &format!("wrong X-MGMT-API-KEY for {}", path),
+        );
         return Err(AppError::Authorization(
             "Invalid X-MGMT-API-KEY".to_string(),
         ));
@@ -229,40 +414,111 @@ pub async fn apikey_auth_middleware_user(
 
     // Prefer header-based auth (no body read required).
     // This enables non-JSON transports (WebSocket, etc.) to authenticate cleanly.
-    if let Some(api_key) = parts.headers.get("X-API-Key").and_then(|v| v.to_str().ok()) {
+    if let Some(api_key) = header_api_key(&parts.headers).map(str::to_string) {
         if !app_state
             .storage
             .client_keys
-            .is_key_real_not_revoked(api_key)
+            .is_key_real_not_revoked(&api_key)
         {
+            metrics.failure(
+                AuthSource::ClientApiKey,
+                "invalid_key",
+                &format!("unknown or revoked X-API-Key for {}", path),
+            );
             return Err(AppError::Authorization("Unauthorized".to_string()));
         }
+        metrics.success(AuthSource::ClientApiKey);
+        enforce_rate_limit(&app_state, &api_key)?;
+        // The body is left to the handler's extractor, bounded by the same
+        // limit through `DefaultBodyLimit`; a declared length over it is
+        // refused here already.
+        let declared = parts
+            .headers
+            .get(axum::http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if let Some(len) = declared {
+            ensure_payload_fits(len, app_state.config.max_request_body_bytes)?;
+        }
+        parts.extensions.insert(HeaderApiKey(api_key));
         let req = Request::from_parts(parts, body);
         return Ok(next.run(req).await);
     }
 
-    // Fallback: read API key from JSON body (backward compatibility).
-    let body_bytes = axum::body::to_bytes(body, app_state.config.max_request_body_bytes)
-        .await
-        .map_err(|e| AppError::BadRequest(e.to_string()))?;
-
-    let api_key_payload: ApiKeyPayload = serde_json::from_slice(&body_bytes)
-        .map_err(|e| AppError::Authorization(format!("Failed to parse JSON body: {}", e)))?;
+    // Fallback: read API key from JSON body (backward compatibility). This
+    // buffers the whole body (up to `MAX_REQUEST_BODY_BYTES`) before the key
+    // is even checked, so under load it costs memory per in-flight request;
+    // clients should prefer the `X-API-Key` header.
+    let body_bytes = read_body_limited(body, app_state.config.max_request_body_bytes).await?;
+
+    let (api_key_payload, parsed) = parse_keyed_body(&body_bytes).map_err(|e| {
+        metrics.failure(
+            AuthSource::ClientApiKey,
+            "missing_key",
+            &format!("no apiKey in JSON body for {}: {}", path, e),
+        );
+        AppError::Authorization(format!("Failed to parse JSON body: {}", e))
+    })?;
 
     if !app_state
         .storage
         .client_keys
         .is_key_real_not_revoked(&api_key_payload.api_key)
     {
+        metrics.failure(
+            AuthSource::ClientApiKey,
+            "invalid_key",
+            &format!("unknown or revoked apiKey for {}", path),
+        );
         return Err(AppError::Authorization(format!(
             "Unauthorized: {api_key_payload:?}"
         )));
     }
-    let new_body = Body::from(body_bytes);
-    let req = Request::from_parts(parts, new_body);
+    metrics.success(AuthSource::ClientApiKey);
+    enforce_rate_limit(&app_state, &api_key_payload.api_key)?;
+    // Handlers read it back through `ClientJson`; the raw bytes are not needed
+    // any more.
+    drop(body_bytes);
+    parts.extensions.insert(ParsedBody(parsed));
+    let req = Request::from_parts(parts, Body::empty());
     Ok(next.run(req).await)
 }
 
+fn ensure_payload_fits(len: usize, limit: usize) -> Result<(), AppError> {
+    if len > limit {
+        return Err(AppError::PayloadTooLarge(format!(
+            "Request body exceeds the {} byte limit",
+            limit
+        )));
+    }
+    Ok(())
+}
+
+/// Buffer `body`, giving up with `413` as soon as it grows past `limit` bytes.
+async fn read_body_limited(body: Body, limit: usize) -> Result<Bytes, AppError> {
+    let mut stream = body.into_data_stream();
+    let mut buf = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| AppError::BadRequest(e.to_string()))?;
+        ensure_payload_fits(buf.len() + chunk.len(), limit)?;
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(buf))
+}
+
+/// Take a request token for an authenticated client key; `429` when its
+/// bucket is empty.
+fn enforce_rate_limit(app_state: &AppState, api_key: &str) -> Result<(), AppError> {
+    if app_state.rate_limiter.check(api_key) {
+        Ok(())
+    } else {
+        Err(AppError::RateLimited(format!(
+            "Over {} requests per second for this API key",
+            app_state.config.client_rate_limit_rps
+        )))
+    }
+}
+
 /// Auth middleware for the Storage API surface.
 /// Reads the client API key from the `X-API-Key` header and validates it
 /// against the same key store used by all other client API endpoints.
@@ -274,12 +530,18 @@ pub async fn apikey_header_auth_middleware_storage(
     mut req: Request<Body>,
     next: Next,
 ) -> Result<Response, AppError> {
+    let metrics = &app_state.auth_metrics;
     let api_key = req
         .headers()
         .get("X-API-Key")
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string())
         .ok_or_else(|| {
+            metrics.failure(
+                AuthSource::StorageApiKey,
+                "missing_key",
+                &format!("no X-API-Key for {}", req.uri().path()),
+            );
             AppError::Authorization(
                 "Missing X-API-Key header (use your regular client API key)".to_string(),
             )
@@ -290,11 +552,212 @@ pub async fn apikey_header_auth_middleware_storage(
         .client_keys
         .is_key_real_not_revoked(&api_key)
     {
+        metrics.failure(
+            AuthSource::StorageApiKey,
+            "invalid_key",
+            &format!("unknown or revoked X-API-Key for {}", req.uri().path()),
+        );
         return Err(AppError::Authorization(
             "Invalid client API key".to_string(),
         ));
     }
+    metrics.success(AuthSource::StorageApiKey);
 
     req.extensions_mut().insert(StorageApiKey(api_key));
     Ok(next.run(req).await)
 }
+
+/// Count the request as in flight for the readiness endpoint while it runs.
+pub async fn count_in_flight(
+    State(app_state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let _guard = app_state.readiness.enter();
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use auth::Auth;
+
+    #[test]
+    fn revoked_sessions_reject_earlier_tokens() {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let auth = Auth::new(b"secret");
+        let (token, _) = auth.create_token("agent-a", None).unwrap();
+        let claims = auth.decode_token(&token).unwrap();
+        assert!(ensure_session_active(&agents, &claims).is_ok());
+
+        agents.revoke_sessions("agent-a").unwrap();
+        let err = ensure_session_active(&agents, &claims).unwrap_err();
+        assert_eq!(err.status_code_number(), 403);
+
+        // A token issued right after the revocation, usually within the same
+        // second, is accepted.
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let (fresh, _) = auth.create_token("agent-a", None).unwrap();
+        let fresh = auth.decode_token(&fresh).unwrap();
+        assert!(ensure_session_active(&agents, &fresh).is_ok());
+
+        // Other agents are unaffected.
+        let (other, _) = auth.create_token("agent-b", None).unwrap();
+        let other = auth.decode_token(&other).unwrap();
+        assert!(ensure_session_active(&agents, &other).is_ok());
+    }
+
+    #[tokio::test]
+    async fn body_over_the_payload_limit_is_refused_with_413() {
+        let limit = 1024;
+        let fits = read_body_limited(Body::from(vec![b'x'; limit]), limit)
+            .await
+            .unwrap();
+        assert_eq!(fits.len(), limit);
+
+        let err = read_body_limited(Body::from(vec![b'x'; limit + 1]), limit)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code_number(), 413);
+        assert_eq!(err.error_type(), "payload_too_large");
+        assert!(ensure_payload_fits(limit + 1, limit).is_err());
+    }
+
+    #[tokio::test]
+    async fn handler_gets_the_full_request_parsed_by_the_middleware() {
+        use crate::schema::TaskSubmissionRequest;
+
+        let payload = serde_json::json!({ "image": "A".repeat(400_000), "n": 3 });
+        let bytes = serde_json::to_vec(&serde_json::json!({
+            "apiKey": "client-key",
+            "capability": "debug.echo",
+            "urgent": true,
+            "payload": payload,
+        }))
+        .unwrap();
+
+        let (key, parsed) = parse_keyed_body(&bytes).unwrap();
+        assert_eq!(key.api_key, "client-key");
+        let mut req = Request::new(Body::empty());
+        req.extensions_mut().insert(ParsedBody(parsed));
+        let ClientJson(task) = ClientJson::<TaskSubmissionRequest>::from_request(req, &())
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(task.api_key, "client-key");
+        assert_eq!(task.capability, "debug.echo");
+        assert!(task.urgent);
+        assert_eq!(task.payload, payload);
+
+        // Header-authenticated requests still carry their body.
+        let req = Request::builder()
+            .header("content-type", "application/json")
+            .body(Body::from(bytes))
+            .unwrap();
+        let ClientJson(task) = ClientJson::<TaskSubmissionRequest>::from_request(req, &())
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(task.payload, payload);
+
+        assert!(parse_keyed_body(br#"{"capability":"debug.echo"}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn submission_authenticated_by_header_needs_no_body_key() {
+        use crate::schema::TaskSubmissionRequest;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-API-Key", "client-key".parse().unwrap());
+        assert_eq!(header_api_key(&headers), Some("client-key"));
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "ApiKey client-key".parse().unwrap());
+        assert_eq!(header_api_key(&headers), Some("client-key"));
+        headers.insert(AUTHORIZATION, "Bearer client-key".parse().unwrap());
+        assert_eq!(header_api_key(&headers), None);
+
+        let submit = |body: &'static str| {
+            let mut req = Request::builder()
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            req.extensions_mut()
+                .insert(HeaderApiKey("client-key".to_string()));
+            ClientJson::<TaskSubmissionRequest>::from_request(req, &())
+        };
+        let ClientJson(task) = submit(r#"{"capability":"debug.echo","payload":{}}"#)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(task.api_key, "client-key");
+        assert_eq!(task.capability, "debug.echo");
+
+        // The body may repeat the header key, but not name another client's.
+        assert!(
+            submit(r#"{"apiKey":"client-key","capability":"debug.echo","payload":{}}"#)
+                .await
+                .is_ok()
+        );
+        let Err(res) =
+            submit(r#"{"apiKey":"other-key","capability":"debug.echo","payload":{}}"#).await
+        else {
+            panic!("mismatched apiKey was accepted");
+        };
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn poll_trusts_capability_claims_and_loads_the_record_without_them() {
+        use axum::{Router, routing::get};
+        use tower::ServiceExt;
+
+        let state = AppState::for_test(|_| {});
+        let mut agent = Agent::for_test("agent-a", &["debug.echo", "llm.qwen"]);
+        state
+            .storage
+            .agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        let describe = |poller: PollingAgent| async move {
+            match poller {
+                PollingAgent::Scoped { caps, .. } => format!("scoped {}", caps.join(",")),
+                PollingAgent::Loaded(agent) => format!("loaded {}", agent.capabilities.join(",")),
+            }
+        };
+        let app = Router::new()
+            .route("/task/poll", get(describe))
+            .route("/ping", get(describe))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                jwt_auth_middleware_agent,
+            ));
+        let call = |path: &'static str, caps: Option<Vec<String>>| {
+            let (token, _) = state.auth.create_token("agent-a", caps).unwrap();
+            let req = Request::builder()
+                .uri(path)
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let res = app.oneshot(req).await.unwrap();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let claims = Some(vec!["debug.echo".to_string()]);
+        assert_eq!(
+            call("/task/poll", claims.clone()).await,
+            "scoped debug.echo"
+        );
+        // Tokens from before capability claims poll with the stored record.
+        assert_eq!(call("/task/poll", None).await, "loaded debug.echo,llm.qwen");
+        // Other routes always load the record.
+        assert_eq!(call("/ping", claims).await, "loaded debug.echo,llm.qwen");
+    }
+}
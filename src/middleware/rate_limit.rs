@@ -0,0 +1,121 @@
+//! Per-client request rate limiting.
+//!
+//! With `CLIENT_RATE_LIMIT_RPS` set, every request authenticated with a client
+//! API key in `apikey_auth_middleware_user` takes a token from that key's
+//! bucket. Buckets refill at the configured rate up to
+//! `CLIENT_RATE_LIMIT_BURST` tokens; a request finding its bucket empty is
+//! answered with `429`. Buckets live in a sharded map so keys rarely contend
+//! on one lock, and [`ClientRateLimiter::sweep`] drops the ones that have
+//! filled up again. In-memory only.
+
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+const SHARDS: usize = 16;
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+pub struct ClientRateLimiter {
+    /// Tokens added per second; zero disables the limiter.
+    rate: f64,
+    /// Bucket size: requests a key may make back to back.
+    burst: f64,
+    hasher: RandomState,
+    shards: Vec<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl ClientRateLimiter {
+    pub fn new(rate_per_sec: f64, burst: u32) -> Arc<Self> {
+        Arc::new(Self {
+            rate: rate_per_sec.max(0.0),
+            burst: f64::from(burst.max(1)),
+            hasher: RandomState::new(),
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rate > 0.0
+    }
+
+    fn shard(&self, api_key: &str) -> &Mutex<HashMap<String, Bucket>> {
+        &self.shards[self.hasher.hash_one(api_key) as usize % SHARDS]
+    }
+
+    /// Take a token for `api_key`. False when its bucket is empty.
+    pub fn check(&self, api_key: &str) -> bool {
+        self.check_at(api_key, Instant::now())
+    }
+
+    fn check_at(&self, api_key: &str, now: Instant) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let mut shard = self.shard(api_key).lock().unwrap();
+        let bucket = shard.entry(api_key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        bucket.refilled_at = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Drop buckets that have refilled completely; a fresh one behaves the
+    /// same. Returns how many are left.
+    pub fn sweep(&self) -> usize {
+        self.sweep_at(Instant::now())
+    }
+
+    fn sweep_at(&self, now: Instant) -> usize {
+        let mut left = 0;
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            shard.retain(|_, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.refilled_at);
+                bucket.tokens + elapsed.as_secs_f64() * self.rate < self.burst
+            });
+            left += shard.len();
+        }
+        left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn key_over_its_limit_is_refused_without_affecting_others() {
+        let limiter = ClientRateLimiter::new(2.0, 5);
+        let start = Instant::now();
+
+        let admitted = (0..20).filter(|_| limiter.check_at("noisy", start)).count();
+        assert_eq!(admitted, 5);
+        assert!(limiter.check_at("quiet", start));
+
+        // Half a second refills one token at 2 per second.
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check_at("noisy", later));
+        assert!(!limiter.check_at("noisy", later));
+
+        // "quiet" has refilled by now; only "noisy" is still short.
+        assert_eq!(limiter.sweep_at(later), 1);
+        assert_eq!(limiter.sweep_at(later + Duration::from_secs(10)), 0);
+        assert!(ClientRateLimiter::new(0.0, 1).check("anyone"));
+    }
+}
@@ -17,6 +17,17 @@ pub struct UnassignedTask {
     pub data: TaskSubmissionRequest,
     /// When the task was created.
     pub created_at: DateTime<Utc>,
+    /// Agents that already failed this task; a restartable task is not
+    /// offered to them again while another online agent could run it.
+    #[serde(default)]
+    pub failed_agents: Vec<String>,
+    /// Not offered to agents before this time (retry delay after a failure).
+    #[serde(default)]
+    pub next_eligible_at: Option<DateTime<Utc>>,
+    /// Events of earlier attempts, carried over when a task is re-queued so the
+    /// next assignment continues the same trail.
+    #[serde(default)]
+    pub history: Vec<TaskEvent>,
 }
 
 impl UnassignedTask {
@@ -28,17 +39,37 @@ impl UnassignedTask {
             agent_id: agent_id.to_string(),
             created_at: self.created_at.clone(),
             assigned_at: now,
+            failed_agents: self.failed_agents.clone(),
             status: TaskStatus::Assigned,
             last_update_at: Some(now),
-            history: vec![TaskEvent {
-                timestamp: now,
-                description: format!("Assigned to {agent_id}"),
-            }],
+            history: self.assignment_history(agent_id, now),
             ..AssignedTask::default()
         }
     }
 
+    /// Carried-over history plus the event for a new assignment to `agent_id`.
+    fn assignment_history(&self, agent_id: &str, now: DateTime<Utc>) -> Vec<TaskEvent> {
+        let mut history = self.history.clone();
+        history.push(TaskEvent {
+            timestamp: now,
+            description: format!("Assigned to {agent_id}"),
+        });
+        history
+    }
+
+    /// When a task still waiting out its retry delay becomes eligible again.
+    pub fn retry_pending_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.next_eligible_at.filter(|at| *at > now)
+    }
+
+    /// When a task submitted with a future `notBefore` becomes eligible.
+    pub fn scheduled_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.data.not_before.filter(|at| *at > now)
+    }
+
     pub fn into_status_report(self) -> TaskStatusResponse {
+        let retry_at = self.retry_pending_at(Utc::now());
+        let not_before = self.scheduled_at(Utc::now());
         TaskStatusResponse {
             id: self.id,
             status: TaskStatus::Queued,
@@ -48,24 +79,43 @@ impl UnassignedTask {
             log: None,
             typical_runtime_seconds: None,
             typical_runtime_parameters: None,
+            timing: None,
+            output_schema_ref: None,
+            output_expired: false,
+            circuit: None,
+            capability_inactive: false,
+            queue_position: None,
+            retry_at,
+            not_before,
+            progress: None,
+            history: None,
+        }
+    }
+
+    pub fn timing(&self) -> TaskTiming {
+        TaskTiming {
+            queued_at: self.created_at,
+            assigned_at: None,
+            completed_at: None,
+            queue_wait_ms: None,
+            execution_ms: None,
         }
     }
 
     pub fn into_assigned(self, agent_id: &str) -> AssignedTask {
         let now = Utc::now();
+        let history = self.assignment_history(agent_id, now);
         AssignedTask {
             id: self.id,
             data: self.data,
             agent_id: agent_id.to_string(),
             created_at: self.created_at,
             assigned_at: now,
+            failed_agents: self.failed_agents,
             status: TaskStatus::Assigned,
             log: None,
             last_update_at: Some(now),
-            history: vec![TaskEvent {
-                timestamp: now,
-                description: format!("Assigned to {agent_id}"),
-            }],
+            history,
             ..AssignedTask::default()
         }
     }
@@ -106,7 +156,14 @@ pub struct AssignedTask {
     pub log: Option<String>,
     #[serde(default)]
     pub stage: Option<String>,
-    #[serde(default)]
+    /// Stored and served as whole milliseconds; records written before the
+    /// switch carry the `{secs, nanos}` form under the old name.
+    #[serde(
+        default,
+        with = "crate::utils::duration_ms_opt",
+        rename = "typicalRuntimeMs",
+        alias = "typicalRuntimeSeconds"
+    )]
     pub typical_runtime_seconds: Option<std::time::Duration>,
     #[serde(default)]
     pub typical_runtime_parameters: Option<TypicalRuntimeParameters>,
@@ -122,9 +179,42 @@ pub struct AssignedTask {
     /// Drives orphan recovery when the assigned agent goes silent and offline.
     #[serde(default)]
     pub last_update_at: Option<DateTime<Utc>>,
+    /// Execution duration the agent reported with a successful result, in seconds.
+    #[serde(default)]
+    pub reported_duration_secs: Option<f64>,
+    /// Agents that failed earlier attempts of this restartable task.
+    #[serde(default)]
+    pub failed_agents: Vec<String>,
+    /// When the result was purged under the capability's `resultTtlSecs`.
+    #[serde(default)]
+    pub output_expired_at: Option<DateTime<Utc>>,
+    /// Latest fraction of the work done (0.0-1.0) from a progress update.
+    #[serde(default)]
+    pub progress: Option<f32>,
 }
 
 impl AssignedTask {
+    /// Whether a failure of this attempt should re-queue the task: it is
+    /// `restartable` and has not used up its retries (`retry.maxRetries`,
+    /// else `max_restarts`).
+    pub fn can_restart(&self, max_restarts: u32) -> bool {
+        let max_retries = self
+            .data
+            .retry
+            .as_ref()
+            .and_then(|retry| retry.max_retries)
+            .unwrap_or(max_restarts);
+        self.data.restartable && (self.failed_agents.len() as u32) < max_retries
+    }
+
+    /// Whether this attempt has outlived its `deadlineSecs`, counted from
+    /// assignment.
+    pub fn deadline_passed(&self, now: DateTime<Utc>) -> bool {
+        self.data
+            .deadline_secs
+            .is_some_and(|secs| now - self.assigned_at >= TimeDelta::seconds(secs as i64))
+    }
+
     pub fn change_status(&mut self, new_status: TaskStatus) {
         if self.status == new_status {
             return;
@@ -154,6 +244,13 @@ impl AssignedTask {
     }
 
     pub fn change_stage(&mut self, stage: &str) {
+        if self.stage.as_deref() == Some(stage) {
+            return;
+        }
+        self.history.push(TaskEvent {
+            timestamp: Utc::now(),
+            description: format!("Stage set to {stage}"),
+        });
         self.stage = Some(stage.to_owned())
     }
 
@@ -167,10 +264,90 @@ impl AssignedTask {
             log: self.log,
             typical_runtime_seconds: self.typical_runtime_seconds,
             typical_runtime_parameters: self.typical_runtime_parameters,
+            timing: None,
+            output_schema_ref: None,
+            output_expired: self.output_expired_at.is_some(),
+            circuit: None,
+            capability_inactive: false,
+            queue_position: None,
+            retry_at: None,
+            not_before: None,
+            progress: self.progress,
+            history: None,
+        }
+    }
+
+    pub fn timing(&self) -> TaskTiming {
+        let execution_ms = self
+            .reported_duration_secs
+            .map(|secs| (secs * 1000.0).round() as i64)
+            .or_else(|| {
+                self.finished_at
+                    .map(|finished| (finished - self.assigned_at).num_milliseconds().max(0))
+            });
+        TaskTiming {
+            queued_at: self.created_at,
+            assigned_at: Some(self.assigned_at),
+            completed_at: self.finished_at,
+            queue_wait_ms: Some(
+                (self.assigned_at - self.created_at)
+                    .num_milliseconds()
+                    .max(0),
+            ),
+            execution_ms,
         }
     }
 }
 
+/// A result report for a task that was no longer assigned when it arrived,
+/// kept for reconciliation under `UNKNOWN_TASK_REPORT_POLICY=store`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LateReport {
+    /// Time-sortable UID, also the storage key.
+    pub record_id: String,
+    pub agent_id: String,
+    pub received_at: DateTime<Utc>,
+    pub report: TaskResultReport,
+}
+
+impl LateReport {
+    pub fn new(agent_id: &str, report: TaskResultReport) -> Self {
+        Self {
+            record_id: time_sortable_uid(),
+            agent_id: agent_id.to_string(),
+            received_at: Utc::now(),
+            report,
+        }
+    }
+}
+
+/// A task that failed for good — its retries ran out or it exceeded
+/// `deadlineSecs` — kept for operators to inspect and requeue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub task: AssignedTask,
+    pub failure_reason: String,
+    pub dead_at: DateTime<Utc>,
+}
+
+/// An urgent task as written through to the `tasks_urgent` tree, so a
+/// restart can put it back in the in-memory urgent store.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UrgentSnapshot {
+    pub task: UnassignedTask,
+    #[serde(default)]
+    pub assigned_task: Option<AssignedTask>,
+    pub status: TaskStatus,
+    pub created_at: DateTime<Utc>,
+    pub last_update: DateTime<Utc>,
+    pub ttl_secs: i64,
+    #[serde(default)]
+    pub global_deadline: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum CommunicationMethod {
@@ -198,10 +375,67 @@ pub struct Agent {
     pub app_version: Option<String>,
     #[serde(default)]
     pub display_name: Option<String>,
+    /// Capability patterns permitted by the API key this agent registered with,
+    /// captured at registration. `None` means unrestricted.
+    #[serde(default)]
+    pub allowed_capabilities: Option<Vec<String>>,
+    /// Overrides the global `URGENT_RESERVED_SLOTS` for this agent.
+    #[serde(default)]
+    pub urgent_reserved_slots: Option<u32>,
+    /// Set by the server when the agent kept faulting: no work is handed to
+    /// it before this time. Not changed by agent updates.
+    #[serde(default)]
+    pub quarantined_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub quarantine_reason: Option<String>,
+    /// Failed task reports, as of `last_error_at`; see
+    /// [`Agent::recent_failures_at`] for the decayed count. Server-owned like
+    /// the quarantine.
+    #[serde(default)]
+    pub recent_failures: u32,
+    /// Message of the agent's latest failed task report.
+    #[serde(default)]
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub last_error_at: Option<DateTime<Utc>>,
 }
 
 impl Agent {
-    const ONLINE_TIMEOUT_SECS: i64 = 120;
+    /// Default for `AGENT_ONLINE_TIMEOUT_SECS`.
+    pub const DEFAULT_ONLINE_TIMEOUT_SECS: i64 = 120;
+
+    /// `recent_failures` halves for every this many seconds without a failure.
+    pub const FAILURE_HALF_LIFE_SECS: i64 = 3600;
+
+    /// Longest `last_error` kept on the record.
+    const MAX_LAST_ERROR_LEN: usize = 500;
+
+    /// `recent_failures` decayed to `now`.
+    pub fn recent_failures_at(&self, now: DateTime<Utc>) -> u32 {
+        let Some(at) = self.last_error_at else {
+            return self.recent_failures;
+        };
+        let halvings = (now - at).num_seconds().max(0) / Self::FAILURE_HALF_LIFE_SECS;
+        u32::try_from(halvings)
+            .ok()
+            .and_then(|n| self.recent_failures.checked_shr(n))
+            .unwrap_or(0)
+    }
+
+    /// Count a failed task report with its error message.
+    pub fn note_failure(&mut self, error: &str, now: DateTime<Utc>) {
+        self.recent_failures = self.recent_failures_at(now).saturating_add(1);
+        let mut error = error.to_string();
+        if error.len() > Self::MAX_LAST_ERROR_LEN {
+            let mut end = Self::MAX_LAST_ERROR_LEN;
+            while !error.is_char_boundary(end) {
+                end -= 1;
+            }
+            error.truncate(end);
+        }
+        self.last_error = Some(error);
+        self.last_error_at = Some(now);
+    }
 
     /// Last activity timestamp for stale-agent cleanup. Falls back to
     /// `registered_at` for legacy records that predate `last_contact` on register.
@@ -209,17 +443,61 @@ impl Agent {
         self.last_contact.unwrap_or(self.registered_at)
     }
 
-    pub fn is_online(&self) -> bool {
+    /// Whether the agent was heard from within the last `timeout_secs`.
+    pub fn is_online(&self, timeout_secs: i64) -> bool {
+        let now = Utc::now();
+        now.signed_duration_since(self.last_activity_at()) <= TimeDelta::seconds(timeout_secs)
+    }
+
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantined_until
+            .is_some_and(|until| Utc::now() < until)
+    }
+}
+
+#[cfg(test)]
+impl Agent {
+    /// An online tier 0 agent with capacity 1 serving `capabilities`; tests
+    /// adjust the fields they care about.
+    pub(crate) fn for_test(uid: &str, capabilities: &[&str]) -> Self {
         let now = Utc::now();
-        now.signed_duration_since(self.last_activity_at())
-            <= TimeDelta::seconds(Self::ONLINE_TIMEOUT_SECS)
+        Agent {
+            uid: uid.to_string(),
+            uid_short: uid.to_string(),
+            personal_login_token: String::new(),
+            registered_at: now,
+            last_contact: Some(now),
+            last_comm_method: CommunicationMethod::default(),
+            capabilities: capabilities.iter().map(|cap| cap.to_string()).collect(),
+            tier: 0,
+            capacity: 1,
+            system_info: SystemInfo {
+                os: "linux".to_string(),
+                client: "test".to_string(),
+                runtime: "test".to_string(),
+                cpu_arch: "x86_64".to_string(),
+                cpu_model: None,
+                total_memory_gb: 1,
+                gpu: None,
+                machine_id: None,
+            },
+            app_version: None,
+            display_name: None,
+            allowed_capabilities: None,
+            urgent_reserved_slots: None,
+            quarantined_until: None,
+            quarantine_reason: None,
+            recent_failures: 0,
+            last_error: None,
+            last_error_at: None,
+        }
     }
 }
 
 impl From<AgentRegistrationRequest> for Agent {
     fn from(request: AgentRegistrationRequest) -> Self {
         let now = Utc::now();
-        let uid = time_sortable_uid();
+        let uid = request.agent_id.unwrap_or_else(time_sortable_uid);
         let short = get_last_six_chars(&uid);
 
         Agent {
@@ -235,6 +513,13 @@ impl From<AgentRegistrationRequest> for Agent {
             system_info: request.system_info,
             app_version: request.app_version,
             display_name: request.display_name,
+            allowed_capabilities: None,
+            urgent_reserved_slots: request.urgent_reserved_slots,
+            quarantined_until: None,
+            quarantine_reason: None,
+            recent_failures: 0,
+            last_error: None,
+            last_error_at: None,
         }
     }
 }
@@ -260,3 +545,41 @@ impl From<CreateApiKeyRequest> for ClientApiKey {
         }
     }
 }
+
+/// A capability registered by an operator, with its governance policies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityDefinition {
+    /// Base capability name (no extended attributes).
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Inactive capabilities reject new submissions.
+    pub active: bool,
+    /// Applied to submissions that don't set `timeoutSecs`.
+    #[serde(default)]
+    pub default_timeout_secs: Option<u64>,
+    /// Applied to submissions that don't set `maxWaitSecs`.
+    #[serde(default)]
+    pub default_max_wait_secs: Option<u64>,
+    /// JSON Schema describing the `output` of a successful task. Successful
+    /// results that don't match are rejected at resolution time.
+    #[serde(default)]
+    pub output_schema: Option<serde_json::Value>,
+    /// Cross-capability scheduling preference: when an agent could take queued
+    /// tasks of several capabilities, those with the highest weight go first.
+    #[serde(default)]
+    pub scheduling_weight: u32,
+    /// `submit_blocking` accepts non-urgent submissions for this capability
+    /// and promotes them to urgent for the duration of the wait.
+    #[serde(default)]
+    pub always_blocking: bool,
+    /// Seconds after completion at which a task's output is purged. The task
+    /// record and status stay; only `result` is dropped.
+    #[serde(default)]
+    pub result_ttl_secs: Option<u64>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
@@ -0,0 +1,46 @@
+//! Capability pattern matching.
+//!
+//! A pattern is `*` (anything), a prefix ending in `*` (`llm.*`, `LLM::*`),
+//! or an exact capability name. Client API keys restrict what may be submitted
+//! with these patterns, and agents may advertise them to serve every matching
+//! task capability without listing each one.
+
+use crate::utils::base_capability;
+
+/// Whether `pattern` covers the concrete capability `concrete`.
+pub fn capability_matches(pattern: &str, concrete: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => concrete.starts_with(prefix),
+        None => pattern == concrete,
+    }
+}
+
+/// Whether an agent advertising `advertised` can run tasks for `cap`. Both
+/// sides are compared by base name, so extended attributes never get in the
+/// way.
+pub fn agent_serves(advertised: &[String], cap: &str) -> bool {
+    let base = base_capability(cap);
+    advertised
+        .iter()
+        .any(|c| capability_matches(base_capability(c), base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_by_prefix() {
+        assert!(capability_matches("LLM::*", "LLM::mistral"));
+        assert!(!capability_matches("LLM::*", "Image::resize"));
+        assert!(capability_matches("*", "Image::resize"));
+        assert!(capability_matches("llm.mistral", "llm.mistral"));
+        assert!(!capability_matches("llm.mistral", "llm.mistral7b"));
+
+        let advertised = vec!["LLM::*[vision]".to_string(), "debug.echo".to_string()];
+        assert!(agent_serves(&advertised, "LLM::mistral"));
+        assert!(agent_serves(&advertised, "LLM::qwen[tools]"));
+        assert!(agent_serves(&advertised, "debug.echo"));
+        assert!(!agent_serves(&advertised, "Image::resize"));
+    }
+}
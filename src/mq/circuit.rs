@@ -0,0 +1,250 @@
+//! Per-capability circuit breaker for non-urgent tasks.
+//!
+//! When most recent attempts of a capability fail (a bad model deploy, a
+//! broken dependency on every runner), handing out more of its tasks only
+//! churns restarts. The breaker tracks each capability's outcomes over a
+//! sliding window and, once the failure rate crosses the threshold, opens:
+//! its tasks stay queued but are not offered to agents. After the cooldown a
+//! single probe task is let through (half-open); its success closes the
+//! circuit, its failure re-opens it for another cooldown.
+//!
+//! Disabled unless `CIRCUIT_BREAKER_FAILURE_RATE` is set. Synchronous, and the
+//! lock is never held across `.await`.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::CircuitBreakerConfig, utils::base_capability};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CircuitState {
+    Closed,
+    /// Tasks of the capability are not offered until the cooldown ends.
+    Open,
+    /// Cooldown over: one probe task may be handed out.
+    HalfOpen,
+}
+
+/// Per-capability view reported by `/stats`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub successes: usize,
+    pub failures: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opened_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct Circuit {
+    /// `(when, succeeded)`, oldest first, trimmed to the window.
+    outcomes: VecDeque<(DateTime<Utc>, bool)>,
+    opened_at: Option<DateTime<Utc>>,
+    /// When the half-open probe was handed out; a probe that never reports
+    /// frees the slot again after another cooldown.
+    probe_at: Option<DateTime<Utc>>,
+}
+
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            circuits: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn cooldown(&self) -> TimeDelta {
+        TimeDelta::seconds(self.config.cooldown_secs)
+    }
+
+    fn state_of(&self, circuit: &Circuit, now: DateTime<Utc>) -> CircuitState {
+        match circuit.opened_at {
+            None => CircuitState::Closed,
+            Some(at) if now - at < self.cooldown() => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Record the outcome of a finished attempt of a `cap` task.
+    pub fn record(&self, cap: &str, succeeded: bool) {
+        self.record_at(cap, succeeded, Utc::now());
+    }
+
+    fn record_at(&self, cap: &str, succeeded: bool, now: DateTime<Utc>) {
+        let Some(threshold) = self.config.failure_rate else {
+            return;
+        };
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits
+            .entry(base_capability(cap).to_string())
+            .or_default();
+        match self.state_of(circuit, now) {
+            CircuitState::HalfOpen => {
+                circuit.probe_at = None;
+                circuit.outcomes.clear();
+                circuit.opened_at = if succeeded { None } else { Some(now) };
+                return;
+            }
+            // A straggler from before the circuit opened; the window already
+            // made its decision.
+            CircuitState::Open => return,
+            CircuitState::Closed => {}
+        }
+        circuit.outcomes.push_back((now, succeeded));
+        let window_start = now - TimeDelta::seconds(self.config.window_secs);
+        while circuit
+            .outcomes
+            .front()
+            .is_some_and(|(at, _)| *at < window_start)
+        {
+            circuit.outcomes.pop_front();
+        }
+        let total = circuit.outcomes.len();
+        let failures = circuit.outcomes.iter().filter(|(_, ok)| !ok).count();
+        if total >= self.config.min_samples && failures as f64 / total as f64 >= threshold {
+            circuit.opened_at = Some(now);
+        }
+    }
+
+    /// Whether tasks of `cap` may be offered right now.
+    pub fn allows(&self, cap: &str) -> bool {
+        self.allows_at(cap, Utc::now())
+    }
+
+    fn allows_at(&self, cap: &str, now: DateTime<Utc>) -> bool {
+        let circuits = self.circuits.lock().unwrap();
+        let Some(circuit) = circuits.get(base_capability(cap)) else {
+            return true;
+        };
+        match self.state_of(circuit, now) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => circuit
+                .probe_at
+                .is_none_or(|at| now - at >= self.cooldown()),
+        }
+    }
+
+    /// The agent capabilities whose circuit lets tasks through.
+    pub fn allowed_caps(&self, caps: &[String]) -> Vec<String> {
+        caps.iter().filter(|c| self.allows(c)).cloned().collect()
+    }
+
+    /// A `cap` task was handed out; in half-open state it is the probe.
+    pub fn assigned(&self, cap: &str) {
+        self.assigned_at(cap, Utc::now());
+    }
+
+    fn assigned_at(&self, cap: &str, now: DateTime<Utc>) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.get_mut(base_capability(cap))
+            && self.state_of(circuit, now) == CircuitState::HalfOpen
+        {
+            circuit.probe_at = Some(now);
+        }
+    }
+
+    pub fn state(&self, cap: &str) -> CircuitState {
+        let circuits = self.circuits.lock().unwrap();
+        circuits
+            .get(base_capability(cap))
+            .map_or(CircuitState::Closed, |c| self.state_of(c, Utc::now()))
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, CircuitStatus> {
+        let now = Utc::now();
+        self.circuits
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(cap, circuit)| {
+                let failures = circuit.outcomes.iter().filter(|(_, ok)| !ok).count();
+                let status = CircuitStatus {
+                    state: self.state_of(circuit, now),
+                    successes: circuit.outcomes.len() - failures,
+                    failures,
+                    opened_at: circuit.opened_at,
+                };
+                (cap.clone(), status)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> Arc<CircuitBreaker> {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            failure_rate: Some(0.5),
+            min_samples: 4,
+            window_secs: 300,
+            cooldown_secs: 60,
+        })
+    }
+
+    #[test]
+    fn opens_on_failures_and_recovers_through_a_probe() {
+        let cb = breaker();
+        let t0 = Utc::now();
+        cb.record_at("llm.qwen[vision]", true, t0);
+        cb.record_at("llm.qwen", false, t0);
+        cb.record_at("llm.qwen", false, t0);
+        assert!(cb.allows_at("llm.qwen", t0), "below min_samples");
+        cb.record_at("llm.qwen", false, t0);
+        assert!(!cb.allows_at("llm.qwen[vision]", t0));
+        assert!(cb.allows_at("debug.echo", t0));
+
+        // Cooldown over: exactly one probe goes out.
+        let t1 = t0 + TimeDelta::seconds(61);
+        assert!(cb.allows_at("llm.qwen", t1));
+        cb.assigned_at("llm.qwen", t1);
+        assert!(!cb.allows_at("llm.qwen", t1));
+
+        // The probe fails: open for another cooldown.
+        cb.record_at("llm.qwen", false, t1);
+        assert!(!cb.allows_at("llm.qwen", t1 + TimeDelta::seconds(30)));
+
+        // The next probe succeeds: closed, with a fresh window.
+        let t2 = t1 + TimeDelta::seconds(61);
+        cb.assigned_at("llm.qwen", t2);
+        cb.record_at("llm.qwen", true, t2);
+        assert!(cb.allows_at("llm.qwen", t2));
+        cb.record_at("llm.qwen", false, t2);
+        assert!(cb.allows_at("llm.qwen", t2));
+    }
+
+    #[test]
+    fn old_outcomes_leave_the_window_and_disabled_breaker_never_opens() {
+        let cb = breaker();
+        let t0 = Utc::now();
+        for _ in 0..3 {
+            cb.record_at("debug.echo", false, t0);
+        }
+        let later = t0 + TimeDelta::seconds(301);
+        cb.record_at("debug.echo", false, later);
+        assert!(cb.allows_at("debug.echo", later));
+
+        let off = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_rate: None,
+            ..breaker().config.clone()
+        });
+        for _ in 0..10 {
+            off.record_at("debug.echo", false, t0);
+        }
+        assert!(off.allows_at("debug.echo", t0));
+    }
+}
@@ -0,0 +1,294 @@
+//! Task dependencies (`dependsOn`).
+//!
+//! A queued non-urgent task that lists dependencies is held back by the
+//! regular store until every dependency is `Completed`; it is then marked
+//! ready and offered to agents like any other task. When a dependency ends in
+//! any other terminal state (or does not exist), the dependent task is closed
+//! per `DEPENDENCY_FAILURE_POLICY`. Dependencies must belong to the same API
+//! key as the dependent task; others count as not found. Waiting tasks are
+//! re-evaluated right after submit, when a task they wait on reports a result
+//! (looked up through the regular store's index of waiters by dependency), and
+//! by the task sweeper.
+
+use std::sync::Arc;
+
+use log::{info, warn};
+
+use crate::{
+    config::DependencyFailurePolicy,
+    db::persistent_task_storage::TaskStorage,
+    error::AppError,
+    models::UnassignedTask,
+    mq::dispatch::dispatch_for_capability,
+    schema::{TaskId, TaskStatus},
+    state::AppState,
+    utils::base_capability,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DependencyState {
+    /// Every dependency is `Completed`.
+    Satisfied,
+    /// At least one dependency is still queued or running.
+    Pending,
+    /// A dependency ended without completing; `status` is `None` when it
+    /// could not be found at all.
+    Failed {
+        dependency: TaskId,
+        status: Option<TaskStatus>,
+    },
+}
+
+/// Where the dependencies of a task submitted under `api_key` stand. A
+/// dependency submitted under another key counts as not found, so its
+/// existence and outcome are not revealed.
+pub fn dependency_state(
+    tasks: &TaskStorage,
+    api_key: &str,
+    depends_on: &[TaskId],
+) -> Result<DependencyState, AppError> {
+    let mut pending = false;
+    for dependency in depends_on {
+        let found = if let Some(task) = tasks.get_assigned(dependency)? {
+            Some((task.data.api_key, task.status))
+        } else if let Some(task) = tasks.get_unassigned(dependency)? {
+            Some((task.data.api_key, TaskStatus::Queued))
+        } else {
+            tasks
+                .get_archived(dependency)?
+                .map(|task| (task.data.api_key, task.status))
+        };
+        let status = found
+            .filter(|(owner, _)| owner == api_key)
+            .map(|(_, status)| status);
+        match status {
+            Some(TaskStatus::Completed) => {}
+            Some(status) if !status.is_terminal() => pending = true,
+            status => {
+                return Ok(DependencyState::Failed {
+                    dependency: dependency.clone(),
+                    status,
+                });
+            }
+        }
+    }
+    Ok(if pending {
+        DependencyState::Pending
+    } else {
+        DependencyState::Satisfied
+    })
+}
+
+/// Release or close every queued task still waiting on dependencies. The
+/// task sweeper's backstop; reports and submissions use the narrower
+/// [`reevaluate_dependents`] and [`reevaluate_tasks`].
+pub async fn reevaluate(state: &Arc<AppState>) {
+    let waiting = state.regular.waiting_on_dependencies().await;
+    settle(state, waiting).await;
+}
+
+/// Release or close the queued tasks that wait on `dependency`, which just
+/// reached a terminal status.
+pub async fn reevaluate_dependents(state: &Arc<AppState>, dependency: &TaskId) {
+    let waiting = state.regular.waiting_on(dependency).await;
+    settle(state, waiting).await;
+}
+
+/// Release or close freshly queued tasks whose dependencies may already be
+/// finished (or failed).
+pub async fn reevaluate_tasks(state: &Arc<AppState>, ids: &[TaskId]) {
+    let mut waiting = Vec::new();
+    for id in ids {
+        if let Some(task) = state.regular.get_task(id).await
+            && !task.data.depends_on.is_empty()
+        {
+            waiting.push(task);
+        }
+    }
+    settle(state, waiting).await;
+}
+
+/// Check each of `waiting` and release or close it. Closing a task can fail
+/// its own dependents, so those are checked in turn.
+async fn settle(state: &Arc<AppState>, mut waiting: Vec<UnassignedTask>) {
+    while let Some(task) = waiting.pop() {
+        match dependency_state(
+            &state.storage.tasks,
+            &task.data.api_key,
+            &task.data.depends_on,
+        ) {
+            Ok(DependencyState::Pending) => {}
+            Ok(DependencyState::Satisfied) => {
+                info!(
+                    "Task {} dependencies completed; releasing to agents",
+                    task.id
+                );
+                state.regular.mark_dependencies_met(&task);
+                dispatch_for_capability(state, base_capability(&task.id.cap)).await;
+            }
+            Ok(DependencyState::Failed { dependency, status }) => {
+                let target = match state.config.dependency_failure {
+                    DependencyFailurePolicy::Fail => TaskStatus::Failed,
+                    DependencyFailurePolicy::Cancel => TaskStatus::Canceled,
+                };
+                let reason = match status {
+                    Some(status) => format!("Dependency {} ended as {:?}", dependency, status),
+                    None => format!("Dependency {} not found", dependency),
+                };
+                match state
+                    .regular
+                    .close_queued(
+                        &state.storage.tasks,
+                        vec![task.id.clone()],
+                        "(dependency-failed)",
+                        target,
+                        |_| Some(reason.clone()),
+                    )
+                    .await
+                {
+                    Ok(0) => {}
+                    Ok(_) => waiting.extend(state.regular.waiting_on(&task.id).await),
+                    Err(e) => warn!(
+                        "Failed to close task {} after dependency failure: {}",
+                        task.id, e
+                    ),
+                }
+            }
+            Err(e) => warn!("Dependency check for task {} failed: {}", task.id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    use crate::schema::TaskSubmissionRequest;
+
+    fn temp_storage() -> TaskStorage {
+        TaskStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    fn queue(storage: &TaskStorage) -> TaskId {
+        queue_for(storage, "key-a", Vec::new()).id
+    }
+
+    fn queue_for(storage: &TaskStorage, api_key: &str, depends_on: Vec<TaskId>) -> UnassignedTask {
+        let task = UnassignedTask {
+            id: TaskId::new_with_cap("cap".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "cap".to_string(),
+                api_key: api_key.to_string(),
+                depends_on,
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        storage.add_unassigned(&task).unwrap();
+        task
+    }
+
+    fn finish(storage: &TaskStorage, id: &TaskId, status: TaskStatus) {
+        let mut assigned = storage.assign_task(id, "agent").unwrap();
+        assigned.change_status(status);
+        storage.update_assigned(&assigned).unwrap();
+    }
+
+    #[test]
+    fn dependencies_are_satisfied_pending_or_failed() {
+        let storage = temp_storage();
+        let first = queue(&storage);
+        let second = queue(&storage);
+        let deps = vec![first.clone(), second.clone()];
+
+        assert_eq!(
+            dependency_state(&storage, "key-a", &deps).unwrap(),
+            DependencyState::Pending
+        );
+
+        finish(&storage, &first, TaskStatus::Completed);
+        assert_eq!(
+            dependency_state(&storage, "key-a", &deps).unwrap(),
+            DependencyState::Pending
+        );
+
+        finish(&storage, &second, TaskStatus::Completed);
+        // Archived dependencies count too.
+        storage
+            .force_archive_older_than(Utc::now() + chrono::Duration::seconds(1), 0)
+            .unwrap();
+        assert_eq!(
+            dependency_state(&storage, "key-a", &deps).unwrap(),
+            DependencyState::Satisfied
+        );
+
+        let third = queue(&storage);
+        finish(&storage, &third, TaskStatus::Failed);
+        assert_eq!(
+            dependency_state(&storage, "key-a", &[first.clone(), third.clone()]).unwrap(),
+            DependencyState::Failed {
+                dependency: third,
+                status: Some(TaskStatus::Failed),
+            }
+        );
+
+        let missing = TaskId {
+            cap: "cap".to_string(),
+            id: "missing".to_string(),
+        };
+        assert_eq!(
+            dependency_state(&storage, "key-a", std::slice::from_ref(&missing)).unwrap(),
+            DependencyState::Failed {
+                dependency: missing,
+                status: None,
+            }
+        );
+    }
+
+    #[test]
+    fn dependencies_of_other_keys_count_as_missing() {
+        let storage = temp_storage();
+        let foreign = queue_for(&storage, "key-b", Vec::new()).id;
+        finish(&storage, &foreign, TaskStatus::Completed);
+        assert_eq!(
+            dependency_state(&storage, "key-a", std::slice::from_ref(&foreign)).unwrap(),
+            DependencyState::Failed {
+                dependency: foreign.clone(),
+                status: None,
+            }
+        );
+        assert_eq!(
+            dependency_state(&storage, "key-b", std::slice::from_ref(&foreign)).unwrap(),
+            DependencyState::Satisfied
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failed_task_closes_its_dependents_in_turn() {
+        let state = AppState::for_test(|_| {});
+        let storage = &state.storage.tasks;
+        let upstream = queue(storage);
+        let middle = queue_for(storage, "key-a", vec![upstream.clone()]);
+        let last = queue_for(storage, "key-a", vec![middle.id.clone()]);
+        let unrelated = queue_for(storage, "key-a", vec![queue(storage)]);
+        for task in [&middle, &last, &unrelated] {
+            state.regular.add_task(task.clone()).await;
+        }
+
+        finish(storage, &upstream, TaskStatus::Failed);
+        reevaluate_dependents(&state, &upstream).await;
+
+        for id in [&middle.id, &last.id] {
+            assert_eq!(
+                storage.get_assigned(id).unwrap().unwrap().status,
+                TaskStatus::Failed
+            );
+            assert!(state.regular.get_task(id).await.is_none());
+        }
+        assert!(state.regular.get_task(&unrelated.id).await.is_some());
+    }
+}
@@ -27,10 +27,13 @@ use crate::{
     mq::{
         registry::WsOut,
         scheduler::{
-            all_online_agents_for, find_assignable_non_urgent_tasks_with_capabilities_for_tier,
-            find_urgent_tasks_with_capabilities, try_unassign_non_urgent_task,
+            all_online_agents_for, dispatchable_caps,
+            find_assignable_non_urgent_tasks_with_capabilities_for_tier,
+            find_urgent_tasks_with_capabilities, has_non_urgent_headroom, least_loaded_caps,
+            try_unassign_non_urgent_task,
         },
     },
+    preferences::SelectionStrategy,
     schema::TaskId,
     state::AppState,
     utils::base_capability,
@@ -43,7 +46,7 @@ const MAX_DISPATCH_ITERS: usize = 1000;
 
 /// Concurrency slots for an agent. Treat 0 as 1 so a legacy/misconfigured agent
 /// still receives exactly one pushed task at a time rather than none or unbounded.
-fn effective_capacity(agent: &Agent) -> usize {
+pub(crate) fn effective_capacity(agent: &Agent) -> usize {
     agent.capacity.max(1) as usize
 }
 
@@ -58,20 +61,37 @@ enum PushOutcome {
 }
 
 /// Pick the next task this agent could take: urgent first (FIFO, runner-pinned),
-/// then regular (tier-filtered). Returns just the id — the atomic claim happens
-/// in [`service::take_task`].
+/// then regular (tier-filtered) while the agent is below its non-urgent share
+/// of capacity. Returns just the id — the atomic claim happens in
+/// [`service::take_task`].
 async fn next_task_for(state: &Arc<AppState>, agent: &Agent) -> Option<TaskId> {
-    if let Some(t) =
-        find_urgent_tasks_with_capabilities(&state.urgent, &agent.capabilities, &agent.uid).await
+    if let Some(t) = find_urgent_tasks_with_capabilities(
+        &state.urgent,
+        &agent.capabilities,
+        &agent.uid,
+        Some(&agent.system_info),
+    )
+    .await
     {
         return Some(t.id);
     }
+    if !has_non_urgent_headroom(agent, &state.agent_load, state.config.urgent.reserved_slots) {
+        return None;
+    }
+    let caps = least_loaded_caps(
+        agent,
+        dispatchable_caps(state, &agent.capabilities),
+        &state.storage.agents,
+        &state.agent_load,
+        state.regular.preferences().selection_strategy,
+    );
     find_assignable_non_urgent_tasks_with_capabilities_for_tier(
         &state.regular,
-        &agent.capabilities,
+        &caps,
         agent.tier,
         &state.storage.agents,
         &agent.uid,
+        &state.storage.capabilities.scheduling_weights(),
     )
     .await
     .map(|t| t.id)
@@ -176,7 +196,7 @@ pub async fn dispatch_for_capability(state: &Arc<AppState>, cap: &str) {
     let mut unhealthy: HashSet<String> = HashSet::new();
 
     for _ in 0..MAX_DISPATCH_ITERS {
-        let candidates: Vec<Agent> = all_online_agents_for(&base, &state.storage.agents)
+        let mut candidates: Vec<Agent> = all_online_agents_for(&base, &state.storage.agents)
             .await
             .into_iter()
             .filter(|a| !unhealthy.contains(&a.uid))
@@ -186,6 +206,15 @@ pub async fn dispatch_for_capability(state: &Arc<AppState>, cap: &str) {
         if candidates.is_empty() {
             break;
         }
+        if state.regular.preferences().selection_strategy == SelectionStrategy::LeastLoaded {
+            // Offer to the most idle agent first (smallest in-flight share).
+            candidates.sort_by_key(|a| {
+                (
+                    state.agent_load.in_flight(&a.uid) * 1000 / effective_capacity(a),
+                    a.uid.clone(),
+                )
+            });
+        }
 
         let mut progressed = false;
         for agent in &candidates {
@@ -225,7 +254,7 @@ pub async fn dispatch_to_agent(state: &Arc<AppState>, uid: &str) {
         let Some(agent) = state.storage.get_agent(uid) else {
             break;
         };
-        if state.agent_load.in_flight(uid) >= effective_capacity(&agent) {
+        if agent.is_quarantined() || state.agent_load.in_flight(uid) >= effective_capacity(&agent) {
             break;
         }
         let Some(task_id) = next_task_for(state, &agent).await else {
@@ -1,8 +1,16 @@
 pub mod agent_load;
+pub mod capability;
+pub mod circuit;
+pub mod dependencies;
 pub mod dispatch;
 pub mod heuristic;
+pub mod online_caps;
+pub mod poll_gate;
+pub mod quarantine;
+pub mod queue_wait;
 pub mod registry;
 pub mod regular;
 pub mod scheduler;
 pub mod types;
 pub mod urgent;
+pub mod webhook;
@@ -0,0 +1,113 @@
+//! Short-lived cache of the capabilities advertised by online agents.
+//!
+//! `capabilities/online` is polled by dashboards and clients; answering it
+//! means a full scan of the agent tree. The cache keeps the last result for
+//! `CAPABILITIES_ONLINE_CACHE_SECS`, so a burst of calls costs one scan.
+//! Agent registration, update and removal invalidate it; agents going online
+//! or offline by heartbeat alone show up once the entry expires.
+
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{db::agent::AgentStorage, utils::base_capability};
+
+pub struct OnlineCapsCache {
+    /// Zero disables caching: every call scans.
+    ttl: Duration,
+    /// Full (extended) capability strings and when they were computed.
+    entry: Mutex<Option<(Instant, Arc<HashSet<String>>)>>,
+    scans: AtomicU64,
+}
+
+impl OnlineCapsCache {
+    pub fn new(ttl_secs: u64) -> Arc<Self> {
+        Arc::new(Self {
+            ttl: Duration::from_secs(ttl_secs),
+            entry: Mutex::new(None),
+            scans: AtomicU64::new(0),
+        })
+    }
+
+    /// Capabilities of online agents, extended attributes included.
+    pub fn extended(&self, agents: &AgentStorage) -> Arc<HashSet<String>> {
+        if let Some((at, caps)) = self.entry.lock().unwrap().as_ref()
+            && at.elapsed() < self.ttl
+        {
+            return caps.clone();
+        }
+        self.scans.fetch_add(1, Ordering::Relaxed);
+        let caps: Arc<HashSet<String>> = Arc::new(
+            agents
+                .list_all_agents()
+                .into_iter()
+                .filter(|a| agents.is_online(a))
+                .flat_map(|agent| agent.capabilities)
+                .collect(),
+        );
+        if !self.ttl.is_zero() {
+            *self.entry.lock().unwrap() = Some((Instant::now(), caps.clone()));
+        }
+        caps
+    }
+
+    /// Base capabilities of online agents (extended attributes stripped).
+    pub fn base(&self, agents: &AgentStorage) -> HashSet<String> {
+        self.extended(agents)
+            .iter()
+            .map(|cap| base_capability(cap).to_string())
+            .collect()
+    }
+
+    /// Drop the cached set; the next call scans again.
+    pub fn invalidate(&self) {
+        *self.entry.lock().unwrap() = None;
+    }
+
+    /// How many times the agent tree has been scanned.
+    pub fn scans(&self) -> u64 {
+        self.scans.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::DuplicateAgentUidPolicy, models::Agent};
+
+    async fn storage_with_agent(caps: &[&str]) -> AgentStorage {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let mut agent = Agent::for_test("agent-a", caps);
+        agents
+            .create_agent(&mut agent, DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        agents
+    }
+
+    #[tokio::test]
+    async fn repeated_calls_within_ttl_scan_once() {
+        let agents = storage_with_agent(&["llm.qwen[vision]", "debug.echo"]).await;
+        let cache = OnlineCapsCache::new(60);
+        for _ in 0..5 {
+            assert!(cache.extended(&agents).contains("llm.qwen[vision]"));
+            assert!(cache.base(&agents).contains("llm.qwen"));
+        }
+        assert_eq!(cache.scans(), 1);
+
+        cache.invalidate();
+        cache.extended(&agents);
+        assert_eq!(cache.scans(), 2);
+
+        let uncached = OnlineCapsCache::new(0);
+        uncached.extended(&agents);
+        uncached.extended(&agents);
+        assert_eq!(uncached.scans(), 2);
+    }
+}
@@ -0,0 +1,179 @@
+//! Debounce for HTTP poll stampedes.
+//!
+//! When many identical agents poll at once and a single task matches, every
+//! poll runs the full scan and all but one lose the pickup. With the gate on,
+//! polls that would scan the same tasks (same tier and base capabilities) are
+//! serialized: one scans, the others wait for it. If the scan handed a task
+//! out, the waiters back off with "no task" instead of scanning for the task
+//! that was just offered. A waiter never waits longer than the debounce
+//! window; after that it scans ungated, so a stuck scan cannot block polling.
+//!
+//! Off by default (`POLL_GATE_MS=0`): it trades throughput for CPU, since at
+//! most one task per gate is handed out per window.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::utils::base_capability;
+
+/// Scan and back-off counters, reported by `/stats`.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub struct PollGateStats {
+    pub scans: u64,
+    pub backoffs: u64,
+}
+
+pub struct PollGate {
+    /// Debounce window; zero disables the gate.
+    window: Duration,
+    /// gate key -> when that gate last handed out a task
+    gates: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<Instant>>>>>,
+    scans: AtomicU64,
+    backoffs: AtomicU64,
+}
+
+impl PollGate {
+    pub fn new(window_ms: u64) -> Arc<Self> {
+        Arc::new(Self {
+            window: Duration::from_millis(window_ms),
+            gates: Mutex::new(HashMap::new()),
+            scans: AtomicU64::new(0),
+            backoffs: AtomicU64::new(0),
+        })
+    }
+
+    /// Polls with the same key see the same tasks.
+    pub fn key(caps: &[String], tier: u8) -> String {
+        let mut bases: Vec<&str> = caps.iter().map(|c| base_capability(c)).collect();
+        bases.sort_unstable();
+        bases.dedup();
+        format!("{}|{}", tier, bases.join(","))
+    }
+
+    pub fn stats(&self) -> PollGateStats {
+        PollGateStats {
+            scans: self.scans.load(Ordering::Relaxed),
+            backoffs: self.backoffs.load(Ordering::Relaxed),
+        }
+    }
+
+    fn gate(&self, key: &str) -> Arc<tokio::sync::Mutex<Option<Instant>>> {
+        self.gates
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone()
+    }
+
+    async fn counted<T, Fut>(&self, scan: Fut) -> Option<T>
+    where
+        Fut: Future<Output = Option<T>>,
+    {
+        self.scans.fetch_add(1, Ordering::Relaxed);
+        scan.await
+    }
+
+    /// Run the find for a poll under the gate for `key`.
+    pub async fn run<T, F, Fut>(&self, key: &str, scan: F) -> Option<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Option<T>>,
+    {
+        if self.window.is_zero() {
+            return self.counted(scan()).await;
+        }
+        let gate = self.gate(key);
+        let (mut last_offer, contended) = match gate.try_lock() {
+            Ok(guard) => (guard, false),
+            Err(_) => match tokio::time::timeout(self.window, gate.lock()).await {
+                Ok(guard) => (guard, true),
+                // Fallback: the holder is taking too long, scan ungated.
+                Err(_) => return self.counted(scan()).await,
+            },
+        };
+        if contended && last_offer.is_some_and(|at| at.elapsed() < self.window) {
+            // The poll we waited on just handed out a task; don't race for it.
+            self.backoffs.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let found = self.counted(scan()).await;
+        if found.is_some() {
+            *last_offer = Some(Instant::now());
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn stampede(gate: Arc<PollGate>, pollers: usize, scan_ms: u64) -> usize {
+        let handles: Vec<_> = (0..pollers)
+            .map(|_| {
+                let gate = gate.clone();
+                tokio::spawn(async move {
+                    gate.run("0|debug.echo", || async {
+                        tokio::time::sleep(Duration::from_millis(scan_ms)).await;
+                        Some(())
+                    })
+                    .await
+                })
+            })
+            .collect();
+        let mut offered = 0;
+        for handle in handles {
+            if handle.await.unwrap().is_some() {
+                offered += 1;
+            }
+        }
+        offered
+    }
+
+    #[tokio::test]
+    async fn concurrent_polls_scan_once() {
+        let gate = PollGate::new(500);
+        assert_eq!(stampede(gate.clone(), 10, 20).await, 1);
+        assert_eq!(
+            gate.stats(),
+            PollGateStats {
+                scans: 1,
+                backoffs: 9
+            }
+        );
+
+        let ungated = PollGate::new(0);
+        assert_eq!(stampede(ungated.clone(), 10, 20).await, 10);
+        assert_eq!(ungated.stats().scans, 10);
+    }
+
+    #[tokio::test]
+    async fn slow_scan_falls_back_to_ungated_polls() {
+        let gate = PollGate::new(20);
+        // The first scan outlives the window, so every waiter times out and
+        // scans on its own rather than blocking.
+        assert_eq!(stampede(gate.clone(), 5, 100).await, 5);
+        assert_eq!(gate.stats().scans, 5);
+    }
+
+    #[test]
+    fn key_ignores_attributes_and_order() {
+        let a = PollGate::key(&["llm.qwen[vision]".into(), "debug.echo".into()], 2);
+        let b = PollGate::key(&["debug.echo".into(), "llm.qwen".into()], 2);
+        assert_eq!(a, b);
+        assert_ne!(
+            a,
+            PollGate::key(&["debug.echo".into(), "llm.qwen".into()], 3)
+        );
+    }
+}
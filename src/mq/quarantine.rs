@@ -0,0 +1,163 @@
+//! Automatic quarantine of misbehaving agents.
+//!
+//! Faults are counted per agent over a sliding window: a reported task
+//! failure, a task lease that expired on the agent (see
+//! `TaskStorage::recover_orphaned_assigned`), or a result report rejected as
+//! malformed. When an agent reaches `AGENT_QUARANTINE_FAULTS` within
+//! `AGENT_QUARANTINE_WINDOW_SECS`, `quarantinedUntil` is set on its record for
+//! `AGENT_QUARANTINE_SECS`: the scheduler, dispatcher and polls hand it no
+//! work until then, or until management clears it. Fault history is
+//! in-memory; the quarantine itself is persisted with the agent.
+//!
+//! Disabled unless `AGENT_QUARANTINE_FAULTS` is set. Synchronous, and the
+//! lock is never held across `.await`.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::warn;
+
+use crate::{config::QuarantineConfig, state::AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentFault {
+    TaskFailed,
+    LeaseExpired,
+    MalformedReport,
+}
+
+impl AgentFault {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AgentFault::TaskFailed => "task failed",
+            AgentFault::LeaseExpired => "lease expired",
+            AgentFault::MalformedReport => "malformed report",
+        }
+    }
+}
+
+/// `(when, fault)`, oldest first, trimmed to the window.
+type FaultHistory = VecDeque<(DateTime<Utc>, AgentFault)>;
+
+pub struct AgentFaults {
+    config: QuarantineConfig,
+    /// Agent uid -> recent faults.
+    faults: Mutex<HashMap<String, FaultHistory>>,
+}
+
+impl AgentFaults {
+    pub fn new(config: QuarantineConfig) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            faults: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Count a fault against `uid`. Returns the quarantine reason when this
+    /// fault reaches the threshold; the agent's history then starts over.
+    pub fn record(&self, uid: &str, fault: AgentFault) -> Option<String> {
+        self.record_at(uid, fault, Utc::now())
+    }
+
+    fn record_at(&self, uid: &str, fault: AgentFault, now: DateTime<Utc>) -> Option<String> {
+        let threshold = self.config.fault_threshold;
+        if threshold == 0 {
+            return None;
+        }
+        let mut faults = self.faults.lock().unwrap();
+        let history = faults.entry(uid.to_string()).or_default();
+        history.push_back((now, fault));
+        let window_start = now - TimeDelta::seconds(self.config.window_secs);
+        while history.front().is_some_and(|(at, _)| *at < window_start) {
+            history.pop_front();
+        }
+        if history.len() < threshold {
+            return None;
+        }
+        let count = |kind: AgentFault| history.iter().filter(|(_, f)| *f == kind).count();
+        let reason = format!(
+            "{} faults in {}s ({} failed tasks, {} expired leases, {} malformed reports)",
+            history.len(),
+            self.config.window_secs,
+            count(AgentFault::TaskFailed),
+            count(AgentFault::LeaseExpired),
+            count(AgentFault::MalformedReport),
+        );
+        faults.remove(uid);
+        Some(reason)
+    }
+
+    /// Forget the fault history of `uid` (management clear).
+    pub fn clear(&self, uid: &str) {
+        self.faults.lock().unwrap().remove(uid);
+    }
+
+    pub fn cooldown(&self) -> TimeDelta {
+        TimeDelta::seconds(self.config.cooldown_secs)
+    }
+}
+
+/// Count a fault against the agent and quarantine it once it crosses the
+/// threshold.
+pub async fn report_fault(state: &AppState, uid: &str, fault: AgentFault) {
+    let Some(reason) = state.agent_faults.record(uid, fault) else {
+        return;
+    };
+    let until = Utc::now() + state.agent_faults.cooldown();
+    match state
+        .storage
+        .agents
+        .set_quarantine(uid, Some(until), Some(reason.clone()))
+        .await
+    {
+        Ok(Some(_)) => warn!(
+            "Agent {} quarantined until {} after {}: {}",
+            uid,
+            until,
+            fault.as_str(),
+            reason
+        ),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to quarantine agent {}: {}", uid, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarantines_on_threshold_within_window_only() {
+        let faults = AgentFaults::new(QuarantineConfig {
+            fault_threshold: 3,
+            window_secs: 60,
+            cooldown_secs: 600,
+        });
+        let t0 = Utc::now();
+        assert_eq!(faults.record_at("a", AgentFault::TaskFailed, t0), None);
+        assert_eq!(faults.record_at("a", AgentFault::LeaseExpired, t0), None);
+        assert_eq!(faults.record_at("b", AgentFault::TaskFailed, t0), None);
+        let reason = faults
+            .record_at("a", AgentFault::MalformedReport, t0)
+            .unwrap();
+        assert!(reason.contains("1 failed tasks, 1 expired leases, 1 malformed reports"));
+
+        // History starts over after quarantine, and old faults age out.
+        assert_eq!(faults.record_at("a", AgentFault::TaskFailed, t0), None);
+        assert_eq!(faults.record_at("a", AgentFault::TaskFailed, t0), None);
+        let later = t0 + TimeDelta::seconds(61);
+        assert_eq!(faults.record_at("a", AgentFault::TaskFailed, later), None);
+
+        let off = AgentFaults::new(QuarantineConfig {
+            fault_threshold: 0,
+            window_secs: 60,
+            cooldown_secs: 600,
+        });
+        for _ in 0..10 {
+            assert_eq!(off.record_at("a", AgentFault::TaskFailed, t0), None);
+        }
+    }
+}
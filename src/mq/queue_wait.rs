@@ -0,0 +1,164 @@
+//! Rolling per-capability queue wait time (task `created_at` → `assigned_at`).
+//!
+//! Feeds autoscaling: together with queue depth it tells operators whether a
+//! capability needs more (or fewer) agents. Each capability keeps a
+//! time-decayed average — a sample's weight halves every [`HALF_LIFE_SECS`] —
+//! so a past spike fades out instead of dominating the figure forever.
+//!
+//! Updated from the single assignment path
+//! ([`crate::api::agent::service::take_task`]); synchronous, and the lock is
+//! never held across `.await`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+
+use crate::utils::base_capability;
+
+/// Age at which a sample counts half as much as a fresh one.
+pub const HALF_LIFE_SECS: f64 = 300.0;
+
+#[derive(Debug, Clone, Copy)]
+struct Decayed {
+    /// Decayed sum of wait times, in milliseconds.
+    weighted_ms: f64,
+    /// Decayed sum of sample weights.
+    weight: f64,
+    samples: u64,
+    updated_at: DateTime<Utc>,
+}
+
+impl Decayed {
+    fn decay_to(&mut self, now: DateTime<Utc>) {
+        let elapsed = (now - self.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        let factor = 0.5_f64.powf(elapsed / HALF_LIFE_SECS);
+        self.weighted_ms *= factor;
+        self.weight *= factor;
+        self.updated_at = now;
+    }
+}
+
+/// Snapshot of one capability's queue wait, as reported by `/stats`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct QueueWaitSnapshot {
+    pub capability: String,
+    /// Time-decayed average wait in milliseconds.
+    pub avg_wait_ms: f64,
+    /// Total assignments observed since startup (not decayed).
+    pub samples: u64,
+    pub last_sample_at: DateTime<Utc>,
+}
+
+pub struct QueueWaitStats {
+    /// base capability -> decayed accumulator
+    inner: Mutex<HashMap<String, Decayed>>,
+}
+
+impl QueueWaitStats {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Record that a task for `cap` waited `wait` before being assigned.
+    pub fn record(&self, cap: &str, wait: TimeDelta) {
+        self.record_at(cap, wait, Utc::now());
+    }
+
+    fn record_at(&self, cap: &str, wait: TimeDelta, now: DateTime<Utc>) {
+        let wait_ms = wait.num_milliseconds().max(0) as f64;
+        let mut guard = self.inner.lock().unwrap();
+        let entry = guard
+            .entry(base_capability(cap).to_string())
+            .or_insert(Decayed {
+                weighted_ms: 0.0,
+                weight: 0.0,
+                samples: 0,
+                updated_at: now,
+            });
+        entry.decay_to(now);
+        entry.weighted_ms += wait_ms;
+        entry.weight += 1.0;
+        entry.samples += 1;
+    }
+
+    /// Recent average wait of `cap`'s base capability; `None` before its first
+    /// assignment.
+    pub fn average_wait(&self, cap: &str) -> Option<TimeDelta> {
+        let guard = self.inner.lock().unwrap();
+        // Decay scales both sums alike, so the ratio needs no catching up.
+        guard
+            .get(base_capability(cap))
+            .filter(|d| d.weight > 0.0)
+            .map(|d| TimeDelta::milliseconds((d.weighted_ms / d.weight) as i64))
+    }
+
+    /// Current averages for every capability that has seen an assignment,
+    /// sorted by capability name.
+    pub fn snapshot(&self) -> Vec<QueueWaitSnapshot> {
+        let guard = self.inner.lock().unwrap();
+        let mut out: Vec<QueueWaitSnapshot> = guard
+            .iter()
+            .filter(|(_, d)| d.weight > 0.0)
+            .map(|(cap, d)| QueueWaitSnapshot {
+                capability: cap.clone(),
+                avg_wait_ms: d.weighted_ms / d.weight,
+                samples: d.samples,
+                last_sample_at: d.updated_at,
+            })
+            .collect();
+        out.sort_by(|a, b| a.capability.cmp(&b.capability));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_per_base_capability() {
+        let stats = QueueWaitStats::new();
+        let now = Utc::now();
+        stats.record_at("llm.qwen3[vision]", TimeDelta::milliseconds(100), now);
+        stats.record_at("llm.qwen3", TimeDelta::milliseconds(300), now);
+        stats.record_at("debug.echo", TimeDelta::milliseconds(50), now);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].capability, "debug.echo");
+        assert_eq!(snapshot[1].capability, "llm.qwen3");
+        assert_eq!(snapshot[1].avg_wait_ms, 200.0);
+        assert_eq!(snapshot[1].samples, 2);
+    }
+
+    #[test]
+    fn average_wait_is_known_once_a_task_was_assigned() {
+        let stats = QueueWaitStats::new();
+        assert_eq!(stats.average_wait("llm.qwen3"), None);
+        let now = Utc::now();
+        stats.record_at("llm.qwen3[vision]", TimeDelta::milliseconds(400), now);
+        stats.record_at("llm.qwen3", TimeDelta::milliseconds(200), now);
+        assert_eq!(
+            stats.average_wait("llm.qwen3"),
+            Some(TimeDelta::milliseconds(300))
+        );
+    }
+
+    #[test]
+    fn old_spike_decays() {
+        let stats = QueueWaitStats::new();
+        let start = Utc::now();
+        stats.record_at("debug.echo", TimeDelta::seconds(600), start);
+        // Ten half-lives later the spike weighs ~1/1024 of a fresh sample.
+        let later = start + TimeDelta::seconds((HALF_LIFE_SECS * 10.0) as i64);
+        stats.record_at("debug.echo", TimeDelta::milliseconds(100), later);
+
+        let avg = stats.snapshot()[0].avg_wait_ms;
+        assert!(avg < 1_000.0, "spike still dominates: {avg}");
+        assert!(avg >= 100.0);
+    }
+}
@@ -110,6 +110,16 @@ impl AgentRegistry {
         self.conns.lock().unwrap().get(uid).map(|c| c.tx.clone())
     }
 
+    /// Tell a connected agent to stop working on `task_id`. Best effort: a
+    /// disconnected agent or a full outbound queue drops the message, and the
+    /// agent learns of the cancel on its next report instead.
+    pub fn push_cancel(&self, uid: &str, task_id: &TaskId) {
+        if let Some(tx) = self.sender(uid) {
+            let msg = serde_json::json!({ "type": "cancel", "taskId": task_id }).to_string();
+            let _ = tx.try_send(WsOut::Text(msg));
+        }
+    }
+
     pub fn is_connected(&self, uid: &str) -> bool {
         self.conns.lock().unwrap().contains_key(uid)
     }
@@ -1,31 +1,58 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
 
 use anyhow::Result;
 use chrono::Utc;
 use indexmap::IndexMap;
 use log::info;
-use rand::seq::IndexedRandom;
+use rand::{Rng, seq::IndexedRandom};
 
 use crate::{
     db::{agent::AgentStorage, persistent_task_storage::TaskStorage},
     models::{AssignedTask, UnassignedTask},
-    schema::{TaskId, TaskStatus},
+    preferences::{self, PickOrder, SelectionStrategy, TierWeighting},
+    schema::{SystemInfo, TaskId, TaskStatus},
     utils::base_capability,
 };
 
 #[derive(Clone)]
 pub struct RegularTaskStore {
     tasks: Arc<tokio::sync::RwLock<IndexMap<TaskId, UnassignedTask>>>,
+    /// Fair-share cursor: base capability -> API key served last.
+    last_served_key: Arc<Mutex<HashMap<String, String>>>,
+    /// Queued tasks with `dependsOn` whose dependencies were all seen
+    /// `Completed`. A task with dependencies is not offered until it is here.
+    dependencies_met: Arc<Mutex<HashSet<TaskId>>>,
+    /// Queued tasks still waiting on dependencies, by dependency id, so a
+    /// finished task only re-checks the tasks that wait on it.
+    waiters: Arc<Mutex<HashMap<TaskId, HashSet<TaskId>>>>,
+    /// Scheduling preferences this queue hands tasks out by.
+    prefs: preferences::Config,
 }
 
 impl RegularTaskStore {
     pub fn new() -> Arc<Self> {
+        Self::with_preferences(preferences::Config::default())
+    }
+
+    pub fn with_preferences(prefs: preferences::Config) -> Arc<Self> {
         Arc::new(Self {
             tasks: Arc::new(tokio::sync::RwLock::new(IndexMap::new())),
+            last_served_key: Arc::new(Mutex::new(HashMap::new())),
+            dependencies_met: Arc::new(Mutex::new(HashSet::new())),
+            waiters: Arc::new(Mutex::new(HashMap::new())),
+            prefs,
         })
     }
 
+    pub fn preferences(&self) -> &preferences::Config {
+        &self.prefs
+    }
+
     pub async fn add_task(&self, task: UnassignedTask) {
+        self.index_waiter(&task);
         self.tasks.write().await.insert(task.id.clone(), task);
     }
 
@@ -34,6 +61,7 @@ impl RegularTaskStore {
         let count = tasks.len();
         let mut guard = self.tasks.write().await;
         for task in tasks {
+            self.index_waiter(&task);
             guard.insert(task.id.clone(), task);
         }
         Ok(count)
@@ -58,6 +86,7 @@ impl RegularTaskStore {
         let mut added = 0;
         for task in persistent {
             if !guard.contains_key(&task.id) {
+                self.index_waiter(&task);
                 guard.insert(task.id.clone(), task);
                 added += 1;
             }
@@ -76,37 +105,245 @@ impl RegularTaskStore {
     pub async fn hard_clear(&self) {
         info!("Cleaning up regular tasks queue");
         self.tasks.write().await.clear();
+        self.dependencies_met.lock().unwrap().clear();
+        self.waiters.lock().unwrap().clear();
     }
 
     pub async fn remove_task(&self, task_id: &TaskId) -> Option<UnassignedTask> {
-        self.tasks.write().await.shift_remove(task_id)
+        self.dependencies_met.lock().unwrap().remove(task_id);
+        let task = self.tasks.write().await.shift_remove(task_id)?;
+        self.unindex_waiter(&task);
+        Some(task)
     }
 
     pub async fn assign_task(&self, task_id: &TaskId, agent_id: &str) -> Option<AssignedTask> {
-        self.tasks
-            .write()
+        self.remove_task(task_id)
             .await
-            .shift_remove(task_id)
             .map(|task| task.assign_to(agent_id))
     }
 
+    /// Let a queued task with dependencies be offered to agents.
+    pub fn mark_dependencies_met(&self, task: &UnassignedTask) {
+        self.dependencies_met
+            .lock()
+            .unwrap()
+            .insert(task.id.clone());
+        self.unindex_waiter(task);
+    }
+
+    fn index_waiter(&self, task: &UnassignedTask) {
+        let mut waiters = self.waiters.lock().unwrap();
+        for dependency in &task.data.depends_on {
+            waiters
+                .entry(dependency.clone())
+                .or_default()
+                .insert(task.id.clone());
+        }
+    }
+
+    fn unindex_waiter(&self, task: &UnassignedTask) {
+        let mut waiters = self.waiters.lock().unwrap();
+        for dependency in &task.data.depends_on {
+            if let Some(ids) = waiters.get_mut(dependency) {
+                ids.remove(&task.id);
+                if ids.is_empty() {
+                    waiters.remove(dependency);
+                }
+            }
+        }
+    }
+
+    /// Queued tasks still held back by `dependsOn` that list `dependency`.
+    pub async fn waiting_on(&self, dependency: &TaskId) -> Vec<UnassignedTask> {
+        let ids: Vec<TaskId> = match self.waiters.lock().unwrap().get(dependency) {
+            Some(ids) => ids.iter().cloned().collect(),
+            None => return Vec::new(),
+        };
+        let tasks = self.tasks.read().await;
+        ids.iter().filter_map(|id| tasks.get(id).cloned()).collect()
+    }
+
+    /// Queued tasks still held back by `dependsOn`.
+    pub async fn waiting_on_dependencies(&self) -> Vec<UnassignedTask> {
+        let met = self.dependencies_met.lock().unwrap().clone();
+        self.tasks
+            .read()
+            .await
+            .values()
+            .filter(|task| !task.data.depends_on.is_empty() && !met.contains(&task.id))
+            .cloned()
+            .collect()
+    }
+
+    /// Pick a task for the agent among those offered to it. Offered tasks whose
+    /// capability has the highest `capability_weights` entry (missing = 0) win;
+    /// among them the [`PickOrder`] preference, or fair share when that
+    /// preference is on, decides which API key is served. `priority` only
+    /// orders that key's own tasks of the picked capability, so one tenant's
+    /// high priorities can't starve the others.
     pub async fn find_with_capabilities_for_tier(
         &self,
         caps: &Vec<String>,
         tier: u8,
         agents: &AgentStorage,
         agent_uid: &str,
+        capability_weights: &HashMap<String, u32>,
+    ) -> Option<UnassignedTask> {
+        self.select_for_tier(
+            caps,
+            tier,
+            agents,
+            agent_uid,
+            capability_weights,
+            &self.prefs,
+        )
+        .await
+    }
+
+    async fn select_for_tier(
+        &self,
+        caps: &Vec<String>,
+        tier: u8,
+        agents: &AgentStorage,
+        agent_uid: &str,
+        capability_weights: &HashMap<String, u32>,
+        prefs: &preferences::Config,
     ) -> Option<UnassignedTask> {
+        let system_info = agents.get_agent(agent_uid).map(|agent| agent.system_info);
+        let candidates = self
+            .eligible_for_tier(
+                caps,
+                tier,
+                agents,
+                agent_uid,
+                system_info.as_ref(),
+                prefs.selection_strategy,
+            )
+            .await;
+        let mut rng = rand::rng();
+        let offered: Vec<(UnassignedTask, u32)> = candidates
+            .into_iter()
+            .filter(|(_, probability)| rng.random_bool(*probability))
+            .map(|(task, _)| {
+                let weight = capability_weights
+                    .get(base_capability(&task.id.cap))
+                    .copied()
+                    .unwrap_or_default();
+                (task, weight)
+            })
+            .collect();
+        let top_weight = offered.iter().map(|(_, weight)| *weight).max()?;
+        let eligible: Vec<UnassignedTask> = offered
+            .into_iter()
+            .filter(|(_, weight)| *weight == top_weight)
+            .map(|(task, _)| task)
+            .collect();
+
+        let all: Vec<&UnassignedTask> = eligible.iter().collect();
+        let picked = pick_by(&all, prefs.effective_pick_order(), &mut rng)?;
+        let cap = base_capability(&picked.id.cap);
+        // Fair share hands the chosen key its oldest task, whatever the order.
+        let (api_key, order) = if prefs.fair_share {
+            (self.next_fair_share(&eligible, cap), PickOrder::Oldest)
+        } else {
+            (picked.data.api_key.clone(), prefs.effective_pick_order())
+        };
+        let own: Vec<&UnassignedTask> = eligible
+            .iter()
+            .filter(|task| base_capability(&task.id.cap) == cap && task.data.api_key == api_key)
+            .collect();
+        let top_priority = own.iter().map(|task| task.data.priority).max()?;
+        let own: Vec<&UnassignedTask> = own
+            .into_iter()
+            .filter(|task| task.data.priority == top_priority)
+            .collect();
+        pick_by(&own, order, &mut rng).cloned()
+    }
+
+    /// Fair share within `cap`: the API key that follows the one served last
+    /// (in key order, wrapping around), recorded as served. `eligible`
+    /// contains at least one task of `cap`.
+    fn next_fair_share(&self, eligible: &[UnassignedTask], cap: &str) -> String {
+        let in_cap: Vec<&UnassignedTask> = eligible
+            .iter()
+            .filter(|task| base_capability(&task.id.cap) == cap)
+            .collect();
+        let mut keys: Vec<&str> = in_cap.iter().map(|t| t.data.api_key.as_str()).collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let mut last_served = self.last_served_key.lock().unwrap();
+        let next_key = match last_served.get(cap) {
+            Some(last) => keys
+                .iter()
+                .find(|key| **key > last.as_str())
+                .unwrap_or(&keys[0]),
+            None => &keys[0],
+        };
+        let next_key = next_key.to_string();
+        last_served.insert(cap.to_string(), next_key.clone());
+        next_key
+    }
+
+    /// Every queued task an agent with `caps` and `tier` may be offered, paired
+    /// with the chance it is actually offered under the active
+    /// [`SelectionStrategy`] (always `1.0` for tasks it is entitled to, `0.0`
+    /// entries are omitted). Read-only: nothing is assigned.
+    ///
+    /// Empty `caps` match nothing and return before touching the queue, so an
+    /// agent without capabilities can't trigger a scan on every poll. Tasks
+    /// with `requirements` are only listed when `system_info` meets them.
+    pub async fn list_eligible_for_tier(
+        &self,
+        caps: &[String],
+        tier: u8,
+        agents: &AgentStorage,
+        agent_uid: &str,
+        system_info: Option<&SystemInfo>,
+    ) -> Vec<(UnassignedTask, f64)> {
+        self.eligible_for_tier(
+            caps,
+            tier,
+            agents,
+            agent_uid,
+            system_info,
+            self.prefs.selection_strategy,
+        )
+        .await
+    }
+
+    async fn eligible_for_tier(
+        &self,
+        caps: &[String],
+        tier: u8,
+        agents: &AgentStorage,
+        agent_uid: &str,
+        system_info: Option<&SystemInfo>,
+        strategy: SelectionStrategy,
+    ) -> Vec<(UnassignedTask, f64)> {
+        if caps.is_empty() {
+            return Vec::new();
+        }
         let tasks = self.tasks.read().await;
-        let mut eligible: Vec<UnassignedTask> = Vec::new();
+        let dependencies_met = self.dependencies_met.lock().unwrap().clone();
+        let now = Utc::now();
+        let mut eligible = Vec::new();
 
         for task in tasks.values() {
+            if !task.data.depends_on.is_empty() && !dependencies_met.contains(&task.id) {
+                continue;
+            }
+            if task.retry_pending_at(now).is_some() || task.scheduled_at(now).is_some() {
+                continue;
+            }
+            if !task.data.hardware_fits(system_info) {
+                continue;
+            }
             // Match on base capability for BOTH sides. Clients are supposed to
             // submit base caps, but a task whose cap carries extended attributes
             // (e.g. `llm.gemma4[vision;tools]`) must still match an agent that
             // advertises the same base — otherwise it sits unassigned forever.
-            let task_base = base_capability(&task.id.cap);
-            if !caps.iter().any(|cap| base_capability(cap) == task_base) {
+            if !task.data.served_by(caps) {
                 continue;
             }
             if let Some(runner) = task
@@ -120,32 +357,56 @@ impl RegularTaskStore {
                     continue;
                 }
             }
+            let same_node_ok = task
+                .data
+                .retry
+                .as_ref()
+                .is_some_and(|retry| retry.retry_on_same_node);
+            // An agent that failed the task steps aside only while an online
+            // agent that has not failed it could take it; otherwise the task
+            // would sit queued forever instead of using up its restarts.
+            if !same_node_ok
+                && task.failed_agents.iter().any(|failed| failed == agent_uid)
+                && agents.agents_serving_task(&task.data).iter().any(|other| {
+                    agents.is_online(other)
+                        && !other.is_quarantined()
+                        && !task.failed_agents.contains(&other.uid)
+                })
+            {
+                continue;
+            }
 
             let top_online_tier = agents
-                .list_all_agents()
+                .agents_serving_task(&task.data)
                 .into_iter()
-                .filter(|agent| {
-                    agent.is_online()
-                        && agent
-                            .capabilities
-                            .iter()
-                            .any(|cap| base_capability(cap) == task_base)
-                })
+                .filter(|agent| agents.is_online(agent))
                 .map(|agent| agent.tier)
                 .max()
                 .unwrap_or_default();
 
-            if top_online_tier <= tier {
-                eligible.push(task.clone());
+            let probability = match strategy {
+                // Reserved only for a strictly higher tier: agents tied at the
+                // top tier all get it.
+                SelectionStrategy::TierCutoff => {
+                    if top_online_tier <= tier {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }
+                SelectionStrategy::WeightedByTier(weighting) => {
+                    tier_offer_probability(weighting, tier, top_online_tier)
+                }
+                // The queue has no view of agent load; callers narrow the
+                // capabilities with `scheduler::least_loaded_caps` instead.
+                SelectionStrategy::LeastLoaded => 1.0,
+            };
+            if probability > 0.0 {
+                eligible.push((task.clone(), probability));
             }
         }
 
-        if eligible.is_empty() {
-            None
-        } else {
-            let mut rng = rand::rng();
-            eligible.choose(&mut rng).cloned()
-        }
+        eligible
     }
 
     /// Fail queued non-urgent tasks that exceeded max wait or total timeout.
@@ -162,15 +423,73 @@ impl RegularTaskStore {
         let mut to_expire: Vec<TaskId> = Vec::new();
         for (task_id, task) in snapshot {
             let elapsed = (now - task.created_at).num_seconds().max(0) as u64;
-            let wait_expired = task.data.max_wait_secs.map_or(false, |mw| elapsed >= mw);
+            // A scheduled task only starts waiting for pickup at `notBefore`.
+            let waited = task.data.not_before.map_or(elapsed, |at| {
+                (now - at.max(task.created_at)).num_seconds().max(0) as u64
+            });
+            let wait_expired = task.data.max_wait_secs.map_or(false, |mw| waited >= mw);
             let total_expired = task.data.timeout_secs.map_or(false, |ts| elapsed >= ts);
             if wait_expired || total_expired {
                 to_expire.push(task_id);
             }
         }
 
+        self.close_queued(
+            task_storage,
+            to_expire,
+            "(timeout)",
+            TaskStatus::Failed,
+            |_| None,
+        )
+        .await
+    }
+
+    /// Fail queued tasks whose base capability is in `inactive`, noting the
+    /// deactivated capability in the task log.
+    pub async fn fail_for_inactive_capabilities(
+        &self,
+        task_storage: &TaskStorage,
+        inactive: &HashSet<String>,
+    ) -> Result<usize> {
+        if inactive.is_empty() {
+            return Ok(0);
+        }
+        let to_fail: Vec<TaskId> = self
+            .tasks
+            .read()
+            .await
+            .keys()
+            .filter(|id| inactive.contains(base_capability(&id.cap)))
+            .cloned()
+            .collect();
+        self.close_queued(
+            task_storage,
+            to_fail,
+            "(capability-inactive)",
+            TaskStatus::Failed,
+            |task| {
+                Some(format!(
+                    "Capability {} was deactivated while the task was queued",
+                    base_capability(&task.id.cap)
+                ))
+            },
+        )
+        .await
+    }
+
+    /// Move queued tasks to the terminal `status` under the placeholder agent
+    /// id, removing them from both the in-memory queue and persistent
+    /// unassigned storage. Returns how many were closed.
+    pub async fn close_queued(
+        &self,
+        task_storage: &TaskStorage,
+        to_close: Vec<TaskId>,
+        agent_id: &str,
+        status: TaskStatus,
+        log: impl Fn(&UnassignedTask) -> Option<String>,
+    ) -> Result<usize> {
         let mut count = 0;
-        for task_id in to_expire {
+        for task_id in to_close {
             let Some(task_snapshot) = self.get_task(&task_id).await else {
                 continue;
             };
@@ -183,9 +502,13 @@ impl RegularTaskStore {
                 let _ = task_storage.add_unassigned(&task_snapshot)?;
                 continue;
             };
-            let mut assigned = task.into_assigned("(timeout)");
-            assigned.change_status(TaskStatus::Failed);
+            let note = log(&task);
+            let mut assigned = task.into_assigned(agent_id);
+            assigned.change_status(status.clone());
             assigned.stage = None;
+            if note.is_some() {
+                assigned.log = note;
+            }
             if let Err(e) = task_storage.update_assigned(&assigned) {
                 // Best-effort rollback to avoid orphaning the queue entry.
                 let _ = task_storage.add_unassigned(&task_snapshot)?;
@@ -194,10 +517,677 @@ impl RegularTaskStore {
             }
             count += 1;
             info!(
-                "Task {} timed out while queued in regular store, marked failed",
-                assigned.id
+                "Task {} closed as {:?} while queued in regular store {}",
+                assigned.id, assigned.status, agent_id
             );
         }
         Ok(count)
     }
 }
+
+/// One of `tasks` by `order`. Ids are time-sortable, so id order is creation
+/// order.
+fn pick_by<'a>(
+    tasks: &[&'a UnassignedTask],
+    order: PickOrder,
+    rng: &mut impl Rng,
+) -> Option<&'a UnassignedTask> {
+    match order {
+        PickOrder::Random => tasks.choose(rng).copied(),
+        PickOrder::Oldest => tasks.iter().min_by(|a, b| a.id.id.cmp(&b.id.id)).copied(),
+        PickOrder::Newest => tasks.iter().max_by(|a, b| a.id.id.cmp(&b.id.id)).copied(),
+    }
+}
+
+/// Chance that an agent of `tier` is offered a task under
+/// [`SelectionStrategy::WeightedByTier`], relative to the top online tier for the
+/// task's capability. Agents at (or above) the top tier are always offered it.
+pub fn tier_offer_probability(weighting: TierWeighting, tier: u8, top_online_tier: u8) -> f64 {
+    if tier >= top_online_tier {
+        return 1.0;
+    }
+    (weighting.weight(tier) / weighting.weight(top_online_tier)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    /// Simulate `rounds` offers per tier against a fixed top tier and return the
+    /// number of accepted offers per tier.
+    fn simulate(weighting: TierWeighting, top: u8, rounds: usize) -> Vec<usize> {
+        let mut rng = StdRng::seed_from_u64(42);
+        (0..=top)
+            .map(|tier| {
+                let p = tier_offer_probability(weighting, tier, top);
+                (0..rounds).filter(|_| rng.random_bool(p)).count()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn top_tier_is_always_offered() {
+        assert_eq!(tier_offer_probability(TierWeighting::Linear, 3, 3), 1.0);
+        assert_eq!(
+            tier_offer_probability(TierWeighting::Exponential, 5, 3),
+            1.0
+        );
+    }
+
+    #[test]
+    fn linear_distribution_skews_toward_higher_tiers() {
+        let counts = simulate(TierWeighting::Linear, 3, 10_000);
+        assert!(counts.windows(2).all(|w| w[0] < w[1]), "{counts:?}");
+        // Tier 0 has weight 1 vs top weight 4 → ~25%.
+        assert!((2_000..3_000).contains(&counts[0]), "{counts:?}");
+        assert_eq!(counts[3], 10_000);
+    }
+
+    #[test]
+    fn exponential_skews_harder_than_linear() {
+        let linear = simulate(TierWeighting::Linear, 3, 10_000);
+        let exponential = simulate(TierWeighting::Exponential, 3, 10_000);
+        assert!(
+            exponential.windows(2).all(|w| w[0] < w[1]),
+            "{exponential:?}"
+        );
+        // Tier 0 has weight 1 vs top weight 8 → ~12.5%, well below linear's ~25%.
+        assert!(exponential[0] < linear[0]);
+        assert!((1_000..1_600).contains(&exponential[0]), "{exponential:?}");
+    }
+
+    #[test]
+    fn parses_strategy_names() {
+        assert_eq!(
+            "weighted_exponential".parse::<SelectionStrategy>(),
+            Ok(SelectionStrategy::WeightedByTier(
+                TierWeighting::Exponential
+            ))
+        );
+        assert_eq!(
+            "tier_cutoff".parse::<SelectionStrategy>(),
+            Ok(SelectionStrategy::TierCutoff)
+        );
+        assert_eq!(
+            "least_loaded".parse::<SelectionStrategy>(),
+            Ok(SelectionStrategy::LeastLoaded)
+        );
+        assert!("bogus".parse::<SelectionStrategy>().is_err());
+    }
+
+    fn queued(cap: &str) -> UnassignedTask {
+        UnassignedTask {
+            id: TaskId::new_with_cap(cap.to_string()),
+            data: crate::schema::TaskSubmissionRequest {
+                capability: cap.to_string(),
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn higher_weighted_capability_is_drained_first() {
+        let store = RegularTaskStore::new();
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        for _ in 0..5 {
+            store.add_task(queued("bulk-ocr")).await;
+        }
+        store.add_task(queued("critical-ocr")).await;
+        store.add_task(queued("critical-ocr")).await;
+
+        let caps = vec!["bulk-ocr".to_string(), "critical-ocr".to_string()];
+        let weights = HashMap::from([("critical-ocr".to_string(), 10)]);
+        let mut taken = Vec::new();
+        for _ in 0..7 {
+            let task = store
+                .find_with_capabilities_for_tier(&caps, 0, &agents, "agent-a", &weights)
+                .await
+                .unwrap();
+            store.remove_task(&task.id).await;
+            taken.push(task.id.cap);
+        }
+        assert_eq!(&taken[..2], ["critical-ocr", "critical-ocr"]);
+        assert!(taken[2..].iter().all(|cap| cap == "bulk-ocr"));
+
+        // Without weights both capabilities compete equally.
+        store.add_task(queued("critical-ocr")).await;
+        store.add_task(queued("bulk-ocr")).await;
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..64 {
+            let task = store
+                .find_with_capabilities_for_tier(&caps, 0, &agents, "agent-a", &HashMap::new())
+                .await
+                .unwrap();
+            seen.insert(task.id.cap);
+        }
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_agent_gets_the_task_back_once_no_one_else_can_run_it() {
+        let store = RegularTaskStore::new();
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        for uid in ["agent-a", "agent-b"] {
+            let mut agent = crate::models::Agent::for_test(uid, &["debug.echo"]);
+            agents
+                .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+                .await
+                .unwrap();
+        }
+        let mut task = queued("debug.echo");
+        task.data.restartable = true;
+        task.failed_agents = vec!["agent-a".to_string()];
+        store.add_task(task).await;
+
+        let caps = vec!["debug.echo".to_string()];
+        let offered =
+            |uid: &'static str| store.list_eligible_for_tier(&caps, 0, &agents, uid, None);
+        assert!(offered("agent-a").await.is_empty());
+        assert_eq!(offered("agent-b").await.len(), 1);
+
+        // agent-b went offline: agent-a is the only one left to retry it.
+        let mut gone = agents.get_agent("agent-b").unwrap();
+        gone.last_contact = Some(Utc::now() - chrono::TimeDelta::days(1));
+        agents.update_agent(gone).await.unwrap();
+        assert_eq!(offered("agent-a").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn queued_tasks_fail_once_their_capability_is_deactivated() {
+        let store = RegularTaskStore::new();
+        let storage =
+            TaskStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let mut ids = Vec::new();
+        for cap in ["llm.qwen", "llm.qwen[vision]", "debug.echo"] {
+            let task = queued(cap);
+            ids.push(task.id.clone());
+            storage.add_unassigned(&task).unwrap();
+            store.add_task(task).await;
+        }
+
+        // Still active: nothing to do.
+        let active = HashSet::new();
+        assert_eq!(
+            store
+                .fail_for_inactive_capabilities(&storage, &active)
+                .await
+                .unwrap(),
+            0
+        );
+
+        let inactive = HashSet::from(["llm.qwen".to_string()]);
+        assert_eq!(
+            store
+                .fail_for_inactive_capabilities(&storage, &inactive)
+                .await
+                .unwrap(),
+            2
+        );
+        let failed = storage.get_assigned(&ids[1]).unwrap().unwrap();
+        assert_eq!(failed.status, TaskStatus::Failed);
+        assert!(failed.log.unwrap().contains("llm.qwen was deactivated"));
+        assert!(store.get_task(&ids[0]).await.is_none());
+        assert!(store.get_task(&ids[2]).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn task_with_dependencies_is_held_until_marked_met() {
+        let store = RegularTaskStore::new();
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let upstream = queued("debug.echo");
+        let mut dependent = queued("debug.echo");
+        dependent.data.depends_on = vec![upstream.id.clone()];
+        let dependent_id = dependent.id.clone();
+        store.add_task(dependent.clone()).await;
+
+        let caps = vec!["debug.echo".to_string()];
+        assert!(
+            store
+                .list_eligible_for_tier(&caps, 0, &agents, "agent-a", None)
+                .await
+                .is_empty()
+        );
+        assert_eq!(store.waiting_on_dependencies().await.len(), 1);
+        assert_eq!(store.waiting_on(&upstream.id).await.len(), 1);
+        assert!(store.waiting_on(&dependent_id).await.is_empty());
+
+        store.mark_dependencies_met(&dependent);
+        let eligible = store
+            .list_eligible_for_tier(&caps, 0, &agents, "agent-a", None)
+            .await;
+        assert_eq!(eligible.len(), 1);
+        assert_eq!(eligible[0].0.id, dependent_id);
+        assert!(store.waiting_on_dependencies().await.is_empty());
+        assert!(store.waiting_on(&upstream.id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn scheduled_task_is_not_offered_before_not_before() {
+        let store = RegularTaskStore::new();
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let mut scheduled = queued("debug.echo");
+        scheduled.data.not_before = Some(Utc::now() + chrono::TimeDelta::seconds(10));
+        let scheduled_id = scheduled.id.clone();
+        store.add_task(scheduled).await;
+        let ready = queued("debug.echo");
+        let ready_id = ready.id.clone();
+        store.add_task(ready).await;
+
+        let caps = vec!["debug.echo".to_string()];
+        let eligible = store
+            .list_eligible_for_tier(&caps, 0, &agents, "agent-a", None)
+            .await;
+        assert_eq!(eligible.len(), 1);
+        assert_eq!(eligible[0].0.id, ready_id);
+        let report = store
+            .get_task(&scheduled_id)
+            .await
+            .unwrap()
+            .into_status_report();
+        assert_eq!(report.status, TaskStatus::Queued);
+        assert!(report.not_before.is_some());
+
+        // Ten seconds later the task is offered like any other.
+        let mut due = store.remove_task(&scheduled_id).await.unwrap();
+        due.data.not_before = Some(Utc::now() - chrono::TimeDelta::seconds(1));
+        store.add_task(due).await;
+        assert_eq!(
+            store
+                .list_eligible_for_tier(&caps, 0, &agents, "agent-a", None)
+                .await
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn agent_without_capabilities_gets_no_task_without_scanning() {
+        let store = RegularTaskStore::new();
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        store.add_task(queued("debug.echo")).await;
+
+        // Holding the write lock makes any scan block until the timeout.
+        let _lock = store.tasks.write().await;
+        let found = tokio::time::timeout(
+            std::time::Duration::from_millis(200),
+            store.find_with_capabilities_for_tier(
+                &Vec::new(),
+                0,
+                &agents,
+                "agent-a",
+                &HashMap::new(),
+            ),
+        )
+        .await
+        .expect("empty capability lookup scanned the queue");
+        assert!(found.is_none());
+    }
+
+    #[tokio::test]
+    async fn pick_order_controls_which_task_is_handed_out() {
+        let store = RegularTaskStore::new();
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            let task = queued("debug.echo");
+            ids.push(task.id.clone());
+            store.add_task(task).await;
+            // Distinct milliseconds, so id order is creation order.
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        let caps = vec!["debug.echo".to_string()];
+        let pick = |order| {
+            let (store, agents, caps) = (&store, &agents, &caps);
+            async move {
+                let prefs = preferences::Config {
+                    pick_order: order,
+                    ..Default::default()
+                };
+                store
+                    .select_for_tier(caps, 0, agents, "agent-a", &HashMap::new(), &prefs)
+                    .await
+                    .unwrap()
+                    .id
+            }
+        };
+
+        for _ in 0..8 {
+            assert_eq!(pick(PickOrder::Oldest).await, ids[0]);
+            assert_eq!(pick(PickOrder::Newest).await, ids[4]);
+        }
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..64 {
+            seen.insert(pick(PickOrder::Random).await);
+        }
+        assert!(seen.len() > 1);
+
+        store.remove_task(&ids[0]).await;
+        assert_eq!(pick(PickOrder::Oldest).await, ids[1]);
+        assert_eq!("fifo".parse::<PickOrder>(), Ok(PickOrder::Oldest));
+    }
+
+    #[tokio::test]
+    async fn stores_schedule_by_their_own_preferences() {
+        let oldest_first = RegularTaskStore::with_preferences(preferences::Config {
+            shuffle_queue: true,
+            pick_order: PickOrder::Oldest,
+            ..Default::default()
+        });
+        let newest_first = RegularTaskStore::with_preferences(preferences::Config {
+            shuffle_queue: false,
+            pick_order: PickOrder::Newest,
+            ..Default::default()
+        });
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let task = queued("debug.echo");
+            ids.push(task.id.clone());
+            oldest_first.add_task(task.clone()).await;
+            newest_first.add_task(task).await;
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+
+        let caps = vec!["debug.echo".to_string()];
+        let pick = |store: Arc<RegularTaskStore>| {
+            let (agents, caps) = (&agents, &caps);
+            async move {
+                store
+                    .find_with_capabilities_for_tier(caps, 0, agents, "agent-a", &HashMap::new())
+                    .await
+                    .unwrap()
+                    .id
+            }
+        };
+        assert_eq!(pick(oldest_first.clone()).await, ids[0]);
+        assert_eq!(pick(newest_first.clone()).await, ids[2]);
+        assert!(oldest_first.preferences().shuffle_queue);
+        assert!(!newest_first.preferences().shuffle_queue);
+    }
+
+    #[tokio::test]
+    async fn queue_is_served_fifo_without_shuffling() {
+        let store = RegularTaskStore::with_preferences(preferences::Config {
+            shuffle_queue: false,
+            pick_order: PickOrder::Random,
+            ..Default::default()
+        });
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            let task = queued("debug.echo");
+            ids.push(task.id.clone());
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        // Queue them out of creation order; FIFO still follows creation.
+        for id in ids.iter().rev() {
+            let mut task = queued("debug.echo");
+            task.id = id.clone();
+            store.add_task(task).await;
+        }
+
+        let caps = vec!["debug.echo".to_string()];
+        let mut served = Vec::new();
+        for _ in 0..ids.len() {
+            let task = store
+                .find_with_capabilities_for_tier(&caps, 0, &agents, "agent-a", &HashMap::new())
+                .await
+                .unwrap();
+            store.remove_task(&task.id).await;
+            served.push(task.id);
+        }
+        assert_eq!(served, ids);
+
+        let shuffled = preferences::Config {
+            shuffle_queue: true,
+            ..Default::default()
+        };
+        assert_eq!(shuffled.effective_pick_order(), PickOrder::Random);
+    }
+
+    async fn add_tiered_agent(agents: &AgentStorage, uid: &str, tier: u8) {
+        let mut agent = crate::models::Agent::for_test(uid, &["debug.echo"]);
+        agent.tier = tier;
+        agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn equal_top_tier_agents_share_tasks() {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        add_tiered_agent(&agents, "low", 1).await;
+        add_tiered_agent(&agents, "top-a", 2).await;
+        add_tiered_agent(&agents, "top-b", 2).await;
+        let caps = vec!["debug.echo".to_string()];
+
+        let store = RegularTaskStore::new();
+        store.add_task(queued("debug.echo")).await;
+        let offered = |uid: &'static str, tier: u8| {
+            let (store, agents, caps) = (store.clone(), &agents, &caps);
+            async move {
+                store
+                    .find_with_capabilities_for_tier(caps, tier, agents, uid, &HashMap::new())
+                    .await
+                    .is_some()
+            }
+        };
+        assert!(!offered("low", 1).await, "tier 1 is below the top tier");
+        assert!(offered("top-a", 2).await);
+        assert!(offered("top-b", 2).await);
+    }
+
+    #[tokio::test]
+    async fn multi_capability_tasks_match_any_or_all_of_the_set() {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let store = RegularTaskStore::new();
+        let mut any_of = queued("llm.mistral");
+        any_of.data = serde_json::from_value(serde_json::json!({
+            "capability": "llm.mistral",
+            "capabilities": ["llm.qwen"],
+            "apiKey": "client-a",
+            "payload": {}
+        }))
+        .unwrap();
+        let mut all_of = queued("gpu.render");
+        all_of.data = serde_json::from_value(serde_json::json!({
+            "capability": "gpu.render",
+            "capabilities": ["gpu.encode"],
+            "matchMode": "allOf",
+            "apiKey": "client-a",
+            "payload": {}
+        }))
+        .unwrap();
+        store.add_task(any_of.clone()).await;
+        store.add_task(all_of.clone()).await;
+
+        let offered = |caps: &[&str]| {
+            let caps: Vec<String> = caps.iter().map(|c| c.to_string()).collect();
+            let (store, agents) = (store.clone(), &agents);
+            async move {
+                let mut ids: Vec<TaskId> = store
+                    .list_eligible_for_tier(&caps, 0, agents, "agent-a", None)
+                    .await
+                    .into_iter()
+                    .map(|(task, _)| task.id)
+                    .collect();
+                ids.sort_by(|a, b| a.cap.cmp(&b.cap));
+                ids
+            }
+        };
+        // Either capability of the any-of set will do.
+        assert_eq!(offered(&["llm.qwen"]).await, vec![any_of.id.clone()]);
+        assert_eq!(
+            offered(&["llm.mistral[gpu]"]).await,
+            vec![any_of.id.clone()]
+        );
+        // The all-of task needs both on the same agent.
+        assert!(offered(&["gpu.render"]).await.is_empty());
+        assert_eq!(
+            offered(&["gpu.encode", "gpu.render"]).await,
+            vec![all_of.id.clone()]
+        );
+        // The primary capability still names the queue and the task id.
+        assert_eq!(all_of.id.cap, "gpu.render");
+        assert!(store.get_task(&all_of.id).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn higher_priority_task_is_offered_first() {
+        let store = RegularTaskStore::new();
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let low = queued("debug.echo");
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        let mut high = queued("debug.echo");
+        high.data.priority = 5;
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        let newer_low = queued("debug.echo");
+        for task in [&low, &high, &newer_low] {
+            store.add_task(task.clone()).await;
+        }
+
+        // Random order picks the key; the key's own top priority goes first.
+        let caps = vec!["debug.echo".to_string()];
+        let first = store
+            .find_with_capabilities_for_tier(&caps, 0, &agents, "agent-a", &HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(first.id, high.id);
+        store.remove_task(&high.id).await;
+
+        let oldest_first = RegularTaskStore::with_preferences(preferences::Config {
+            pick_order: PickOrder::Oldest,
+            ..Default::default()
+        });
+        for task in [&newer_low, &low] {
+            oldest_first.add_task(task.clone()).await;
+        }
+        let next = oldest_first
+            .find_with_capabilities_for_tier(&caps, 0, &agents, "agent-a", &HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(next.id, low.id);
+    }
+
+    #[tokio::test]
+    async fn priority_only_reorders_tasks_of_the_same_key() {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let caps = vec!["debug.echo".to_string()];
+        let patient = queued_for("debug.echo", "key-a");
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        let mut pushy = queued_for("debug.echo", "key-b");
+        pushy.data.priority = i32::MAX;
+
+        // FIFO across keys: key-b's priority does not overtake key-a's older task.
+        let oldest_first = RegularTaskStore::with_preferences(preferences::Config {
+            pick_order: PickOrder::Oldest,
+            ..Default::default()
+        });
+        for task in [&patient, &pushy] {
+            oldest_first.add_task(task.clone()).await;
+        }
+        let next = oldest_first
+            .find_with_capabilities_for_tier(&caps, 0, &agents, "agent-a", &HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(next.id, patient.id);
+
+        // Fair share alternates keys whatever their priorities.
+        let fair = RegularTaskStore::with_preferences(preferences::Config {
+            fair_share: true,
+            ..Default::default()
+        });
+        let mut flood = vec![pushy.clone()];
+        for _ in 0..3 {
+            let mut task = queued_for("debug.echo", "key-b");
+            task.data.priority = i32::MAX;
+            flood.push(task);
+        }
+        for task in flood.iter().chain([&patient]) {
+            fair.add_task(task.clone()).await;
+        }
+        let mut served = Vec::new();
+        for _ in 0..2 {
+            let task = fair
+                .find_with_capabilities_for_tier(&caps, 0, &agents, "agent-a", &HashMap::new())
+                .await
+                .unwrap();
+            fair.remove_task(&task.id).await;
+            served.push(task.data.api_key);
+        }
+        served.sort();
+        assert_eq!(served, vec!["key-a", "key-b"]);
+    }
+
+    fn queued_for(cap: &str, api_key: &str) -> UnassignedTask {
+        let mut task = queued(cap);
+        task.data.api_key = api_key.to_string();
+        task
+    }
+
+    #[tokio::test]
+    async fn fair_share_interleaves_api_keys() {
+        let store = RegularTaskStore::new();
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        // The flooding client queues everything first.
+        for _ in 0..10 {
+            store.add_task(queued_for("debug.echo", "key-flood")).await;
+        }
+        for _ in 0..3 {
+            store.add_task(queued_for("debug.echo", "key-quiet")).await;
+        }
+
+        let caps = vec!["debug.echo".to_string()];
+        let mut served = Vec::new();
+        for _ in 0..8 {
+            let task = store
+                .select_for_tier(
+                    &caps,
+                    0,
+                    &agents,
+                    "agent-a",
+                    &HashMap::new(),
+                    &preferences::Config {
+                        fair_share: true,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+            store.remove_task(&task.id).await;
+            served.push(task.data.api_key);
+        }
+        assert_eq!(
+            served,
+            [
+                "key-flood",
+                "key-quiet",
+                "key-flood",
+                "key-quiet",
+                "key-flood",
+                "key-quiet",
+                "key-flood",
+                "key-flood",
+            ]
+        );
+    }
+}
@@ -1,7 +1,8 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
-use chrono::Utc;
+use chrono::{DateTime, TimeDelta, Utc};
 use log::{debug, warn};
+use tokio::{sync::watch, time};
 
 use crate::{
     db::{
@@ -11,11 +12,22 @@ use crate::{
     error::AppError,
     models::{Agent, AssignedTask, UnassignedTask},
     mq::{
-        heuristic::HeuristicRecord, regular::RegularTaskStore, types::UrgentSubmitOutcome,
+        agent_load::AgentLoad,
+        capability::agent_serves,
+        dependencies::{DependencyState, dependency_state},
+        dispatch::effective_capacity,
+        heuristic::HeuristicRecord,
+        regular::RegularTaskStore,
+        types::{NonUrgentReportOutcome, UrgentAssignOutcome, UrgentSubmitOutcome},
         urgent::UrgentTaskStore,
     },
-    schema::{TaskId, TaskResultReport, TaskResultStatus, TaskStatus, TaskUpdate},
+    preferences::SelectionStrategy,
+    schema::{
+        SystemInfo, TaskId, TaskRequirements, TaskResultReport, TaskResultStatus, TaskStatus,
+        TaskUpdate,
+    },
     state::AppState,
+    supervisor::spawn_supervised,
     utils::base_capability,
 };
 
@@ -23,8 +35,24 @@ pub async fn find_urgent_tasks_with_capabilities(
     store: &UrgentTaskStore,
     caps: &Vec<String>,
     agent_uid: &str,
+    system_info: Option<&SystemInfo>,
 ) -> Option<UnassignedTask> {
-    store.find_with_capabilities(caps, agent_uid).await
+    store
+        .find_with_capabilities(caps, agent_uid, system_info)
+        .await
+}
+
+/// Conflict unless `agent`'s hardware meets the task's `requirements`, so an
+/// explicit take can't bypass the filtering polls and dispatch apply.
+pub fn ensure_hardware_fits(task: &UnassignedTask, agent: &Agent) -> Result<(), AppError> {
+    if task.data.hardware_fits(Some(&agent.system_info)) {
+        Ok(())
+    } else {
+        Err(AppError::Conflict(format!(
+            "Agent {} does not meet the hardware requirements of task {}",
+            agent.uid, task.id
+        )))
+    }
 }
 
 pub async fn find_assignable_non_urgent_tasks_with_capabilities_for_tier(
@@ -33,9 +61,10 @@ pub async fn find_assignable_non_urgent_tasks_with_capabilities_for_tier(
     tier: u8,
     agents: &AgentStorage,
     agent_uid: &str,
+    capability_weights: &HashMap<String, u32>,
 ) -> Option<UnassignedTask> {
     let found = store
-        .find_with_capabilities_for_tier(caps, tier, agents, agent_uid)
+        .find_with_capabilities_for_tier(caps, tier, agents, agent_uid, capability_weights)
         .await;
     if found.is_none() {
         debug!("No regular task eligible for tier {}", tier);
@@ -48,6 +77,9 @@ pub async fn try_pick_up_urgent_task(
     agent: &Agent,
     uid: &TaskId,
 ) -> Result<Option<AssignedTask>, AppError> {
+    if let Some(task) = store.get_pending_task(uid).await {
+        ensure_hardware_fits(&task, agent)?;
+    }
     let success = store.assign_task(uid, &agent.uid).await;
     if !success {
         return Ok(None);
@@ -70,6 +102,17 @@ pub async fn try_pick_up_non_urgent_task(
         .get_task(&uid)
         .await
         .ok_or_else(|| AppError::Conflict(format!("Task already taken: {}", uid)))?;
+    ensure_hardware_fits(&task, agent)?;
+
+    if !task.data.depends_on.is_empty()
+        && dependency_state(persistent_store, &task.data.api_key, &task.data.depends_on)?
+            != DependencyState::Satisfied
+    {
+        return Err(AppError::Conflict(format!(
+            "Task dependencies not completed: {}",
+            uid
+        )));
+    }
 
     let removed_persistent = persistent_store.remove_unassigned(&uid)?;
     if !removed_persistent {
@@ -124,10 +167,13 @@ pub async fn try_unassign_non_urgent_task(
     }
 }
 
+/// Record an agent's result for an urgent task. Returns `false` when the task
+/// is not in the urgent store; `Authorization` if another agent holds it.
 pub async fn report_urgent_task<'a>(
     store: &'a UrgentTaskStore,
     report: TaskResultReport,
     task_id: TaskId,
+    agent_uid: &str,
 ) -> Result<bool, AppError> {
     let success = if let TaskResultStatus::Success(_duration) = report.status {
         true
@@ -135,31 +181,90 @@ pub async fn report_urgent_task<'a>(
         false
     };
     store
-        .complete_task(&task_id, success, report.output.clone().unwrap_or_default())
+        .complete_task(
+            &task_id,
+            agent_uid,
+            success,
+            report.output.clone().unwrap_or_default(),
+        )
         .await
 }
 
+/// Reject a progress update whose `progress` is outside 0.0-1.0.
+pub fn validate_progress(update: &TaskUpdate) -> Result<(), AppError> {
+    match update.progress {
+        Some(progress) if !(0.0..=1.0).contains(&progress) => Err(AppError::Validation(format!(
+            "progress must be between 0.0 and 1.0, got {}",
+            progress
+        ))),
+        _ => Ok(()),
+    }
+}
+
 pub async fn update_urgent_task<'a>(
     store: &'a UrgentTaskStore,
     report: TaskUpdate,
     task_id: TaskId,
 ) -> Result<bool, AppError> {
     store
-        .update_task(&task_id, report.log_update, report.stage, report.status)
+        .update_task(
+            &task_id,
+            report.log_update,
+            report.stage,
+            report.status,
+            report.progress,
+        )
         .await
 }
 
+/// Record an agent's result for a non-urgent task. A failed `restartable` task
+/// that has not exhausted `max_restarts` is re-queued for a different agent
+/// instead of being marked `Failed`. Only the agent the task is assigned to may
+/// report it.
+///
+/// Reports are idempotent: the first terminal status wins, and repeating a
+/// report for a task that is already terminal (or that this agent's failure
+/// already re-queued) changes nothing and succeeds again. The store is flushed
+/// before returning, so an acknowledged result survives a crash.
 pub async fn report_non_urgent_task<'a>(
     store: &TaskStorage,
+    regular_store: &RegularTaskStore,
     report: TaskResultReport,
     agent: &Agent,
     heuristic_storage: &HeuristicStorage,
-) -> Result<(), AppError> {
+    max_restarts: u32,
+) -> Result<NonUrgentReportOutcome, AppError> {
     let success = matches!(&report.status, TaskResultStatus::Success(_));
 
-    let mut got = store
-        .get_assigned(&report.id)?
-        .ok_or(AppError::NotFound(report.id.to_string()))?;
+    let Some(mut got) = store.get_assigned(&report.id)? else {
+        // An earlier failure report from this agent may have re-queued it.
+        return match store.get_unassigned(&report.id)? {
+            Some(task) if task.failed_agents.contains(&agent.uid) => {
+                already_reported(store, &report.id).await
+            }
+            _ => Err(AppError::NotFound(report.id.to_string())),
+        };
+    };
+    if got.agent_id != agent.uid {
+        if got.failed_agents.contains(&agent.uid) {
+            return already_reported(store, &report.id).await;
+        }
+        return Err(AppError::Authorization(format!(
+            "Task {} is not assigned to this agent",
+            report.id
+        )));
+    }
+    if got.status.is_terminal() {
+        if got.status == TaskStatus::Canceled {
+            // Same answer the cancel acknowledgement got the first time.
+            store.flush().await?;
+            return Err(AppError::ClientClosedRequest(format!(
+                "Task {} has been cancelled by the client",
+                report.id
+            )));
+        }
+        return already_reported(store, &report.id).await;
+    }
 
     let execution_time_ms = if matches!(&report.status, TaskResultStatus::NotExecuted(_)) {
         0.0
@@ -171,6 +276,7 @@ pub async fn report_non_urgent_task<'a>(
     };
 
     let is_cancel_requested = got.status == TaskStatus::CancelRequested;
+    let restart = !success && !is_cancel_requested && got.can_restart(max_restarts);
     if is_cancel_requested {
         // Agent acknowledged the cancel signal — move to the terminal Canceled
         // state (keeping whatever partial output the agent reported).
@@ -184,7 +290,46 @@ pub async fn report_non_urgent_task<'a>(
     }
     got.stage = None;
     got.result = report.output;
-    store.update_assigned(&got)?;
+    if let TaskResultStatus::Success(duration_secs) = report.status {
+        got.reported_duration_secs = Some(duration_secs);
+    }
+    let requeued = if restart {
+        match store.requeue_failed(&got)? {
+            Some(unassigned) => {
+                debug!(
+                    "Restartable task {} failed on {}, re-queued (attempt {})",
+                    report.id,
+                    agent.uid_short,
+                    unassigned.failed_agents.len() + 1
+                );
+                regular_store.add_task(unassigned).await;
+                true
+            }
+            None => false,
+        }
+    } else {
+        false
+    };
+    if !requeued {
+        store.update_assigned(&got)?;
+        if !success && !is_cancel_requested && got.data.restartable && !restart {
+            let reason = match &report.status {
+                TaskResultStatus::Failure(message, _) | TaskResultStatus::NotExecuted(message) => {
+                    message.clone()
+                }
+                TaskResultStatus::Success(_) => String::new(),
+            };
+            store.add_dead_letter(
+                &got,
+                format!(
+                    "retries exhausted after {} attempts: {}",
+                    got.failed_agents.len() + 1,
+                    reason
+                ),
+            )?;
+        }
+    }
+    store.flush().await?;
 
     // Log heuristic for non-urgent task completion
     let buckets_used = got.data.file_bucket.clone();
@@ -211,7 +356,22 @@ pub async fn report_non_urgent_task<'a>(
         )));
     }
 
-    Ok(())
+    Ok(if requeued {
+        NonUrgentReportOutcome::Requeued
+    } else {
+        NonUrgentReportOutcome::Recorded
+    })
+}
+
+async fn already_reported(
+    store: &TaskStorage,
+    id: &TaskId,
+) -> Result<NonUrgentReportOutcome, AppError> {
+    debug!("Task {} already reported, acknowledging again", id);
+    // The first report may have been stored but not yet flushed when its
+    // acknowledgement was lost.
+    store.flush().await?;
+    Ok(NonUrgentReportOutcome::AlreadyReported)
 }
 
 pub async fn update_non_urgent_task<'a>(
@@ -224,8 +384,11 @@ pub async fn update_non_urgent_task<'a>(
     let is_cancel_requested = got.status == TaskStatus::CancelRequested;
     got.append_log(report.log_update);
     got.last_update_at = Some(Utc::now());
-    if report.stage.is_some() {
-        got.stage = report.stage
+    if let Some(stage) = &report.stage {
+        got.change_stage(stage);
+    }
+    if report.progress.is_some() {
+        got.progress = report.progress;
     }
     if !is_cancel_requested {
         if let Some(new_status) = report.status {
@@ -250,50 +413,218 @@ pub async fn update_non_urgent_task<'a>(
     Ok(())
 }
 
-pub async fn has_potential_agents_for(cap: &std::string::String, agents: &AgentStorage) -> bool {
-    let base = base_capability(cap);
-    for agent in agents.list_all_agents() {
-        if agent
-            .capabilities
-            .iter()
-            .any(|c| base_capability(c) == base)
-            && agent.is_online()
-        {
-            return true;
+/// Renew the lease on a non-urgent task held by `agent_uid` without touching its
+/// log, stage or status. Returns the new lease expiry: the moment orphan
+/// recovery may reclaim the task if the agent is offline and silent by then.
+pub async fn touch_non_urgent_task(
+    store: &TaskStorage,
+    task_id: &TaskId,
+    agent_uid: &str,
+    orphan_silence_secs: i64,
+) -> Result<DateTime<Utc>, AppError> {
+    let mut got = store
+        .get_assigned(task_id)?
+        .ok_or(AppError::NotFound(task_id.to_string()))?;
+    if got.agent_id != agent_uid {
+        return Err(AppError::Authorization(format!(
+            "Task {} is not assigned to this agent",
+            task_id
+        )));
+    }
+    match got.status {
+        TaskStatus::Assigned | TaskStatus::Starting | TaskStatus::Running => {}
+        TaskStatus::CancelRequested => {
+            return Err(AppError::ClientClosedRequest(format!(
+                "Task {} has been cancelled by the client",
+                task_id
+            )));
+        }
+        status => {
+            return Err(AppError::Conflict(format!(
+                "Task {} is {:?} and cannot be kept alive",
+                task_id, status
+            )));
         }
     }
-    return false;
+    let now = Utc::now();
+    got.last_update_at = Some(now);
+    store.update_assigned(&got)?;
+    Ok(now + TimeDelta::seconds(orphan_silence_secs))
 }
 
-pub async fn all_online_agents_for(cap: &std::string::String, agents: &AgentStorage) -> Vec<Agent> {
-    let base = base_capability(cap);
-    let mut collection = vec![];
-    for agent in agents.list_all_agents() {
-        if agent
-            .capabilities
-            .iter()
-            .any(|c| base_capability(c) == base)
-            && agent.is_online()
-        {
-            collection.push(agent);
-        }
+pub async fn has_potential_agents_for(cap: &std::string::String, agents: &AgentStorage) -> bool {
+    agents
+        .agents_serving(cap)
+        .iter()
+        .any(|agent| agents.is_online(agent) && !agent.is_quarantined())
+}
+
+/// Whether at least one online agent serving `cap` has a free concurrency slot
+/// right now, according to the in-flight load tracker.
+pub async fn has_free_capacity_for(cap: &String, agents: &AgentStorage, load: &AgentLoad) -> bool {
+    all_online_agents_for(cap, agents)
+        .await
+        .iter()
+        .any(|agent| load.in_flight(&agent.uid) < effective_capacity(agent))
+}
+
+/// Fraction of `agent`'s capacity in use, compared without division:
+/// `a` is less loaded than `b` when `a.in_flight * b.cap < b.in_flight * a.cap`.
+fn less_loaded(a: &Agent, b: &Agent, load: &AgentLoad) -> bool {
+    load.in_flight(&a.uid) * effective_capacity(b) < load.in_flight(&b.uid) * effective_capacity(a)
+}
+
+/// Under [`SelectionStrategy::LeastLoaded`], the subset of `caps` for which
+/// no other online agent serving the capability is less loaded than `agent`,
+/// so a busier agent leaves the work to a more idle one. Other strategies
+/// keep `caps` unchanged.
+pub(crate) fn least_loaded_caps(
+    agent: &Agent,
+    caps: Vec<String>,
+    agents: &AgentStorage,
+    load: &AgentLoad,
+    strategy: SelectionStrategy,
+) -> Vec<String> {
+    if strategy != SelectionStrategy::LeastLoaded {
+        return caps;
     }
+    let others: Vec<Agent> = agents
+        .list_all_agents()
+        .into_iter()
+        .filter(|other| {
+            other.uid != agent.uid && agents.is_online(other) && !other.is_quarantined()
+        })
+        .collect();
+    caps.into_iter()
+        .filter(|cap| {
+            let base = base_capability(cap);
+            !others.iter().any(|other| {
+                agent_serves(&other.capabilities, base) && less_loaded(other, agent, load)
+            })
+        })
+        .collect()
+}
+
+/// Slots non-urgent work may fill on `agent`: its capacity minus the urgent
+/// reservation (the agent's own override, else `global_reserved`). Urgent tasks
+/// may still use the full capacity. A reservation covering the whole capacity
+/// makes the agent urgent-only.
+pub(crate) fn non_urgent_capacity(agent: &Agent, global_reserved: u32) -> usize {
+    let reserved = agent.urgent_reserved_slots.unwrap_or(global_reserved) as usize;
+    effective_capacity(agent).saturating_sub(reserved)
+}
+
+/// Whether `agent` may take another non-urgent task without eating into the
+/// slots reserved for urgent work.
+pub(crate) fn has_non_urgent_headroom(
+    agent: &Agent,
+    load: &AgentLoad,
+    global_reserved: u32,
+) -> bool {
+    load.in_flight(&agent.uid) < non_urgent_capacity(agent, global_reserved)
+}
+
+/// Reject an explicit take that would push `agent` past its capacity: the full
+/// capacity for urgent tasks, the non-urgent share (see
+/// [`non_urgent_capacity`]) otherwise.
+pub(crate) fn ensure_take_capacity(
+    agent: &Agent,
+    load: &AgentLoad,
+    urgent: bool,
+    global_reserved: u32,
+) -> Result<(), AppError> {
+    let limit = if urgent {
+        effective_capacity(agent)
+    } else {
+        non_urgent_capacity(agent, global_reserved)
+    };
+    let in_flight = load.in_flight(&agent.uid);
+    if in_flight >= limit {
+        return Err(AppError::Conflict(format!(
+            "Agent {} is at capacity: {} of {} {}slots in use",
+            agent.uid_short,
+            in_flight,
+            limit,
+            if urgent { "" } else { "non-urgent " }
+        )));
+    }
+    Ok(())
+}
+
+/// The agent capabilities whose non-urgent tasks may be handed out now: not
+/// behind an open circuit and not deactivated in the registry (tasks of a
+/// deactivated capability are held in the queue until it is re-activated).
+pub(crate) fn dispatchable_caps(state: &AppState, caps: &[String]) -> Vec<String> {
+    let inactive = state.storage.capabilities.inactive_names();
+    state
+        .circuit
+        .allowed_caps(caps)
+        .into_iter()
+        .filter(|cap| !inactive.contains(base_capability(cap)))
+        .collect()
+}
+
+/// Whether some online agent for `cap` meets `requirements`. With no agent
+/// for `cap` online there is nothing to judge by, so this answers true and the
+/// task may wait in the queue for suitable hardware to connect.
+pub fn capability_can_be_satisfied(
+    cap: &str,
+    requirements: &TaskRequirements,
+    agents: &AgentStorage,
+) -> bool {
+    let mut serving = agents
+        .agents_serving(cap)
+        .into_iter()
+        .filter(|agent| agents.is_online(agent) && !agent.is_quarantined())
+        .peekable();
+    serving.peek().is_none()
+        || serving.any(|agent| requirements.satisfied_by(Some(&agent.system_info)))
+}
+
+pub async fn all_online_agents_for(cap: &std::string::String, agents: &AgentStorage) -> Vec<Agent> {
+    let mut collection: Vec<Agent> = agents
+        .agents_serving(cap)
+        .into_iter()
+        .filter(|agent| agents.is_online(agent) && !agent.is_quarantined())
+        .collect();
     collection.sort_by(|a, b| b.tier.cmp(&a.tier));
     collection
 }
 
-pub async fn submit_urgent_task(
+/// How long an urgent task answered early by [`submit_urgent_task_until_assigned`]
+/// stays in the store after reaching a terminal status, so the client can still
+/// poll its result.
+const DETACHED_RESULT_RETENTION: time::Duration = time::Duration::from_secs(60);
+
+/// Validate, store and push an urgent task. Returns a receiver subscribed
+/// before dispatch, so an instant take+resolve can't be missed.
+async fn enqueue_urgent_task(
     app_state: &Arc<AppState>,
-    task: UnassignedTask,
-) -> Result<UrgentSubmitOutcome, AppError> {
+    task: &UnassignedTask,
+) -> Result<watch::Receiver<TaskStatus>, AppError> {
     let store = &app_state.urgent;
     let agents = &app_state.storage.agents;
-    if !has_potential_agents_for(&task.id.cap, agents).await {
+    let runners: Vec<Agent> = agents
+        .agents_serving_task(&task.data)
+        .into_iter()
+        .filter(|agent| agents.is_online(agent) && !agent.is_quarantined())
+        .collect();
+    if runners.is_empty() {
         return Err(AppError::SchedulingImpossible(format!(
             "no online runners for capability {}",
             task.id.cap
         )));
     }
+    if task.data.fail_fast
+        && !runners
+            .iter()
+            .any(|agent| app_state.agent_load.in_flight(&agent.uid) < effective_capacity(agent))
+    {
+        return Err(AppError::SchedulingImpossible(format!(
+            "no online runner for capability {} has free capacity",
+            task.id.cap
+        )));
+    }
     // Pending TTL: how long to wait for an agent to pick up before giving up.
     // max_wait_secs takes precedence; fall back to 60 s for urgent tasks.
     let pending_ttl_secs = task.data.max_wait_secs.map(|s| s as i64).unwrap_or(60);
@@ -306,33 +637,1148 @@ pub async fn submit_urgent_task(
         .add_task(task.clone(), pending_ttl_secs, global_deadline)
         .await?;
 
-    // Subscribe BEFORE dispatching so an instant take+resolve can't be missed.
-    let mut rx = task_state.notify.subscribe();
+    let rx = task_state.notify.subscribe();
 
     // Push the task to a connected eligible agent now instead of waiting for a
-    // poll. The pushed agent takes + resolves, which notifies `rx` below.
-    crate::mq::dispatch::dispatch_for_capability(app_state, &task.id.cap).await;
+    // poll. The pushed agent takes + resolves, which notifies `rx`.
+    for cap in task.data.match_capabilities() {
+        crate::mq::dispatch::dispatch_for_capability(app_state, cap).await;
+    }
+    Ok(rx)
+}
 
-    // Wait for status change that is terminal (Completed or Failed)
+fn is_terminal_urgent(status: &TaskStatus) -> bool {
+    matches!(
+        status,
+        TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Canceled
+    )
+}
+
+/// Submit an urgent task and wait up to `wait` for it to finish. On timeout
+/// the task keeps running and is retained like a detached task so the client
+/// can poll it.
+pub async fn submit_urgent_task(
+    app_state: &Arc<AppState>,
+    task: UnassignedTask,
+    wait: time::Duration,
+) -> Result<UrgentSubmitOutcome, AppError> {
+    let rx = enqueue_urgent_task(app_state, &task).await?;
+    await_urgent_result(
+        &app_state.urgent,
+        task.id,
+        rx,
+        app_state.subscribe_shutdown(),
+        wait,
+    )
+    .await
+}
+
+/// Wait for a terminal status on `rx`, or give up after `wait` or once the
+/// server stops so graceful shutdown is not held open. `rx` is subscribed
+/// before dispatch, so a task that already finished is answered at once.
+async fn await_urgent_result(
+    store: &Arc<UrgentTaskStore>,
+    id: TaskId,
+    mut rx: watch::Receiver<TaskStatus>,
+    mut shutdown: watch::Receiver<bool>,
+    wait: time::Duration,
+) -> Result<UrgentSubmitOutcome, AppError> {
+    let deadline = time::Instant::now() + wait;
     loop {
-        rx.changed().await.unwrap();
-        let status = rx.borrow().clone();
-
-        if status == TaskStatus::Completed
-            || status == TaskStatus::Failed
-            || status == TaskStatus::Canceled
-        {
-            if let Some(assigned_task) = store.get_assigned_task(&task.id).await {
-                store.remove_task(&task.id).await;
-                return Ok(UrgentSubmitOutcome::Completed(assigned_task));
-            } else {
-                store.remove_task(&task.id).await;
-                return Ok(UrgentSubmitOutcome::CompletedPartial {
-                    id: task.id,
+        let status = rx.borrow_and_update().clone();
+        if is_terminal_urgent(&status) {
+            let outcome = match store.get_assigned_task(&id).await {
+                Some(assigned_task) => UrgentSubmitOutcome::Completed(assigned_task),
+                None => UrgentSubmitOutcome::CompletedPartial {
+                    id: id.clone(),
                     status,
                     message: "Task completed but full info unavailable".into(),
-                });
+                },
+            };
+            store.remove_task(&id).await;
+            return Ok(outcome);
+        }
+        tokio::select! {
+            changed = rx.changed() => {
+                if changed.is_err() {
+                    return Err(AppError::NotFound(id.to_string()));
+                }
+            }
+            _ = time::sleep_until(deadline) => {
+                let report = match store.get_assigned_task(&id).await {
+                    Some(assigned) => assigned.into_status_report(),
+                    None => store
+                        .get_pending_task(&id)
+                        .await
+                        .ok_or_else(|| AppError::NotFound(id.to_string()))?
+                        .into_status_report(),
+                };
+                retain_until_terminal(store, id, rx);
+                return Ok(UrgentSubmitOutcome::TimedOut(Box::new(report)));
+            }
+            _ = async { shutdown.wait_for(|stop| *stop).await.is_ok() } => {
+                store.remove_task(&id).await;
+                return Err(AppError::SchedulingImpossible(format!(
+                    "Server shut down before task {} finished",
+                    id
+                )));
+            }
+        }
+    }
+}
+
+/// Submit an urgent task but answer as soon as an agent picks it up, or after
+/// `wait` if none has. The task keeps running either way; a background waiter
+/// takes over the submitter's job of removing it once it is terminal.
+pub async fn submit_urgent_task_until_assigned(
+    app_state: &Arc<AppState>,
+    task: UnassignedTask,
+    wait: time::Duration,
+) -> Result<UrgentAssignOutcome, AppError> {
+    let store = &app_state.urgent;
+    let mut rx = enqueue_urgent_task(app_state, &task).await?;
+
+    let picked_up = time::timeout(wait, async {
+        loop {
+            if *rx.borrow_and_update() != TaskStatus::Pending {
+                return true;
+            }
+            if rx.changed().await.is_err() {
+                return false;
+            }
+        }
+    })
+    .await
+    .unwrap_or(false);
+
+    let outcome = match store.get_assigned_task(&task.id).await {
+        Some(assigned) if picked_up => UrgentAssignOutcome::Assigned(Box::new(assigned)),
+        _ if picked_up => UrgentAssignOutcome::Finished {
+            id: task.id.clone(),
+            status: rx.borrow().clone(),
+        },
+        _ => UrgentAssignOutcome::Pending {
+            id: task.id.clone(),
+            estimated_start: estimated_start(app_state, &task),
+        },
+    };
+
+    retain_until_terminal(store, task.id, rx);
+    Ok(outcome)
+}
+
+/// When a still-pending task is likely to be picked up: its submission time
+/// plus the capability's recent average queue wait, or now once that moment
+/// has passed. `None` before the capability has seen an assignment.
+fn estimated_start(app_state: &AppState, task: &UnassignedTask) -> Option<DateTime<Utc>> {
+    let wait = app_state.queue_wait.average_wait(&task.id.cap)?;
+    Some((task.created_at + wait).max(Utc::now()))
+}
+
+/// Move a queued non-urgent task into the urgent store under the same id.
+/// Nobody waits on the result, and the client submitted it expecting a
+/// regular task's retention, so once terminal it is moved to the archive
+/// instead of expiring with the urgent store. The caller has already taken
+/// the task out of the regular queue and restores it on error.
+pub async fn escalate_to_urgent(
+    app_state: &Arc<AppState>,
+    mut task: UnassignedTask,
+    pending_ttl_secs: u64,
+) -> Result<(), AppError> {
+    task.data.urgent = true;
+    task.data.max_wait_secs = Some(pending_ttl_secs);
+    let rx = enqueue_urgent_task(app_state, &task).await?;
+    archive_when_terminal(app_state, task.id, rx);
+    Ok(())
+}
+
+/// Once an escalated task is terminal, archive its record and drop it from
+/// the urgent store. Archived first, so a poll in between still finds it.
+fn archive_when_terminal(app_state: &Arc<AppState>, id: TaskId, rx: watch::Receiver<TaskStatus>) {
+    let state = Arc::clone(app_state);
+    spawn_supervised("urgent-escalation-archive", move || {
+        let (state, id, mut rx) = (Arc::clone(&state), id.clone(), rx.clone());
+        async move {
+            while !is_terminal_urgent(&rx.borrow_and_update()) {
+                if rx.changed().await.is_err() {
+                    return;
+                }
+            }
+            archive_escalated(&state, &id).await;
+        }
+    });
+}
+
+async fn archive_escalated(state: &AppState, id: &TaskId) {
+    let Some(task) = state.urgent.finished_record(id).await else {
+        return;
+    };
+    if let Err(e) = state.storage.tasks.archive_result(&task) {
+        warn!("Failed to archive escalated task {id}: {e}");
+        return;
+    }
+    state.urgent.remove_task(id).await;
+}
+
+/// Take over the submitter's job of removing a detached urgent task: once it
+/// is terminal, keep it for [`DETACHED_RESULT_RETENTION`] and drop it.
+fn retain_until_terminal(
+    store: &Arc<UrgentTaskStore>,
+    id: TaskId,
+    rx: watch::Receiver<TaskStatus>,
+) {
+    let store = Arc::clone(store);
+    spawn_supervised("urgent-retention", move || {
+        let (store, id, mut rx) = (Arc::clone(&store), id.clone(), rx.clone());
+        async move {
+            while !is_terminal_urgent(&rx.borrow_and_update()) {
+                if rx.changed().await.is_err() {
+                    return;
+                }
             }
+            time::sleep(DETACHED_RESULT_RETENTION).await;
+            store.remove_task(&id).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        models::CommunicationMethod,
+        schema::{GpuInfo, TaskRetryConfiguration, TaskSubmissionRequest},
+    };
+
+    struct Harness {
+        tasks: TaskStorage,
+        regular: Arc<RegularTaskStore>,
+        heuristics: HeuristicStorage,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let temp_db = || sled::Config::new().temporary(true).open().unwrap();
+            Self {
+                tasks: TaskStorage::from_db(temp_db()).unwrap(),
+                regular: RegularTaskStore::new(),
+                heuristics: HeuristicStorage::from_db(temp_db()).unwrap(),
+            }
+        }
+
+        fn queue(&self, restartable: bool) -> TaskId {
+            self.queue_with_retry(restartable, None)
+        }
+
+        fn queue_with_retry(
+            &self,
+            restartable: bool,
+            retry: Option<TaskRetryConfiguration>,
+        ) -> TaskId {
+            let task = UnassignedTask {
+                id: TaskId::new_with_cap("debug.echo".to_string()),
+                data: TaskSubmissionRequest {
+                    capability: "debug.echo".to_string(),
+                    restartable,
+                    retry,
+                    ..Default::default()
+                },
+                created_at: Utc::now(),
+                failed_agents: Vec::new(),
+                next_eligible_at: None,
+                history: Vec::new(),
+            };
+            self.tasks.add_unassigned(&task).unwrap();
+            task.id
+        }
+
+        async fn fail_on(&self, id: &TaskId, agent_uid: &str, max_restarts: u32) -> bool {
+            self.tasks.assign_task(id, agent_uid).unwrap();
+            self.regular.remove_task(id).await;
+            let report = TaskResultReport {
+                id: id.clone(),
+                capability: id.cap.clone(),
+                status: TaskResultStatus::Failure("boom".to_string(), 1.0),
+                output: None,
+            };
+            report_non_urgent_task(
+                &self.tasks,
+                &self.regular,
+                report,
+                &test_agent(agent_uid),
+                &self.heuristics,
+                max_restarts,
+            )
+            .await
+            .unwrap()
+                == NonUrgentReportOutcome::Requeued
+        }
+    }
+
+    fn test_agent(uid: &str) -> Agent {
+        Agent::for_test(uid, &["debug.echo"])
+    }
+
+    #[tokio::test]
+    async fn non_restartable_failure_is_terminal() {
+        let h = Harness::new();
+        let id = h.queue(false);
+
+        assert!(!h.fail_on(&id, "agent-a", 3).await);
+        let stored = h.tasks.get_assigned(&id).unwrap().unwrap();
+        assert_eq!(stored.status, TaskStatus::Failed);
+        assert!(h.tasks.get_unassigned(&id).unwrap().is_none());
+        assert!(h.regular.get_task(&id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn pick_up_moves_task_to_assigned_once() {
+        let h = Harness::new();
+        let id = h.queue(false);
+        let queued = h.tasks.get_unassigned(&id).unwrap().unwrap();
+        h.regular.add_task(queued).await;
+
+        let assigned =
+            try_pick_up_non_urgent_task(&h.regular, &h.tasks, &test_agent("agent-a"), id.clone())
+                .await
+                .unwrap();
+        assert_eq!(assigned.id, id);
+        assert_eq!(assigned.agent_id, "agent-a");
+        assert_eq!(
+            h.tasks.get_assigned(&id).unwrap().unwrap().agent_id,
+            "agent-a"
+        );
+        assert!(h.tasks.get_unassigned(&id).unwrap().is_none());
+        assert!(h.regular.get_task(&id).await.is_none());
+
+        let again =
+            try_pick_up_non_urgent_task(&h.regular, &h.tasks, &test_agent("agent-b"), id).await;
+        assert!(matches!(again, Err(AppError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn restartable_failure_requeues_for_a_different_agent() {
+        let h = Harness::new();
+        let id = h.queue(true);
+
+        assert!(h.fail_on(&id, "agent-a", 3).await);
+        assert!(h.tasks.get_assigned(&id).unwrap().is_none());
+        let queued = h.regular.get_task(&id).await.unwrap();
+        assert_eq!(queued.failed_agents, vec!["agent-a".to_string()]);
+        assert_eq!(
+            queued.history.last().unwrap().description,
+            "Re-queued for retry (attempt 2)"
+        );
+        assert_eq!(
+            h.tasks.get_unassigned(&id).unwrap().unwrap().failed_agents,
+            vec!["agent-a".to_string()]
+        );
+
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        agents
+            .create_agent(
+                &mut test_agent("agent-b"),
+                crate::config::DuplicateAgentUidPolicy::Reject,
+            )
+            .await
+            .unwrap();
+        let caps = vec!["debug.echo".to_string()];
+        assert!(
+            h.regular
+                .list_eligible_for_tier(&caps, 0, &agents, "agent-a", None)
+                .await
+                .is_empty()
+        );
+        assert_eq!(
+            h.regular
+                .list_eligible_for_tier(&caps, 0, &agents, "agent-b", None)
+                .await
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn restartable_failure_is_terminal_once_restarts_are_exhausted() {
+        let h = Harness::new();
+        let id = h.queue(true);
+
+        assert!(h.fail_on(&id, "agent-a", 1).await);
+        assert!(!h.fail_on(&id, "agent-b", 1).await);
+        let stored = h.tasks.get_assigned(&id).unwrap().unwrap();
+        assert_eq!(stored.status, TaskStatus::Failed);
+        assert_eq!(stored.failed_agents, vec!["agent-a".to_string()]);
+        assert!(h.regular.get_task(&id).await.is_none());
+        let letter = h.tasks.get_dead_letter(&id).unwrap().unwrap();
+        assert_eq!(
+            letter.failure_reason,
+            "retries exhausted after 2 attempts: boom"
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_configuration_sets_retries_and_allows_the_same_agent() {
+        let h = Harness::new();
+        let id = h.queue_with_retry(
+            true,
+            Some(TaskRetryConfiguration {
+                max_retries: Some(1),
+                retry_on_same_node: true,
+                ..Default::default()
+            }),
+        );
+
+        // TASK_MAX_RESTARTS=0 would make the first failure final.
+        assert!(h.fail_on(&id, "agent-a", 0).await);
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let caps = vec!["debug.echo".to_string()];
+        assert_eq!(
+            h.regular
+                .list_eligible_for_tier(&caps, 0, &agents, "agent-a", None)
+                .await
+                .len(),
+            1
+        );
+
+        // Retries exhausted, however high TASK_MAX_RESTARTS is.
+        assert!(!h.fail_on(&id, "agent-a", 5).await);
+        let stored = h.tasks.get_assigned(&id).unwrap().unwrap();
+        assert_eq!(stored.status, TaskStatus::Failed);
+        assert!(h.regular.get_task(&id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn retry_delay_holds_a_failed_task_back() {
+        let h = Harness::new();
+        let id = h.queue_with_retry(
+            true,
+            Some(TaskRetryConfiguration {
+                retry_delay_secs: Some(30),
+                ..Default::default()
+            }),
+        );
+        assert!(h.fail_on(&id, "agent-a", 3).await);
+
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let caps = vec!["debug.echo".to_string()];
+        assert!(
+            h.regular
+                .list_eligible_for_tier(&caps, 0, &agents, "agent-b", None)
+                .await
+                .is_empty()
+        );
+        let queued = h.regular.get_task(&id).await.unwrap();
+        assert!(queued.clone().into_status_report().retry_at.is_some());
+
+        // The delay has passed.
+        let mut elapsed = h.regular.remove_task(&id).await.unwrap();
+        elapsed.next_eligible_at = Some(Utc::now() - TimeDelta::seconds(1));
+        h.regular.add_task(elapsed).await;
+        assert_eq!(
+            h.regular
+                .list_eligible_for_tier(&caps, 0, &agents, "agent-b", None)
+                .await
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn progress_updates_show_in_status_poll() {
+        let h = Harness::new();
+        let id = h.queue(false);
+        h.tasks.assign_task(&id, "agent-a").unwrap();
+        for (progress, stage) in [(0.25, "download"), (0.5, "inference")] {
+            let update = TaskUpdate {
+                id: id.clone(),
+                stage: Some(stage.to_string()),
+                log_update: None,
+                status: None,
+                progress: Some(progress),
+            };
+            validate_progress(&update).unwrap();
+            update_non_urgent_task(&h.tasks, update).await.unwrap();
         }
+        let report = h
+            .tasks
+            .get_assigned(&id)
+            .unwrap()
+            .unwrap()
+            .into_status_report();
+        assert_eq!(report.progress, Some(0.5));
+        assert_eq!(report.stage.as_deref(), Some("inference"));
+
+        let out_of_range = TaskUpdate {
+            id,
+            stage: None,
+            log_update: None,
+            status: None,
+            progress: Some(1.5),
+        };
+        assert!(matches!(
+            validate_progress(&out_of_range),
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn history_records_each_lifecycle_transition_in_order() {
+        let h = Harness::new();
+        let id = h.queue(false);
+        h.tasks.assign_task(&id, "agent-a").unwrap();
+        let running = TaskUpdate {
+            id: id.clone(),
+            stage: None,
+            log_update: None,
+            status: Some(TaskStatus::Running),
+            progress: None,
+        };
+        update_non_urgent_task(&h.tasks, running).await.unwrap();
+        let report = TaskResultReport {
+            id: id.clone(),
+            capability: id.cap.clone(),
+            status: TaskResultStatus::Success(1.0),
+            output: None,
+        };
+        report_non_urgent_task(
+            &h.tasks,
+            &h.regular,
+            report,
+            &test_agent("agent-a"),
+            &h.heuristics,
+            3,
+        )
+        .await
+        .unwrap();
+
+        let history = h.tasks.get_assigned(&id).unwrap().unwrap().history;
+        let descriptions: Vec<&str> = history.iter().map(|e| e.description.as_str()).collect();
+        assert_eq!(
+            descriptions,
+            vec![
+                "Assigned to agent-a",
+                "Status set to Running",
+                "Status set to Completed"
+            ]
+        );
+        assert!(history.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+    }
+
+    #[test]
+    fn urgent_reservation_caps_non_urgent_pickup() {
+        let load = AgentLoad::new();
+        let mut agent = test_agent("agent-a");
+        agent.capacity = 4;
+        assert_eq!(non_urgent_capacity(&agent, 1), 3);
+
+        load.assigned("agent-a", TaskId::new_with_cap("debug.echo".to_string()));
+        load.assigned("agent-a", TaskId::new_with_cap("debug.echo".to_string()));
+        assert!(has_non_urgent_headroom(&agent, &load, 1));
+        load.assigned("agent-a", TaskId::new_with_cap("debug.echo".to_string()));
+        assert!(!has_non_urgent_headroom(&agent, &load, 1));
+        assert!(has_non_urgent_headroom(&agent, &load, 0));
+
+        // The agent's own setting wins over the global one.
+        agent.urgent_reserved_slots = Some(0);
+        assert!(has_non_urgent_headroom(&agent, &load, 1));
+        agent.urgent_reserved_slots = Some(2);
+        assert_eq!(non_urgent_capacity(&agent, 0), 2);
+    }
+
+    #[test]
+    fn take_beyond_capacity_is_a_conflict() {
+        let load = AgentLoad::new();
+        let agent = test_agent("agent-a");
+        assert_eq!(agent.capacity, 1);
+        assert!(ensure_take_capacity(&agent, &load, false, 0).is_ok());
+
+        load.assigned("agent-a", TaskId::new_with_cap("debug.echo".to_string()));
+        for urgent in [false, true] {
+            assert!(matches!(
+                ensure_take_capacity(&agent, &load, urgent, 0),
+                Err(AppError::Conflict(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn full_reservation_keeps_non_urgent_work_off_the_agent() {
+        let load = AgentLoad::new();
+        let mut agent = test_agent("agent-a");
+        assert_eq!(agent.capacity, 1);
+        assert_eq!(non_urgent_capacity(&agent, 1), 0);
+        assert!(!has_non_urgent_headroom(&agent, &load, 1));
+        assert!(matches!(
+            ensure_take_capacity(&agent, &load, false, 1),
+            Err(AppError::Conflict(_))
+        ));
+        // Urgent tasks still get the reserved slot.
+        assert!(ensure_take_capacity(&agent, &load, true, 1).is_ok());
+
+        agent.capacity = 2;
+        assert_eq!(non_urgent_capacity(&agent, 5), 0);
+    }
+
+    #[tokio::test]
+    async fn least_loaded_strategy_offers_work_to_the_most_idle_agent() {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let load = AgentLoad::new();
+        let mut busy = test_agent("busy");
+        busy.capacity = 2;
+        busy.capabilities.push("llm.qwen".to_string());
+        let mut idle = test_agent("idle");
+        idle.capacity = 4;
+        for agent in [&mut busy, &mut idle] {
+            agents
+                .create_agent(agent, crate::config::DuplicateAgentUidPolicy::Reject)
+                .await
+                .unwrap();
+        }
+        let caps = || vec!["debug.echo".to_string(), "llm.qwen".to_string()];
+        let offered =
+            |agent: &Agent, strategy| least_loaded_caps(agent, caps(), &agents, &load, strategy);
+
+        // Equal load (none): both agents compete for everything they serve.
+        assert_eq!(offered(&busy, SelectionStrategy::LeastLoaded), caps());
+
+        // busy holds 1 of 2 slots, idle 1 of 4: busy keeps only the capability
+        // idle doesn't serve.
+        load.assigned("busy", TaskId::new_with_cap("debug.echo".to_string()));
+        load.assigned("idle", TaskId::new_with_cap("debug.echo".to_string()));
+        assert_eq!(
+            offered(&busy, SelectionStrategy::LeastLoaded),
+            vec!["llm.qwen".to_string()]
+        );
+        assert_eq!(offered(&idle, SelectionStrategy::LeastLoaded), caps());
+
+        // Other strategies leave the capabilities alone.
+        assert_eq!(offered(&busy, SelectionStrategy::TierCutoff), caps());
+    }
+
+    #[tokio::test]
+    async fn free_capacity_requires_an_online_agent_with_a_spare_slot() {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let load = AgentLoad::new();
+        let cap = "debug.echo".to_string();
+        assert!(!has_free_capacity_for(&cap, &agents, &load).await);
+
+        agents
+            .create_agent(
+                &mut test_agent("agent-a"),
+                crate::config::DuplicateAgentUidPolicy::Reject,
+            )
+            .await
+            .unwrap();
+        assert!(has_free_capacity_for(&cap, &agents, &load).await);
+
+        load.assigned("agent-a", TaskId::new_with_cap(cap.clone()));
+        assert!(!has_free_capacity_for(&cap, &agents, &load).await);
+    }
+
+    #[tokio::test]
+    async fn report_from_another_agent_is_rejected() {
+        let h = Harness::new();
+        let id = h.queue(false);
+        h.tasks.assign_task(&id, "agent-a").unwrap();
+        let report = TaskResultReport {
+            id: id.clone(),
+            capability: id.cap.clone(),
+            status: TaskResultStatus::Success(1.0),
+            output: None,
+        };
+
+        let err = report_non_urgent_task(
+            &h.tasks,
+            &h.regular,
+            report.clone(),
+            &test_agent("agent-b"),
+            &h.heuristics,
+            3,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, AppError::Authorization(_)));
+        let stored = h.tasks.get_assigned(&id).unwrap().unwrap();
+        assert_eq!(stored.status, TaskStatus::Assigned);
+
+        let urgent = UrgentTaskStore::new(0, 300, None, None, None);
+        let task = h.tasks.get_assigned(&id).unwrap().unwrap();
+        let unassigned = UnassignedTask {
+            id: id.clone(),
+            data: task.data,
+            created_at: task.created_at,
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        urgent.add_task(unassigned, 60, None).await.unwrap();
+        assert!(urgent.assign_task(&id, "agent-a").await);
+        let err = report_urgent_task(&urgent, report.clone(), id.clone(), "agent-b")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Authorization(_)));
+        assert!(
+            report_urgent_task(&urgent, report, id.clone(), "agent-a")
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_report_is_acknowledged_without_changes() {
+        let h = Harness::new();
+        let id = h.queue(false);
+        h.tasks.assign_task(&id, "agent-a").unwrap();
+        let agent = test_agent("agent-a");
+        let report = |output: &str| TaskResultReport {
+            id: id.clone(),
+            capability: id.cap.clone(),
+            status: TaskResultStatus::Success(1.0),
+            output: Some(json!(output)),
+        };
+
+        let first = report_non_urgent_task(
+            &h.tasks,
+            &h.regular,
+            report("first"),
+            &agent,
+            &h.heuristics,
+            3,
+        )
+        .await
+        .unwrap();
+        assert_eq!(first, NonUrgentReportOutcome::Recorded);
+        let stored = h.tasks.get_assigned(&id).unwrap().unwrap();
+
+        let second = report_non_urgent_task(
+            &h.tasks,
+            &h.regular,
+            report("second"),
+            &agent,
+            &h.heuristics,
+            3,
+        )
+        .await
+        .unwrap();
+        assert_eq!(second, NonUrgentReportOutcome::AlreadyReported);
+        let again = h.tasks.get_assigned(&id).unwrap().unwrap();
+        assert_eq!(again.status, TaskStatus::Completed);
+        assert_eq!(again.result, Some(json!("first")));
+        assert_eq!(again.history.len(), stored.history.len());
+    }
+
+    #[tokio::test]
+    async fn hardware_requirements_filter_vram_and_vendor() {
+        let task = UnassignedTask {
+            id: TaskId::new_with_cap("debug.echo".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "debug.echo".to_string(),
+                requirements: Some(TaskRequirements {
+                    min_vram_gb: Some(24),
+                    gpu_vendor: Some("nvidia".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        let regular = RegularTaskStore::new();
+        regular.add_task(task.clone()).await;
+        let urgent = UrgentTaskStore::new(0, 300, None, None, None);
+        urgent.add_task(task.clone(), 60, None).await.unwrap();
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let caps = vec!["debug.echo".to_string()];
+
+        let cpu_only = test_agent("cpu").system_info;
+        let with_gpu = |vendor: &str, vram_gb| SystemInfo {
+            gpu: Some(GpuInfo {
+                vendor: vendor.to_string(),
+                model: "test".to_string(),
+                vram_gb,
+            }),
+            ..cpu_only.clone()
+        };
+        let offered = |info: SystemInfo| {
+            let (regular, urgent, agents, caps) = (&regular, &urgent, &agents, &caps);
+            async move {
+                let non_urgent = regular
+                    .list_eligible_for_tier(caps, 0, agents, "agent", Some(&info))
+                    .await
+                    .len();
+                let urgent = urgent
+                    .list_offerable(caps, "agent", Some(&info))
+                    .await
+                    .len();
+                (non_urgent, urgent)
+            }
+        };
+        assert_eq!(offered(cpu_only.clone()).await, (0, 0));
+        assert_eq!(offered(with_gpu("NVIDIA", 12)).await, (0, 0));
+        assert_eq!(offered(with_gpu("AMD", 24)).await, (0, 0));
+        assert_eq!(offered(with_gpu("NVIDIA", 24)).await, (1, 1));
+
+        // An explicit take is held to the same requirements.
+        assert!(matches!(
+            ensure_hardware_fits(&task, &test_agent("cpu")),
+            Err(AppError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn agent_without_capabilities_gets_no_urgent_task_without_scanning() {
+        let h = Harness::new();
+        h.queue(false);
+        let task = h.tasks.list_unassigned_all().unwrap().remove(0);
+        let urgent = UrgentTaskStore::new(0, 300, None, None, None);
+        urgent.add_task(task, 60, None).await.unwrap();
+        let no_caps: Vec<String> = Vec::new();
+
+        // Holding the write lock makes any scan block until the timeout.
+        let _lock = urgent.tasks.write().await;
+        let found = time::timeout(
+            time::Duration::from_millis(200),
+            find_urgent_tasks_with_capabilities(&urgent, &no_caps, "agent-a", None),
+        )
+        .await
+        .expect("empty capability lookup scanned the urgent queue");
+        assert!(found.is_none());
+        assert!(
+            h.tasks
+                .list_unassigned_with_caps(&no_caps)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn wildcard_agent_serves_matching_capabilities() {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let mut agent = test_agent("agent-a");
+        agent.capabilities = vec!["LLM::*".to_string()];
+        agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        assert!(has_potential_agents_for(&"LLM::mistral".to_string(), &agents).await);
+        assert_eq!(
+            all_online_agents_for(&"LLM::mistral".to_string(), &agents)
+                .await
+                .len(),
+            1
+        );
+        assert!(!has_potential_agents_for(&"Image::resize".to_string(), &agents).await);
+    }
+
+    #[tokio::test]
+    async fn oversized_memory_requirement_is_unsatisfiable() {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let huge = TaskRequirements {
+            min_memory_gb: Some(1024),
+            ..Default::default()
+        };
+        // Nothing online yet: the task may wait for bigger hardware.
+        assert!(capability_can_be_satisfied("debug.echo", &huge, &agents));
+
+        for uid in ["agent-a", "agent-b"] {
+            agents
+                .create_agent(
+                    &mut test_agent(uid),
+                    crate::config::DuplicateAgentUidPolicy::Reject,
+                )
+                .await
+                .unwrap();
+        }
+        assert!(!capability_can_be_satisfied("debug.echo", &huge, &agents));
+        let small = TaskRequirements {
+            min_memory_gb: Some(1),
+            ..Default::default()
+        };
+        assert!(capability_can_be_satisfied("debug.echo", &small, &agents));
+    }
+
+    #[tokio::test]
+    async fn quarantined_agent_gets_no_work_until_lifted() {
+        let agents =
+            AgentStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let mut agent = test_agent("agent-a");
+        agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        let cap = "debug.echo".to_string();
+        assert_eq!(all_online_agents_for(&cap, &agents).await.len(), 1);
+
+        let until = Utc::now() + TimeDelta::seconds(600);
+        agents
+            .set_quarantine("agent-a", Some(until), Some("faults".to_string()))
+            .await
+            .unwrap();
+        // A regular agent write keeps the server-owned quarantine.
+        agents
+            .update_agent_last_contact(agent.clone(), CommunicationMethod::Http)
+            .await
+            .unwrap();
+        assert!(agents.get_agent("agent-a").unwrap().is_quarantined());
+        assert!(all_online_agents_for(&cap, &agents).await.is_empty());
+        assert!(!has_potential_agents_for(&cap, &agents).await);
+
+        // Expired quarantine no longer applies.
+        let expired = Utc::now() - TimeDelta::seconds(1);
+        agents
+            .set_quarantine("agent-a", Some(expired), Some("faults".to_string()))
+            .await
+            .unwrap();
+        assert!(has_potential_agents_for(&cap, &agents).await);
+
+        // Management clear.
+        agents
+            .set_quarantine("agent-a", Some(until), None)
+            .await
+            .unwrap();
+        agents.set_quarantine("agent-a", None, None).await.unwrap();
+        assert_eq!(all_online_agents_for(&cap, &agents).await.len(), 1);
+    }
+
+    async fn urgent_task(store: &Arc<UrgentTaskStore>) -> (TaskId, watch::Receiver<TaskStatus>) {
+        let task = UnassignedTask {
+            id: TaskId::new_with_cap("debug.echo".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "debug.echo".to_string(),
+                urgent: true,
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        let state = store.add_task(task.clone(), 60, None).await.unwrap();
+        (task.id, state.notify.subscribe())
+    }
+
+    #[tokio::test]
+    async fn submit_until_assigned_answers_on_pickup_or_with_an_estimate() {
+        let state = AppState::for_test(|_| {});
+        state
+            .storage
+            .agents
+            .create_agent(
+                &mut test_agent("agent-a"),
+                crate::config::DuplicateAgentUidPolicy::Reject,
+            )
+            .await
+            .unwrap();
+        let urgent = || UnassignedTask {
+            id: TaskId::new_with_cap("debug.echo".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "debug.echo".to_string(),
+                urgent: true,
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        let wait = time::Duration::from_millis(50);
+
+        // Nobody picks it up, and nothing is known about the wait yet.
+        let outcome = submit_urgent_task_until_assigned(&state, urgent(), wait)
+            .await
+            .unwrap();
+        let UrgentAssignOutcome::Pending {
+            estimated_start, ..
+        } = outcome
+        else {
+            panic!("expected a pending answer");
+        };
+        assert!(estimated_start.is_none());
+
+        state
+            .queue_wait
+            .record("debug.echo", TimeDelta::seconds(30));
+        let task = urgent();
+        let outcome = submit_urgent_task_until_assigned(&state, task.clone(), wait)
+            .await
+            .unwrap();
+        let UrgentAssignOutcome::Pending {
+            estimated_start: Some(start),
+            ..
+        } = outcome
+        else {
+            panic!("expected a pending answer with an estimate");
+        };
+        assert_eq!(start, task.created_at + TimeDelta::seconds(30));
+
+        // Picked up while the submitter waits.
+        let task = urgent();
+        let store = Arc::clone(&state.urgent);
+        let id = task.id.clone();
+        tokio::spawn(async move {
+            while !store.assign_task(&id, "agent-a").await {
+                tokio::task::yield_now().await;
+            }
+        });
+        let outcome = submit_urgent_task_until_assigned(&state, task, time::Duration::from_secs(5))
+            .await
+            .unwrap();
+        let UrgentAssignOutcome::Assigned(assigned) = outcome else {
+            panic!("expected the assignment");
+        };
+        assert_eq!(assigned.agent_id, "agent-a");
+    }
+
+    #[tokio::test]
+    async fn escalated_task_result_is_archived_once_finished() {
+        let state = AppState::for_test(|_| {});
+        let mut agent = test_agent("agent-a");
+        state
+            .storage
+            .agents
+            .create_agent(&mut agent, crate::config::DuplicateAgentUidPolicy::Reject)
+            .await
+            .unwrap();
+        let task = UnassignedTask {
+            id: TaskId::new_with_cap("debug.echo".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "debug.echo".to_string(),
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        };
+        escalate_to_urgent(&state, task.clone(), 30).await.unwrap();
+        assert!(state.urgent.assign_task(&task.id, "agent-a").await);
+        archive_escalated(&state, &task.id).await;
+        assert!(
+            state
+                .storage
+                .tasks
+                .get_archived(&task.id)
+                .unwrap()
+                .is_none()
+        );
+
+        state
+            .urgent
+            .complete_task(&task.id, "agent-a", true, json!({"echo": 1}))
+            .await
+            .unwrap();
+        let mut archived = None;
+        for _ in 0..100 {
+            archived = state.storage.tasks.get_archived(&task.id).unwrap();
+            if archived.is_some() {
+                break;
+            }
+            time::sleep(time::Duration::from_millis(10)).await;
+        }
+        let archived = archived.expect("escalated result was not archived");
+        assert_eq!(archived.status, TaskStatus::Completed);
+        assert_eq!(archived.result, Some(json!({"echo": 1})));
+        assert!(state.urgent.get_assigned_task(&task.id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn blocking_wait_returns_result_finished_before_awaiting() {
+        let store = UrgentTaskStore::new(0, 300, None, None, None);
+        let (_stop, shutdown) = watch::channel(false);
+        let (id, rx) = urgent_task(&store).await;
+        assert!(store.assign_task(&id, "agent-a").await);
+        store
+            .complete_task(&id, "agent-a", true, json!({"echo": 1}))
+            .await
+            .unwrap();
+
+        let outcome = await_urgent_result(
+            &store,
+            id.clone(),
+            rx,
+            shutdown,
+            time::Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        let UrgentSubmitOutcome::Completed(task) = outcome else {
+            panic!("expected the completed task");
+        };
+        assert_eq!(task.status, TaskStatus::Completed);
+        assert_eq!(task.result, Some(json!({"echo": 1})));
+        assert!(store.get_assigned_task(&id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn blocking_wait_returns_failure_result() {
+        let store = UrgentTaskStore::new(0, 300, None, None, None);
+        let (_stop, shutdown) = watch::channel(false);
+        let (id, rx) = urgent_task(&store).await;
+        assert!(store.assign_task(&id, "agent-a").await);
+        let agent_store = Arc::clone(&store);
+        let agent_task = id.clone();
+        tokio::spawn(async move {
+            time::sleep(time::Duration::from_millis(20)).await;
+            agent_store
+                .complete_task(&agent_task, "agent-a", false, json!("boom"))
+                .await
+                .unwrap();
+        });
+
+        let outcome = await_urgent_result(&store, id, rx, shutdown, time::Duration::from_secs(5))
+            .await
+            .unwrap();
+        let UrgentSubmitOutcome::Completed(task) = outcome else {
+            panic!("expected the failed task");
+        };
+        assert_eq!(task.status, TaskStatus::Failed);
+        assert_eq!(task.result, Some(json!("boom")));
+    }
+
+    #[tokio::test]
+    async fn blocking_wait_times_out_without_cancelling() {
+        let store = UrgentTaskStore::new(0, 300, None, None, None);
+        let (_stop, shutdown) = watch::channel(false);
+        let (id, rx) = urgent_task(&store).await;
+        assert!(store.assign_task(&id, "agent-a").await);
+
+        let outcome = await_urgent_result(
+            &store,
+            id.clone(),
+            rx,
+            shutdown,
+            time::Duration::from_millis(20),
+        )
+        .await
+        .unwrap();
+        let UrgentSubmitOutcome::TimedOut(report) = outcome else {
+            panic!("expected a timeout");
+        };
+        assert_eq!(report.id, id);
+        assert_eq!(report.status, TaskStatus::Assigned);
+
+        // The task keeps running and its result still reaches the store.
+        assert!(
+            store
+                .complete_task(&id, "agent-a", true, json!(null))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            store.get_assigned_task(&id).await.unwrap().status,
+            TaskStatus::Completed
+        );
     }
 }
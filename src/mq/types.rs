@@ -1,5 +1,7 @@
+use chrono::{DateTime, Utc};
+
 use crate::models::AssignedTask;
-use crate::schema::{TaskId, TaskStatus};
+use crate::schema::{TaskId, TaskStatus, TaskStatusResponse};
 
 /// Domain-level outcome of an urgent (blocking) task submission.
 /// No framework types — usable from any transport adapter.
@@ -12,4 +14,37 @@ pub enum UrgentSubmitOutcome {
         status: TaskStatus,
         message: String,
     },
+    /// The wait ran out first; the task keeps running and can be polled.
+    TimedOut(Box<TaskStatusResponse>),
+}
+
+/// Outcome of an urgent submission that returns once the task is picked up
+/// rather than once it finishes.
+pub enum UrgentAssignOutcome {
+    /// An agent took the task; the record carries the agent, assignment time
+    /// and runtime estimate.
+    Assigned(Box<AssignedTask>),
+    /// No agent picked the task up within the wait; it stays queued.
+    /// `estimated_start` is unknown until its capability has seen an
+    /// assignment.
+    Pending {
+        id: TaskId,
+        estimated_start: Option<DateTime<Utc>>,
+    },
+    /// The task reached a terminal status and its record was already gone.
+    Finished { id: TaskId, status: TaskStatus },
+}
+
+/// Outcome of an agent reporting the result of a non-urgent task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonUrgentReportOutcome {
+    /// The result was stored and flushed to disk.
+    Recorded,
+    /// The failed task was restartable and went back to the queue for another
+    /// agent.
+    Requeued,
+    /// The task had already reached a terminal status (or was already
+    /// re-queued away from this agent); nothing changed. Lets an agent retry
+    /// a report whose acknowledgement it never received.
+    AlreadyReported,
 }
@@ -1,14 +1,27 @@
-use std::sync::Arc;
+use std::sync::{
+    Arc, OnceLock,
+    atomic::{AtomicI64, Ordering},
+};
 
 use chrono::{DateTime, TimeDelta, Utc};
-use log::info;
-use tokio::{sync::watch, time};
+use log::{info, warn};
+use tokio::{
+    sync::{mpsc, watch},
+    time,
+};
 
 use crate::{
+    db::{
+        persistent_task_storage::TaskStorage,
+        task_events::TaskTransition,
+        urgent_audit::{UrgentAuditEvent, UrgentAuditRecord},
+    },
     error::AppError,
-    models::{AssignedTask, UnassignedTask},
-    schema::{TaskId, TaskStatus},
-    utils::base_capability,
+    models::{AssignedTask, TaskEvent, UnassignedTask, UrgentSnapshot},
+    mq::webhook::{self, WebhookDelivery},
+    schema::{SystemInfo, TaskId, TaskStatus},
+    state::DbWriteRequest,
+    supervisor::spawn_supervised,
 };
 
 pub struct TaskState {
@@ -31,66 +44,328 @@ pub struct UrgentTaskEntry {
     /// the task is failed regardless of agent activity, and any in-flight
     /// assigned task is marked `CancelRequested` so the agent receives HTTP 499.
     pub global_deadline: Option<DateTime<Utc>>,
+    /// Set when the pending TTL passed but the store has an expiry grace window:
+    /// the task stays offered (ahead of other pending tasks) until this moment,
+    /// then fails on the next sweep.
+    pub last_chance_until: Option<DateTime<Utc>>,
+}
+
+impl UrgentTaskEntry {
+    fn snapshot(&self, status: &TaskStatus) -> UrgentSnapshot {
+        UrgentSnapshot {
+            task: self.task.clone(),
+            assigned_task: self.assigned_task.clone(),
+            status: status.clone(),
+            created_at: self.created_at,
+            last_update: self.last_update,
+            ttl_secs: self.ttl.num_seconds(),
+            global_deadline: self.global_deadline,
+        }
+    }
+
+    fn restore(snapshot: UrgentSnapshot) -> Self {
+        let (tx, _) = watch::channel(snapshot.status.clone());
+        Self {
+            task: snapshot.task,
+            assigned_task: snapshot.assigned_task,
+            state: Arc::new(TaskState {
+                status: tokio::sync::RwLock::new(snapshot.status),
+                notify: tx,
+            }),
+            created_at: snapshot.created_at,
+            last_update: snapshot.last_update,
+            ttl: TimeDelta::seconds(snapshot.ttl_secs),
+            global_deadline: snapshot.global_deadline,
+            last_chance_until: None,
+        }
+    }
+
+    /// Whether this entry can be offered to an agent with `caps`: it must be
+    /// unassigned, match on base capability, fit the agent's hardware, and not
+    /// be pinned to another runner.
+    fn is_offerable_to(
+        &self,
+        caps: &[String],
+        agent_uid: &str,
+        system_info: Option<&SystemInfo>,
+    ) -> bool {
+        if self.assigned_task.is_some() {
+            return false;
+        }
+        if !self.task.data.hardware_fits(system_info) {
+            return false;
+        }
+        if !self.task.data.served_by(caps) {
+            return false;
+        }
+        if let Some(runner) = self
+            .task
+            .data
+            .payload
+            .get("runner")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())
+        {
+            return runner == agent_uid;
+        }
+        true
+    }
 }
 
 pub struct UrgentTaskStore {
     pub tasks: tokio::sync::RwLock<indexmap::IndexMap<TaskId, UrgentTaskEntry>>,
+    /// Grace window for the "last chance" expiry phase; zero fails expired
+    /// pending tasks immediately.
+    expiry_grace: TimeDelta,
+    /// How long a finished task stays pollable before the expiry sweep drops
+    /// it, in case its submitter never came back to remove it.
+    terminal_retention: TimeDelta,
+    /// Unix timestamp (ms) of the last completed expiry sweep; 0 until the first.
+    last_expiry_sweep_ms: AtomicI64,
+    /// Background DB writer queue for the audit trail; `None` when
+    /// `URGENT_AUDIT_LOG` is off.
+    audit: Option<mpsc::Sender<DbWriteRequest>>,
+    /// Background DB writer queue for the task event log; `None` only in
+    /// tests.
+    events: Option<mpsc::Sender<DbWriteRequest>>,
+    /// Whether entry changes are written through to the task database (via
+    /// the `events` queue); off when `URGENT_PERSIST` is and urgent tasks are
+    /// memory-only.
+    persist: bool,
+    /// Webhook queue for completion callbacks; unset only in tests.
+    callbacks: OnceLock<mpsc::Sender<WebhookDelivery>>,
 }
 
 impl UrgentTaskStore {
-    pub fn new() -> Arc<Self> {
+    pub fn new(
+        expiry_grace_secs: i64,
+        terminal_retention_secs: i64,
+        audit: Option<mpsc::Sender<DbWriteRequest>>,
+        events: Option<mpsc::Sender<DbWriteRequest>>,
+        persist: Option<&TaskStorage>,
+    ) -> Arc<Self> {
+        let tasks = persist.map(Self::reload).unwrap_or_default();
         let store = Arc::new(Self {
-            tasks: tokio::sync::RwLock::new(indexmap::IndexMap::new()),
+            tasks: tokio::sync::RwLock::new(tasks),
+            expiry_grace: TimeDelta::seconds(expiry_grace_secs),
+            terminal_retention: TimeDelta::seconds(terminal_retention_secs),
+            last_expiry_sweep_ms: AtomicI64::new(0),
+            audit,
+            events,
+            persist: persist.is_some(),
+            callbacks: OnceLock::new(),
         });
 
         // Clone Arc for the background task
         let store_clone = Arc::clone(&store);
 
-        // Spawn background Tokio task for expiration every 10 seconds
-        tokio::spawn(async move {
-            let mut interval = time::interval(time::Duration::from_secs(10));
-            loop {
-                interval.tick().await;
-                store_clone.expire_tasks().await;
+        // Expire tasks every 10 seconds; restarted if a sweep ever panics.
+        spawn_supervised("urgent-expiry", move || {
+            let store = Arc::clone(&store_clone);
+            async move {
+                let mut interval = time::interval(time::Duration::from_secs(10));
+                loop {
+                    interval.tick().await;
+                    store.expire_tasks().await;
+                    store
+                        .last_expiry_sweep_ms
+                        .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+                }
             }
         });
 
         store
     }
 
+    /// Pending and in-flight urgent tasks persisted by an earlier run, in
+    /// submission order. Terminal leftovers are dropped from the tree.
+    fn reload(storage: &TaskStorage) -> indexmap::IndexMap<TaskId, UrgentTaskEntry> {
+        let mut snapshots = match storage.list_urgent_all() {
+            Ok(snapshots) => snapshots,
+            Err(e) => {
+                warn!("Failed to load persisted urgent tasks: {e}");
+                return indexmap::IndexMap::new();
+            }
+        };
+        snapshots.sort_by_key(|snapshot| snapshot.created_at);
+        let mut tasks = indexmap::IndexMap::new();
+        for snapshot in snapshots {
+            if snapshot.status.is_terminal() {
+                if let Err(e) = storage.remove_urgent(&snapshot.task.id) {
+                    warn!(
+                        "Failed to drop finished urgent task {}: {e}",
+                        snapshot.task.id
+                    );
+                }
+                continue;
+            }
+            tasks.insert(snapshot.task.id.clone(), UrgentTaskEntry::restore(snapshot));
+        }
+        if !tasks.is_empty() {
+            info!("Restored {} urgent tasks", tasks.len());
+        }
+        tasks
+    }
+
+    /// Queue a write-through of `entry` for the background DB writer, or its
+    /// removal once `status` is terminal. Never blocks the caller, which holds
+    /// the task lock; the writer applies the queue in order.
+    fn persist(&self, entry: &UrgentTaskEntry, status: &TaskStatus) {
+        if status.is_terminal() {
+            self.forget(&entry.task.id);
+            return;
+        }
+        self.write_through(
+            &entry.task.id,
+            DbWriteRequest::UrgentSnapshot(Box::new(entry.snapshot(status))),
+        );
+    }
+
+    /// Queue the removal of `task_id` from the task database.
+    fn forget(&self, task_id: &TaskId) {
+        self.write_through(task_id, DbWriteRequest::UrgentForget(task_id.clone()));
+    }
+
+    fn write_through(&self, task_id: &TaskId, request: DbWriteRequest) {
+        if !self.persist {
+            return;
+        }
+        let Some(tx) = &self.events else {
+            return;
+        };
+        if let Err(e) = tx.try_send(request) {
+            warn!("Dropped persisted state of urgent task {task_id}: {e}");
+        }
+    }
+
+    /// Queue an audit record for the background DB writer. Best-effort and never
+    /// blocks the caller (which usually holds the task lock): when the queue is
+    /// full the record is dropped with a warning.
+    fn audit(&self, task_id: &TaskId, event: UrgentAuditEvent, agent_id: Option<&str>) {
+        let Some(tx) = &self.audit else {
+            return;
+        };
+        let record = UrgentAuditRecord::new(task_id, event, agent_id);
+        if let Err(e) = tx.try_send(DbWriteRequest::UrgentAudit(record)) {
+            warn!("Dropped urgent audit record for {task_id}: {e}");
+        }
+    }
+
+    /// Queue a status transition for the task event log, the same best-effort
+    /// way as [`UrgentTaskStore::audit`]: it gets its `record_id` once the DB
+    /// writer stores it, and is lost if the queue is full.
+    fn transition(
+        &self,
+        task_id: &TaskId,
+        from: Option<TaskStatus>,
+        to: TaskStatus,
+        agent_id: Option<&str>,
+    ) {
+        let Some(tx) = &self.events else {
+            return;
+        };
+        let record = TaskTransition::new(task_id, true, from, to, agent_id);
+        if let Err(e) = tx.try_send(DbWriteRequest::TaskTransition(record)) {
+            warn!("Dropped transition record for {task_id}: {e}");
+        }
+    }
+
+    /// Record that `entry` reached the terminal status `to` and queue its
+    /// completion callback. Every way an urgent task can finish goes through
+    /// here.
+    fn finish(
+        &self,
+        entry: &UrgentTaskEntry,
+        from: TaskStatus,
+        to: TaskStatus,
+        agent_id: Option<&str>,
+    ) {
+        self.transition(&entry.task.id, Some(from), to.clone(), agent_id);
+        let Some(tx) = self.callbacks.get() else {
+            return;
+        };
+        // A task that never reached an agent has no assigned record yet.
+        let mut task = entry
+            .assigned_task
+            .clone()
+            .unwrap_or_else(|| entry.task.clone().into_assigned("(expired)"));
+        task.change_status(to);
+        webhook::queue_callback(tx, &task);
+    }
+
+    /// Queue the completion callback of every task that finishes from now on.
+    pub fn send_callbacks_to(&self, tx: mpsc::Sender<WebhookDelivery>) {
+        let _ = self.callbacks.set(tx);
+    }
+
+    /// Append `event` to the history of an urgent task that holds an assigned
+    /// record. Returns whether the task was found.
+    pub async fn add_history(&self, task_id: &TaskId, event: &TaskEvent) -> bool {
+        let mut tasks = self.tasks.write().await;
+        match tasks
+            .get_mut(task_id)
+            .and_then(|entry| entry.assigned_task.as_mut())
+        {
+            Some(task) => {
+                task.history.push(event.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// When the background expiry sweep last completed, `None` before the first.
+    /// An old value means the sweeper is stalled.
+    pub fn last_expiry_sweep(&self) -> Option<DateTime<Utc>> {
+        match self.last_expiry_sweep_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => DateTime::from_timestamp_millis(ms),
+        }
+    }
+
+    /// First pending task an agent with `caps` could pick up. Empty `caps`
+    /// match nothing and skip the store entirely.
     pub async fn find_with_capabilities(
         &self,
         caps: &Vec<String>,
         agent_uid: &str,
+        system_info: Option<&SystemInfo>,
     ) -> Option<UnassignedTask> {
+        if caps.is_empty() {
+            return None;
+        }
+        let tasks = self.tasks.read().await;
+        // Tasks in their last-chance window are offered first.
+        tasks
+            .values()
+            .filter(|entry| entry.last_chance_until.is_some())
+            .chain(
+                tasks
+                    .values()
+                    .filter(|entry| entry.last_chance_until.is_none()),
+            )
+            .find(|entry| entry.is_offerable_to(caps, agent_uid, system_info))
+            .map(|entry| entry.task.clone())
+    }
+
+    /// All pending tasks an agent with `caps` could pick up, in queue order.
+    /// Empty `caps` match nothing.
+    pub async fn list_offerable(
+        &self,
+        caps: &[String],
+        agent_uid: &str,
+        system_info: Option<&SystemInfo>,
+    ) -> Vec<UnassignedTask> {
+        if caps.is_empty() {
+            return Vec::new();
+        }
         self.tasks
             .read()
             .await
-            .iter()
-            .find(|item| {
-                if item.1.assigned_task.is_some() {
-                    return false;
-                }
-                if !caps
-                    .iter()
-                    .any(|c| base_capability(c) == base_capability(&item.1.task.id.cap))
-                {
-                    return false;
-                }
-                if let Some(runner) = item
-                    .1
-                    .task
-                    .data
-                    .payload
-                    .get("runner")
-                    .and_then(|v| v.as_str())
-                    .filter(|s| !s.is_empty())
-                {
-                    return runner == agent_uid;
-                }
-                true
-            })
-            .map(|(_id, item)| item.task.clone())
+            .values()
+            .filter(|entry| entry.is_offerable_to(caps, agent_uid, system_info))
+            .map(|entry| entry.task.clone())
+            .collect()
     }
 
     pub async fn add_task(
@@ -113,16 +388,28 @@ impl UrgentTaskStore {
             last_update: Utc::now(),
             ttl: TimeDelta::seconds(ttl_secs),
             global_deadline,
+            last_chance_until: None,
         };
 
-        self.tasks
-            .write()
-            .await
-            .insert(entry.task.id.clone(), entry);
+        let id = entry.task.id.clone();
+        self.persist(&entry, &TaskStatus::Pending);
+        self.tasks.write().await.insert(id.clone(), entry);
+        self.audit(&id, UrgentAuditEvent::Submitted, None);
+        self.transition(&id, None, TaskStatus::Pending, None);
 
         Ok(state)
     }
 
+    /// The task behind `task_id` if it is still waiting for an agent.
+    pub async fn get_pending_task(&self, task_id: &TaskId) -> Option<UnassignedTask> {
+        self.tasks
+            .read()
+            .await
+            .get(task_id)
+            .filter(|entry| entry.assigned_task.is_none())
+            .map(|entry| entry.task.clone())
+    }
+
     pub async fn assign_task(&self, task_id: &TaskId, agent: &str) -> bool {
         let mut tasks = self.tasks.write().await;
         if let Some(entry) = tasks.get_mut(task_id) {
@@ -134,6 +421,14 @@ impl UrgentTaskStore {
                 entry.last_update = Utc::now();
                 *status = TaskStatus::Assigned;
                 let _ = entry.state.notify.send(TaskStatus::Assigned);
+                self.persist(entry, &status);
+                self.audit(task_id, UrgentAuditEvent::Assigned, Some(agent));
+                self.transition(
+                    task_id,
+                    Some(TaskStatus::Pending),
+                    TaskStatus::Assigned,
+                    Some(agent),
+                );
                 return true;
             }
         }
@@ -153,11 +448,20 @@ impl UrgentTaskStore {
                 Some(TaskStatus::Assigned)
             );
             if un_started {
-                entry.assigned_task = None;
+                let agent_id = entry.assigned_task.take().map(|a| a.agent_id);
                 entry.last_update = Utc::now();
+                entry.last_chance_until = None;
                 let mut status = entry.state.status.write().await;
                 *status = TaskStatus::Pending;
                 let _ = entry.state.notify.send(TaskStatus::Pending);
+                self.persist(entry, &status);
+                self.audit(task_id, UrgentAuditEvent::Unassigned, agent_id.as_deref());
+                self.transition(
+                    task_id,
+                    Some(TaskStatus::Assigned),
+                    TaskStatus::Pending,
+                    agent_id.as_deref(),
+                );
                 return true;
             }
         }
@@ -168,11 +472,18 @@ impl UrgentTaskStore {
         info!("Cleaning up urgent tasks queue");
 
         self.tasks.write().await.clear();
+        if self.persist
+            && let Some(tx) = &self.events
+            && let Err(e) = tx.try_send(DbWriteRequest::UrgentClear)
+        {
+            warn!("Dropped clearing of persisted urgent tasks: {e}");
+        }
     }
 
     pub async fn complete_task(
         &self,
         task_id: &TaskId,
+        agent_uid: &str,
         success: bool,
         payload: serde_json::Value,
     ) -> Result<bool, AppError> {
@@ -181,15 +492,25 @@ impl UrgentTaskStore {
             let task = entry.assigned_task.as_mut().ok_or(AppError::Conflict(
                 "Task is not assigned but reported".to_string(),
             ))?;
+            if task.agent_id != agent_uid {
+                return Err(AppError::Authorization(format!(
+                    "Task {} is not assigned to this agent",
+                    task_id
+                )));
+            }
             let is_cancel_requested = task.status == TaskStatus::CancelRequested;
             task.result = Some(payload);
             task.stage = None;
+            // Reported, so there is nothing left to restore after a restart.
+            self.forget(task_id);
             if !is_cancel_requested {
+                let from = task.status.clone();
                 task.change_status(if success {
                     TaskStatus::Completed
                 } else {
                     TaskStatus::Failed
                 });
+                entry.last_update = Utc::now();
 
                 let mut status = entry.state.status.write().await;
                 *status = if success {
@@ -198,6 +519,13 @@ impl UrgentTaskStore {
                     TaskStatus::Failed
                 };
                 let _ = entry.state.notify.send(status.clone());
+                let event = if success {
+                    UrgentAuditEvent::Completed
+                } else {
+                    UrgentAuditEvent::Failed
+                };
+                self.audit(task_id, event, Some(agent_uid));
+                self.finish(entry, from, status.clone(), Some(agent_uid));
             }
             if is_cancel_requested {
                 return Err(AppError::ClientClosedRequest(format!(
@@ -216,6 +544,7 @@ impl UrgentTaskStore {
         log: Option<String>,
         stage: Option<String>,
         status: Option<TaskStatus>,
+        progress: Option<f32>,
     ) -> Result<bool, AppError> {
         let mut tasks = self.tasks.write().await;
         if let Some(entry) = tasks.get_mut(task_id) {
@@ -224,14 +553,33 @@ impl UrgentTaskStore {
                 "Task is not assigned but reported".to_string(),
             ))?;
             let is_cancel_requested = task.status == TaskStatus::CancelRequested;
+            let mut status_changed = false;
             task.append_log(log);
             if let Some(stage_text) = stage {
                 task.change_stage(&stage_text);
             }
+            if progress.is_some() {
+                task.progress = progress;
+            }
             if !is_cancel_requested {
                 if let Some(new_status) = status {
                     match new_status {
                         TaskStatus::Starting | TaskStatus::Running => {
+                            if task.status != new_status {
+                                let event = if new_status == TaskStatus::Starting {
+                                    UrgentAuditEvent::Starting
+                                } else {
+                                    UrgentAuditEvent::Running
+                                };
+                                self.audit(task_id, event, Some(&task.agent_id));
+                                self.transition(
+                                    task_id,
+                                    Some(task.status.clone()),
+                                    new_status.clone(),
+                                    Some(&task.agent_id),
+                                );
+                                status_changed = true;
+                            }
                             task.change_status(new_status)
                         }
                         _ => {
@@ -243,6 +591,12 @@ impl UrgentTaskStore {
                     }
                 }
             }
+            // Log and progress lines are not worth a database write each; a
+            // restart resumes the task from its last status change.
+            if status_changed {
+                let current = entry.state.status.read().await.clone();
+                self.persist(entry, &current);
+            }
             if is_cancel_requested {
                 return Err(AppError::ClientClosedRequest(format!(
                     "Task {} has been cancelled by the client",
@@ -260,23 +614,61 @@ impl UrgentTaskStore {
         let mut tasks = self.tasks.write().await;
         // (task_id, global_deadline_was_the_trigger)
         let mut to_remove: Vec<(TaskId, bool)> = vec![];
-        for (id, entry) in tasks.iter() {
+        // Finished tasks past their retention window, dropped as they are.
+        let mut reaped: Vec<TaskId> = vec![];
+        for (id, entry) in tasks.iter_mut() {
             let status = entry.state.status.read().await.clone();
             let global_expired = entry.global_deadline.map_or(false, |d| now >= d);
             let expired = match status {
-                // Already terminal — the waiting submitter will remove it.
-                TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Canceled => false,
+                // Already terminal — normally the waiting submitter removes it;
+                // reap it here if it is still around after the retention window
+                // (e.g. the blocking request was dropped).
+                TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Canceled => {
+                    if now - entry.last_update >= self.terminal_retention {
+                        reaped.push(id.clone());
+                    }
+                    false
+                }
                 // Never picked up: expire when pending TTL or global deadline passes.
-                TaskStatus::Pending => now - entry.created_at > entry.ttl || global_expired,
+                // With a grace window, a TTL-expired task first gets one last chance
+                // to be picked up before it fails.
+                TaskStatus::Pending if global_expired => true,
+                TaskStatus::Pending => match entry.last_chance_until {
+                    Some(until) => now >= until,
+                    None if now - entry.created_at <= entry.ttl => false,
+                    None if self.expiry_grace > TimeDelta::zero() => {
+                        entry.last_chance_until = Some(now + self.expiry_grace);
+                        info!(
+                            "Urgent task {} pending TTL passed, entering last chance",
+                            id
+                        );
+                        false
+                    }
+                    None => true,
+                },
                 // Picked up but in-flight: expire when the assigned agent has
                 // gone silent (no progress / no resolution) for longer than the
-                // TTL, OR when the global wall-clock deadline passes.
-                _ => now - entry.last_update > entry.ttl || global_expired,
+                // TTL, when the global wall-clock deadline passes, or when the
+                // attempt outlived its `deadlineSecs`.
+                _ => {
+                    now - entry.last_update > entry.ttl
+                        || global_expired
+                        || entry
+                            .assigned_task
+                            .as_ref()
+                            .is_some_and(|assigned| assigned.deadline_passed(now))
+                }
             };
             if expired {
                 to_remove.push((id.clone(), global_expired));
             }
         }
+        for (id, _) in &to_remove {
+            self.forget(id);
+        }
+        for id in reaped {
+            tasks.shift_remove(&id);
+        }
 
         for (id, global_expired) in to_remove {
             // When the global deadline fires on an in-flight task, mark the
@@ -292,6 +684,12 @@ impl UrgentTaskStore {
                                 | TaskStatus::CancelRequested
                                 | TaskStatus::Canceled
                         ) {
+                            self.transition(
+                                &id,
+                                Some(assigned.status.clone()),
+                                TaskStatus::CancelRequested,
+                                Some(&assigned.agent_id),
+                            );
                             assigned.change_status(TaskStatus::CancelRequested);
                         }
                     }
@@ -302,17 +700,52 @@ impl UrgentTaskStore {
                 if *status != TaskStatus::Completed && *status != TaskStatus::Failed {
                     *status = TaskStatus::Failed;
                     let _ = entry.state.notify.send(TaskStatus::Failed);
+                    let agent_id = entry.assigned_task.as_ref().map(|a| a.agent_id.as_str());
+                    self.audit(&id, UrgentAuditEvent::Expired, agent_id);
+                    let from = entry
+                        .assigned_task
+                        .as_ref()
+                        .map_or(TaskStatus::Pending, |a| a.status.clone());
+                    self.finish(entry, from, TaskStatus::Failed, agent_id);
                 }
             }
             tasks.shift_remove(&id);
         }
     }
 
+    /// Renew the in-flight lease of `task_id` for the agent holding it, so the
+    /// expiry sweep does not take the task back while the agent works on it
+    /// quietly. Returns when the lease now ends, or `None` if the task is not
+    /// an assigned urgent task.
+    pub async fn touch_task(
+        &self,
+        task_id: &TaskId,
+        agent_uid: &str,
+    ) -> Result<Option<DateTime<Utc>>, AppError> {
+        let mut tasks = self.tasks.write().await;
+        let Some(entry) = tasks.get_mut(task_id) else {
+            return Ok(None);
+        };
+        let Some(assigned) = &entry.assigned_task else {
+            return Ok(None);
+        };
+        if assigned.agent_id != agent_uid {
+            return Err(AppError::Authorization(format!(
+                "Task {} is not assigned to this agent",
+                task_id
+            )));
+        }
+        entry.last_update = Utc::now();
+        Ok(Some(entry.last_update + entry.ttl))
+    }
+
     pub async fn set_runtime_estimate(&self, task_id: &TaskId, duration: std::time::Duration) {
         let mut tasks = self.tasks.write().await;
         if let Some(entry) = tasks.get_mut(task_id) {
             if let Some(assigned) = entry.assigned_task.as_mut() {
                 assigned.typical_runtime_seconds = Some(duration);
+                let current = entry.state.status.read().await.clone();
+                self.persist(entry, &current);
             }
         }
     }
@@ -346,11 +779,32 @@ impl UrgentTaskStore {
             .flatten()
     }
 
+    /// The final record of a terminal task; one that never reached an agent
+    /// gets a placeholder assignment. `None` while the task is unknown or
+    /// still running.
+    pub async fn finished_record(&self, task_id: &TaskId) -> Option<AssignedTask> {
+        let tasks = self.tasks.read().await;
+        let entry = tasks.get(task_id)?;
+        let status = entry.state.status.read().await.clone();
+        if !status.is_terminal() {
+            return None;
+        }
+        let mut task = entry
+            .assigned_task
+            .clone()
+            .unwrap_or_else(|| entry.task.clone().into_assigned("(expired)"));
+        if task.status != status {
+            task.change_status(status);
+        }
+        Some(task)
+    }
+
     pub async fn remove_task(&self, task_id: &TaskId) {
         {
             let mut tasks = self.tasks.write().await;
             tasks.shift_remove(task_id);
         }
+        self.forget(task_id);
     }
 
     /// Cancel an urgent task (queued or in-flight). Returns `Canceled` when the
@@ -377,7 +831,20 @@ impl UrgentTaskStore {
                     )));
                 }
                 _ => {
+                    self.transition(
+                        task_id,
+                        Some(assigned.status.clone()),
+                        TaskStatus::CancelRequested,
+                        Some(&assigned.agent_id),
+                    );
                     assigned.change_status(TaskStatus::CancelRequested);
+                    self.audit(
+                        task_id,
+                        UrgentAuditEvent::CancelRequested,
+                        Some(&assigned.agent_id),
+                    );
+                    let current = entry.state.status.read().await.clone();
+                    self.persist(entry, &current);
                     return Ok(TaskStatus::CancelRequested);
                 }
             }
@@ -388,11 +855,293 @@ impl UrgentTaskStore {
         let mut assigned = entry.task.clone().into_assigned("(cancelled)");
         assigned.change_status(TaskStatus::Canceled);
         entry.assigned_task = Some(assigned);
+        entry.last_update = Utc::now();
         {
             let mut status = entry.state.status.write().await;
             *status = TaskStatus::Canceled;
             let _ = entry.state.notify.send(TaskStatus::Canceled);
         }
+        self.forget(task_id);
+        self.audit(task_id, UrgentAuditEvent::Canceled, None);
+        self.finish(entry, TaskStatus::Pending, TaskStatus::Canceled, None);
         Ok(TaskStatus::Canceled)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::TaskSubmissionRequest;
+
+    fn urgent_task() -> UnassignedTask {
+        UnassignedTask {
+            id: TaskId::new_with_cap("debug.echo".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "debug.echo".to_string(),
+                urgent: true,
+                ..Default::default()
+            },
+            created_at: Utc::now(),
+            failed_agents: Vec::new(),
+            next_eligible_at: None,
+            history: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn finished_tasks_are_reaped_after_the_retention_window() {
+        let store = UrgentTaskStore::new(0, 60, None, None, None);
+        let (old, recent) = (urgent_task(), urgent_task());
+        for task in [&old, &recent] {
+            store.add_task(task.clone(), 60, None).await.unwrap();
+            assert!(store.assign_task(&task.id, "agent-a").await);
+            store
+                .complete_task(&task.id, "agent-a", true, serde_json::json!({}))
+                .await
+                .unwrap();
+        }
+        store.expire_tasks().await;
+        assert_eq!(store.tasks.read().await.len(), 2);
+
+        store
+            .tasks
+            .write()
+            .await
+            .get_mut(&old.id)
+            .unwrap()
+            .last_update -= TimeDelta::seconds(61);
+        store.expire_tasks().await;
+        assert!(store.get_assigned_task(&old.id).await.is_none());
+        assert_eq!(
+            store.get_assigned_task(&recent.id).await.unwrap().status,
+            TaskStatus::Completed
+        );
+    }
+
+    /// Stand-in for the DB writer: apply the queued write-throughs to `storage`.
+    fn apply_writes(rx: &mut mpsc::Receiver<DbWriteRequest>, storage: &TaskStorage) {
+        while let Ok(request) = rx.try_recv() {
+            match request {
+                DbWriteRequest::UrgentSnapshot(snapshot) => storage.put_urgent(&snapshot).unwrap(),
+                DbWriteRequest::UrgentForget(task_id) => storage.remove_urgent(&task_id).unwrap(),
+                DbWriteRequest::UrgentClear => storage.clear_urgent().unwrap(),
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn persisted_tasks_are_restored_after_restart() {
+        let storage =
+            TaskStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let (tx, mut rx) = mpsc::channel(64);
+        let store = UrgentTaskStore::new(0, 300, None, Some(tx), Some(&storage));
+        let (pending, assigned, done) = (urgent_task(), urgent_task(), urgent_task());
+        for task in [&pending, &assigned, &done] {
+            store.add_task(task.clone(), 60, None).await.unwrap();
+        }
+        assert!(store.assign_task(&assigned.id, "agent-a").await);
+        assert!(store.assign_task(&done.id, "agent-a").await);
+        store
+            .complete_task(&done.id, "agent-a", true, serde_json::json!({}))
+            .await
+            .unwrap();
+        apply_writes(&mut rx, &storage);
+
+        let (tx, mut rx) = mpsc::channel(64);
+        let restarted = UrgentTaskStore::new(0, 300, None, Some(tx), Some(&storage));
+        let tasks = restarted.tasks.read().await;
+        assert_eq!(
+            tasks.keys().cloned().collect::<Vec<_>>(),
+            vec![pending.id.clone(), assigned.id.clone()]
+        );
+        assert_eq!(
+            *tasks[&pending.id].state.status.read().await,
+            TaskStatus::Pending
+        );
+        assert_eq!(
+            *tasks[&assigned.id].state.status.read().await,
+            TaskStatus::Assigned
+        );
+        assert_eq!(
+            tasks[&assigned.id].assigned_task.as_ref().unwrap().agent_id,
+            "agent-a"
+        );
+        drop(tasks);
+        // The restored assignment still counts against its agent's capacity.
+        assert_eq!(
+            restarted.list_assigned_owners().await,
+            vec![("agent-a".to_string(), assigned.id.clone())]
+        );
+
+        restarted.remove_task(&pending.id).await;
+        apply_writes(&mut rx, &storage);
+        assert_eq!(storage.list_urgent_all().unwrap().len(), 1);
+        let ephemeral = UrgentTaskStore::new(0, 300, None, None, None);
+        assert!(ephemeral.tasks.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn progress_updates_are_not_written_through() {
+        let storage =
+            TaskStorage::from_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let (tx, mut rx) = mpsc::channel(64);
+        let store = UrgentTaskStore::new(0, 300, None, Some(tx), Some(&storage));
+        let task = urgent_task();
+        store.add_task(task.clone(), 60, None).await.unwrap();
+        assert!(store.assign_task(&task.id, "agent-a").await);
+        let snapshots = |rx: &mut mpsc::Receiver<DbWriteRequest>| {
+            std::iter::from_fn(|| rx.try_recv().ok())
+                .filter(|request| matches!(request, DbWriteRequest::UrgentSnapshot(_)))
+                .count()
+        };
+        assert_eq!(snapshots(&mut rx), 2);
+
+        store
+            .update_task(&task.id, Some("line".to_string()), None, None, Some(0.5))
+            .await
+            .unwrap();
+        assert_eq!(snapshots(&mut rx), 0);
+        store
+            .update_task(&task.id, None, None, Some(TaskStatus::Running), None)
+            .await
+            .unwrap();
+        assert_eq!(snapshots(&mut rx), 1);
+    }
+
+    #[tokio::test]
+    async fn transitions_are_queued_for_the_event_log() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let store = UrgentTaskStore::new(0, 300, None, Some(tx), None);
+        let task = urgent_task();
+        store.add_task(task.clone(), 60, None).await.unwrap();
+        assert!(store.assign_task(&task.id, "agent-a").await);
+        store
+            .update_task(&task.id, None, None, Some(TaskStatus::Running), None)
+            .await
+            .unwrap();
+        store
+            .complete_task(&task.id, "agent-a", false, serde_json::json!({}))
+            .await
+            .unwrap();
+
+        let mut steps = Vec::new();
+        while let Ok(request) = rx.try_recv() {
+            if let DbWriteRequest::TaskTransition(record) = request {
+                assert!(record.urgent);
+                steps.push((record.from, record.to));
+            }
+        }
+        assert_eq!(
+            steps,
+            vec![
+                (None, TaskStatus::Pending),
+                (Some(TaskStatus::Pending), TaskStatus::Assigned),
+                (Some(TaskStatus::Assigned), TaskStatus::Running),
+                (Some(TaskStatus::Running), TaskStatus::Failed),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn touched_tasks_survive_the_expiry_sweep() {
+        let store = UrgentTaskStore::new(0, 300, None, None, None);
+        let (touched, silent) = (urgent_task(), urgent_task());
+        for task in [&touched, &silent] {
+            store.add_task(task.clone(), 60, None).await.unwrap();
+            assert!(store.assign_task(&task.id, "agent-a").await);
+            store
+                .tasks
+                .write()
+                .await
+                .get_mut(&task.id)
+                .unwrap()
+                .last_update -= TimeDelta::seconds(61);
+        }
+
+        let err = store.touch_task(&touched.id, "agent-b").await.unwrap_err();
+        assert_eq!(err.status_code_number(), 403);
+        let lease = store.touch_task(&touched.id, "agent-a").await.unwrap();
+        assert!(lease.unwrap() > Utc::now() + TimeDelta::seconds(59));
+        store.expire_tasks().await;
+
+        assert!(store.get_assigned_task(&touched.id).await.is_some());
+        assert!(store.get_assigned_task(&silent.id).await.is_none());
+        let missing = TaskId::new_with_cap("debug.echo".to_string());
+        assert_eq!(store.touch_task(&missing, "agent-a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn expired_and_canceled_tasks_queue_their_callbacks() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let store = UrgentTaskStore::new(0, 300, None, None, None);
+        store.send_callbacks_to(tx);
+        let with_callback = || {
+            let mut task = urgent_task();
+            task.data.callback_url = Some("https://example.com/hook".to_string());
+            task
+        };
+        let (expired, canceled, silent) = (with_callback(), with_callback(), urgent_task());
+        for task in [&expired, &canceled, &silent] {
+            store.add_task(task.clone(), 0, None).await.unwrap();
+        }
+        store.cancel_task(&canceled.id).await.unwrap();
+        store.expire_tasks().await;
+
+        let mut delivered = Vec::new();
+        while let Ok(delivery) = rx.try_recv() {
+            let report: serde_json::Value = serde_json::from_slice(&delivery.body).unwrap();
+            delivered.push((
+                delivery.task_id,
+                report["status"].as_str().unwrap().to_string(),
+            ));
+        }
+        assert_eq!(
+            delivered,
+            vec![
+                (canceled.id.clone(), "canceled".to_string()),
+                (expired.id.clone(), "failed".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_pending_task_gets_a_last_chance_ahead_of_the_queue() {
+        let caps = vec!["debug.echo".to_string()];
+        for grace in [0, 30] {
+            let store = UrgentTaskStore::new(grace, 300, None, None, None);
+            let (fresh, stale) = (urgent_task(), urgent_task());
+            for task in [&fresh, &stale] {
+                store.add_task(task.clone(), 60, None).await.unwrap();
+            }
+            store
+                .tasks
+                .write()
+                .await
+                .get_mut(&stale.id)
+                .unwrap()
+                .created_at -= TimeDelta::seconds(61);
+            store.expire_tasks().await;
+
+            if grace == 0 {
+                assert!(store.get_pending_task(&stale.id).await.is_none());
+                continue;
+            }
+            // Still pending, and offered before the task queued ahead of it.
+            assert!(store.get_pending_task(&stale.id).await.is_some());
+            let offered = store.find_with_capabilities(&caps, "agent-a", None).await;
+            assert_eq!(offered.unwrap().id, stale.id);
+
+            store
+                .tasks
+                .write()
+                .await
+                .get_mut(&stale.id)
+                .unwrap()
+                .last_chance_until = Some(Utc::now() - TimeDelta::seconds(1));
+            store.expire_tasks().await;
+            assert!(store.get_pending_task(&stale.id).await.is_none());
+            assert!(store.get_pending_task(&fresh.id).await.is_some());
+        }
+    }
+}
@@ -0,0 +1,485 @@
+//! Completion callbacks.
+//!
+//! A task submitted with a `callbackUrl` has its final status report POSTed
+//! there once it reaches a terminal status. Deliveries go through a bounded
+//! queue to [`run_worker`], which retries failed attempts with exponential
+//! backoff. Each body is signed with HMAC-SHA256 keyed by the submitting
+//! client's API key, sent as `X-OffloadMQ-Signature: sha256=<hex>`, so the
+//! receiver can check it came from this server.
+//!
+//! Callback targets are client-chosen, so they are kept off the server's own
+//! network: unless a host is in `WEBHOOK_ALLOWED_HOSTS`, it must resolve to
+//! public addresses only. The check runs again at delivery, the request is
+//! pinned to the addresses it checked, and redirects are not followed.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{debug, info, warn};
+use sha2::Sha256;
+use tokio::sync::{Semaphore, mpsc, watch};
+
+use crate::{
+    config::WebhookConfig,
+    models::{AssignedTask, TaskEvent},
+    schema::{TaskId, TaskStatusResponse},
+    state::AppState,
+};
+
+/// Header carrying the body signature.
+pub const SIGNATURE_HEADER: &str = "X-OffloadMQ-Signature";
+
+/// Deliveries in flight at once; the rest wait in the queue.
+const MAX_CONCURRENT_DELIVERIES: usize = 8;
+
+/// One callback waiting to be delivered.
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub task_id: TaskId,
+    pub url: String,
+    pub body: Vec<u8>,
+    pub signature: String,
+}
+
+impl WebhookDelivery {
+    /// Callback for a finished `task`, or `None` if it has no `callbackUrl`.
+    pub fn for_task(task: &AssignedTask) -> Option<Self> {
+        let url = task.data.callback_url.clone()?;
+        let report = TaskStatusResponse {
+            timing: Some(task.timing()),
+            ..task.clone().into_status_report()
+        };
+        let body = serde_json::to_vec(&report).ok()?;
+        Some(Self {
+            task_id: task.id.clone(),
+            signature: sign(&task.data.api_key, &body),
+            url,
+            body,
+        })
+    }
+}
+
+/// `sha256=<hex>` HMAC-SHA256 of `body` keyed by the client API key.
+pub fn sign(api_key: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(api_key.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    let digest: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", digest)
+}
+
+/// Check a `callbackUrl` at submission: an http(s) URL whose host is either
+/// allow-listed or neither `localhost` nor a non-public address literal.
+/// Names are only resolved at delivery ([`resolve_target`]), since what they
+/// resolve to may change in between.
+pub fn check_callback_url(url: &str, config: &WebhookConfig) -> Result<(), String> {
+    let url = parse_target(url)?;
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    if config.allowed_hosts.contains(&host) {
+        return Ok(());
+    }
+    let internal = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => !is_public(ip),
+        Err(_) => host == "localhost" || host.ends_with(".localhost"),
+    };
+    if internal {
+        return Err(format!("callbackUrl host {} is not a public address", host));
+    }
+    Ok(())
+}
+
+/// Resolve the host of `url` for delivery. Allow-listed hosts are used as
+/// they are (`Ok(None)`); any other host must resolve to public addresses
+/// only, returned so the request can be pinned to them.
+pub async fn resolve_target(
+    url: &str,
+    config: &WebhookConfig,
+) -> Result<Option<(String, Vec<SocketAddr>)>, String> {
+    check_callback_url(url, config)?;
+    let url = parse_target(url)?;
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    if config.allowed_hosts.contains(&host) {
+        return Ok(None);
+    }
+    let port = url.port_or_known_default().unwrap_or(80);
+    let lookup = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((lookup, port))
+        .await
+        .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("{} has no addresses", host));
+    }
+    if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        return Err(format!(
+            "{} resolves to non-public address {}",
+            host,
+            addr.ip()
+        ));
+    }
+    Ok(Some((host, addrs)))
+}
+
+fn parse_target(url: &str) -> Result<reqwest::Url, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("invalid callbackUrl: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        return Err("callbackUrl must be an http:// or https:// URL".to_string());
+    }
+    Ok(url)
+}
+
+/// Whether `ip` is reachable on the public internet, as opposed to loopback,
+/// private, link-local, shared (CGNAT), unspecified or multicast space.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Queue the callback of a task that just reached a terminal status, if it
+/// asked for one. Both task stores call this from the hook every transition
+/// of theirs goes through, so a task sends its callback however it finished.
+pub fn queue_callback(tx: &mpsc::Sender<WebhookDelivery>, task: &AssignedTask) {
+    let Some(delivery) = WebhookDelivery::for_task(task) else {
+        return;
+    };
+    if let Err(e) = tx.try_send(delivery) {
+        warn!("Dropped completion callback for task {}: {}", task.id, e);
+    }
+}
+
+/// POST `delivery` until it succeeds or `config.max_attempts` are used up.
+/// Returns the number of attempts made, or the last error.
+pub async fn deliver(
+    client: &reqwest::Client,
+    delivery: &WebhookDelivery,
+    config: &WebhookConfig,
+) -> Result<u32, String> {
+    let mut backoff = Duration::from_millis(config.backoff_ms);
+    let mut last_error = String::new();
+    for attempt in 1..=config.max_attempts {
+        let sent = client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &delivery.signature)
+            .body(delivery.body.clone())
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .send()
+            .await;
+        match sent {
+            Ok(response) if response.status().is_success() => return Ok(attempt),
+            Ok(response) => last_error = format!("HTTP {}", response.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+        debug!(
+            "Callback for task {} failed (attempt {}): {}",
+            delivery.task_id, attempt, last_error
+        );
+        if attempt < config.max_attempts {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+    Err(last_error)
+}
+
+/// Note a callback that could not be delivered in the task's history, be it
+/// urgent, assigned or archived.
+async fn record_failure(state: &AppState, delivery: &WebhookDelivery, error: &str) {
+    let event = TaskEvent {
+        timestamp: Utc::now(),
+        description: format!("Callback to {} failed: {}", delivery.url, error),
+    };
+    if state.urgent.add_history(&delivery.task_id, &event).await {
+        return;
+    }
+    if let Err(e) = state.storage.tasks.add_history(&delivery.task_id, &event) {
+        warn!(
+            "Failed to record callback failure on task {}: {}",
+            delivery.task_id, e
+        );
+    }
+}
+
+/// HTTP client for callbacks. Redirects are not followed, since they would
+/// bypass the target check; `pin` fixes the addresses a host connects to.
+/// There is no fallback client: one without these settings would undo the
+/// target check, so a build failure fails the delivery instead.
+fn delivery_client(pin: Option<(String, Vec<SocketAddr>)>) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    if let Some((host, addrs)) = pin {
+        builder = builder.resolve_to_addrs(&host, &addrs);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("cannot build callback client: {}", e))
+}
+
+/// Deliver queued callbacks, a few at a time, until shutdown. Shutdown is
+/// noticed even while every delivery slot is taken by a slow target.
+pub async fn run_worker(
+    state: Arc<AppState>,
+    mut rx: mpsc::Receiver<WebhookDelivery>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let client = delivery_client(None);
+    if let Err(e) = &client {
+        warn!("Callbacks will fail: {}", e);
+    }
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_DELIVERIES));
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => {
+                if *shutdown.borrow() {
+                    info!("Webhook worker shutting down");
+                    break;
+                }
+            }
+            delivery = rx.recv() => {
+                let Some(delivery) = delivery else {
+                    info!("Webhook queue closed, worker exiting");
+                    break;
+                };
+                let permit = tokio::select! {
+                    permit = permits.clone().acquire_owned() => permit,
+                    _ = shutdown.wait_for(|stop| *stop) => {
+                        info!("Webhook worker shutting down");
+                        break;
+                    }
+                };
+                let Ok(permit) = permit else {
+                    break;
+                };
+                let (state, client) = (state.clone(), client.clone());
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    let client = match resolve_target(&delivery.url, &state.config.webhook).await {
+                        Ok(None) => client,
+                        Ok(Some(pin)) => delivery_client(Some(pin)),
+                        Err(e) => Err(e),
+                    };
+                    let client = match client {
+                        Ok(client) => client,
+                        Err(e) => {
+                            warn!("Refusing callback for task {}: {}", delivery.task_id, e);
+                            record_failure(&state, &delivery, &e).await;
+                            return;
+                        }
+                    };
+                    match deliver(&client, &delivery, &state.config.webhook).await {
+                        Ok(attempts) => debug!(
+                            "Delivered callback for task {} after {} attempt(s)",
+                            delivery.task_id, attempts
+                        ),
+                        Err(e) => {
+                            warn!(
+                                "Giving up on callback for task {} to {} after {} attempts: {}",
+                                delivery.task_id, delivery.url, state.config.webhook.max_attempts, e
+                            );
+                            record_failure(&state, &delivery, &e).await;
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{TaskStatus, TaskSubmissionRequest};
+    use axum::{Router, http::HeaderMap, http::StatusCode, routing::post};
+    use std::sync::Mutex;
+
+    /// Signature header and body of each callback the test server accepted.
+    type Received = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+    #[tokio::test]
+    async fn completion_callback_is_retried_and_signed() {
+        // Fails the first attempt, then records what it receives.
+        let received: Received = Arc::default();
+        let calls = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let app = {
+            let (received, calls) = (received.clone(), calls.clone());
+            Router::new().route(
+                "/hook",
+                post(
+                    move |headers: HeaderMap, body: axum::body::Bytes| async move {
+                        if calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                            return StatusCode::SERVICE_UNAVAILABLE;
+                        }
+                        let signature = headers[SIGNATURE_HEADER].to_str().unwrap().to_string();
+                        received.lock().unwrap().push((signature, body.to_vec()));
+                        StatusCode::OK
+                    },
+                ),
+            )
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut task = AssignedTask {
+            id: TaskId::new_with_cap("debug.echo".to_string()),
+            data: TaskSubmissionRequest {
+                capability: "debug.echo".to_string(),
+                api_key: "client-key".to_string(),
+                callback_url: Some(format!("http://{}/hook", addr)),
+                ..Default::default()
+            },
+            agent_id: "agent-a".to_string(),
+            result: Some(serde_json::json!({ "echo": 1 })),
+            ..Default::default()
+        };
+        task.change_status(TaskStatus::Completed);
+        let delivery = WebhookDelivery::for_task(&task).unwrap();
+        let config = WebhookConfig {
+            max_attempts: 3,
+            backoff_ms: 10,
+            timeout_secs: 5,
+            allowed_hosts: Vec::new(),
+        };
+
+        let attempts = deliver(&reqwest::Client::new(), &delivery, &config).await;
+        assert_eq!(attempts, Ok(2));
+        let received = received.lock().unwrap();
+        let (signature, body) = &received[0];
+        assert_eq!(*signature, sign("client-key", body));
+        assert_ne!(*signature, sign("other-key", body));
+        let report: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(report["status"], "completed");
+        assert_eq!(report["output"]["echo"], 1);
+        assert!(report["timing"].is_object());
+    }
+
+    #[tokio::test]
+    async fn worker_stops_while_every_delivery_slot_is_taken() {
+        // Accepts callbacks and never answers them.
+        let hanging = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let app = {
+            let hanging = hanging.clone();
+            Router::new().route(
+                "/hook",
+                post(move || async move {
+                    hanging.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    std::future::pending::<StatusCode>().await
+                }),
+            )
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let state = AppState::for_test(|config| {
+            config.webhook = WebhookConfig {
+                max_attempts: 1,
+                backoff_ms: 0,
+                timeout_secs: 60,
+                allowed_hosts: vec!["127.0.0.1".to_string()],
+            }
+        });
+        let (tx, rx) = mpsc::channel(16);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        for _ in 0..=MAX_CONCURRENT_DELIVERIES {
+            tx.try_send(WebhookDelivery {
+                task_id: TaskId::new_with_cap("debug.echo".to_string()),
+                url: format!("http://{}/hook", addr),
+                body: b"{}".to_vec(),
+                signature: sign("client-key", b"{}"),
+            })
+            .unwrap();
+        }
+        let worker = tokio::spawn(run_worker(state, rx, shutdown_rx));
+        while hanging.load(std::sync::atomic::Ordering::SeqCst) < MAX_CONCURRENT_DELIVERIES {
+            tokio::task::yield_now().await;
+        }
+
+        shutdown_tx.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), worker)
+            .await
+            .expect("worker kept waiting for a delivery slot")
+            .unwrap();
+    }
+
+    #[test]
+    fn internal_callback_targets_are_rejected_unless_allow_listed() {
+        let config = WebhookConfig {
+            max_attempts: 1,
+            backoff_ms: 0,
+            timeout_secs: 1,
+            allowed_hosts: vec!["hooks.internal".to_string(), "10.0.0.5".to_string()],
+        };
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://192.168.1.10/hook",
+            "http://100.64.0.1/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:10.0.0.1]/hook",
+            "ftp://example.com/hook",
+        ] {
+            assert!(check_callback_url(url, &config).is_err(), "{}", url);
+        }
+        for url in [
+            "https://example.com/hook",
+            "http://93.184.216.34/hook",
+            "http://hooks.internal/hook",
+            "http://10.0.0.5:9000/hook",
+        ] {
+            assert!(check_callback_url(url, &config).is_ok(), "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn delivery_rechecks_targets_but_trusts_allowed_hosts() {
+        let config = WebhookConfig {
+            max_attempts: 1,
+            backoff_ms: 0,
+            timeout_secs: 1,
+            allowed_hosts: vec!["127.0.0.1".to_string()],
+        };
+        assert_eq!(
+            resolve_target("http://127.0.0.1:9/hook", &config).await,
+            Ok(None)
+        );
+        let err = resolve_target("http://[::1]:9/hook", &config)
+            .await
+            .unwrap_err();
+        assert!(err.contains("not a public address"));
+    }
+}
@@ -1,43 +1,155 @@
-use std::sync::RwLock;
+use std::env;
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
-pub struct Config {
-    pub shuffle_queue: bool,
-    pub allow_assigning_to_same_top_tier: bool,
+use log::warn;
+
+/// How the non-urgent handler decides whether an eligible agent is offered a task
+/// when higher-tier agents serving the same capability are online.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelectionStrategy {
+    /// Strict reservation: a task is only offered when no higher-tier agent is online.
+    #[default]
+    TierCutoff,
+    /// Lower-tier agents may still be offered the task, with a chance that scales
+    /// with their tier relative to the top online tier.
+    WeightedByTier(TierWeighting),
+    /// Tiers are ignored; a task is offered to the online agents serving it that
+    /// have the smallest share of their capacity in use, so work spreads across
+    /// idle agents instead of piling onto whoever polls first.
+    LeastLoaded,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            shuffle_queue: false,
-            allow_assigning_to_same_top_tier: false,
+/// Weighting function used by [`SelectionStrategy::WeightedByTier`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TierWeighting {
+    /// weight = tier + 1
+    #[default]
+    Linear,
+    /// weight = 2^tier
+    Exponential,
+}
+
+impl TierWeighting {
+    pub fn weight(&self, tier: u8) -> f64 {
+        match self {
+            TierWeighting::Linear => tier as f64 + 1.0,
+            TierWeighting::Exponential => 2f64.powi(tier as i32),
+        }
+    }
+}
+
+impl FromStr for SelectionStrategy {
+    type Err = String;
+
+    /// Accepts `tier_cutoff`, `weighted` / `weighted_linear`, `weighted_exponential`
+    /// and `least_loaded`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "tier_cutoff" | "cutoff" => Ok(SelectionStrategy::TierCutoff),
+            "weighted" | "weighted_linear" => {
+                Ok(SelectionStrategy::WeightedByTier(TierWeighting::Linear))
+            }
+            "weighted_exponential" => Ok(SelectionStrategy::WeightedByTier(
+                TierWeighting::Exponential,
+            )),
+            "least_loaded" => Ok(SelectionStrategy::LeastLoaded),
+            other => Err(format!("Unknown selection strategy: {other}")),
         }
     }
 }
 
-// Global configuration using RwLock
-static CONFIG: RwLock<Config> = RwLock::new(Config {
-    shuffle_queue: false,
-    allow_assigning_to_same_top_tier: false,
-});
+/// Which of the eligible non-urgent tasks an agent is handed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PickOrder {
+    /// Uniformly at random.
+    #[default]
+    Random,
+    /// Earliest created first (FIFO); task ids are time-sortable.
+    Oldest,
+    /// Most recently created first (LIFO).
+    Newest,
+}
+
+impl FromStr for PickOrder {
+    type Err = String;
 
-// Initialize configuration (call once at startup)
-pub fn init_config(shuffle_queue: bool, allow_assigning_to_same_top_tier: bool) {
-    let mut config = CONFIG.write().unwrap();
-    config.shuffle_queue = shuffle_queue;
-    config.allow_assigning_to_same_top_tier = allow_assigning_to_same_top_tier;
+    /// Accepts `random`, `oldest` / `fifo` and `newest` / `lifo`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "random" => Ok(PickOrder::Random),
+            "oldest" | "fifo" => Ok(PickOrder::Oldest),
+            "newest" | "lifo" => Ok(PickOrder::Newest),
+            other => Err(format!("Unknown pick order: {other}")),
+        }
+    }
 }
 
-// Get a copy of the current configuration
-pub fn get_config() -> Config {
-    CONFIG.read().unwrap().clone()
+/// Scheduling preferences of one server instance, read from the environment
+/// at startup (see [`Config::from_env`]) and held by the regular task store.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Pick randomly among eligible tasks under [`PickOrder::Random`]; when
+    /// off, the oldest eligible task is taken instead (see
+    /// [`Config::effective_pick_order`]).
+    pub shuffle_queue: bool,
+    pub selection_strategy: SelectionStrategy,
+    /// Round-robin non-urgent tasks of a capability across the client API keys
+    /// that have tasks queued, instead of picking among them at random.
+    pub fair_share: bool,
+    pub pick_order: PickOrder,
 }
 
-// Convenience functions to access individual fields
-pub fn shuffle_queue() -> bool {
-    CONFIG.read().unwrap().shuffle_queue
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            shuffle_queue: true,
+            selection_strategy: SelectionStrategy::TierCutoff,
+            fair_share: false,
+            pick_order: PickOrder::Random,
+        }
+    }
 }
 
-pub fn allow_assigning_to_same_top_tier() -> bool {
-    CONFIG.read().unwrap().allow_assigning_to_same_top_tier
+impl Config {
+    /// The pick order actually applied: a random pick only happens while
+    /// `shuffle_queue` is on; with it off the queue is served FIFO.
+    pub fn effective_pick_order(&self) -> PickOrder {
+        match self.pick_order {
+            PickOrder::Random if !self.shuffle_queue => PickOrder::Oldest,
+            order => order,
+        }
+    }
+
+    /// Read `SHUFFLE_QUEUE`, `FAIR_SHARE_SCHEDULING`, `SELECTION_STRATEGY` and `TASK_PICK_ORDER`.
+    /// Unknown strategy or order names are logged and fall back to the default.
+    pub fn from_env() -> Self {
+        let shuffle_queue = env::var("SHUFFLE_QUEUE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(true);
+        let fair_share = env::var("FAIR_SHARE_SCHEDULING")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+        let selection_strategy = match env::var("SELECTION_STRATEGY") {
+            Ok(strategy) => strategy.parse().unwrap_or_else(|e| {
+                warn!("{e}, falling back to tier cutoff");
+                SelectionStrategy::default()
+            }),
+            Err(_) => SelectionStrategy::default(),
+        };
+        let pick_order = match env::var("TASK_PICK_ORDER") {
+            Ok(order) => order.parse().unwrap_or_else(|e| {
+                warn!("{e}, falling back to random");
+                PickOrder::default()
+            }),
+            Err(_) => PickOrder::default(),
+        };
+        Self {
+            shuffle_queue,
+            selection_strategy,
+            fair_share,
+            pick_order,
+        }
+    }
 }
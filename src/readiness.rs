@@ -0,0 +1,147 @@
+//! Readiness and drain state for rolling deploys.
+//!
+//! Every HTTP request is counted while it runs (see
+//! [`crate::middleware::count_in_flight`]). On a shutdown signal the server is
+//! marked draining: `/ready` answers `503` so the load balancer stops routing
+//! new traffic and new submissions are refused, while requests already in
+//! flight are left to finish by axum's graceful shutdown. A `submit_blocking`
+//! call still waiting when the drain window ends is answered with `503` so it
+//! does not hold the shutdown open.
+//!
+//! `/health/ready` also probes the subsystems a request needs (see
+//! [`probe`]) and answers `503` naming the ones that failed, while
+//! `/health/live` only says the process is up.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use axum::http::StatusCode;
+use serde::Serialize;
+use serde_json::{Value, json};
+
+#[derive(Default)]
+pub struct Readiness {
+    in_flight: AtomicUsize,
+    draining: AtomicBool,
+}
+
+/// Decrements the in-flight count when dropped, so a request that errors,
+/// panics or is cancelled mid-flight is still released.
+pub struct InFlightGuard<'a> {
+    readiness: &'a Readiness,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.readiness.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Readiness {
+    /// Count a request as in flight until the returned guard is dropped.
+    pub fn enter(&self) -> InFlightGuard<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard { readiness: self }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Stop reporting ready. There is no way back: draining ends in shutdown.
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+}
+
+/// A subsystem that failed its readiness probe.
+#[derive(Debug, Serialize)]
+pub struct ProbeFailure {
+    pub subsystem: &'static str,
+    pub error: String,
+}
+
+/// Run the probe of `subsystem`, naming it if the check fails.
+pub fn probe<E: std::fmt::Display>(
+    subsystem: &'static str,
+    check: impl FnOnce() -> Result<(), E>,
+) -> Option<ProbeFailure> {
+    check().err().map(|e| ProbeFailure {
+        subsystem,
+        error: e.to_string(),
+    })
+}
+
+impl Readiness {
+    /// Status and body of `/health/ready`: `503` while draining or when any
+    /// subsystem probe failed.
+    pub fn report(&self, failures: &[ProbeFailure]) -> (StatusCode, Value) {
+        let in_flight = self.in_flight();
+        if self.is_draining() {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({ "status": "draining", "inFlight": in_flight }),
+            )
+        } else if !failures.is_empty() {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({ "status": "unavailable", "inFlight": in_flight, "failures": failures }),
+            )
+        } else {
+            (
+                StatusCode::OK,
+                json!({ "status": "ready", "inFlight": in_flight }),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failing_probe_makes_the_server_not_ready() {
+        let readiness = Readiness::default();
+        let failures: Vec<ProbeFailure> = [
+            probe("agents", || Ok::<(), String>(())),
+            probe("tasks", || Err("tree is locked")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        let (status, body) = readiness.report(&failures);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["failures"][0]["subsystem"], "tasks");
+        assert_eq!(body["failures"][0]["error"], "tree is locked");
+
+        assert_eq!(readiness.report(&[]).0, StatusCode::OK);
+        readiness.start_draining();
+        assert_eq!(readiness.report(&[]).1["status"], "draining");
+    }
+
+    #[test]
+    fn requests_are_counted_until_they_end_even_by_panicking() {
+        let readiness = Readiness::default();
+        let first = readiness.enter();
+        let second = readiness.enter();
+        assert_eq!(readiness.in_flight(), 2);
+        assert_eq!(readiness.report(&[]).1["inFlight"], 2);
+
+        drop(first);
+        assert_eq!(readiness.in_flight(), 1);
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = readiness.enter();
+            panic!("handler blew up");
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(readiness.in_flight(), 1);
+        drop(second);
+        assert_eq!(readiness.in_flight(), 0);
+    }
+}
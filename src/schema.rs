@@ -11,6 +11,7 @@ use serde_json::Value; // Using Value for flexible payloads
 
 use crate::{
     error::AppError,
+    mq::capability::agent_serves,
     utils::{time_sortable_uid, url_decode},
 };
 
@@ -91,6 +92,14 @@ pub struct AgentRegistrationRequest {
     /// Optional human-readable display name (max 50 chars).
     #[serde(default)]
     pub display_name: Option<String>,
+    /// Optional uid to register under instead of a generated one. What happens
+    /// when it is already taken depends on `AGENT_DUPLICATE_UID_POLICY`.
+    #[serde(default)]
+    pub agent_id: Option<String>,
+    /// Optional per-agent override of `URGENT_RESERVED_SLOTS`: slots that
+    /// non-urgent tasks never fill, kept free for urgent work.
+    #[serde(default)]
+    pub urgent_reserved_slots: Option<u32>,
 }
 
 /// Body of the request for an agent to update itself.
@@ -111,6 +120,10 @@ pub struct AgentUpdateRequest {
     /// Optional human-readable display name (max 50 chars).
     #[serde(default)]
     pub display_name: Option<String>,
+    /// Optional per-agent override of `URGENT_RESERVED_SLOTS`: slots that
+    /// non-urgent tasks never fill, kept free for urgent work.
+    #[serde(default)]
+    pub urgent_reserved_slots: Option<u32>,
 }
 
 /// Body of management request to create API key
@@ -121,6 +134,63 @@ pub struct CreateApiKeyRequest {
     pub capabilities: Vec<String>,
 }
 
+/// Body of management request to create or update a capability registry entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityDefinitionRequest {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default = "default_true")]
+    pub active: bool,
+    #[serde(default)]
+    pub default_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub default_max_wait_secs: Option<u64>,
+    /// JSON Schema (object) for the output of successful tasks.
+    #[serde(default)]
+    pub output_schema: Option<Value>,
+    #[serde(default)]
+    pub scheduling_weight: u32,
+    #[serde(default)]
+    pub always_blocking: bool,
+    #[serde(default)]
+    pub result_ttl_secs: Option<u64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Body of management request to preview which queued tasks a hypothetical
+/// agent would be offered. Nothing is assigned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulateAgentRequest {
+    pub capabilities: Vec<String>,
+    #[serde(default)]
+    pub tier: u8,
+    /// Hardware of the hypothetical agent.
+    #[serde(default)]
+    pub system_info: Option<SystemInfo>,
+    /// Simulate as this agent uid, so tasks pinned to it via `runner` are included.
+    #[serde(default)]
+    pub agent_id: Option<String>,
+}
+
+/// A task the simulated agent would be offered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedOffer {
+    pub id: TaskId,
+    pub urgent: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Chance the task is offered on a poll; below 1.0 only under a weighted
+    /// selection strategy when higher-tier agents are online.
+    pub offer_probability: f64,
+}
+
 /// A simple confirmation response after a successful agent registration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -130,6 +200,38 @@ pub struct AgentRegistrationResponse {
     pub key: String,
     /// A confirmation message.
     pub message: String,
+    /// Server-recommended polling cadence and endpoint paths. Older servers
+    /// omit it; agents should fall back to their built-in defaults.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub polling: Option<AgentPollingSettings>,
+}
+
+/// How an HTTP agent should talk to this server, derived from its config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentPollingSettings {
+    pub urgent_poll_interval_ms: u64,
+    pub poll_interval_ms: u64,
+    /// Ping at least this often to stay online for scheduling.
+    pub heartbeat_interval_secs: u64,
+    pub endpoints: AgentEndpoints,
+}
+
+/// Agent API paths; `{cap}` and `{id}` are the percent-encoded task id parts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentEndpoints {
+    pub auth: String,
+    pub refresh_token: String,
+    pub websocket: String,
+    pub ping: String,
+    pub info_update: String,
+    pub poll_urgent: String,
+    pub poll: String,
+    pub take: String,
+    pub progress: String,
+    pub resolve: String,
+    pub touch: String,
 }
 
 /// Request body for an agent to log in and receive a JWT.
@@ -139,6 +241,9 @@ pub struct AgentLoginRequest {
     /// The ID of the agent wishing to log in.
     pub agent_id: String,
     pub key: String,
+    /// Also issue a long-lived refresh token (see `POST /agent/refresh_token`).
+    #[serde(default)]
+    pub request_refresh_token: bool,
 }
 
 /// Response containing the session JWT for an authenticated agent.
@@ -149,6 +254,16 @@ pub struct AgentLoginResponse {
     pub token: String,
     /// The token's validity period in seconds.
     pub expires_in: usize,
+    /// Present when requested at login; exchange it for a new `token`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+/// Request body for exchanging a refresh token for a new session JWT.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentRefreshTokenRequest {
+    pub refresh_token: String,
 }
 
 /// Convert legacy megabyte counts to whole gigabytes (same rounding as the agent).
@@ -293,12 +408,77 @@ pub struct FileReference {
 //  Task Lifecycle API
 //=============================================================================
 
+/// Per-task retry policy for a `restartable` task.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskRetryConfiguration {
+    /// Retries after the first failure; overrides `TASK_MAX_RESTARTS`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+    /// Allow a retry on an agent that already failed the task. By default
+    /// each retry goes to an agent that has not.
+    #[serde(default)]
+    pub retry_on_same_node: bool,
+    /// Seconds a failed task waits in the queue before it is offered again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_delay_secs: Option<u64>,
+}
+
+/// Hardware an agent must report in its `systemInfo` to be offered a task.
+/// Units match `SystemInfo` (whole gigabytes).
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskRequirements {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_vram_gb: Option<u64>,
+    /// GPU vendor, compared case-insensitively (e.g. "nvidia").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu_vendor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_memory_gb: Option<u64>,
+}
+
+impl TaskRequirements {
+    /// Whether an agent reporting `system_info` meets every requirement. An
+    /// unknown system (`None`) meets none.
+    pub fn satisfied_by(&self, system_info: Option<&SystemInfo>) -> bool {
+        let Some(info) = system_info else {
+            return false;
+        };
+        if self
+            .min_memory_gb
+            .is_some_and(|min| info.total_memory_gb < min)
+        {
+            return false;
+        }
+        if self.min_vram_gb.is_none() && self.gpu_vendor.is_none() {
+            return true;
+        }
+        let Some(gpu) = &info.gpu else {
+            return false;
+        };
+        self.min_vram_gb.is_none_or(|min| gpu.vram_gb >= min)
+            && self
+                .gpu_vendor
+                .as_deref()
+                .is_none_or(|vendor| gpu.vendor.eq_ignore_ascii_case(vendor))
+    }
+}
+
 /// Request body for a client to submit a new task.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct TaskSubmissionRequest {
     /// The specific capability required to execute this task.
     pub capability: String,
+    /// Further capabilities besides `capability`, which stays the queue the
+    /// task is stored and polled under. When set, agents are matched against
+    /// the whole set according to `match_mode`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Vec<String>>,
+    /// How `capability` plus `capabilities` are matched (default: any of them).
+    #[serde(default)]
+    pub match_mode: CapabilityMatchMode,
     /// If true, the task will be pushed to an agent immediately.
     /// If false, it will be queued persistently.
     #[serde(default)]
@@ -306,6 +486,14 @@ pub struct TaskSubmissionRequest {
     /// If true, the task can be re-assigned to another agent upon failure.
     #[serde(default)]
     pub restartable: bool,
+    /// How a `restartable` task is retried after a failure. Unset means up to
+    /// `TASK_MAX_RESTARTS` retries, each on a different agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<TaskRetryConfiguration>,
+    /// Hardware the agent must have. Unset means any agent with the
+    /// capability.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requirements: Option<TaskRequirements>,
     /// The task-specific data payload.
     /// Can be any valid JSON object.
     pub payload: Value,
@@ -343,18 +531,223 @@ pub struct TaskSubmissionRequest {
     /// If not set, agents fall back to `timeoutSecs` or their own defaults.
     #[serde(default, rename = "runtimeSecs")]
     pub runtime_secs: Option<u64>,
+    /// Seconds an agent has to finish the task once it is assigned. Past
+    /// this the server fails the attempt (re-queuing it when restartable),
+    /// whether or not the agent is still reporting.
+    #[serde(default, rename = "deadlineSecs", skip_serializing_if = "Option::is_none")]
+    pub deadline_secs: Option<u64>,
     #[serde(default)]
     pub artifacts: Vec<FileReference>,
     #[serde(default)]
     pub data_preparation: HashMap<String, String>,
+    /// Urgent tasks only. If true, the submission is rejected immediately when
+    /// no online agent for the capability has a free slot, instead of waiting
+    /// for one until the task expires.
+    #[serde(default)]
+    pub fail_fast: bool,
+    /// Non-urgent tasks only. When the submitting key is served, its tasks
+    /// with the highest priority are handed out first; other keys' tasks are
+    /// not affected (default: 0).
+    #[serde(default)]
+    pub priority: i32,
+    /// Optional client-chosen identifier for this task, unique per API key.
+    /// Submitting again with an id that is already in use returns the existing
+    /// task instead of creating a duplicate. Only supported for non-urgent tasks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_task_id: Option<String>,
+    /// `http(s)` URL the final status report is POSTed to once the task
+    /// finishes (see `mq::webhook`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+    /// Earliest moment the task may be handed to an agent. Until then it
+    /// stays queued. Only supported for non-urgent tasks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// Non-urgent tasks that must complete successfully before this one is
+    /// handed out. Only supported for non-urgent tasks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<TaskId>,
+    /// May be left out when the key is sent in a header instead.
+    #[serde(default)]
     pub api_key: String,
 }
 
+/// How a task with several capabilities is matched against an agent's.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CapabilityMatchMode {
+    /// The agent serves at least one of them.
+    #[default]
+    AnyOf,
+    /// The agent serves every one of them.
+    AllOf,
+}
+
+impl TaskSubmissionRequest {
+    /// Whether an agent reporting `system_info` meets the task's
+    /// `requirements` (always true without requirements).
+    pub fn hardware_fits(&self, system_info: Option<&SystemInfo>) -> bool {
+        self.requirements
+            .as_ref()
+            .is_none_or(|requirements| requirements.satisfied_by(system_info))
+    }
+
+    /// `capability` followed by any extra `capabilities`.
+    pub fn match_capabilities(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.capability.as_str())
+            .chain(self.capabilities.iter().flatten().map(String::as_str))
+    }
+
+    /// Whether an agent advertising `advertised` can run this task. Without
+    /// extra `capabilities` that is just serving `capability`.
+    pub fn served_by(&self, advertised: &[String]) -> bool {
+        let mut caps = self.match_capabilities();
+        match self.match_mode {
+            CapabilityMatchMode::AnyOf => caps.any(|cap| agent_serves(advertised, cap)),
+            CapabilityMatchMode::AllOf => caps.all(|cap| agent_serves(advertised, cap)),
+        }
+    }
+}
+
+/// A client request body acting for an API key. [`crate::middleware::ClientJson`]
+/// fills the key in from the request header when the body leaves it out.
+pub trait ClientRequest {
+    fn api_key_mut(&mut self) -> &mut String;
+}
+
+impl ClientRequest for TaskSubmissionRequest {
+    fn api_key_mut(&mut self) -> &mut String {
+        &mut self.api_key
+    }
+}
+
+impl ClientRequest for ApiKeyRequest {
+    fn api_key_mut(&mut self) -> &mut String {
+        &mut self.api_key
+    }
+}
+
+impl ClientRequest for BatchSubmitRequest {
+    fn api_key_mut(&mut self) -> &mut String {
+        &mut self.api_key
+    }
+}
+
+impl ClientRequest for TaskListRequest {
+    fn api_key_mut(&mut self) -> &mut String {
+        &mut self.api_key
+    }
+}
+
+impl ClientRequest for BatchPollRequest {
+    fn api_key_mut(&mut self) -> &mut String {
+        &mut self.api_key
+    }
+}
+
+impl ClientRequest for ClientTaskIdRequest {
+    fn api_key_mut(&mut self) -> &mut String {
+        &mut self.api_key
+    }
+}
+
+impl ClientRequest for EscalateTaskRequest {
+    fn api_key_mut(&mut self) -> &mut String {
+        &mut self.api_key
+    }
+}
+
 /// Request body for a client with api_key field.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ApiKeyRequest {
+    /// May be left out when the key is sent in a header instead.
+    #[serde(default)]
+    pub api_key: String,
+}
+
+/// Request body for submitting several non-urgent tasks at once. Each entry
+/// is a task submission without its own `apiKey`: the batch key applies to all.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchSubmitRequest {
+    /// May be left out when the key is sent in a header instead.
+    #[serde(default)]
+    pub api_key: String,
+    pub tasks: Vec<serde_json::Value>,
+}
+
+/// Request body for listing the caller's own non-urgent tasks.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskListRequest {
+    /// May be left out when the key is sent in a header instead.
+    #[serde(default)]
+    pub api_key: String,
+    /// Only tasks of this base capability (extended attributes are ignored).
+    #[serde(default)]
+    pub capability: Option<String>,
+    #[serde(default)]
+    pub status: Option<TaskStatus>,
+    /// Page size, default 100, at most 1000.
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// `nextCursor` of the previous page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+/// Request body for polling several tasks at once.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchPollRequest {
+    /// May be left out when the key is sent in a header instead.
+    #[serde(default)]
+    pub api_key: String,
+    pub ids: Vec<TaskId>,
+}
+
+/// How one id of a batch poll was resolved.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BatchPollResult {
+    Found,
+    NotFound,
+    /// The task exists but belongs to another API key.
+    Denied,
+}
+
+/// One entry of a batch poll response, in request order.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchPollEntry {
+    pub id: TaskId,
+    pub result: BatchPollResult,
+    /// Present only when `result` is `found`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task: Option<TaskStatusResponse>,
+}
+
+/// Request body for looking up a task by its client-chosen id.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientTaskIdRequest {
+    /// May be left out when the key is sent in a header instead.
+    #[serde(default)]
     pub api_key: String,
+    pub client_task_id: String,
+}
+
+/// Request body for moving a queued non-urgent task to the urgent queue.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct EscalateTaskRequest {
+    /// May be left out when the key is sent in a header instead.
+    #[serde(default)]
+    pub api_key: String,
+    /// Pending TTL in the urgent queue; defaults to the urgent default of 60s.
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
 }
 
 /// Unique task identifier that contains queue id (capability) and task id within that queue
@@ -444,7 +837,13 @@ pub struct TaskStatusResponse {
 
     pub log: Option<String>,
 
-    #[serde(default)]
+    /// Historical typical runtime, in whole milliseconds on the wire.
+    #[serde(
+        default,
+        with = "crate::utils::duration_ms_opt",
+        rename = "typicalRuntimeMs",
+        alias = "typicalRuntimeSeconds"
+    )]
     pub typical_runtime_seconds: Option<std::time::Duration>,
 
     /// Informational: the effort parameters (resolution/length) extracted from
@@ -452,9 +851,75 @@ pub struct TaskStatusResponse {
     /// by these — consumers do not need to re-derive or apply any scaling themselves.
     #[serde(default, skip_serializing_if = "Option::is_none", rename = "typicalRuntimeParameters")]
     pub typical_runtime_parameters: Option<TypicalRuntimeParameters>,
+
+    /// Queue/execution timing, only included when requested with `?includeTiming=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<TaskTiming>,
+
+    /// Where to fetch the JSON Schema of `output`. Only set for completed tasks
+    /// whose capability declares an output schema in the registry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_schema_ref: Option<String>,
+
+    /// The output was purged after the capability's result TTL; `output` is
+    /// absent for good.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub output_expired: bool,
+
+    /// Set while the task is queued behind an open (or half-open) circuit
+    /// breaker for its capability: it won't be handed out until it closes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit: Option<crate::mq::circuit::CircuitState>,
+
+    /// Set while the task is queued for a capability deactivated in the
+    /// registry: it is held, not handed out, until the capability is active.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub capability_inactive: bool,
+
+    /// Number of tasks for the same capability queued ahead of this one
+    /// (0 = next in line). Only for queued tasks, and only when requested with
+    /// `?includePosition=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
+
+    /// Set while a failed task waits out its `retry.retryDelaySecs` before it
+    /// is offered to agents again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Set while a task submitted with a future `notBefore` waits for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Latest fraction of the work done (0.0-1.0) reported by the agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f32>,
+
+    /// Lifecycle events in order, only included when requested with `?includeHistory=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<crate::models::TaskEvent>>,
+}
+
+/// Timing breakdown of a task's lifecycle, for SLA monitoring.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskTiming {
+    pub queued_at: chrono::DateTime<chrono::Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assigned_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Time spent waiting in the queue before assignment, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_wait_ms: Option<i64>,
+    /// Execution time in milliseconds. Uses the duration the agent reported with a
+    /// successful result when available, otherwise `completed_at - assigned_at`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execution_ms: Option<i64>,
 }
 
-/// The message pushed to an agent via WebSocket to assign a new task.
+/// Minimal task assignment shape. Not what the WebSocket push sends: that is
+/// `{"type": "task", "task": <AssignedTask>}` (see `mq::dispatch`).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TaskAssignment {
@@ -488,6 +953,19 @@ pub struct TaskUpdate {
     /// Optional status transition (e.g. Starting, Running).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub status: Option<TaskStatus>,
+    /// Fraction of the work done, 0.0-1.0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f32>,
+}
+
+/// Response to a task keepalive ("touch") from the executing agent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskTouchResponse {
+    pub id: TaskId,
+    /// When the task becomes eligible for reclamation unless touched or updated
+    /// again. `None` for urgent tasks, which are not lease-reclaimed.
+    pub lease_expires_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 /// Metadata for a single file within a bucket, returned by `bucket_stat`.
@@ -1,22 +1,34 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
+use log::warn;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::sync::{broadcast, mpsc, watch};
 
 use crate::{
     config::AppConfig,
-    db::{app_storage::AppStorage, service_message_storage::ServiceMessage},
-    middleware::auth::Auth,
+    db::{
+        app_storage::AppStorage, service_message_storage::ServiceMessage,
+        task_events::TaskTransition, urgent_audit::UrgentAuditRecord,
+    },
+    middleware::{auth::Auth, auth_metrics::AuthMetrics, rate_limit::ClientRateLimiter},
+    models::UrgentSnapshot,
     mq::{
-        agent_load::AgentLoad, registry::AgentRegistry, regular::RegularTaskStore,
-        urgent::UrgentTaskStore,
+        agent_load::AgentLoad, circuit::CircuitBreaker, online_caps::OnlineCapsCache,
+        poll_gate::PollGate, quarantine::AgentFaults, queue_wait::QueueWaitStats,
+        registry::AgentRegistry, regular::RegularTaskStore, urgent::UrgentTaskStore,
+        webhook::WebhookDelivery,
     },
+    readiness::Readiness,
     schema::{TaskId, TaskResultStatus, TaskStatus},
 };
 
 const STREAM_BROADCAST_CAPACITY: usize = 256;
 const DB_WRITE_QUEUE_CAPACITY: usize = 1024;
+const WEBHOOK_QUEUE_CAPACITY: usize = 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
@@ -55,17 +67,26 @@ pub enum DbWriteRequest {
         kind: String,
         content: Value,
     },
+    UrgentAudit(UrgentAuditRecord),
+    TaskTransition(TaskTransition),
+    /// Write-through of an urgent task (`URGENT_PERSIST`).
+    UrgentSnapshot(Box<UrgentSnapshot>),
+    UrgentForget(TaskId),
+    UrgentClear,
 }
 
 #[derive(Clone)]
 pub struct AppChannels {
     pub stream_tx: broadcast::Sender<StreamEvent>,
     pub db_write_tx: mpsc::Sender<DbWriteRequest>,
+    /// Completion callbacks waiting for the webhook worker.
+    pub webhook_tx: mpsc::Sender<WebhookDelivery>,
     pub shutdown_tx: watch::Sender<bool>,
 }
 
 pub struct AppWorkers {
     pub db_write_rx: mpsc::Receiver<DbWriteRequest>,
+    pub webhook_rx: mpsc::Receiver<WebhookDelivery>,
     pub shutdown_rx: watch::Receiver<bool>,
 }
 
@@ -73,15 +94,18 @@ impl AppChannels {
     pub fn new() -> (Self, AppWorkers) {
         let (stream_tx, _) = broadcast::channel(STREAM_BROADCAST_CAPACITY);
         let (db_write_tx, db_write_rx) = mpsc::channel(DB_WRITE_QUEUE_CAPACITY);
+        let (webhook_tx, webhook_rx) = mpsc::channel(WEBHOOK_QUEUE_CAPACITY);
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
         (
             Self {
                 stream_tx,
                 db_write_tx,
+                webhook_tx,
                 shutdown_tx,
             },
             AppWorkers {
                 db_write_rx,
+                webhook_rx,
                 shutdown_rx,
             },
         )
@@ -101,25 +125,78 @@ pub struct AppState {
     /// Source of truth for the dispatch capacity gate; reconciled from the task
     /// store so it cannot leak the way the per-connection counter did.
     pub agent_load: Arc<AgentLoad>,
+    /// Rolling per-capability wait between task creation and assignment.
+    pub queue_wait: Arc<QueueWaitStats>,
+    /// Serializes stampeding HTTP polls (`POLL_GATE_MS`).
+    pub poll_gate: Arc<PollGate>,
+    /// Recently computed `capabilities/online` set
+    /// (`CAPABILITIES_ONLINE_CACHE_SECS`).
+    pub online_caps: Arc<OnlineCapsCache>,
+    /// Holds back non-urgent tasks of capabilities that keep failing.
+    pub circuit: Arc<CircuitBreaker>,
+    /// Per-agent fault counts behind automatic quarantine.
+    pub agent_faults: Arc<AgentFaults>,
+    /// Auth successes/failures by credential source.
+    pub auth_metrics: Arc<AuthMetrics>,
+    /// Per-client-key token buckets (`CLIENT_RATE_LIMIT_RPS`).
+    pub rate_limiter: Arc<ClientRateLimiter>,
     pub channels: AppChannels,
     /// Serializes bucket validation + reservation during task submission so two
     /// concurrent submissions can't both pass the `rm_after_task` single-use
     /// check before either records its task id (TOCTOU).
     pub bucket_submit_lock: Arc<tokio::sync::Mutex<()>>,
+    /// When this server instance started; drives the uptime reported at `/`.
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// In-flight request count and drain flag behind `/ready`.
+    pub readiness: Arc<Readiness>,
 }
 
 impl AppState {
     pub fn new(storage: AppStorage, config: AppConfig, auth: Auth, channels: AppChannels) -> Self {
+        let audit = config
+            .urgent
+            .audit_log
+            .then(|| channels.db_write_tx.clone());
+        let persist = config.urgent.persist.then_some(storage.tasks.as_ref());
+        let urgent = UrgentTaskStore::new(
+            config.urgent.expiry_grace_secs,
+            config.urgent.terminal_retention_secs,
+            audit,
+            Some(channels.db_write_tx.clone()),
+            persist,
+        );
+        storage.tasks.send_callbacks_to(channels.webhook_tx.clone());
+        urgent.send_callbacks_to(channels.webhook_tx.clone());
+        let regular = RegularTaskStore::with_preferences(config.preferences.clone());
+        let poll_gate = PollGate::new(config.poll_gate_ms);
+        let online_caps = OnlineCapsCache::new(config.capabilities_online_cache_secs);
+        let circuit = CircuitBreaker::new(config.circuit_breaker.clone());
+        let agent_faults = AgentFaults::new(config.quarantine.clone());
+        let auth_metrics = AuthMetrics::new(
+            config.auth_failure_alert_threshold,
+            config.auth_failure_alert_window_secs,
+        );
+        let rate_limiter =
+            ClientRateLimiter::new(config.client_rate_limit_rps, config.client_rate_limit_burst);
         Self {
             storage: Arc::new(storage),
             config: Arc::new(config),
             auth: Arc::new(auth),
-            urgent: UrgentTaskStore::new(),
-            regular: RegularTaskStore::new(),
+            urgent,
+            regular,
             registry: AgentRegistry::new(),
             agent_load: AgentLoad::new(),
+            queue_wait: QueueWaitStats::new(),
+            poll_gate,
+            online_caps,
+            circuit,
+            agent_faults,
+            auth_metrics,
+            rate_limiter,
             channels,
             bucket_submit_lock: Arc::new(tokio::sync::Mutex::new(())),
+            started_at: chrono::Utc::now(),
+            readiness: Arc::new(Readiness::default()),
         }
     }
 
@@ -130,4 +207,48 @@ impl AppState {
     pub fn subscribe_shutdown(&self) -> watch::Receiver<bool> {
         self.channels.shutdown_tx.subscribe()
     }
+
+    /// Rebuild the authoritative per-agent in-flight load from the source of
+    /// truth: non-terminal assigned tasks in the persistent regular store plus
+    /// in-flight urgent assignments (restored ones included), grouped by owning
+    /// agent uid. Seeds the load at startup and reconciles it on every
+    /// maintenance tick (self-healing).
+    pub async fn reconcile_agent_load(&self) {
+        let mut live: HashMap<String, HashSet<TaskId>> = HashMap::new();
+        match self.storage.tasks.list_assigned_all() {
+            Ok(assigned) => {
+                for task in assigned {
+                    if !task.status.is_terminal() {
+                        live.entry(task.agent_id.clone())
+                            .or_default()
+                            .insert(task.id.clone());
+                    }
+                }
+            }
+            Err(e) => warn!("agent_load reconcile: failed to list assigned tasks: {e}"),
+        }
+        for (agent_id, task_id) in self.urgent.list_assigned_owners().await {
+            live.entry(agent_id).or_default().insert(task_id);
+        }
+        self.agent_load.reconcile(live);
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State over fresh storage in a temporary directory, configured from the
+    /// environment with `tweak` applied. Background queues have no workers.
+    pub(crate) fn for_test(tweak: impl FnOnce(&mut AppConfig)) -> Arc<Self> {
+        let base = std::env::temp_dir().join(format!("offloadmq-test-{}", uuid::Uuid::new_v4()));
+        let base = base.to_str().unwrap().to_string();
+        let mut config = AppConfig::from_env().unwrap();
+        config.database_root_path = base.clone();
+        config.storage = crate::config::StorageConfig::from_env(&base);
+        tweak(&mut config);
+        let storage =
+            AppStorage::new(&base, &config.storage, config.agent_online_timeout_secs).unwrap();
+        let auth = Auth::new(config.jwt_secret.as_bytes());
+        let (channels, _) = AppChannels::new();
+        Arc::new(Self::new(storage, config, auth, channels))
+    }
 }
@@ -0,0 +1,47 @@
+//! Supervised background tasks.
+//!
+//! A plain `tokio::spawn`ed loop that panics simply stops, silently taking its
+//! periodic work (expiry, sweeps, logging) with it. [`spawn_supervised`] runs
+//! the loop under a supervisor that logs the panic and starts a fresh instance.
+
+use std::future::Future;
+
+use log::{error, info};
+use tokio::{task::JoinHandle, time};
+
+/// Delay before restarting a task that panicked, so a loop that panics on every
+/// iteration can't spin the CPU.
+const RESTART_DELAY: time::Duration = time::Duration::from_secs(1);
+
+/// Spawn the future produced by `make_task` and restart it whenever it panics.
+/// When the task returns normally (e.g. on shutdown) the supervisor exits too.
+/// `make_task` is called again for each restart, so it should capture clones
+/// of whatever state the loop needs rather than moving it in.
+pub fn spawn_supervised<F, Fut>(name: &'static str, make_task: F) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            match tokio::spawn(make_task()).await {
+                Ok(()) => {
+                    info!("Background task '{}' finished", name);
+                    return;
+                }
+                Err(e) if e.is_panic() => {
+                    error!(
+                        "Background task '{}' panicked, restarting in {:?}: {}",
+                        name, RESTART_DELAY, e
+                    );
+                    time::sleep(RESTART_DELAY).await;
+                }
+                Err(e) => {
+                    // Cancelled: the runtime is shutting down.
+                    info!("Background task '{}' cancelled: {}", name, e);
+                    return;
+                }
+            }
+        }
+    })
+}
@@ -117,6 +117,15 @@ pub fn capability_attrs(cap: &str) -> Vec<&str> {
     }
 }
 
+/// Escape a Prometheus label value: backslash, double quote and newline must
+/// be escaped in the text exposition format.
+pub fn prometheus_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
 /// Helper to parse a u64 from a JSON Value (supporting both numbers and string numbers)
 pub fn val_to_u64(val: &serde_json::Value) -> Option<u64> {
     if let Some(n) = val.as_u64() {
@@ -145,3 +154,198 @@ pub fn extract_image_resolution(payload: &serde_json::Value) -> Option<(u64, u64
     None
 }
 
+/// Check `value` against a small, commonly used subset of JSON Schema: `type`
+/// (single or list), `enum`, `required`, `properties` and `items`. Unknown
+/// keywords are ignored, so richer schemas are accepted but only partially
+/// enforced. Returns a message naming the first offending path.
+pub fn validate_json_schema(
+    value: &serde_json::Value,
+    schema: &serde_json::Value,
+) -> Result<(), String> {
+    validate_json_schema_at(value, schema, "$")
+}
+
+fn validate_json_schema_at(
+    value: &serde_json::Value,
+    schema: &serde_json::Value,
+    path: &str,
+) -> Result<(), String> {
+    use serde_json::Value;
+
+    let Some(schema) = schema.as_object() else {
+        // `true` / `{}`-like schemas accept anything; `false` rejects everything.
+        return match schema {
+            Value::Bool(false) => Err(format!("{path}: no value is allowed here")),
+            _ => Ok(()),
+        };
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let type_matches = |name: &str| match name {
+            "null" => value.is_null(),
+            "boolean" => value.is_boolean(),
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "integer" => value.is_i64() || value.is_u64(),
+            _ => true,
+        };
+        let ok = match expected {
+            Value::String(name) => type_matches(name),
+            Value::Array(names) => names.iter().filter_map(Value::as_str).any(type_matches),
+            _ => true,
+        };
+        if !ok {
+            return Err(format!("{path}: expected type {expected}"));
+        }
+    }
+
+    if let Some(Value::Array(allowed)) = schema.get("enum")
+        && !allowed.contains(value)
+    {
+        return Err(format!("{path}: value is not one of the allowed values"));
+    }
+
+    if let Some(object) = value.as_object() {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for field in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(field) {
+                    return Err(format!("{path}: missing required field '{field}'"));
+                }
+            }
+        }
+        if let Some(Value::Object(properties)) = schema.get("properties") {
+            for (field, field_schema) in properties {
+                if let Some(field_value) = object.get(field) {
+                    validate_json_schema_at(field_value, field_schema, &format!("{path}.{field}"))?;
+                }
+            }
+        }
+    }
+
+    if let (Some(items), Some(item_schema)) = (value.as_array(), schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            validate_json_schema_at(item, item_schema, &format!("{path}[{i}]"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Serde helper that puts an optional [`std::time::Duration`] on the wire as a
+/// plain integer number of milliseconds (`null` when absent), which every agent
+/// SDK can read without knowing serde's `{secs, nanos}` struct layout. The
+/// legacy struct form is still accepted on input.
+///
+/// Use with `#[serde(default, with = "crate::utils::duration_ms_opt")]`.
+pub mod duration_ms_opt {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Millis(u64),
+        Legacy { secs: u64, nanos: u32 },
+    }
+
+    pub fn serialize<S: Serializer>(value: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(d) => s.serialize_u64(d.as_millis().try_into().unwrap_or(u64::MAX)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<Repr>::deserialize(d)?.map(|repr| match repr {
+            Repr::Millis(ms) => Duration::from_millis(ms),
+            Repr::Legacy { secs, nanos } => Duration::new(secs, nanos),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn summary_schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "required": ["summary", "tokens"],
+            "properties": {
+                "summary": { "type": "string" },
+                "tokens": { "type": "integer" },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "mode": { "enum": ["fast", "full"] }
+            }
+        })
+    }
+
+    #[test]
+    fn accepts_matching_output() {
+        let output = json!({ "summary": "ok", "tokens": 12, "tags": ["a"], "mode": "fast" });
+        assert_eq!(validate_json_schema(&output, &summary_schema()), Ok(()));
+    }
+
+    #[test]
+    fn reports_first_offending_path() {
+        let missing = json!({ "summary": "ok" });
+        assert_eq!(
+            validate_json_schema(&missing, &summary_schema()),
+            Err("$: missing required field 'tokens'".to_string())
+        );
+
+        let bad_item = json!({ "summary": "ok", "tokens": 1, "tags": ["a", 2] });
+        assert_eq!(
+            validate_json_schema(&bad_item, &summary_schema()),
+            Err("$.tags[1]: expected type \"string\"".to_string())
+        );
+
+        let bad_enum = json!({ "summary": "ok", "tokens": 1, "mode": "slow" });
+        assert!(validate_json_schema(&bad_enum, &summary_schema()).is_err());
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Timed {
+        #[serde(default, with = "duration_ms_opt")]
+        duration_ms: Option<std::time::Duration>,
+    }
+
+    #[test]
+    fn duration_ms_round_trips_as_integer_millis() {
+        let timed = Timed {
+            duration_ms: Some(std::time::Duration::from_millis(1234)),
+        };
+        let wire = serde_json::to_value(&timed).unwrap();
+        assert_eq!(wire, json!({ "durationMs": 1234 }));
+        assert_eq!(serde_json::from_value::<Timed>(wire).unwrap(), timed);
+
+        let none = Timed { duration_ms: None };
+        let wire = serde_json::to_value(&none).unwrap();
+        assert_eq!(wire, json!({ "durationMs": null }));
+        assert_eq!(serde_json::from_value::<Timed>(wire).unwrap(), none);
+        assert_eq!(serde_json::from_value::<Timed>(json!({})).unwrap(), none);
+    }
+
+    #[test]
+    fn duration_ms_accepts_legacy_secs_nanos() {
+        let timed: Timed =
+            serde_json::from_value(json!({ "durationMs": { "secs": 2, "nanos": 500_000_000 } }))
+                .unwrap();
+        assert_eq!(
+            timed.duration_ms,
+            Some(std::time::Duration::from_millis(2500))
+        );
+    }
+
+    #[test]
+    fn prometheus_label_escapes_quotes_backslashes_and_newlines() {
+        assert_eq!(prometheus_label("llm.qwen3:8b[gpu]"), "llm.qwen3:8b[gpu]");
+        assert_eq!(prometheus_label("a\\b\"c\nd"), "a\\\\b\\\"c\\nd");
+    }
+}